@@ -0,0 +1,65 @@
+/*
+ * milti_llm_client の C のAPI（src/ffi.rs）
+ *
+ * cargo build --release でできる共有ライブラリ（target/release/libmilti_llm_client.so / .dylib / .dll）とリンクする。
+ * このライブラリが返した文字列は mlc_string_free、クライアントは mlc_client_free で解放する。
+ *
+ * スレッドについて:
+ *   - 推論はライブラリの中のスレッドで動く。どの関数も、呼んだスレッドを答えが届くまで止めることはない
+ *     （mlc_send_blocking と、timeout_ms を付けた mlc_poll だけが待つ）。
+ *   - 1つのクライアントのイベントは1つのキューに入る。mlc_poll と mlc_send_blocking は、そのクライアントについては
+ *     1つのスレッドから呼ぶ（別のスレッドから同時に呼ぶと、片方が待たされ、どちらがどのイベントを受け取るかは決まらない）。
+ *   - mlc_send_blocking は自分が送った回の答えだけを返す。前の mlc_send のイベントの読み残しは捨てる。
+ *   - mlc_client_free は、ほかのスレッドがそのクライアントの関数を呼んでいないときに呼ぶ。
+ *   - 別々のクライアントは、別々のスレッドから同時に使ってよい。
+ *   - ライブラリの中でパニックしても C 側には巻き戻さず、エラーとして返す（そのクライアントはもう送れない）。
+ */
+#ifndef MILTI_LLM_CLIENT_H
+#define MILTI_LLM_CLIENT_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct MlcClient MlcClient;
+
+/* mlc_send の返り値 */
+#define MLC_OK 0
+#define MLC_INVALID_ARGUMENT 1
+#define MLC_BUSY 2 /* ほかのメッセージを送っている途中 */
+#define MLC_INTERNAL_ERROR 3 /* ライブラリの中でパニックした（このクライアントはもう送れない） */
+
+/* mlc_poll で受け取るイベントの種類 */
+#define MLC_EVENT_NONE 0        /* まだ何も届いていない（NULL を返す） */
+#define MLC_EVENT_TEXT 1        /* 答えの断片 */
+#define MLC_EVENT_REASONING 2   /* 考え中の部分の断片 */
+#define MLC_EVENT_ROUTED 3      /* {"language": …, "model": …} */
+#define MLC_EVENT_TOOL_CALL 4   /* {"name": …, "arguments": …} */
+#define MLC_EVENT_TOOL_RESULT 5 /* {"name": …, "output": …} */
+#define MLC_EVENT_DONE 6        /* 答えの全体（このあと次を送れる） */
+#define MLC_EVENT_ERROR 7       /* エラーのメッセージ（このあと次を送れる） */
+
+/* 設定ファイル（JSON / TOML / YAML）か JSON の文字列からクライアントを作る。
+ * 失敗したら NULL を返し、error が NULL でなければ理由の文字列を入れる。 */
+MlcClient *mlc_client_from_file(const char *path, char **error);
+MlcClient *mlc_client_from_json(const char *json, char **error);
+
+/* メッセージを送り始める（答えを待たずに戻る）。 */
+int mlc_send(MlcClient *client, const char *message);
+
+/* 届いたイベントを1つ取り出す（なければ timeout_ms ミリ秒まで待つ。0 なら待たない）。
+ * 同じクライアントについては1つのスレッドから呼ぶ（上の「スレッドについて」を参照）。 */
+char *mlc_poll(MlcClient *client, int timeout_ms, int *kind);
+
+/* 送って、答えの全体を待つ（失敗したら NULL を返し、error に理由を入れる）。
+ * 待っているあいだに届く途中のイベントと、前に送った回の読み残しは捨てる。 */
+char *mlc_send_blocking(MlcClient *client, const char *message, char **error);
+
+void mlc_string_free(char *text);
+void mlc_client_free(MlcClient *client);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,332 @@
+// ほかの Rust のアプリに組み込むための、安定した公開API（Client::builder / ChatSession / StreamEvent）
+//
+// ここで公開している型とメソッドは、semver に従って互換を保つ（0.x のあいだは、マイナーバージョンを上げるときだけ変える）。
+// Client のほかのメソッドはコマンドラインのクライアントのためのもので、パッチバージョンでも変わることがある。
+// StreamEvent と、結果として返す Completion / Usage / Error / ErrorKind には #[non_exhaustive] を付けてあるので、項目を足しても互換は崩れない。
+use serde_json::Value;
+use crate::stream::Token;
+use crate::{config_file, Client, Completion, Config, Error, Event, Message, Options};
+
+impl Client {
+    /// 設定を組み立てて [`Client`] を作るビルダーを返す。
+    ///
+    /// 設定ファイルを読むなら [`ClientBuilder::config_file`]、読まないなら
+    /// [`ClientBuilder::model`] と [`ClientBuilder::provider`] / [`ClientBuilder::endpoint`] で行き先を決める。
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::default()
+    }
+}
+
+/// [`Client`] を作るビルダー（[`Client::builder`] で作る）。
+///
+/// 設定ファイル（か [`Config`]）の上に、ここで指定した項目を重ねる。
+#[derive(Default)]
+#[must_use]
+pub struct ClientBuilder {
+    config: Option<Result<Config, Error>>,
+    model: Option<String>,
+    profile: Option<String>,
+    provider: Option<String>,
+    endpoint: Option<String>,
+    api_key: Option<String>,
+    system_prompt: Option<String>,
+    stream: bool,
+}
+
+impl ClientBuilder {
+    /// 読み込んである設定を使う。
+    pub fn config(mut self, config: Config) -> ClientBuilder {
+        self.config = Some(Ok(config));
+        self
+    }
+
+    /// 設定ファイル（JSON / TOML / YAML）を読む。読めなければ [`ClientBuilder::build`] が `Err` を返す。
+    pub fn config_file(mut self, path: &str) -> ClientBuilder {
+        self.config = Some(Config::from_file(path));
+        self
+    }
+
+    /// 答えるモデル。プロファイル名、`"モデル名@行き先"`、別名のどれでもよい。
+    pub fn model(mut self, model: &str) -> ClientBuilder {
+        self.model = Some(model.to_string());
+        self
+    }
+
+    /// 設定ファイルの `profiles` から使うプロファイル。
+    pub fn profile(mut self, profile: &str) -> ClientBuilder {
+        self.profile = Some(profile.to_string());
+        self
+    }
+
+    /// 名前つきのプロバイダー（`"anthropic"` / `"gemini"` など）。
+    pub fn provider(mut self, provider: &str) -> ClientBuilder {
+        self.provider = Some(provider.to_string());
+        self
+    }
+
+    /// OpenAI 互換のAPIのURL（provider を指定しないとき）。
+    pub fn endpoint(mut self, endpoint: &str) -> ClientBuilder {
+        self.endpoint = Some(endpoint.to_string());
+        self
+    }
+
+    pub fn api_key(mut self, api_key: &str) -> ClientBuilder {
+        self.api_key = Some(api_key.to_string());
+        self
+    }
+
+    /// 会話の最初に送る system メッセージ。
+    pub fn system_prompt(mut self, system_prompt: &str) -> ClientBuilder {
+        self.system_prompt = Some(system_prompt.to_string());
+        self
+    }
+
+    /// ストリーミングで受け取る（[`ChatSession::send_streaming`] はこれがなくてもストリーミングする）。
+    pub fn stream(mut self, stream: bool) -> ClientBuilder {
+        self.stream = stream;
+        self
+    }
+
+    /// 設定を確かめて [`Client`] を作る。設定に問題があれば [`Error::Config`] を返す。
+    pub fn build(self) -> Result<Client, Error> {
+        let mut config = match self.config {
+            Some(config) => config?,
+            // 設定ファイルを使わないときは、指定した項目だけで設定を作る（モデルはあとで選び直す）
+            None => Config::from_value(serde_json::json!({
+                "model_name": self.model.clone().unwrap_or_default(),
+                "use_local_model": false,
+                "openai_compatible": self.endpoint.is_some(),
+                "provider": self.provider,
+                "endpoint": self.endpoint,
+            }))?,
+        };
+        if self.provider.is_some() {
+            config.provider = self.provider;
+        }
+        if self.endpoint.is_some() {
+            config.endpoint = self.endpoint;
+        }
+        if self.api_key.is_some() {
+            config.api_key = self.api_key;
+        }
+        if self.system_prompt.is_some() {
+            config.system_prompt = self.system_prompt;
+        }
+        config_file::validate(&config).map_err(Error::Config)?;
+        Client::with_options(config, Options { profile: self.profile, model: self.model, stream: self.stream, ..Options::default() })
+    }
+}
+
+/// 推論の途中で届くイベント（[`ChatSession::send_streaming`] で受け取る）。
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum StreamEvent {
+    /// 言語ごとの振り分けで、この1回だけ別のモデルが答える（判定した言語と振り分け先）。
+    Routed { language: String, model: String },
+    /// 答えの本文の断片。
+    Text(String),
+    /// 推論モデルの考え中の部分の断片（答えには含まれない）。
+    Reasoning(String),
+    /// モデルがツールを呼び出す前。
+    ToolCallStarted { name: String, arguments: Value },
+    /// ツールの結果（モデルに返す内容）。
+    ToolCallFinished { name: String, output: String },
+}
+
+impl StreamEvent {
+    fn from_event(event: Event) -> Option<StreamEvent> {
+        Some(match event {
+            Event::Routed { language, model } => StreamEvent::Routed { language, model },
+            Event::TokenDelta(Token::Answer(text)) => StreamEvent::Text(text),
+            Event::TokenDelta(Token::Reasoning(text)) => StreamEvent::Reasoning(text),
+            Event::ToolCallStarted { name, arguments } => StreamEvent::ToolCallStarted { name, arguments },
+            Event::ToolCallFinished { name, output } => StreamEvent::ToolCallFinished { name, output },
+            _ => return None,
+        })
+    }
+}
+
+/// 1つの会話。送るたびに、これまでのやりとりを履歴として一緒に送る。
+///
+/// 振り分け（`language_routes`）、middleware、ツール、出力フィルター、統計は、コマンドラインの対話と同じように働く。
+///
+/// ```no_run
+/// # async fn run() -> Result<(), milti_llm_client::Error> {
+/// use milti_llm_client::{ChatSession, Client, StreamEvent};
+///
+/// let client = Client::builder().provider("anthropic").model("claude-sonnet-4-5").api_key("sk-...").build()?;
+/// let mut session = ChatSession::new(client);
+/// let answer = session.send("こんにちは").await?;
+/// println!("{}", answer.text);
+/// session.send_streaming("続けて", |event| {
+///     if let StreamEvent::Text(text) = event {
+///         print!("{}", text);
+///     }
+/// }).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ChatSession {
+    client: Client,
+}
+
+impl ChatSession {
+    /// 新しい会話を始める（ファイルには保存しない。保存するなら [`ChatSession::save`]）。
+    pub fn new(mut client: Client) -> ChatSession {
+        client.set_chat(true);
+        ChatSession { client }
+    }
+
+    /// `sessions_dir` に保存した会話を読み込んで、その続きとして話す。
+    ///
+    /// モデル・system メッセージ・パラメータは保存したときのものに戻す（[`ClientBuilder::model`] を指定していれば、モデルはそちら）。
+    /// 読み直せなかった添付や、戻したモデルのお知らせを返す。
+    pub fn resume(client: Client, id: &str) -> Result<(ChatSession, Vec<String>), Error> {
+        let mut session = ChatSession::new(client);
+        let warnings = session.client.resume(id)?;
+        Ok((session, warnings))
+    }
+
+    /// これからのやりとりを `sessions_dir` に保存して、そのセッションのIDを返す。
+    pub fn save(&mut self) -> &str {
+        if self.client.session_id().is_none() {
+            self.client.new_session();
+        }
+        self.client.session_id().unwrap_or_default()
+    }
+
+    /// 保存しているセッションのID（保存していなければ `None`）。
+    pub fn id(&self) -> Option<&str> {
+        self.client.session_id()
+    }
+
+    /// メッセージを送って、答えを待つ。成功したら今回のやりとりを履歴に加える。
+    pub async fn send(&mut self, message: &str) -> Result<Completion, Error> {
+        self.send_with(message, false, |_| {}).await
+    }
+
+    /// メッセージを送って、届いた分から `on_event` に渡す。終わったら答えの全体を返す。
+    pub async fn send_streaming(&mut self, message: &str, on_event: impl FnMut(StreamEvent)) -> Result<Completion, Error> {
+        self.send_with(message, true, on_event).await
+    }
+
+    async fn send_with(&mut self, message: &str, stream: bool, mut on_event: impl FnMut(StreamEvent)) -> Result<Completion, Error> {
+        let streams = self.client.config().stream;
+        self.client.set_stream(streams || stream);
+        // ask の状態は大きいので、呼ぶ側のスタック（tokio のワーカーは 2MB）に載せずにヒープに置く
+        let answer = Box::pin(self.client.ask(message, std::future::pending(), |event| {
+            if let Some(event) = StreamEvent::from_event(event) {
+                on_event(event);
+            }
+        })).await;
+        self.client.set_stream(streams);
+        answer.completion.into_result()
+    }
+
+    /// これまでのやりとり（古い順）。
+    pub fn history(&self) -> &[Message] {
+        self.client.history()
+    }
+
+    /// 履歴を消して、新しい会話にする（保存していれば、新しいセッションとして保存する）。
+    pub fn clear(&mut self) {
+        self.client.clear_history();
+    }
+
+    pub fn system_prompt(&self) -> Option<&str> {
+        self.client.system_prompt()
+    }
+
+    /// 次に送るときから system メッセージを変える（`None` なら外す）。
+    pub fn set_system_prompt(&mut self, system_prompt: Option<&str>) {
+        self.client.set_system_prompt(system_prompt);
+    }
+
+    /// 答えるモデルを変える（プロファイル名、`"モデル名@行き先"`、別名のどれでもよい）。
+    pub fn set_model(&mut self, model: &str) {
+        self.client.set_model(model);
+    }
+
+    pub fn model_name(&self) -> &str {
+        self.client.model_name()
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    pub fn into_client(self) -> Client {
+        self.client
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mock_client() -> Client {
+        let config = Config::from_json(r#"{
+            "model_name": "m", "use_local_model": true, "openai_compatible": false, "local_framework": "mock",
+            "mock": { "chunk_delay_ms": 0 }
+        }"#).unwrap();
+        Client::builder().config(config).system_prompt("短く答える").build().unwrap()
+    }
+
+    #[tokio::test]
+    async fn a_session_keeps_the_history() {
+        let mut session = ChatSession::new(mock_client());
+        assert_eq!(session.system_prompt(), Some("短く答える"));
+        let answer = session.send("hello").await.unwrap();
+        assert_eq!(answer.text, "echo: hello");
+        session.send("again").await.unwrap();
+        let roles: Vec<&str> = session.history().iter().map(|message| message.role.as_str()).collect();
+        assert_eq!(roles, ["user", "assistant", "user", "assistant"]);
+        assert_eq!(session.id(), None);
+        session.clear();
+        assert!(session.history().is_empty());
+    }
+
+    #[tokio::test]
+    async fn streaming_hands_over_the_text_as_it_arrives() {
+        let mut session = ChatSession::new(mock_client());
+        let mut streamed = String::new();
+        let answer = session.send_streaming("hi there", |event| {
+            if let StreamEvent::Text(text) = event {
+                streamed.push_str(&text);
+            }
+        }).await.unwrap();
+        assert_eq!(streamed, "echo: hi there");
+        assert_eq!(answer.text, streamed);
+        // ストリーミングするのはこの1回だけ
+        assert!(!session.client().config().stream);
+    }
+
+    #[tokio::test]
+    async fn resuming_goes_back_to_the_saved_model() {
+        let dir = std::env::temp_dir().join(format!("milti_llm_client-api-{}", std::process::id()));
+        let json = serde_json::json!({
+            "model_name": "m", "use_local_model": true, "openai_compatible": false, "local_framework": "mock",
+            "mock": { "chunk_delay_ms": 0 }, "sessions_dir": dir.to_string_lossy(),
+        }).to_string();
+        let mut session = ChatSession::new(Client::builder().config(Config::from_json(&json).unwrap()).build().unwrap());
+        session.set_model("other");
+        session.set_system_prompt(Some("丁寧に"));
+        let id = session.save().to_string();
+        session.send("hello").await.unwrap();
+
+        let client = Client::builder().config(Config::from_json(&json).unwrap()).build().unwrap();
+        let (resumed, notes) = ChatSession::resume(client, &id).unwrap();
+        assert_eq!((resumed.model_name(), resumed.system_prompt()), ("other", Some("丁寧に")));
+        assert_eq!(resumed.history().len(), 2);
+        assert!(notes.iter().any(|note| note.contains("other")));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn the_builder_checks_the_settings() {
+        assert!(matches!(Client::builder().config_file("no-such-config.json").build(), Err(Error::Config(_))));
+        let client = Client::builder().provider("anthropic").model("claude-sonnet-4-5").api_key("key").build().unwrap();
+        assert_eq!(client.model_name(), "claude-sonnet-4-5");
+        assert!(matches!(Client::builder().provider("nope").model("m").build(), Err(Error::Config(_))));
+    }
+}
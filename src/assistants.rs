@@ -0,0 +1,110 @@
+// OpenAI の Assistants API（スレッドとラン）を使うバックエンド
+//
+// コードインタプリタやファイル検索のような、サーバー側で動くツールを使いたいときのためのもの。
+// スレッドはセッション中ずっと同じものを使うので、会話の文脈はサーバー側に残る。
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use serde_json::Value;
+use crate::{runtime, Config};
+use crate::completion::Completion;
+use crate::error::Error;
+use crate::files::{self, api_base, authorized};
+use crate::request::PreparedRequest;
+
+// ランの状態を問い合わせる間隔
+const RUN_POLL_MILLIS: u64 = 1000;
+
+// ランがこの状態になったら終わり
+const FINISHED_STATUSES: [&str; 5] = ["completed", "failed", "cancelled", "expired", "incomplete"];
+
+// この Client で使っているスレッドのID（設定を複製しても同じスレッドを使う）
+pub type Thread = Arc<Mutex<Option<String>>>;
+
+// Assistants API には専用のヘッダーが必要
+fn assistants_request(request: PreparedRequest, config: &Config) -> PreparedRequest {
+    authorized(request, config).header("OpenAI-Beta", "assistants=v2".to_string())
+}
+
+// アシスタントにメッセージを送り、ランが終わるのを待って返事を受け取る
+pub async fn assistant_inference(prompt: &str, config: &Config) -> Result<Completion, Error> {
+    let assistant_id = config.assistant_id.as_deref()
+        .ok_or("assistant_id が設定されていません")?;
+    let base = api_base(config)?;
+
+    let thread_id = match config.thread.lock().ok().and_then(|id| id.clone()) {
+        Some(thread_id) => thread_id,
+        None if config.dry_run => "<thread_id>".to_string(),
+        None => {
+            let thread = files::send_json(assistants_request(
+                PreparedRequest::new(&format!("{}/threads", base), serde_json::json!({})), config,
+            ), config).await?;
+            let thread_id = str_field(&thread, "id").to_string();
+            if let Ok(mut id) = config.thread.lock() {
+                *id = Some(thread_id.clone());
+            }
+            thread_id
+        }
+    };
+
+    let message = assistants_request(PreparedRequest::new(
+        &format!("{}/threads/{}/messages", base, thread_id),
+        serde_json::json!({ "role": "user", "content": prompt }),
+    ), config);
+    if config.dry_run {
+        return Ok(format!("{}\n\n（この後、ランを作成して完了までポーリングします）", message.dry_run()).into());
+    }
+    files::send_json(message, config).await?;
+
+    let mut run_body = serde_json::json!({ "assistant_id": assistant_id });
+    if !config.assistant_tools.is_empty() {
+        let tools: Vec<Value> = config.assistant_tools.iter()
+            .map(|tool| serde_json::json!({ "type": tool }))
+            .collect();
+        run_body["tools"] = Value::Array(tools);
+    }
+    let run = files::send_json(assistants_request(
+        PreparedRequest::new(&format!("{}/threads/{}/runs", base, thread_id), run_body), config,
+    ), config).await?;
+    let run_id = str_field(&run, "id").to_string();
+
+    let run = loop {
+        let run = files::send_json(assistants_request(
+            PreparedRequest::get(&format!("{}/threads/{}/runs/{}", base, thread_id, run_id)), config,
+        ), config).await?;
+        let status = str_field(&run, "status");
+        if FINISHED_STATUSES.contains(&status) {
+            break run;
+        }
+        if status == "requires_action" {
+            // 関数ツールの呼び出しはまだ扱えないので、ランを取り消して知らせる
+            let cancel = format!("{}/threads/{}/runs/{}/cancel", base, thread_id, run_id);
+            let _ = files::send_json(assistants_request(PreparedRequest::new(&cancel, serde_json::json!({})), config), config).await;
+            return Err(Error::from("アシスタントが関数ツールの実行を求めましたが、このクライアントは未対応です"));
+        }
+        runtime::sleep(Duration::from_millis(RUN_POLL_MILLIS)).await;
+    };
+    if str_field(&run, "status") != "completed" {
+        let reason = run.pointer("/last_error/message").and_then(|m| m.as_str()).unwrap_or("理由不明");
+        return Err(format!("ランが {} で終わりました: {}", str_field(&run, "status"), reason).into());
+    }
+
+    // いちばん新しいメッセージがアシスタントの返事
+    let messages = files::send_json(assistants_request(
+        PreparedRequest::get(&format!("{}/threads/{}/messages?order=desc&limit=1&run_id={}", base, thread_id, run_id)), config,
+    ), config).await?;
+    let text: Vec<&str> = messages.pointer("/data/0/content")
+        .and_then(|content| content.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|part| part.pointer("/text/value").and_then(|v| v.as_str()))
+        .collect();
+    Ok(Completion {
+        text: text.join("\n"),
+        finish_reason: Some("stop".to_string()),
+        ..Default::default()
+    })
+}
+
+fn str_field<'a>(json: &'a Value, key: &str) -> &'a str {
+    json.get(key).and_then(|value| value.as_str()).unwrap_or("?")
+}
@@ -0,0 +1,299 @@
+// /attach（/file、/image、--attach）で添付するファイルの種類を判別して読み込む
+//
+// テキストは区切り線つきでプロンプトに埋め込み、画像は base64 にして次のリクエストのメッセージに付ける。
+// 種類は先頭のバイト列（マジックナンバー）で判別し、上限より大きいファイルやバイナリは添付しない。
+// "file-" で始まる名前（同じ名前のファイルがないとき）は Files API でアップロードしたファイルのIDとみなし、
+// 読み込まずに、OpenAI の形のチャットの file の部品（file_id）として送る。
+use std::path::Path;
+use base64::Engine;
+use crate::runtime::{self, Command};
+use web_time::Instant;
+use crate::{profiles, providers, respond, stats, supports_images, Config};
+use crate::conversation::AttachedFile;
+use crate::transcribe;
+
+// クリップボードから画像を取り出すコマンドの候補（macOS / Wayland / X11）
+const CLIPBOARD_COMMANDS: [&[&str]; 3] = [
+    &["pngpaste", "-"],
+    &["wl-paste", "--type", "image/png"],
+    &["xclip", "-selection", "clipboard", "-t", "image/png", "-o"],
+];
+
+// vision_profile のモデルに、画像の代わりに送る文を書いてもらう指示
+const DESCRIBE_PROMPT: &str = "この画像に写っているものを詳しく説明してください。文字が書いてあれば、そのまま書き写してください。";
+
+// セッションに残すテキストの先頭の文字数
+const EXCERPT_CHARS: usize = 200;
+
+// 添付できるファイルの大きさの上限
+const MAX_TEXT_BYTES: usize = 256 * 1024;
+const MAX_IMAGE_BYTES: usize = 20 * 1024 * 1024;
+const MAX_PDF_BYTES: usize = 50 * 1024 * 1024;
+const MAX_AUDIO_BYTES: usize = 25 * 1024 * 1024;
+
+// 読み込んだ添付ファイル
+pub enum Attachment {
+    Text { name: String, content: String }, // プロンプトに埋め込むテキスト（PDFや音声から起こしたものも含む）
+    Image { name: String, base64: String }, // 画像（base64）
+}
+
+// 添付するときに求めるファイルの種類（/attach は何でも、/file はテキスト、/image は画像）
+#[derive(Clone, Copy, PartialEq)]
+pub enum Expected {
+    Any,
+    Text,
+    Image,
+}
+
+// ファイルの種類
+enum FileKind {
+    Image,
+    Pdf,
+    Audio,
+    Text,
+    Binary,
+}
+
+// ファイルを読み込んで、種類に合わせた添付ファイルにする
+pub async fn load(path: &str, config: &Config) -> Result<Attachment, String> {
+    let bytes = std::fs::read(path)
+        .map_err(|e| format!("ファイルの読み込みに失敗しました: {:?}", e))?;
+    let name = Path::new(path).file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string());
+
+    match detect(path, &bytes) {
+        FileKind::Image => {
+            check_size(&bytes, MAX_IMAGE_BYTES)?;
+            let base64 = base64::engine::general_purpose::STANDARD.encode(&bytes);
+            Ok(Attachment::Image { name, base64 })
+        }
+        FileKind::Pdf => {
+            check_size(&bytes, MAX_PDF_BYTES)?;
+            let content = extract_pdf_text(path).await?;
+            check_size(content.as_bytes(), MAX_TEXT_BYTES)?;
+            Ok(Attachment::Text { name, content })
+        }
+        FileKind::Audio => {
+            check_size(&bytes, MAX_AUDIO_BYTES)?;
+            println!("{} を文字起こししています…", name);
+            let content = transcribe::transcribe_file(config, path, "text", None).await?;
+            Ok(Attachment::Text { name, content })
+        }
+        FileKind::Text => {
+            check_size(&bytes, MAX_TEXT_BYTES)?;
+            let content = String::from_utf8(bytes).unwrap_or_default();
+            Ok(Attachment::Text { name, content })
+        }
+        FileKind::Binary => Err(format!("{} はテキストでも画像でもないバイナリファイルのため添付できません", name)),
+    }
+}
+
+// ファイルを読み込んで次のメッセージに添付し、表示するメッセージを返す
+// （テキストは texts に、画像は config.images に入れる。画像を送れないモデルなら、vision_profile で説明した文か
+//   ocr_fallback でOCRした文字を添付する）
+// 添付したことは config.attached_files に残し、次のメッセージと一緒にセッションに保存する
+pub async fn attach(path: &str, expected: Expected, config: &mut Config, texts: &mut Vec<String>) -> Result<String, String> {
+    if is_file_id(path) {
+        return attach_file_id(path, expected, config);
+    }
+    let attachment = load(path, config).await?;
+    match attachment {
+        Attachment::Text { name, .. } if expected == Expected::Image => {
+            Err(format!("{} は画像ではありません（PNG / JPEG / GIF / WebP を指定してください）", name))
+        }
+        Attachment::Image { name, .. } if expected == Expected::Text => {
+            Err(format!("{} は画像です（/image か /attach で添付してください）", name))
+        }
+        Attachment::Text { name, content } => {
+            config.attached_files.push(record("text", &name, path, &content, None));
+            texts.push(inline_text(&name, &content));
+            Ok(format!("{} を次のメッセージに添付します（テキスト）", name))
+        }
+        Attachment::Image { name, base64 } if !supports_images(config) && (config.vision_profile.is_some() || config.ocr_fallback) => {
+            let (text, how, note) = image_as_text(path, &base64, config).await?;
+            let label = format!("{}（{}）", name, how);
+            config.attached_files.push(record("text", &label, path, &text, None));
+            texts.push(inline_text(&label, &text));
+            Ok(format!("{} は今のモデルでは画像として送れないため、{}を添付します{}", name, how, note))
+        }
+        Attachment::Image { name, .. } if !supports_images(config) => Err(format!(
+            "{} は画像ですが、今のモデル設定では画像を送れません（Ollama、OpenAI互換のチャット形式、openai / anthropic / gemini / ollama プロバイダーのみ対応。\"vision_profile\" に画像を扱えるプロファイルを書くと、その説明を送れます）",
+            name
+        )),
+        Attachment::Image { name, base64 } => {
+            config.attached_files.push(record("image", &name, path, &base64, Some(base64.clone())));
+            config.images.push(base64);
+            Ok(format!("{} を次のメッセージに添付します（画像）", name))
+        }
+    }
+}
+
+// Files API でアップロードしたファイルのIDか（"file-" で始まり、同じ名前のファイルがないもの）
+fn is_file_id(path: &str) -> bool {
+    path.starts_with("file-") && !Path::new(path).exists()
+}
+
+// アップロードしたファイルを、IDのまま次のメッセージに付ける（中身はプロバイダーが読む）
+fn attach_file_id(file_id: &str, expected: Expected, config: &mut Config) -> Result<String, String> {
+    if expected != Expected::Any {
+        return Err(format!("{} はアップロードしたファイルのIDです（/attach で添付してください）", file_id));
+    }
+    if !providers::accepts_file_ids(config) {
+        return Err(format!(
+            "{} はアップロードしたファイルのIDですが、今のモデル設定ではファイルIDを送れません（OpenAI互換のチャット形式と openai プロバイダーのみ対応）",
+            file_id
+        ));
+    }
+    let hash = content_hash(file_id);
+    config.attached_files.push(AttachedFile { kind: "file".to_string(), name: file_id.to_string(), path: file_id.to_string(), hash, excerpt: None, data: None });
+    config.file_ids.push(file_id.to_string());
+    Ok(format!("{} を次のメッセージに添付します（アップロードしたファイル）", file_id))
+}
+
+// 添付したファイルの記録（画像は data に base64 を持たせて、セッションに写しを置けるようにする）
+fn record(kind: &str, name: &str, path: &str, content: &str, data: Option<String>) -> AttachedFile {
+    let path = std::fs::canonicalize(path).map(|path| path.to_string_lossy().to_string()).unwrap_or_else(|_| path.to_string());
+    let excerpt = (kind == "text").then(|| {
+        let excerpt: String = content.chars().take(EXCERPT_CHARS).collect();
+        if excerpt.len() < content.len() { format!("{}…", excerpt) } else { excerpt }
+    });
+    AttachedFile { kind: kind.to_string(), name: name.to_string(), path, hash: content_hash(content), excerpt, data }
+}
+
+// 中身のハッシュ（FNV-1a 64ビットの16進。画像は base64 の文字列から求める）
+pub fn content_hash(content: &str) -> String {
+    let hash = content.bytes().fold(0xcbf29ce484222325u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3));
+    format!("{:016x}", hash)
+}
+
+// 画像ファイルを読み直して base64 にする（中身が変わっていないかは呼ぶ側がハッシュで確かめる）
+pub fn read_image(path: &Path) -> Option<String> {
+    std::fs::read(path).ok().map(|bytes| base64::engine::general_purpose::STANDARD.encode(bytes))
+}
+
+// テキストの添付ファイルを、区切り線つきでプロンプトに埋め込める形にする
+pub fn inline_text(name: &str, content: &str) -> String {
+    format!("--- ファイル: {} ---\n{}\n--- ここまで: {} ---", name, content.trim_end(), name)
+}
+
+// 先頭のバイト列（マジックナンバー）と拡張子からファイルの種類を判別する
+fn detect(path: &str, bytes: &[u8]) -> FileKind {
+    let extension = Path::new(path).extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let is_riff = |kind: &[u8]| bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == kind;
+
+    if bytes.starts_with(b"\x89PNG") || bytes.starts_with(b"\xFF\xD8\xFF")
+        || bytes.starts_with(b"GIF8") || is_riff(b"WEBP")
+    {
+        FileKind::Image
+    } else if bytes.starts_with(b"%PDF") {
+        FileKind::Pdf
+    } else if bytes.starts_with(b"ID3") || bytes.starts_with(b"OggS") || bytes.starts_with(b"fLaC")
+        || is_riff(b"WAVE") || ["mp3", "m4a", "wav", "ogg", "flac", "webm"].contains(&extension.as_str())
+    {
+        FileKind::Audio
+    } else if std::str::from_utf8(bytes).is_ok() && !bytes.contains(&0) {
+        FileKind::Text
+    } else {
+        FileKind::Binary
+    }
+}
+
+fn check_size(bytes: &[u8], max: usize) -> Result<(), String> {
+    if bytes.len() > max {
+        return Err(format!("ファイルが大きすぎます（{} KB、上限 {} KB）", bytes.len() / 1024, max / 1024));
+    }
+    Ok(())
+}
+
+// PDFからテキストを抜き出す（poppler の pdftotext を使う）
+async fn extract_pdf_text(path: &str) -> Result<String, String> {
+    let output = Command::new("pdftotext")
+        .arg("-layout")
+        .arg(path)
+        .arg("-")
+        .output()
+        .await
+        .map_err(|_| "PDFの読み込みには pdftotext（poppler-utils）が必要です".to_string())?;
+    if !output.status.success() {
+        return Err(format!("PDFからテキストを抜き出せませんでした: {}", String::from_utf8_lossy(&output.stderr)));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+// 画像の文字をOCRで読み取る（tesseract を使う。languages は "jpn+eng" のような指定）
+// 画像の代わりに送る文と、その作り方（vision_profile で説明してもらい、だめなら最後に tesseract でOCRする）
+// 説明できずにOCRしたときは、その理由を3つ目に入れて返す
+async fn image_as_text(path: &str, base64: &str, config: &Config) -> Result<(String, &'static str, String), String> {
+    let mut note = String::new();
+    if let Some(profile) = &config.vision_profile {
+        match describe_image(base64, profile, config).await {
+            Ok(description) => return Ok((description, "画像の説明", String::new())),
+            Err(e) if !config.ocr_fallback => return Err(e),
+            Err(e) => note = format!("（{}）", e),
+        }
+    }
+    let text = ocr_image(path, config.ocr_languages.as_deref()).await?;
+    Ok((text, "OCRした文字", note))
+}
+
+// 画像を扱えるプロファイル（かモデル）に、画像の説明を書いてもらう（使ったトークンはこのセッションの統計に数える）
+async fn describe_image(base64: &str, profile: &str, config: &Config) -> Result<String, String> {
+    // 説明役は、履歴・ツール・参考資料を持たず、ストリーミングもしない
+    let mut vision = config.clone();
+    profiles::select(&mut vision, profile);
+    vision.chat = true;
+    vision.stream = false;
+    vision.events = None;
+    vision.system_prompt = None;
+    vision.history.clear();
+    vision.memories.clear();
+    vision.retrieved.clear();
+    vision.tools.clear();
+    vision.file_ids.clear();
+    vision.images = vec![base64.to_string()];
+    if !supports_images(&vision) {
+        return Err(format!("vision_profile の {} は画像を扱えません", profile));
+    }
+    let started = Instant::now();
+    // attach の future に respond がまるごと入ると大きくなりすぎるので、箱に入れる
+    let completion = Box::pin(respond(DESCRIBE_PROMPT, &vision)).await;
+    stats::record(config, &vision.model_name, DESCRIBE_PROMPT, &completion, started.elapsed());
+    match completion.error {
+        Some(error) => Err(format!("{} で画像を説明できませんでした: {}", profile, error)),
+        None => Ok(completion.text.trim().to_string()),
+    }
+}
+
+pub async fn ocr_image(path: &str, languages: Option<&str>) -> Result<String, String> {
+    let mut command = Command::new("tesseract");
+    command.arg(path).arg("-");
+    if let Some(languages) = languages {
+        command.arg("-l").arg(languages);
+    }
+    let output = command.output()
+        .await
+        .map_err(|_| "OCRには tesseract が必要です".to_string())?;
+    if !output.status.success() {
+        return Err(format!("OCRに失敗しました: {}", String::from_utf8_lossy(&output.stderr)));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+// クリップボードの画像を一時ファイルに保存して、そのパスを返す
+pub async fn paste_clipboard_image() -> Result<String, String> {
+    for command in CLIPBOARD_COMMANDS {
+        let Ok(output) = Command::new(command[0]).args(&command[1..]).output().await else {
+            continue; // コマンドがなければ次の候補を試す
+        };
+        if output.status.success() && !output.stdout.is_empty() {
+            let path = std::env::temp_dir().join(format!("clipboard-{}.png", runtime::process_id()));
+            std::fs::write(&path, &output.stdout)
+                .map_err(|e| format!("クリップボードの画像の保存に失敗しました: {:?}", e))?;
+            return Ok(path.to_string_lossy().to_string());
+        }
+    }
+    Err("クリップボードに画像がないか、画像を取り出すコマンド（pngpaste / wl-paste / xclip）が見つかりません".to_string())
+}
@@ -0,0 +1,168 @@
+// OpenAI の Batch API を使ったオフライン一括処理（料金が半額になる代わりに最大24時間かかる）
+//
+//   batch submit <jobs.jsonl>          ジョブをアップロードしてバッチを作る
+//   batch status <batch_id>            バッチの状態を表示する
+//   batch fetch <batch_id> [out.jsonl] 完了まで待って結果をダウンロードする
+//
+// jobs.jsonl は1行1ジョブで、{"custom_id": "...", "prompt": "..."} か、ただのテキスト（そのままプロンプト）。
+// 結果は1行1ジョブの {"custom_id", "text", "error"} 形式で保存する。
+use std::time::Duration;
+use serde_json::Value;
+use crate::{queue, runtime, sampling, Config};
+use crate::error::Error;
+use crate::files::{self, api_base, authorized};
+use crate::request::PreparedRequest;
+
+// バッチで呼び出すエンドポイント（このクライアントと同じ補完API）
+const BATCH_ENDPOINT: &str = "/v1/completions";
+
+// 完了を待つときの問い合わせ間隔
+const BATCH_POLL_SECS: u64 = 30;
+
+// これ以上状態が変わらないバッチの状態
+const FINISHED_STATUSES: [&str; 4] = ["completed", "failed", "expired", "cancelled"];
+
+// batch サブコマンド
+pub enum BatchCommand {
+    Submit { path: String },
+    Status { batch_id: String },
+    Fetch { batch_id: String, output: Option<String> }, // output を省略すると "<batch_id>.results.jsonl"
+}
+
+// batch サブコマンドを実行する
+pub async fn run(command: BatchCommand, config: &Config) -> Result<(), Error> {
+    match command {
+        BatchCommand::Submit { path } => match submit(config, &path).await {
+            // つながらなければキューに入れて、queue run / queue watch で送り直す
+            Err(e) if queue::is_enabled(config) && e.kind().is_offline() => {
+                let id = queue::enqueue(config, queue::Job::BatchSubmit { path: path.clone() })?;
+                let message = format!("{}\nつながらないため、キューに入れました（{}。queue watch で送り直します）", e, id);
+                Err(Error::Inference { kind: e.kind(), message })
+            }
+            result => result.map(|_| ()),
+        },
+        BatchCommand::Status { batch_id } => {
+            let batch = get_batch(config, &batch_id).await?;
+            print_status(&batch);
+            Ok(())
+        }
+        BatchCommand::Fetch { batch_id, output } => {
+            let out_path = output.unwrap_or_else(|| format!("{}.results.jsonl", batch_id));
+            fetch(config, &batch_id, &out_path).await
+        }
+    }
+}
+
+// ジョブファイルを Batch API の形式に変換してアップロードし、バッチを作る（作ったバッチのIDを返す）
+pub async fn submit(config: &Config, path: &str) -> Result<String, Error> {
+    let data = std::fs::read_to_string(path)
+        .map_err(|e| format!("ジョブファイルの読み込みに失敗しました: {:?}", e))?;
+    let mut lines = Vec::new();
+    for (i, line) in data.lines().filter(|line| !line.trim().is_empty()).enumerate() {
+        let (custom_id, prompt) = match serde_json::from_str::<Value>(line) {
+            Ok(job) if job.get("prompt").is_some() => (
+                job.get("custom_id").and_then(|id| id.as_str()).map(|id| id.to_string())
+                    .unwrap_or_else(|| format!("job-{}", i + 1)),
+                job["prompt"].as_str().unwrap_or_default().to_string(),
+            ),
+            _ => (format!("job-{}", i + 1), line.to_string()),
+        };
+        let mut body = serde_json::json!({
+            "model": config.model_name,
+            "prompt": prompt,
+            "max_tokens": config.max_tokens.unwrap_or(64),
+        });
+        if let Some(effort) = &config.reasoning_effort {
+            body["reasoning_effort"] = serde_json::json!(effort);
+        }
+        sampling::extend(&mut body, config, &sampling::OPENAI);
+        let job = serde_json::json!({
+            "custom_id": custom_id,
+            "method": "POST",
+            "url": BATCH_ENDPOINT,
+            "body": body,
+        });
+        lines.push(job.to_string());
+    }
+    if lines.is_empty() {
+        return Err(Error::from("ジョブファイルが空です"));
+    }
+
+    let contents = format!("{}\n", lines.join("\n")).into_bytes();
+    let file_id = files::upload_file(config, "batch_input.jsonl", contents, "batch").await?;
+    println!("{}件のジョブをアップロードしました（ファイルID: {}）", lines.len(), file_id);
+
+    let url = format!("{}/batches", api_base(config)?);
+    let body = serde_json::json!({
+        "input_file_id": file_id,
+        "endpoint": BATCH_ENDPOINT,
+        "completion_window": "24h",
+    });
+    let batch = files::send_json(authorized(PreparedRequest::new(&url, body), config), config).await?;
+    let batch_id = batch.get("id").and_then(|id| id.as_str()).unwrap_or("?");
+    println!("バッチを作成しました: {}", batch_id);
+    println!("結果の取得: batch fetch {}", batch_id);
+    Ok(batch_id.to_string())
+}
+
+// バッチの情報を取得する
+async fn get_batch(config: &Config, batch_id: &str) -> Result<Value, Error> {
+    let url = format!("{}/batches/{}", api_base(config)?, batch_id);
+    files::send_json(authorized(PreparedRequest::get(&url), config), config).await
+}
+
+// バッチの状態と進み具合を表示する
+fn print_status(batch: &Value) {
+    let count = |key: &str| batch.pointer(&format!("/request_counts/{}", key))
+        .and_then(|c| c.as_u64())
+        .unwrap_or(0);
+    println!(
+        "{}: {}（完了 {} / 失敗 {} / 全体 {}）",
+        batch.get("id").and_then(|id| id.as_str()).unwrap_or("?"),
+        batch.get("status").and_then(|s| s.as_str()).unwrap_or("?"),
+        count("completed"), count("failed"), count("total"),
+    );
+}
+
+// 完了まで待って、結果をこのクライアントの形式で保存する
+async fn fetch(config: &Config, batch_id: &str, out_path: &str) -> Result<(), Error> {
+    let batch = loop {
+        let batch = get_batch(config, batch_id).await?;
+        print_status(&batch);
+        let status = batch.get("status").and_then(|s| s.as_str()).unwrap_or_default();
+        if FINISHED_STATUSES.contains(&status) {
+            break batch;
+        }
+        runtime::sleep(Duration::from_secs(BATCH_POLL_SECS)).await;
+    };
+
+    let mut results = Vec::new();
+    for key in ["output_file_id", "error_file_id"] {
+        if let Some(file_id) = batch.get(key).and_then(|id| id.as_str()) {
+            let content = files::file_content(config, file_id).await?;
+            results.extend(content.lines().filter_map(|line| serde_json::from_str::<Value>(line).ok()).map(to_result));
+        }
+    }
+    if results.is_empty() {
+        return Err(format!("バッチの結果がありません（状態: {}）", batch["status"].as_str().unwrap_or("?")).into());
+    }
+
+    let lines: Vec<String> = results.iter().map(|result| result.to_string()).collect();
+    std::fs::write(out_path, format!("{}\n", lines.join("\n")))
+        .map_err(|e| format!("結果の書き込みに失敗しました: {:?}", e))?;
+    println!("{}件の結果を {} に保存しました", results.len(), out_path);
+    Ok(())
+}
+
+// Batch API の出力1行を {"custom_id", "text", "error"} に変換する
+fn to_result(line: Value) -> Value {
+    let text = line.pointer("/response/body/choices/0/text").and_then(|t| t.as_str());
+    let error = line.pointer("/error/message")
+        .or_else(|| line.pointer("/response/body/error/message"))
+        .and_then(|m| m.as_str());
+    serde_json::json!({
+        "custom_id": line.get("custom_id"),
+        "text": text,
+        "error": error,
+    })
+}
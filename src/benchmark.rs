@@ -0,0 +1,161 @@
+// プロファイルごとの速さを測って記録する（bench サブコマンド）
+//
+//   bench [プロファイル...] [--probes 3]
+//
+// それぞれのプロファイルに短いプロンプト（probe）を何回か送り、応答時間の中央値と
+// 出力の速さ（トークン/秒）を benchmark_file（デフォルトは "benchmarks.json"）に書く。
+// プロファイルが2つ以上あって、まだ記録がなければ、対話モードを始めるときに測るかどうかを聞く（聞くのはコマンドラインの側）。
+// 記録は、言語の振り分け（振り分け先に "fastest" と書くと、いちばん速いプロファイル）、
+// 比べる表示（ふだんの応答時間）、プロファイルの選択の一覧で使う。
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::time::Duration;
+use web_time::{Instant, SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use crate::{profiles, respond, stats, Config};
+
+const DEFAULT_BENCHMARK_FILE: &str = "benchmarks.json";
+
+const DEFAULT_PROBES: usize = 3;
+
+// probe の返事の長さの上限（測るのに時間をかけない）
+const PROBE_MAX_TOKENS: u32 = 16;
+
+const PROBE_PROMPTS: [&str; 3] = [
+    "1から10までの数字を、数字だけで並べてください",
+    "「おはよう」を英語にしてください",
+    "晴れた日の空の色を一言で答えてください",
+];
+
+// 1つのプロファイルを測った結果
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Measurement {
+    pub latency_ms: u64, // 応答時間の中央値
+    pub tokens_per_sec: f64, // 出力トークン数をかかった時間で割ったもの（プロバイダーが返さなければ見積もり）
+    pub probes: usize,
+    pub measured_at: u64, // 測った時刻（UNIX時間の秒）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>, // 失敗したときのエラー（振り分けでは使わない）
+}
+
+impl Measurement {
+    // 表示用（"0.80秒・45 トークン/秒"）
+    pub fn describe(&self) -> String {
+        match &self.error {
+            Some(_) => "測定に失敗".to_string(),
+            None => format!("{:.2}秒・{:.0} トークン/秒", self.latency_ms as f64 / 1000.0, self.tokens_per_sec),
+        }
+    }
+}
+
+fn benchmark_file(config: &Config) -> &str {
+    config.benchmark_file.as_deref().unwrap_or(DEFAULT_BENCHMARK_FILE)
+}
+
+// 記録した結果（プロファイル名ごと。なければ空）
+pub fn load(config: &Config) -> HashMap<String, Measurement> {
+    std::fs::read_to_string(benchmark_file(config)).ok()
+        .and_then(|text| serde_json::from_str(&text).ok())
+        .unwrap_or_default()
+}
+
+fn save(config: &Config, results: &HashMap<String, Measurement>) -> Result<(), String> {
+    let path = benchmark_file(config);
+    let text = serde_json::to_string_pretty(results).unwrap_or_default();
+    std::fs::write(path, text).map_err(|e| format!("{} の書き込みに失敗しました: {:?}", path, e))
+}
+
+// 測った中でいちばん応答の速いプロファイル（失敗したものと、今はないプロファイルは除く）
+pub fn fastest(config: &Config) -> Option<String> {
+    load(config).into_iter()
+        .filter(|(name, measurement)| measurement.error.is_none() && config.profiles.contains_key(name))
+        .min_by_key(|(_, measurement)| measurement.latency_ms)
+        .map(|(name, _)| name)
+}
+
+pub async fn run(mut names: Vec<String>, probes: Option<usize>, config: &Config) -> Result<(), String> {
+    let probes = match probes {
+        Some(probes) if probes > 0 => probes,
+        Some(_) => return Err("--probes には1以上の数を指定してください".to_string()),
+        None => DEFAULT_PROBES,
+    };
+    if names.is_empty() {
+        names = config.profiles.keys().cloned().collect();
+        names.sort();
+    }
+    if names.is_empty() {
+        return Err("測るプロファイルがありません（config.json の profiles に書くか、プロファイル名を指定してください）".to_string());
+    }
+    if let Some(name) = names.iter().find(|name| !config.profiles.contains_key(*name)) {
+        return Err(format!("プロファイル {} がありません", name));
+    }
+    measure_all(config, &names, probes).await
+}
+
+// 記録がなく、プロファイルが2つ以上あれば、測るかどうかを聞く（聞くのは呼ぶ側。dry-run のときは聞かない）
+pub fn should_offer(config: &Config) -> bool {
+    config.profiles.len() >= 2 && !config.dry_run && !std::path::Path::new(benchmark_file(config)).exists()
+}
+
+// 聞いた答えに合わせて、全部のプロファイルを測るか、空の記録を書く（断ったら次からは聞かない）
+pub async fn answer_offer(config: &Config, measure: bool) -> Result<(), String> {
+    let mut names: Vec<String> = config.profiles.keys().cloned().collect();
+    names.sort();
+    match measure {
+        true => measure_all(config, &names, DEFAULT_PROBES).await,
+        false => save(config, &HashMap::new()),
+    }
+}
+
+async fn measure_all(config: &Config, names: &[String], probes: usize) -> Result<(), String> {
+    let mut results = load(config);
+    for name in names {
+        print!("{} を測っています…", name);
+        let _ = io::stdout().flush();
+        let measurement = measure(config, name, probes).await;
+        match &measurement.error {
+            Some(error) => println!(" 失敗しました: {}", error),
+            None => println!(" {}", measurement.describe()),
+        }
+        results.insert(name.clone(), measurement);
+    }
+    save(config, &results)?;
+    println!("{} に記録しました", benchmark_file(config));
+    Ok(())
+}
+
+async fn measure(config: &Config, name: &str, probes: usize) -> Measurement {
+    // 履歴やキャッシュで結果が変わらないように、probe だけを送る
+    let mut probe_config = config.clone();
+    profiles::select(&mut probe_config, name);
+    probe_config.stream = false;
+    probe_config.history.clear();
+    probe_config.middleware.clear();
+    probe_config.max_tokens = Some(PROBE_MAX_TOKENS);
+
+    let mut latencies = Vec::new();
+    let mut total = Duration::ZERO;
+    let mut tokens = 0;
+    let mut error = None;
+    for prompt in PROBE_PROMPTS.iter().cycle().take(probes) {
+        let started = Instant::now();
+        let completion = respond(prompt, &probe_config).await;
+        let elapsed = started.elapsed();
+        if let Some(e) = completion.error.as_ref() {
+            error = Some(e.clone());
+            break;
+        }
+        latencies.push(elapsed);
+        total += elapsed;
+        tokens += stats::token_counts(prompt, &completion).1;
+    }
+    latencies.sort();
+    let seconds = total.as_secs_f64();
+    Measurement {
+        latency_ms: latencies.get(latencies.len() / 2).map(|d| d.as_millis() as u64).unwrap_or(0),
+        tokens_per_sec: if seconds > 0.0 { tokens as f64 / seconds } else { 0.0 },
+        probes: latencies.len(),
+        measured_at: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+        error,
+    }
+}
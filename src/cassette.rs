@@ -0,0 +1,221 @@
+// プロバイダーとのやりとりを記録・再生する（VCR風のカセット）
+//
+// 記録モードでは送ったリクエストと受け取ったレスポンスをJSONLに追記し、
+// 再生モードではネットワークに出ずに記録済みのレスポンスを返す。
+// テストやCIで本物のAPIを叩かずに各プロバイダーの動きを確かめるためのもの。
+// 読み込んだカセットは Config の tape に入れて持ち回る（クローンした設定のあいだでは再生済みの印も共有する）。
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::request::{HttpResponse, PreparedRequest};
+use crate::Config;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum CassetteMode {
+    Record,
+    Replay,
+}
+
+// カセットに保存する1往復分のやりとり（ヘッダーは秘密情報を含むので保存しない）
+#[derive(Serialize, Deserialize)]
+struct Interaction {
+    #[serde(default = "default_method")]
+    method: String,
+    url: String,
+    request_body: Value,
+    status: u16,
+    response_body: String,
+}
+
+// method のない古いカセットはPOSTとして読む
+fn default_method() -> String {
+    "POST".to_string()
+}
+
+pub struct Cassette {
+    path: String,
+    mode: CassetteMode,
+    interactions: Vec<Interaction>,
+    used: Vec<bool>, // 再生済みかどうか（同じリクエストが何度も来たら記録順に返す）
+}
+
+pub type Tape = Arc<Mutex<Cassette>>;
+
+// 設定の cassette / cassette_mode に従ってカセットを読み込み、tape に入れる（指定がなければ何もしない）
+pub fn load(config: &mut Config) -> Result<Option<CassetteMode>, String> {
+    let Some(path) = config.cassette.clone() else {
+        return Ok(None);
+    };
+    let mode = parse_mode(config.cassette_mode.as_deref(), &path)?;
+    config.tape = Some(open(&path, mode)?);
+    Ok(Some(mode))
+}
+
+// モードの文字列を解釈する（指定がなければ、ファイルがあれば再生・なければ記録）
+pub fn parse_mode(mode: Option<&str>, path: &str) -> Result<CassetteMode, String> {
+    match mode {
+        Some("record") => Ok(CassetteMode::Record),
+        Some("replay") => Ok(CassetteMode::Replay),
+        Some(other) => Err(format!("不明なカセットモードです: {}", other)),
+        None if Path::new(path).exists() => Ok(CassetteMode::Replay),
+        None => Ok(CassetteMode::Record),
+    }
+}
+
+// カセットを開く（再生モードならここで記録を読み込む）
+pub fn open(path: &str, mode: CassetteMode) -> Result<Tape, String> {
+    let interactions: Vec<Interaction> = if mode == CassetteMode::Replay {
+        let data = fs::read_to_string(path)
+            .map_err(|e| format!("カセットの読み込みに失敗しました: {:?}", e))?;
+        data.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("カセットのパースに失敗しました: {:?}", e))?
+    } else {
+        Vec::new()
+    };
+    let used = vec![false; interactions.len()];
+    Ok(Arc::new(Mutex::new(Cassette { path: path.to_string(), mode, interactions, used })))
+}
+
+// 再生モードなら、記録済みのレスポンスを返す
+// 一致する記録がなければ Err にメッセージを入れて返す（ネットワークには出ない）
+pub fn replay(config: &Config, request: &PreparedRequest) -> Option<Result<HttpResponse, String>> {
+    let mut cassette = config.tape.as_ref()?.lock().ok()?;
+    if cassette.mode != CassetteMode::Replay {
+        return None;
+    }
+    let matches: Vec<usize> = cassette.interactions.iter()
+        .enumerate()
+        .filter(|(_, i)| i.method == request.method && i.url == request.url && i.request_body == request.body)
+        .map(|(index, _)| index)
+        .collect();
+    let index = matches.iter().copied()
+        .find(|&index| !cassette.used[index])
+        .or_else(|| matches.last().copied());
+    Some(match index {
+        Some(index) => {
+            cassette.used[index] = true;
+            let interaction = &cassette.interactions[index];
+            Ok(HttpResponse { status: interaction.status, body: interaction.response_body.clone() })
+        }
+        None => Err(format!("カセットに一致する記録がありません: {} {}", request.method, request.url)),
+    })
+}
+
+// カセットを使っているかどうか（記録でも再生でも）
+pub fn is_active(config: &Config) -> bool {
+    config.tape.is_some()
+}
+
+// 使っているカセットのお知らせ（起動したときに表示する）
+pub fn describe(config: &Config) -> Option<String> {
+    let cassette = config.tape.as_ref()?.lock().ok()?;
+    Some(match cassette.mode {
+        CassetteMode::Record => format!("カセット {} に記録します", cassette.path),
+        CassetteMode::Replay => format!("カセット {} から再生します", cassette.path),
+    })
+}
+
+// 再生モードのカセットを使っているかどうか
+pub fn is_replaying(config: &Config) -> bool {
+    config.tape.as_ref()
+        .and_then(|cassette| cassette.lock().ok().map(|cassette| cassette.mode == CassetteMode::Replay))
+        .unwrap_or(false)
+}
+
+// 記録モードなら、やりとりをカセットに追記する
+pub fn store(config: &Config, request: &PreparedRequest, response: &HttpResponse) {
+    let Some(Ok(cassette)) = config.tape.as_ref().map(|c| c.lock()) else {
+        return;
+    };
+    if cassette.mode != CassetteMode::Record {
+        return;
+    }
+    let interaction = Interaction {
+        method: request.method.to_string(),
+        url: request.url.clone(),
+        request_body: request.body.clone(),
+        status: response.status,
+        response_body: response.body.clone(),
+    };
+    let line = serde_json::to_string(&interaction).unwrap_or_default();
+    let written = OpenOptions::new().create(true).append(true).open(&cassette.path)
+        .and_then(|mut file| writeln!(file, "{}", line));
+    if let Err(e) = written {
+        eprintln!("カセットの書き込みに失敗しました: {:?}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_with(tape: Tape) -> Config {
+        let mut config: Config = serde_json::from_value(serde_json::json!({
+            "model_name": "gpt-4o-mini", "use_local_model": false, "openai_compatible": true,
+        })).unwrap();
+        config.tape = Some(tape);
+        config
+    }
+
+    fn cassette_file(name: &str, lines: &[Value]) -> String {
+        let path = std::env::temp_dir().join(format!("milti_llm_client-{}-{}.jsonl", name, std::process::id()));
+        let text: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+        fs::write(&path, text).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    fn interaction(body: &str) -> Value {
+        serde_json::json!({ "url": "https://example.test/v1", "request_body": { "q": 1 }, "status": 200, "response_body": body })
+    }
+
+    #[test]
+    fn replays_repeated_requests_in_recorded_order() {
+        let path = cassette_file("order", &[interaction("1"), interaction("2")]);
+        let config = config_with(open(&path, CassetteMode::Replay).unwrap());
+        let request = PreparedRequest::new("https://example.test/v1", serde_json::json!({ "q": 1 }));
+        let bodies: Vec<String> = (0..3).map(|_| replay(&config, &request).unwrap().unwrap().body).collect();
+        // 使い切ったら最後の記録を返し続ける
+        assert_eq!(bodies, ["1", "2", "2"]);
+        // クローンした設定とも再生済みの印を共有する
+        assert_eq!(replay(&config.clone(), &request).unwrap().unwrap().body, "2");
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn a_different_body_does_not_match() {
+        let path = cassette_file("mismatch", &[interaction("1")]);
+        let config = config_with(open(&path, CassetteMode::Replay).unwrap());
+        let request = PreparedRequest::new("https://example.test/v1", serde_json::json!({ "q": 2 }));
+        assert!(replay(&config, &request).unwrap().is_err());
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn record_mode_appends_and_never_replays() {
+        let path = std::env::temp_dir().join(format!("milti_llm_client-record-{}.jsonl", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        let _ = fs::remove_file(&path);
+        let config = config_with(open(&path, CassetteMode::Record).unwrap());
+        let request = PreparedRequest::new("https://example.test/v1", serde_json::json!({ "q": 1 }));
+        assert!(replay(&config, &request).is_none());
+        store(&config, &request, &HttpResponse { status: 200, body: "ok".to_string() });
+
+        let replaying = config_with(open(&path, CassetteMode::Replay).unwrap());
+        assert!(is_replaying(&replaying) && !is_replaying(&config));
+        assert_eq!(replay(&replaying, &request).unwrap().unwrap().body, "ok");
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn mode_defaults_to_replay_only_when_the_file_exists() {
+        assert!(parse_mode(None, "tests/fixtures/providers/openai.jsonl").unwrap() == CassetteMode::Replay);
+        assert!(parse_mode(None, "no/such/cassette.jsonl").unwrap() == CassetteMode::Record);
+        assert!(parse_mode(Some("rewind"), "x").is_err());
+    }
+}
@@ -0,0 +1,118 @@
+// コンテキストウィンドウに収まらない長い入力を分割するためのヘルパー
+//
+// トークン数は厳密には数えられないので、ざっくり見積もる。
+// 英数字は4文字でだいたい1トークン、日本語などそれ以外の文字は1文字1トークンとみなす。
+
+// 指示文などのために、チャンクとは別に空けておくトークン数
+pub const PROMPT_OVERHEAD_TOKENS: u32 = 64;
+
+// テキストのトークン数を見積もる
+pub fn estimate_tokens(text: &str) -> u32 {
+    let ascii = text.chars().filter(|c| c.is_ascii()).count() as u32;
+    let others = text.chars().count() as u32 - ascii;
+    ascii.div_ceil(4) + others
+}
+
+// テキストを、それぞれ max_tokens に収まるチャンクに分割する
+// なるべく行の区切りで分け、1行だけで溢れる場合は文字単位で分ける
+pub fn split_into_chunks(text: &str, max_tokens: u32) -> Vec<String> {
+    let max_tokens = max_tokens.max(1);
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    let mut current_tokens = 0;
+    for line in text.split_inclusive('\n') {
+        let line_tokens = estimate_tokens(line);
+        if current_tokens + line_tokens > max_tokens && !current.is_empty() {
+            chunks.push(std::mem::take(&mut current));
+            current_tokens = 0;
+        }
+        if line_tokens <= max_tokens {
+            current.push_str(line);
+            current_tokens += line_tokens;
+            continue;
+        }
+        // 1行が長すぎるので、英数字とそれ以外の文字数を数えながら文字単位で詰める
+        let (mut ascii, mut others) = (0u32, 0u32);
+        for c in line.chars() {
+            let (next_ascii, next_others) = if c.is_ascii() { (ascii + 1, others) } else { (ascii, others + 1) };
+            if next_ascii.div_ceil(4) + next_others > max_tokens && !current.is_empty() {
+                chunks.push(std::mem::take(&mut current));
+                (ascii, others) = if c.is_ascii() { (1, 0) } else { (0, 1) };
+            } else {
+                (ascii, others) = (next_ascii, next_others);
+            }
+            current.push(c);
+        }
+        current_tokens = ascii.div_ceil(4) + others;
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+// 各チャンクを要約してもらうためのプロンプト（map）
+pub fn map_prompt(chunk: &str, index: usize, total: usize) -> String {
+    format!(
+        "以下は長い入力を分割したものの {}/{} 番目です。後でまとめて使うので、重要な情報を落とさずに要約してください。\n\n{}",
+        index + 1, total, chunk
+    )
+}
+
+// 要約をまとめて最終的な応答をしてもらうためのプロンプト（reduce）
+pub fn reduce_prompt(summaries: &[String]) -> String {
+    let mut prompt = String::from(
+        "以下は、長すぎて一度に送れなかった入力を分割して要約したものです。これらをひとつの入力とみなして応答してください。\n",
+    );
+    for (i, summary) in summaries.iter().enumerate() {
+        prompt.push_str(&format!("\n[{}]\n{}\n", i + 1, summary));
+    }
+    prompt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimates_ascii_by_four_and_others_by_one() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("abcd"), 1);
+        assert_eq!(estimate_tokens("abcde"), 2);
+        assert_eq!(estimate_tokens("日本語"), 3);
+        assert_eq!(estimate_tokens("ab日本"), 3);
+    }
+
+    #[test]
+    fn splits_on_line_boundaries() {
+        let text = "aaaa\nbbbb\ncccc\n";
+        // 1行は "aaaa\n" で2トークン
+        assert_eq!(split_into_chunks(text, 4), vec!["aaaa\nbbbb\n", "cccc\n"]);
+        assert_eq!(split_into_chunks(text, 100), vec![text]);
+    }
+
+    #[test]
+    fn splits_a_long_line_by_characters() {
+        let chunks = split_into_chunks("あいうえおかきくけこ", 3);
+        assert_eq!(chunks, vec!["あいう", "えおか", "きくけ", "こ"]);
+        assert!(chunks.iter().all(|chunk| estimate_tokens(chunk) <= 3));
+    }
+
+    #[test]
+    fn every_chunk_fits_and_nothing_is_lost() {
+        let text = "short\nこれはとても長い一行で、予算を超えてしまうので文字単位で分ける必要があります\nend\n";
+        for budget in [0, 1, 2, 5, 8, 13] {
+            let chunks = split_into_chunks(text, budget);
+            assert_eq!(chunks.concat(), text);
+            assert!(chunks.iter().all(|chunk| estimate_tokens(chunk) <= budget.max(1)), "budget {}", budget);
+        }
+    }
+
+    #[test]
+    fn reduce_prompt_numbers_the_summaries() {
+        let prompt = reduce_prompt(&["一つ目".to_string(), "二つ目".to_string()]);
+        assert!(prompt.contains("\n[1]\n一つ目\n"));
+        assert!(prompt.contains("\n[2]\n二つ目\n"));
+        assert!(map_prompt("本文", 1, 3).contains("2/3 番目"));
+    }
+}
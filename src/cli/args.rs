@@ -0,0 +1,416 @@
+// コマンドライン引数の定義（clap）
+//
+// 設定に関わるフラグは global なので、サブコマンドの前にも後ろにも書ける（--config x batch も batch --config x も同じ）。
+// --format と --output だけは、チャット・1回だけのモードと、サブコマンドごとに意味が違うので、それぞれで定義する。
+use clap::{Parser, Subcommand};
+use milti_llm_client::{BatchCommand, CacheCommand, Command, FilesCommand, FinetuneCommand, JudgeCommand, Options, QueueCommand, SessionCommand, TranscribeCommand};
+
+#[derive(Parser)]
+#[command(name = "milti_llm_client", version, about = "複数のLLM（ローカル・オンライン）に同じ使い方でつなぐチャットクライアント")]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Subcommands>,
+
+    /// 設定ファイル（省略するとカレントディレクトリか XDG の設定ディレクトリから探す）
+    #[arg(long, global = true)]
+    pub config: Option<String>,
+
+    /// 使うプロファイル
+    #[arg(long, global = true)]
+    pub profile: Option<String>,
+
+    /// 使うモデル（プロファイル名、"モデル名@行き先"、別名）
+    #[arg(long, global = true)]
+    pub model: Option<String>,
+
+    /// リクエストは送らずに内容を表示する
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+
+    /// 通信内容をこのファイルに記録する
+    #[arg(long, global = true, value_name = "FILE")]
+    pub record: Option<String>,
+
+    /// 出力フィルターや表示の加工をせず、応答をそのまま表示する
+    #[arg(long, global = true)]
+    pub raw: bool,
+
+    /// 時間の内訳を表示する
+    #[arg(long, global = true)]
+    pub verbose: bool,
+
+    /// 応答をストリーミングで表示する
+    #[arg(long, global = true)]
+    pub stream: bool,
+
+    /// 速いモデルと強いモデルに同時に送る（"<速いモデル>,<強いモデル>"）
+    #[arg(long, global = true, value_name = "FAST,STRONG")]
+    pub speculative: Option<String>,
+
+    /// 同じプロンプトを送って答えを並べるモデル（"a,b,c"）
+    #[arg(long, global = true, value_name = "MODELS")]
+    pub compare: Option<String>,
+
+    /// セッションで使うトークン数の上限
+    #[arg(long, global = true, value_name = "TOKENS")]
+    pub max_session_tokens: Option<u64>,
+
+    /// セッションの推定料金の上限（ドル）
+    #[arg(long, global = true, value_name = "DOLLARS")]
+    pub max_cost: Option<f64>,
+
+    /// やりとりを記録・再生するカセット
+    #[arg(long, global = true, value_name = "FILE")]
+    pub cassette: Option<String>,
+
+    /// カセットのモード（record / replay。省略するとファイルがあれば再生）
+    #[arg(long, global = true, value_name = "MODE")]
+    pub cassette_mode: Option<String>,
+
+    /// 通信エラーで失敗したプロンプトをキューに入れる
+    #[arg(long, global = true)]
+    pub queue: bool,
+
+    /// Gemini で Google 検索を使って答える
+    #[arg(long, global = true)]
+    pub google_search: bool,
+
+    /// 次のメッセージに添付するファイル（何度でも指定できる）
+    #[arg(long, global = true, value_name = "FILE")]
+    pub attach: Vec<String>,
+
+    /// 保存したセッションの続きから始める
+    #[arg(long, global = true, value_name = "SESSION_ID")]
+    pub resume: Option<String>,
+
+    /// 端末いっぱいの画面で会話する
+    #[arg(long, global = true)]
+    pub tui: bool,
+
+    /// OpenAI の Realtime API に声で話しかける（マイクの音声を送り、答えを再生する）
+    #[arg(long)]
+    pub realtime: bool,
+
+    /// 1回だけ推論して終わる（"-" も付けると、後ろに標準入力の内容を付ける）
+    #[arg(short, long)]
+    pub prompt: Option<String>,
+
+    /// "-" なら標準入力をプロンプトにして、1回だけ推論して終わる
+    #[arg(value_name = "-")]
+    pub stdin: Option<String>,
+
+    /// 応答を整形する Handlebars テンプレート（"@ファイル名" でファイルから読む）
+    #[arg(long, value_name = "TEMPLATE")]
+    pub format: Option<String>,
+
+    /// 1回だけのモードの出力（text / json）
+    #[arg(long, value_name = "FORMAT", value_parser = ["text", "json"])]
+    pub output: Option<String>,
+}
+
+#[derive(Subcommand)]
+pub enum Subcommands {
+    /// テンプレートの設定にしてから会話を始める
+    New {
+        /// 使う会話テンプレート
+        #[arg(long)]
+        template: Option<String>,
+        /// テンプレートの一覧を表示する
+        #[arg(long)]
+        list: bool,
+    },
+    /// Batch API でまとめて推論する
+    #[command(subcommand)]
+    Batch(BatchArgs),
+    /// ファインチューニングのジョブを扱う
+    #[command(subcommand)]
+    Finetune(FinetuneArgs),
+    /// Files API のファイルを扱う
+    #[command(subcommand)]
+    Files(FilesArgs),
+    /// Gemini のコンテキストキャッシュを扱う
+    #[command(subcommand)]
+    Cache(CacheArgs),
+    /// 音声ファイルを文字起こしする
+    Transcribe {
+        path: String,
+        /// text / srt / vtt（デフォルトは text）
+        #[arg(long)]
+        format: Option<String>,
+        /// 音声の言語（"ja" など。省略すると自動で判定する）
+        #[arg(long)]
+        language: Option<String>,
+        /// 書き出すファイル（省略すると標準出力）
+        #[arg(long)]
+        output: Option<String>,
+    },
+    /// 標準入力のJSONを1行ずつ推論して、JSONで1行ずつ答える
+    Pipe {
+        /// JSONの代わりに使う Handlebars テンプレート
+        #[arg(long, value_name = "TEMPLATE")]
+        format: Option<String>,
+    },
+    /// 候補の答えを審査役のモデルに採点してもらう
+    Judge {
+        path: String,
+        /// 審査役のモデル（config.json の "judge" より優先する）
+        #[arg(long)]
+        judge_model: Option<String>,
+        /// 採点の指示のテンプレート（"@ファイル名" でファイルから読む）
+        #[arg(long)]
+        rubric: Option<String>,
+        /// 書き出すファイル（省略すると標準出力）
+        #[arg(long)]
+        output: Option<String>,
+    },
+    /// 保存したセッションを扱う
+    #[command(subcommand)]
+    Session(SessionArgs),
+    /// これまでの会話の統計
+    #[command(subcommand)]
+    History(HistoryArgs),
+    /// オフラインのあいだにキューに入れたプロンプトを扱う
+    #[command(subcommand)]
+    Queue(QueueArgs),
+    /// スナップショットと比べてテストする
+    Test {
+        path: String,
+        /// 比べずにスナップショットを書き換える
+        #[arg(long)]
+        update: bool,
+    },
+    /// プロファイルごとの速さを測る（省略するとすべてのプロファイル）
+    Bench {
+        profiles: Vec<String>,
+        /// プロファイルごとに送る回数
+        #[arg(long)]
+        probes: Option<usize>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum BatchArgs {
+    /// JSONLのリクエストを送ってジョブを作る
+    Submit { path: String },
+    /// ジョブの進み具合を表示する
+    Status { batch_id: String },
+    /// 終わったジョブの結果を取ってくる（省略すると "<batch_id>.results.jsonl"）
+    Fetch { batch_id: String, output: Option<String> },
+}
+
+#[derive(Subcommand)]
+pub enum FinetuneArgs {
+    /// 学習データをアップロードする
+    Upload { path: String },
+    /// アップロードした学習データでジョブを作る
+    Create { training_file: String, suffix: Option<String> },
+    /// ジョブの一覧
+    List,
+    /// ジョブを取り消す
+    Cancel { job_id: String },
+    /// ジョブが終わるまで進み具合を表示する
+    Follow {
+        job_id: String,
+        /// できたモデルを、この名前のプロファイルとして設定ファイルに書き足す
+        #[arg(long, value_name = "NAME")]
+        register: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum FilesArgs {
+    /// ファイルをアップロードする
+    Upload { path: String, purpose: Option<String> },
+    /// アップロードしたファイルの一覧
+    List,
+    /// ファイルを消す
+    Delete { file_id: String },
+}
+
+#[derive(Subcommand)]
+pub enum CacheArgs {
+    /// ファイルと system メッセージをキャッシュにする
+    Create {
+        paths: Vec<String>,
+        /// キャッシュに入れる system メッセージ（省略すると "system_prompt"）
+        #[arg(long)]
+        system: Option<String>,
+        /// 消えるまでの秒数（省略すると1時間）
+        #[arg(long)]
+        ttl: Option<u64>,
+        /// 一覧に表示する名前
+        #[arg(long)]
+        display_name: Option<String>,
+    },
+    /// キャッシュの一覧
+    List,
+    /// 期限を今から ttl 秒後にする
+    Extend { name: String, ttl: u64 },
+    /// キャッシュを消す
+    Delete { name: String },
+}
+
+#[derive(Subcommand)]
+pub enum SessionArgs {
+    /// 保存したセッションの一覧
+    List,
+    /// セッションを Markdown に書き出す（省略すると "<セッションID>.md"）
+    Export {
+        id: String,
+        output: Option<String>,
+        /// APIキーやメールアドレスを伏せて書き出す
+        #[arg(long)]
+        redact: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum HistoryArgs {
+    /// モデルやテンプレートごとの使い方をまとめる
+    Stats {
+        /// JSONで書き出す
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum QueueArgs {
+    /// キューに入っているプロンプトの一覧
+    List,
+    /// キューのプロンプトを1回だけ送り直す
+    Run,
+    /// つながるまで間隔をおいて送り直し続ける
+    Watch {
+        /// 送り直す間隔（秒）
+        #[arg(long)]
+        interval: Option<u64>,
+    },
+}
+
+impl Args {
+    // 設定ファイルの上から重ねる指定（pipe の --format は、その出力の整形に使う）
+    pub fn options(&self) -> Options {
+        let format = match &self.command {
+            Some(Subcommands::Pipe { format }) => format.clone(),
+            _ => self.format.clone(),
+        };
+        Options {
+            profile: self.profile.clone(),
+            model: self.model.clone(),
+            dry_run: self.dry_run,
+            record: self.record.clone(),
+            raw: self.raw,
+            verbose: self.verbose,
+            stream: self.stream,
+            speculative: self.speculative.clone(),
+            compare: self.compare.clone(),
+            max_session_tokens: self.max_session_tokens,
+            max_cost: self.max_cost,
+            format,
+            cassette: self.cassette.clone(),
+            cassette_mode: self.cassette_mode.clone(),
+            queue: self.queue,
+            google_search: self.google_search,
+        }
+    }
+
+    // 1回だけのモードかどうか（-p / --prompt か、標準入力を読む "-" があれば）
+    pub fn is_oneshot(&self) -> bool {
+        self.prompt.is_some() || self.stdin.is_some()
+    }
+}
+
+impl Subcommands {
+    // 実行して終わるサブコマンドにする（new は会話を始めるので None）
+    pub fn into_command(self) -> Option<Command> {
+        Some(match self {
+            Subcommands::New { .. } => return None,
+            Subcommands::Batch(args) => Command::Batch(match args {
+                BatchArgs::Submit { path } => BatchCommand::Submit { path },
+                BatchArgs::Status { batch_id } => BatchCommand::Status { batch_id },
+                BatchArgs::Fetch { batch_id, output } => BatchCommand::Fetch { batch_id, output },
+            }),
+            Subcommands::Finetune(args) => Command::Finetune(match args {
+                FinetuneArgs::Upload { path } => FinetuneCommand::Upload { path },
+                FinetuneArgs::Create { training_file, suffix } => FinetuneCommand::Create { training_file, suffix },
+                FinetuneArgs::List => FinetuneCommand::List,
+                FinetuneArgs::Cancel { job_id } => FinetuneCommand::Cancel { job_id },
+                FinetuneArgs::Follow { job_id, register } => FinetuneCommand::Follow { job_id, register },
+            }),
+            Subcommands::Files(args) => Command::Files(match args {
+                FilesArgs::Upload { path, purpose } => FilesCommand::Upload { path, purpose },
+                FilesArgs::List => FilesCommand::List,
+                FilesArgs::Delete { file_id } => FilesCommand::Delete { file_id },
+            }),
+            Subcommands::Cache(args) => Command::Cache(match args {
+                CacheArgs::Create { paths, system, ttl, display_name } => CacheCommand::Create { paths, system, ttl_secs: ttl, display_name },
+                CacheArgs::List => CacheCommand::List,
+                CacheArgs::Extend { name, ttl } => CacheCommand::Extend { name, ttl_secs: ttl },
+                CacheArgs::Delete { name } => CacheCommand::Delete { name },
+            }),
+            Subcommands::Transcribe { path, format, language, output } => Command::Transcribe(TranscribeCommand { path, format, language, output }),
+            Subcommands::Pipe { .. } => Command::Pipe,
+            Subcommands::Judge { path, judge_model, rubric, output } => Command::Judge(JudgeCommand { path, judge_model, rubric, output }),
+            Subcommands::Session(args) => Command::Session(match args {
+                SessionArgs::List => SessionCommand::List,
+                SessionArgs::Export { id, output, redact } => SessionCommand::Export { id, output, redact },
+            }),
+            Subcommands::History(HistoryArgs::Stats { json }) => Command::History { json },
+            Subcommands::Queue(args) => Command::Queue(match args {
+                QueueArgs::List => QueueCommand::List,
+                QueueArgs::Run => QueueCommand::Run,
+                QueueArgs::Watch { interval } => QueueCommand::Watch { interval_secs: interval },
+            }),
+            Subcommands::Test { path, update } => Command::Test { path, update },
+            Subcommands::Bench { profiles, probes } => Command::Bench { profiles, probes },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Args {
+        Args::try_parse_from(std::iter::once("milti_llm_client").chain(args.iter().copied())).unwrap()
+    }
+
+    #[test]
+    fn global_flags_can_come_before_the_subcommand() {
+        let args = parse(&["--config", "x.json", "batch", "status", "b1"]);
+        assert_eq!(args.config.as_deref(), Some("x.json"));
+        assert!(matches!(args.command, Some(Subcommands::Batch(BatchArgs::Status { ref batch_id })) if batch_id == "b1"));
+        let args = parse(&["batch", "status", "b1", "--config", "x.json"]);
+        assert_eq!(args.config.as_deref(), Some("x.json"));
+    }
+
+    #[test]
+    fn transcribe_format_and_output_are_its_own() {
+        let args = parse(&["transcribe", "a.m4a", "--format", "srt", "--output", "a.srt"]);
+        assert_eq!(args.format, None);
+        assert_eq!(args.output, None);
+        assert!(args.options().format.is_none());
+        match args.command.and_then(Subcommands::into_command) {
+            Some(Command::Transcribe(command)) => {
+                assert_eq!(command.format.as_deref(), Some("srt"));
+                assert_eq!(command.output.as_deref(), Some("a.srt"));
+            }
+            _ => panic!("transcribe として読めません"),
+        }
+    }
+
+    #[test]
+    fn pipe_format_is_the_output_template() {
+        let args = parse(&["pipe", "--format", "{{content}}"]);
+        assert_eq!(args.options().format.as_deref(), Some("{{content}}"));
+    }
+
+    #[test]
+    fn oneshot_reads_the_prompt_and_stdin_marker() {
+        let args = parse(&["-p", "要約して", "-", "--output", "json"]);
+        assert!(args.is_oneshot() && args.command.is_none());
+        assert_eq!(args.stdin.as_deref(), Some("-"));
+        assert!(Args::try_parse_from(["milti_llm_client", "--output", "yaml"]).is_err());
+    }
+}
@@ -0,0 +1,564 @@
+// コマンドラインのチャットクライアント（起動時のフラグ、サブコマンド、対話のループ）
+//
+// ライブラリの Client が公開している操作だけを使い、ここでは引数を読むことと画面への表示だけを受け持つ。
+mod args;
+mod oneshot;
+mod startup;
+mod tui;
+
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::Notify;
+use clap::Parser;
+use milti_llm_client::exit_code;
+use milti_llm_client::width::text_width;
+use milti_llm_client::{Answer, AttachmentKind, Client, Config, Event, LiveThroughput, ModelInfo, Streamed, Token};
+use args::{Args, Subcommands};
+
+// エラーを標準エラーに出して、その種類の終了コードで終わる
+fn exit_with(code: i32, message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(code)
+}
+
+// デフォルト設定ファイルを生成する関数
+fn generate_default_config(path: &str) {
+    let default_config = r#"{
+        "model_name": "rinna/nekomata-7b",
+        "endpoint": null,
+        "use_local_model": true,
+        "local_framework": "python",
+        "openai_compatible": false,
+        "max_tokens": 128,
+        "api_key": null
+    }"#;
+    if let Err(e) = fs::write(path, default_config) {
+        exit_with(exit_code::CONFIG_ERROR, &format!("設定ファイルの作成に失敗しました: {:?}", e));
+    }
+}
+
+// 設定ファイルを読み込む関数（--config で指定したファイルがなければエラー。指定がなく見つからない場合は自動生成）
+fn load_config(args: &Args) -> Config {
+    let path = &match args.config.clone() {
+        Some(path) if !Path::new(&path).exists() => {
+            exit_with(exit_code::CONFIG_ERROR, &format!("--config の設定ファイルが見つかりません: {}", path))
+        }
+        Some(path) => path,
+        None => Config::default_path(),
+    };
+    if !Path::new(path).exists() {
+        println!("設定ファイルが見つかりません。デフォルト設定を作成します...");
+        generate_default_config(path);
+    }
+    Config::from_file(path).unwrap_or_else(|e| exit_with(exit_code::CONFIG_ERROR, &e.to_string()))
+}
+
+// ファイルを次のメッセージに添付する（/attach、/file、/image、/paste-image、--attach で使う）
+async fn attach_file(client: &mut Client, path: &str, kind: AttachmentKind) {
+    match client.attach(path, kind).await {
+        Ok(message) => println!("{}", message),
+        Err(e) => println!("{}", e),
+    }
+}
+
+// /models の一覧を表示する（今のモデルの料金がわかり、まだ設定していなければ /stats の推定に使う）
+fn show_models(models: &[ModelInfo], client: &mut Client) {
+    for model in models {
+        let mut line = format!("{} {}", if model.id == client.model_name() { "*" } else { " " }, model.id);
+        if let Some(length) = model.context_length {
+            line.push_str(&format!("  コンテキスト {}", length));
+        }
+        if let (Some(input), Some(output)) = (model.prompt_price, model.completion_price) {
+            line.push_str(&format!("  入力 ${} / 出力 ${}（1Mトークンあたり）", input, output));
+        }
+        println!("{}", line);
+    }
+    if let Some(message) = client.adopt_prices(models) {
+        println!("{}", message);
+    }
+}
+
+// セッションの上限を超えていたら、まとめを表示して終わる
+fn exit_if_over_budget(client: &Client) {
+    if let Some(e) = client.budget_exceeded() {
+        exit_with(e.kind().exit_code(), &e.to_string());
+    }
+}
+
+// ストリーミングで表示しているあいだか（そのあいだの Ctrl+C は、生成を止めてそこまでを答えとして残す）
+static STREAMING: AtomicBool = AtomicBool::new(false);
+static STOP: Notify = Notify::const_new();
+
+// Ctrl+C を受け取る（ストリーミング中でなければ、いつもどおり終わる）
+fn listen_for_stop() {
+    tokio::spawn(async {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            if !STREAMING.load(Ordering::Relaxed) {
+                println!();
+                std::process::exit(130);
+            }
+            STOP.notify_one();
+        }
+    });
+}
+
+// ストリーミング中に、表示した文のすぐ後ろに出しておく速度（次の断片を表示する前に消す）
+// 行の残りに収まるときだけ出す（折り返すと、消すときにカーソルを戻せないため）
+struct LiveIndicator {
+    live: LiveThroughput,
+    width: usize, // 端末の幅（端末でないときと --raw のときは 0 にして出さない）
+    column: usize, // 今の行に表示した幅
+    shown: bool,
+}
+
+impl LiveIndicator {
+    fn new(client: &Client) -> Self {
+        let width = match client.shows_throughput() && io::stdout().is_terminal() {
+            true => crossterm::terminal::size().map(|(cols, _)| cols as usize).unwrap_or(0),
+            false => 0,
+        };
+        LiveIndicator { live: LiveThroughput::new(), width, column: 0, shown: false }
+    }
+
+    // 出している速度を消す（カーソルは速度の前に戻してある）
+    fn clear(&mut self) {
+        if self.shown {
+            print!("\x1b[K");
+            self.shown = false;
+        }
+    }
+
+    // 表示した文の幅を数える
+    fn printed(&mut self, text: &str) {
+        match text.rsplit_once('\n') {
+            Some((_, rest)) => self.column = text_width(rest),
+            None => self.column += text_width(text),
+        }
+    }
+
+    // 速度を出して、カーソルを速度の前に戻す（考え中の部分は薄い色のまま続くので、色を戻さない）
+    fn show(&mut self, dim: bool) {
+        let Some(label) = self.live.label() else {
+            return;
+        };
+        let label = format!("  {}", label);
+        let width = text_width(&label);
+        let column = self.column % self.width.max(1);
+        if self.width == 0 || (column == 0 && self.column > 0) || column + width + 2 >= self.width {
+            return;
+        }
+        match dim {
+            true => print!("\x1b[2m{}\x1b[22m\x1b[{}D", label, width),
+            false => print!("{}\x1b[{}D", label, width),
+        }
+        self.shown = true;
+    }
+}
+
+// 1ターン分を送り、届いたトークンを少しずつ表示する（表示した部分を返す）
+async fn ask(client: &mut Client, prompt: &str) -> (Answer, Streamed) {
+    let show_reasoning = client.shows_reasoning();
+    let mut streamed = Streamed::default();
+    let mut indicator = LiveIndicator::new(client);
+    let print_event = |event: Event| {
+        match event {
+            Event::Routed { language, model } => {
+                indicator.clear();
+                println!("\x1b[2m（{} → {}）\x1b[0m", language, model);
+                indicator.printed("\n");
+            }
+            Event::TokenDelta(Token::Reasoning(text)) if show_reasoning && !streamed.answer => {
+                indicator.clear();
+                if !streamed.reasoning {
+                    println!("\x1b[2m（考え中）");
+                    indicator.printed("\n");
+                    streamed.reasoning = true;
+                }
+                print!("{}", text);
+                indicator.printed(&text);
+                indicator.live.add(&text);
+                indicator.show(false);
+            }
+            // 表示しない考え中の部分も、生成した分として速度に数える
+            Event::TokenDelta(Token::Reasoning(text)) => indicator.live.add(&text),
+            Event::TokenDelta(Token::Answer(text)) => {
+                indicator.clear();
+                if !streamed.answer {
+                    if streamed.reasoning {
+                        println!("\x1b[0m");
+                        indicator.printed("\n");
+                    }
+                    print!("AI > ");
+                    indicator.printed("AI > ");
+                    streamed.answer = true;
+                }
+                print!("{}", text);
+                indicator.printed(&text);
+                indicator.live.add(&text);
+                indicator.show(true);
+            }
+            _ => {}
+        }
+        let _ = io::stdout().flush();
+    };
+
+    STREAMING.store(client.streams(), Ordering::Relaxed);
+    let answer = client.ask(prompt, STOP.notified(), print_event).await;
+    STREAMING.store(false, Ordering::Relaxed);
+    indicator.clear();
+    if streamed.reasoning && !streamed.answer {
+        print!("\x1b[0m");
+    }
+    if streamed.reasoning || streamed.answer {
+        println!();
+    }
+    (answer, streamed)
+}
+
+// --realtime の会話（話した内容と答えの文字起こしを表示する）
+async fn talk(client: &mut Client) {
+    println!("Realtime API で声の会話を始めます（話しかけてください。Ctrl+C で終了）");
+    let mut answering = false;
+    let print_event = |event: Event| {
+        match event {
+            Event::UserMessage(text) => {
+                if answering {
+                    println!();
+                    answering = false;
+                }
+                println!("You > {}", text);
+            }
+            Event::TokenDelta(Token::Answer(text)) => {
+                if !answering {
+                    print!("AI > ");
+                    answering = true;
+                }
+                print!("{}", text);
+            }
+            Event::AssistantMessage(_) if answering => {
+                println!();
+                answering = false;
+            }
+            Event::Error(e) => println!("{}", e),
+            _ => {}
+        }
+        let _ = io::stdout().flush();
+    };
+    let stop = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    if let Err(e) = client.realtime(stop, print_event).await {
+        exit_with(e.kind().exit_code(), &e.to_string());
+    }
+    println!();
+    exit_if_over_budget(client);
+}
+
+// 実行ファイルの本体
+pub async fn run() {
+    let args = Args::parse();
+    if args.stdin.as_deref().is_some_and(|stdin| stdin != "-") {
+        exit_with(exit_code::CONFIG_ERROR, "標準入力を読むときは - を指定してください");
+    }
+    let config = load_config(&args);
+    let mut client = Client::with_options(config, args.options())
+        .unwrap_or_else(|e| exit_with(e.kind().exit_code(), &e.to_string()));
+    for notice in client.notices() {
+        eprintln!("{}", notice);
+    }
+
+    // new は、テンプレートの設定にしてから会話を始める（--template がなければ何もせずに始める）
+    let mut first_message = None;
+    match &args.command {
+        Some(Subcommands::New { list: true, .. }) => {
+            let names = client.templates();
+            if names.is_empty() {
+                println!("テンプレートはありません（config.json の templates に書きます）");
+            }
+            names.iter().for_each(|name| println!("{}", name));
+            return;
+        }
+        Some(Subcommands::New { template: Some(name), .. }) => match client.start_template(name).await {
+            Ok(message) => {
+                println!("テンプレート {} で始めます", name);
+                first_message = message;
+            }
+            Err(e) => exit_with(exit_code::CONFIG_ERROR, &e.to_string()),
+        },
+        _ => {}
+    }
+    // サブコマンドが指定されていれば、それだけを実行して終わる
+    let mut args = args;
+    if let Some(command) = args.command.take().and_then(Subcommands::into_command) {
+        if let Err(e) = client.run(command).await {
+            exit_with(e.kind().exit_code(), &e.to_string());
+        }
+        return;
+    }
+    if args.is_oneshot() {
+        oneshot::run(client, &args).await;
+        return;
+    }
+
+    // 初めて使うときは、プロファイルごとの速さを測っておくか聞く
+    startup::offer_benchmark(&client).await;
+
+    // プロファイルが複数あって、どれも指定されていなければ一覧から選んでもらう（入力がパイプのときは聞かない）
+    // 再開するときは、保存したときのプロファイルに戻すので聞かない
+    if args.model.is_none() && args.resume.is_none() && io::stdin().is_terminal() {
+        if let Err(e) = startup::pick_profile(&mut client) {
+            exit_with(exit_code::CONFIG_ERROR, &e);
+        }
+    }
+
+    match &args.resume {
+        Some(id) => match client.resume(id) {
+            Ok(warnings) => {
+                println!("セッション {} を再開します（{}件のメッセージ）", id, client.history().len());
+                warnings.iter().for_each(|warning| eprintln!("{}", warning));
+            }
+            Err(e) => exit_with(exit_code::CONFIG_ERROR, &e.to_string()),
+        },
+        None => client.new_session(),
+    }
+    for line in client.summary() {
+        println!("{}", line);
+    }
+    if let Some(id) = client.session_id().filter(|_| client.saves_sessions()) {
+        println!("会話はセッション {} として保存します（--resume {} で再開できます）", id, id);
+    }
+
+    // --attach のファイルは最初のメッセージに付ける
+    for path in &args.attach {
+        attach_file(&mut client, path, AttachmentKind::Any).await;
+    }
+
+    // Ollama のモデルを先に読み込ませておく（warm_up / keep_alive_interval_secs）
+    client.warm_up();
+
+    // --realtime なら声で会話する（Ctrl+C で終わる）
+    if args.realtime {
+        talk(&mut client).await;
+        return;
+    }
+
+    // --tui なら端末いっぱいの画面で会話する（使えなければ今までの画面で続ける）
+    if args.tui {
+        if !tui::is_available() {
+            println!("端末ではないため、--tui を使わずに開始します");
+        } else {
+            match tui::run(&mut client, first_message.take()).await {
+                Ok(()) => {
+                    exit_if_over_budget(&client);
+                    return;
+                }
+                Err(e) => println!("{}（--tui を使わずに開始します）", e),
+            }
+        }
+    }
+
+    listen_for_stop();
+    println!("チャットクライアントを開始します（空行で終了）");
+    if let Some(greeting) = client.greeting() {
+        println!("AI > {}", greeting);
+    }
+
+    loop {
+        print!("You > ");
+        let _ = io::stdout().flush();
+
+        let mut prompt = String::new();
+        if let Some(message) = first_message.take() {
+            println!("{}", message);
+            prompt = message;
+        } else if io::stdin().read_line(&mut prompt).is_err() {
+            println!("入力エラー");
+            break;
+        }
+        let prompt = prompt.trim();
+
+        if prompt == "/bye" {
+            println!("バイバイ！またね！");
+            break;
+        }
+
+        if prompt.is_empty() {
+            break;
+        }
+
+        if let Some(args) = prompt.strip_prefix("/set ") {
+            match args.split_once(' ') {
+                Some((key, value)) => match client.set(key.trim(), value.trim()) {
+                    Ok(message) => println!("{}", message),
+                    Err(e) => println!("{}", e),
+                },
+                None => println!("使い方: /set <設定名> <値>（例: /set reasoning_effort high）"),
+            }
+            continue;
+        }
+
+        let attach_command = [
+            ("/attach ", AttachmentKind::Any),
+            ("/file ", AttachmentKind::Text),
+            ("/image ", AttachmentKind::Image),
+        ].into_iter().find_map(|(command, kind)| prompt.strip_prefix(command).map(|path| (path.trim(), kind)));
+        if let Some((path, kind)) = attach_command {
+            attach_file(&mut client, path, kind).await;
+            continue;
+        }
+
+        if prompt == "/paste-image" {
+            match client.paste_image().await {
+                Ok(message) => println!("{}", message),
+                Err(e) => println!("{}", e),
+            }
+            continue;
+        }
+
+        if let Some(args) = prompt.strip_prefix("/export ") {
+            let redact = args.split_whitespace().any(|arg| arg == "--redact");
+            match args.split_whitespace().find(|arg| *arg != "--redact") {
+                Some(path) => match client.export_transcript(path, redact) {
+                    Ok(share_safe) => println!("会話を {} に書き出しました{}", path, if share_safe { "（共有用）" } else { "" }),
+                    Err(e) => println!("{}", e),
+                },
+                None => println!("使い方: /export <ファイル名.md> [--redact]"),
+            }
+            continue;
+        }
+
+        if prompt == "/model" {
+            println!("{}", client.profile_list());
+            continue;
+        }
+
+        if let Some(name) = prompt.strip_prefix("/model ") {
+            match client.use_profile(name.trim()) {
+                Ok(message) => {
+                    println!("{}", message);
+                    if let Some(greeting) = client.greeting() {
+                        println!("AI > {}", greeting);
+                    }
+                }
+                Err(e) => println!("{}", e),
+            }
+            continue;
+        }
+
+        if prompt == "/compare" || prompt.starts_with("/compare ") {
+            match prompt["/compare".len()..].trim() {
+                "" => match client.comparing() {
+                    Some(models) => println!("比較モード: {}", models.join(" / ")),
+                    None => println!("使い方: /compare <モデル,モデル,...>（終わるときは /compare off。/compare export <ファイル> で比較レポートを書き出す）"),
+                },
+                "off" => {
+                    let _ = client.set_compare(None);
+                    println!("比較モードを終了しました");
+                }
+                "export" => println!("使い方: /compare export <ファイル名.md / .json>"),
+                args if args.starts_with("export ") => {
+                    let path = args["export ".len()..].trim();
+                    match client.export_comparison(path) {
+                        Ok(turns) => println!("比較レポートを {} に書き出しました（{}ターン）", path, turns),
+                        Err(e) => println!("{}", e),
+                    }
+                }
+                list => match client.set_compare(Some(list)) {
+                    Ok(()) => println!("比較モード: 次のメッセージから {} に同時に送ります", client.comparing().unwrap_or_default().join(" / ")),
+                    Err(e) => println!("{}", e),
+                },
+            }
+            continue;
+        }
+
+        if prompt == "/clear" {
+            // 消した後の会話は新しいセッションとして保存する
+            client.clear_history();
+            println!("会話の履歴を消去しました");
+            continue;
+        }
+
+        if prompt == "/sessions" {
+            for (id, first) in client.sessions() {
+                let current = if client.session_id() == Some(id.as_str()) { "*" } else { " " };
+                println!("{} {}  {}", current, id, first);
+            }
+            continue;
+        }
+
+        if let Some(id) = prompt.strip_prefix("/load ") {
+            match client.resume(id.trim()) {
+                Ok(warnings) => {
+                    println!("セッション {} を読み込みました（{}件のメッセージ）", id.trim(), client.history().len());
+                    warnings.iter().for_each(|warning| println!("{}", warning));
+                }
+                Err(e) => println!("{}", e),
+            }
+            continue;
+        }
+
+        if prompt == "/system" || prompt.starts_with("/system ") {
+            match prompt["/system".len()..].trim() {
+                "" => println!("system メッセージ: {}", client.system_prompt().unwrap_or("（なし）")),
+                "off" => {
+                    client.set_system_prompt(None);
+                    println!("system メッセージを外しました");
+                }
+                text => {
+                    client.set_system_prompt(Some(text));
+                    println!("system メッセージを設定しました");
+                }
+            }
+            continue;
+        }
+
+        if prompt == "/models" {
+            match client.list_models().await {
+                Ok(models) => show_models(&models, &mut client),
+                Err(e) => println!("モデル一覧を取得できませんでした: {}", e),
+            }
+            continue;
+        }
+
+        if prompt == "/usage" {
+            println!("{}", client.usage_report());
+            continue;
+        }
+
+        if prompt == "/stats" {
+            println!("{}", client.stats_report());
+            continue;
+        }
+
+        if prompt == "/curl" {
+            match client.last_request_curl() {
+                Some(curl) => println!("{}", curl),
+                None => println!("直前のリクエストはありません"),
+            }
+            continue;
+        }
+
+        // 比較モードでは、答えを並べて表示してモデルごとのやりとりとして覚えておく（会話の履歴には加えない）
+        if client.comparing().is_some() && !client.dry_run() {
+            let comparison = client.compare(prompt).await;
+            println!("{}", comparison.render());
+            exit_if_over_budget(&client);
+            continue;
+        }
+
+        let (answer, streamed) = ask(&mut client, prompt).await;
+        // 失敗した回は表示だけして、履歴・セッション・統計には残さない
+        for line in client.render(&answer, streamed) {
+            println!("{}", line);
+        }
+        if answer.completion.error.is_some() || client.dry_run() {
+            continue;
+        }
+        if let Err(e) = client.publish(&answer).await {
+            println!("応答の送信に失敗しました: {}", e);
+        }
+        exit_if_over_budget(&client);
+    }
+}
@@ -0,0 +1,119 @@
+// 1回だけ推論して終わるモード（シェルのパイプラインやスクリプトから使う）
+//
+//   milti_llm_client -p "こんにちは"
+//   cat file.txt | milti_llm_client -                 （標準入力をそのままプロンプトにする）
+//   cat file.txt | milti_llm_client -p "要約して" -   （-p の指示の後ろに標準入力を付ける）
+//
+// 標準出力には応答だけを書き、失敗したときは標準エラーにエラーを出して、種類ごとの終了コードで終わる。
+// --output json なら、モデル・トークン数・かかった時間・本文を1つのJSONにして書く。
+// --compare を指定したときは、モデルごとの結果を並べて書く（JSONなら配列）。
+// --attach <ファイル>（何度でも指定できる）で、テキストや画像を添付して送る。
+// "offline_queue": true（か --queue）なら、通信エラーで失敗したプロンプトをキューに入れておく（queue.rs）。
+use std::io::{self, IsTerminal, Read};
+use milti_llm_client::{exit_code, AttachmentKind, Client, ErrorKind, Event};
+use super::args::Args;
+use super::exit_with;
+
+// プロンプトを組み立てる（-p の指示と、"-" なら標準入力の内容）
+fn read_prompt(args: &Args) -> Result<String, String> {
+    let instruction = args.prompt.clone();
+    let mut input = String::new();
+    if args.stdin.is_some() {
+        if io::stdin().is_terminal() {
+            eprintln!("標準入力から読み込みます（Ctrl+D で終わり）");
+        }
+        io::stdin().read_to_string(&mut input)
+            .map_err(|e| format!("標準入力の読み込みに失敗しました: {:?}", e))?;
+    }
+    let prompt = match (instruction, input.trim()) {
+        (Some(instruction), "") => instruction,
+        (Some(instruction), input) => format!("{}\n\n{}", instruction, input),
+        (None, input) => input.to_string(),
+    };
+    if prompt.trim().is_empty() {
+        return Err("プロンプトが空です（-p \"プロンプト\" か、標準入力に内容を渡して - を指定してください）".to_string());
+    }
+    Ok(prompt)
+}
+
+pub async fn run(mut client: Client, args: &Args) {
+    // --output は text か json（それ以外は引数を読むときに弾いている）
+    let json_output = args.output.as_deref() == Some("json");
+    let prompt = read_prompt(args).unwrap_or_else(|e| exit_with(exit_code::CONFIG_ERROR, &e));
+
+    // --attach のファイルは、テキストならプロンプトの前に埋め込み、画像ならメッセージに付ける
+    for path in &args.attach {
+        match client.attach(path, AttachmentKind::Any).await {
+            Ok(message) => eprintln!("{}", message),
+            Err(e) => exit_with(exit_code::CONFIG_ERROR, &e.to_string()),
+        }
+    }
+
+    if client.comparing().is_some() && !client.dry_run() {
+        let comparison = client.compare(&prompt).await;
+        if json_output {
+            println!("{}", comparison.to_json());
+        } else {
+            println!("{}", comparison.render());
+        }
+        // 1つでも失敗したら、その種類の終了コードで終わる
+        if let Some(completion) = comparison.failure() {
+            exit_with(exit_code::for_completion(completion), completion.error.as_deref().unwrap_or_default());
+        }
+        return;
+    }
+
+    let show_route = |event: Event| {
+        if let Event::Routed { language, model } = event {
+            eprintln!("{} のため {} で答えます", language, model);
+        }
+    };
+    let answer = client.ask(&prompt, std::future::pending(), show_route).await;
+    let completion = &answer.completion;
+    if let Some(error) = &completion.error {
+        let code = exit_code::for_completion(completion);
+        // つながらなければキューに入れて、queue run / queue watch で送り直す
+        match client.queue_if_offline(&answer) {
+            Some(Ok(id)) => eprintln!("つながらないため、キューに入れました（{}。queue watch で送り直します）", id),
+            Some(Err(e)) => eprintln!("{}", e),
+            None => {}
+        }
+        if json_output {
+            let kind = completion.error_kind.map(|kind| kind.as_str());
+            println!("{}", serde_json::json!({ "model": answer.model_name, "error": error, "error_kind": kind, "exit_code": code }));
+        }
+        exit_with(code, error);
+    }
+    if completion.is_interrupted() {
+        eprintln!("接続が切れて再開もできなかったため、応答は途中までです");
+    }
+
+    if json_output {
+        let usage = completion.usage.as_ref().map(|usage| serde_json::json!({
+            "prompt_tokens": usage.prompt_tokens,
+            "completion_tokens": usage.completion_tokens,
+            "cached_tokens": usage.cached_tokens,
+        }));
+        println!("{}", serde_json::json!({
+            "model": answer.model_name,
+            "text": completion.text,
+            "finish_reason": completion.finish_reason,
+            "reasoning": completion.reasoning,
+            "citations": completion.citations,
+            "search_suggestions": completion.search_suggestions,
+            "usage": usage,
+            "latency_ms": answer.elapsed.as_millis() as u64,
+        }));
+    } else if let Some(output) = client.format(&answer) {
+        match output {
+            Ok(output) => println!("{}", output),
+            Err(e) => exit_with(exit_code::CONFIG_ERROR, &e.to_string()),
+        }
+    } else {
+        println!("{}", completion.text);
+    }
+    // 答えは返ってきても、コンテンツフィルターで止められていれば、それがわかる終了コードで終わる
+    if exit_code::for_completion(completion) == exit_code::MODERATION_BLOCK {
+        exit_with(exit_code::MODERATION_BLOCK, ErrorKind::ContentFilter.describe());
+    }
+}
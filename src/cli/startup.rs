@@ -0,0 +1,109 @@
+// 対話モードを始めるときに聞くこと（初めて使うときの速さの計測と、プロファイルの選択）
+//
+// 聞くかどうかと一覧はライブラリの Client に聞き、標準入力を読むのはここだけにする。
+// プロファイルは番号か名前の一部（入力した文字がこの順に含まれていれば一致。例: "g4" → "gpt-4o"）で選ぶ。
+use std::io::{self, IsTerminal, Write};
+use milti_llm_client::Client;
+
+// 初めて使うときは、プロファイルごとの速さを測っておくか聞く（端末でなければ聞かない）
+pub async fn offer_benchmark(client: &Client) {
+    if !client.offers_benchmark() || !io::stdin().is_terminal() {
+        return;
+    }
+    print!("プロファイルごとの速さを測っておきますか？（振り分けと比較の表示に使います。あとから bench でも測れます）[y/N] ");
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return;
+    }
+    if let Err(e) = client.answer_benchmark_offer(matches!(answer.trim(), "y" | "Y" | "yes")).await {
+        println!("{}", e);
+    }
+}
+
+// プロファイルが複数あって、どれも指定されていなければ、一覧から選んでもらう（空行なら今のまま）
+pub fn pick_profile(client: &mut Client) -> Result<(), String> {
+    let choices = client.profile_choices();
+    if choices.is_empty() {
+        return Ok(());
+    }
+    match ask_profile(&choices, client.model_name()) {
+        Some(name) => client.use_profile(&name).map(|_| ()).map_err(|e| e.to_string()),
+        None => Ok(()),
+    }
+}
+
+// 番号か名前の一部を入力してもらう（空行か入力の終わりなら None。いくつも一致したら、その中からもう一度選ぶ）
+fn ask_profile(choices: &[(String, String)], model_name: &str) -> Option<String> {
+    let mut candidates: Vec<&(String, String)> = choices.iter().collect();
+    loop {
+        println!("プロファイルを選んでください（番号か名前の一部で絞り込み。空行なら {} のまま）", model_name);
+        for (i, (_, label)) in candidates.iter().enumerate() {
+            println!("  {}. {}", i + 1, label);
+        }
+        print!("プロファイル > ");
+        let _ = io::stdout().flush();
+        let mut input = String::new();
+        if io::stdin().read_line(&mut input).ok()? == 0 {
+            return None;
+        }
+        let input = input.trim();
+        if input.is_empty() {
+            return None;
+        }
+        match choose(choices, &candidates, input) {
+            Ok(name) => return Some(name),
+            Err(matched) if matched.is_empty() => {
+                println!("「{}」に一致するプロファイルはありません", input);
+                candidates = choices.iter().collect();
+            }
+            Err(matched) => candidates = matched,
+        }
+    }
+}
+
+// 入力から1つに決まればその名前、決まらなければ一致したもの（なければ空）を返す
+fn choose<'a>(choices: &'a [(String, String)], candidates: &[&(String, String)], input: &str) -> Result<String, Vec<&'a (String, String)>> {
+    if let Some(index) = input.parse::<usize>().ok().filter(|i| (1..=candidates.len()).contains(i)) {
+        return Ok(candidates[index - 1].0.clone());
+    }
+    let matched: Vec<&(String, String)> = choices.iter().filter(|(_, label)| fuzzy_match(label, input)).collect();
+    match matched.as_slice() {
+        [(name, _)] => Ok(name.clone()),
+        _ => Err(matched),
+    }
+}
+
+// 名前の一部で絞り込めるか（入力した文字が、この順に含まれていれば一致。例: "g4" → "gpt-4o"）
+fn fuzzy_match(text: &str, query: &str) -> bool {
+    let mut chars = text.chars().flat_map(char::to_lowercase);
+    query.chars().flat_map(char::to_lowercase)
+        .filter(|c| !c.is_whitespace())
+        .all(|q| chars.any(|c| c == q))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn choices() -> Vec<(String, String)> {
+        vec![
+            ("fast".to_string(), "fast（gpt-4o-mini）".to_string()),
+            ("smart".to_string(), "smart（gpt-4o）".to_string()),
+            ("local".to_string(), "local（llama3）".to_string()),
+        ]
+    }
+
+    #[test]
+    fn a_number_or_a_unique_match_picks_the_profile() {
+        let choices = choices();
+        let all: Vec<&(String, String)> = choices.iter().collect();
+        assert_eq!(choose(&choices, &all, "2"), Ok("smart".to_string()));
+        assert_eq!(choose(&choices, &all, "ll3"), Ok("local".to_string()));
+        // いくつも一致したら、その中から選び直す（番号は絞り込んだ一覧の番号）
+        let matched = choose(&choices, &all, "g4").unwrap_err();
+        assert_eq!(matched.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), ["fast", "smart"]);
+        assert_eq!(choose(&choices, &matched, "2"), Ok("smart".to_string()));
+        assert!(choose(&choices, &all, "claude").unwrap_err().is_empty());
+    }
+}
@@ -0,0 +1,1039 @@
+// 端末いっぱいに表示するチャット画面（--tui）
+//
+//   milti_llm_client --tui
+//
+// 上に会話、その下に状態（モデル・トークン数）、いちばん下に入力欄を表示する。
+// 会話は画面の外に流れても ↑↓ / PageUp・PageDown でスクロールして読み返せて、
+// AIの答えは見出し・箇条書き・引用・強調・インラインコード・コードブロック（```）を色を付けて表示する。
+// Enter で送信、Ctrl+J（か Alt+Enter）で改行、生成中の Esc で止めてそこまでを残し、Ctrl+C で中断（捨てる）、Ctrl+D か /bye で終了。
+// Ctrl+O で保存済みの次のセッションに切り替える。キーは "tui_keys" で変えられ、"tui_input_mode": "vi" なら Esc でノーマルモードになる。
+// 画面は ratatui で描き、キーは crossterm で読む（端末でなければ今までの画面で動く）。
+// TUI で使えるコマンドは /model /system /clear /stats /usage /compare /bye。ほかのコマンドは今までの画面で使う。
+// /compare <モデル,モデル,...>（か --compare）で、モデルごとのペインを横に並べて開き、同じ入力を同時に送って、
+// それぞれの答えを届いた分から表示する。/compare off で1つの画面に戻る。
+// ツールを実行する前の確認は画面に出し、y で実行、ほかのキーで断る。
+use std::io::{self, IsTerminal};
+use crossterm::event::{self, DisableBracketedPaste, EnableBracketedPaste, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::execute;
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use tokio::sync::Notify;
+use milti_llm_client::keybindings::{Action, KeyBinding, KeyName, Keymap};
+use milti_llm_client::width::char_width;
+use milti_llm_client::{Answer, Client, Event, LiveThroughput, Token, ToolConfirmation};
+
+// 入力欄に表示する最大の行数（それより長い入力は最後の行だけ見せる）
+const MAX_INPUT_ROWS: usize = 6;
+
+// 今までの画面でしか使えないコマンド
+const UNSUPPORTED_COMMANDS: [&str; 10] = [
+    "/set", "/attach", "/file", "/image", "/paste-image", "/export", "/sessions", "/load", "/models", "/curl",
+];
+
+// ペインを開いているあいだに使えるコマンド
+const PANE_COMMANDS: [&str; 3] = ["/compare", "/clear", "/bye"];
+
+const PLAIN: Style = Style::new();
+const BOLD: Style = Style::new().add_modifier(Modifier::BOLD);
+const DIM: Style = Style::new().add_modifier(Modifier::DIM);
+const ITALIC: Style = Style::new().add_modifier(Modifier::ITALIC);
+const HEADING: Style = Style::new().add_modifier(Modifier::BOLD.union(Modifier::UNDERLINED));
+const CODE: Style = Style::new().fg(Color::Cyan);
+const CODE_BLOCK: Style = Style::new().fg(Color::Yellow);
+const USER: Style = Style::new().fg(Color::Green).add_modifier(Modifier::BOLD);
+const ASSISTANT: Style = Style::new().fg(Color::Cyan).add_modifier(Modifier::BOLD);
+const ERROR: Style = Style::new().fg(Color::Red);
+const STATUS: Style = Style::new().add_modifier(Modifier::REVERSED);
+
+// 入力も出力も端末なら使える
+pub fn is_available() -> bool {
+    io::stdin().is_terminal() && io::stdout().is_terminal()
+}
+
+// 端末を raw モードと代替画面に切り替え、終わったら（パニックしたときも）元に戻す
+struct Terminal {
+    terminal: DefaultTerminal,
+    input: UnboundedReceiver<event::Event>,
+}
+
+impl Terminal {
+    fn enter() -> Result<Terminal, String> {
+        let terminal = ratatui::try_init().map_err(|e| {
+            ratatui::restore();
+            format!("端末を切り替えられません: {:?}", e)
+        })?;
+        // 貼り付けた文字列は、改行が入っていても1回の入力として受け取る
+        let _ = execute!(io::stdout(), EnableBracketedPaste);
+        Ok(Terminal { terminal, input: read_input() })
+    }
+
+    // 次の入力を待って、キーに分ける（端末が閉じられたら None）
+    async fn keys(&mut self, keymap: &Keymap) -> Option<Vec<Key>> {
+        self.input.recv().await.map(|event| keys(keymap, event))
+    }
+
+    fn draw(&mut self, screen: &mut Screen) {
+        let _ = self.terminal.draw(|frame| screen.render(frame));
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), DisableBracketedPaste);
+        ratatui::restore();
+    }
+}
+
+// 端末のイベントを、別のスレッドで読んでは渡す
+fn read_input() -> UnboundedReceiver<event::Event> {
+    let (sender, receiver) = unbounded_channel();
+    std::thread::spawn(move || {
+        while let Ok(event) = event::read() {
+            if sender.send(event).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+enum Key {
+    Char(char),
+    Backspace,
+    Escape, // 動作を割り当てていない Esc（vi ならノーマルモードにする）
+    Action(Action), // "tui_keys" で割り当てた動作
+}
+
+// 届いたイベントをキーに分ける（貼り付けは1文字ずつ入れ、改行は送信ではなく改行にする）
+// 端末の大きさが変わったときなどはキーにならないが、受け取った後に描き直す
+fn keys(keymap: &Keymap, event: event::Event) -> Vec<Key> {
+    match event {
+        event::Event::Key(key) if key.kind != KeyEventKind::Release => key_of(keymap, key).into_iter().collect(),
+        event::Event::Paste(text) => text.replace("\r\n", "\n").chars()
+            .filter_map(|c| match c {
+                '\n' | '\r' => Some(Key::Action(Action::Newline)),
+                '\t' => Some(Key::Char(' ')),
+                c if c.is_control() => None,
+                c => Some(Key::Char(c)),
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+// 割り当てた動作があればそれにし、なければ文字の入力と Backspace にする
+fn key_of(keymap: &Keymap, key: KeyEvent) -> Option<Key> {
+    let name = match key.code {
+        KeyCode::Char(c) => KeyName::Char(c),
+        KeyCode::Enter => KeyName::Enter,
+        KeyCode::Esc => KeyName::Esc,
+        KeyCode::Tab => KeyName::Tab,
+        KeyCode::Backspace => KeyName::Backspace,
+        KeyCode::Up => KeyName::Up,
+        KeyCode::Down => KeyName::Down,
+        KeyCode::Left => KeyName::Left,
+        KeyCode::Right => KeyName::Right,
+        KeyCode::PageUp => KeyName::PageUp,
+        KeyCode::PageDown => KeyName::PageDown,
+        KeyCode::Home => KeyName::Home,
+        KeyCode::End => KeyName::End,
+        KeyCode::F(n) => KeyName::F(n),
+        _ => return None,
+    };
+    let control = key.modifiers.contains(KeyModifiers::CONTROL);
+    let binding = KeyBinding {
+        name,
+        control,
+        alt: key.modifiers.contains(KeyModifiers::ALT),
+        shift: key.modifiers.contains(KeyModifiers::SHIFT),
+    };
+    if let Some(action) = keymap.action(&binding) {
+        return Some(Key::Action(action));
+    }
+    Some(match key.code {
+        KeyCode::Char(_) if control => return None,
+        KeyCode::Char(c) => Key::Char(c),
+        KeyCode::Tab => Key::Char(' '),
+        KeyCode::Backspace => Key::Backspace,
+        KeyCode::Esc => Key::Escape,
+        _ => return None,
+    })
+}
+
+// 1行分を、画面の幅で折り返す（スクロールできるように、画面に出る行の数をこちらで数えておく）
+fn wrap(spans: Vec<Span<'static>>, width: usize) -> Vec<Line<'static>> {
+    let mut lines = vec![Vec::new()];
+    let mut used = 0;
+    for span in spans {
+        let mut current = String::new();
+        for c in span.content.chars() {
+            let w = char_width(c);
+            if used + w > width && used > 0 {
+                if !current.is_empty() {
+                    lines.last_mut().unwrap().push(Span::styled(std::mem::take(&mut current), span.style));
+                }
+                lines.push(Vec::new());
+                used = 0;
+            }
+            current.push(c);
+            used += w;
+        }
+        if !current.is_empty() {
+            lines.last_mut().unwrap().push(Span::styled(current, span.style));
+        }
+    }
+    lines.into_iter().map(Line::from).collect()
+}
+
+// 行の中の **強調** と `インラインコード` に色を付ける
+fn inline(text: &str, base: Style) -> Vec<Span<'static>> {
+    let mut spans = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let next = [("`", CODE), ("**", BOLD)].into_iter().filter_map(|(mark, style)| {
+            let start = rest.find(mark)?;
+            let end = rest[start + mark.len()..].find(mark)? + start + mark.len();
+            Some((start, end, mark.len(), style))
+        }).min_by_key(|(start, ..)| *start);
+        let Some((start, end, length, style)) = next else {
+            spans.push(Span::styled(rest.to_string(), base));
+            break;
+        };
+        if start > 0 {
+            spans.push(Span::styled(rest[..start].to_string(), base));
+        }
+        spans.push(Span::styled(rest[start + length..end].to_string(), style));
+        rest = &rest[end + length..];
+    }
+    spans
+}
+
+// AIの答えのマークダウンを、色を付けた行にする
+fn markdown(text: &str) -> Vec<Vec<Span<'static>>> {
+    let mut lines = Vec::new();
+    let mut in_code = false;
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        if let Some(language) = trimmed.strip_prefix("```") {
+            let fence = if in_code { "└────".to_string() } else { format!("┌──── {}", language.trim()) };
+            lines.push(vec![Span::styled(fence, DIM)]);
+            in_code = !in_code;
+            continue;
+        }
+        if in_code {
+            lines.push(vec![Span::styled("│ ", DIM), Span::styled(line.to_string(), CODE_BLOCK)]);
+            continue;
+        }
+        let indent = line[..line.len() - trimmed.len()].to_string();
+        let hashes = trimmed.chars().take_while(|c| *c == '#').count();
+        if (1..=6).contains(&hashes) && trimmed[hashes..].starts_with(' ') {
+            lines.push(vec![Span::styled(trimmed[hashes..].trim().to_string(), HEADING)]);
+        } else if let Some(quote) = trimmed.strip_prefix('>') {
+            let mut spans = vec![Span::styled(format!("{}│ ", indent), DIM)];
+            spans.extend(inline(quote.trim_start(), ITALIC));
+            lines.push(spans);
+        } else if let Some(item) = ["- ", "* ", "+ "].iter().find_map(|mark| trimmed.strip_prefix(mark)) {
+            let mut spans = vec![Span::styled(format!("{}• ", indent), PLAIN)];
+            spans.extend(inline(item, PLAIN));
+            lines.push(spans);
+        } else {
+            lines.push(inline(line, PLAIN));
+        }
+    }
+    lines
+}
+
+// 1行ずつ同じ色にする
+fn styled_lines(text: &str, style: Style) -> impl Iterator<Item = Vec<Span<'static>>> + '_ {
+    text.lines().map(move |line| vec![Span::styled(line.to_string(), style)])
+}
+
+enum Role {
+    User,
+    Assistant(String), // 答えたモデル
+    Reasoning,
+    Info,
+    Error,
+}
+
+struct Entry {
+    role: Role,
+    text: String,
+}
+
+// 比べているモデルの1つ分のペイン（そのモデルとのやりとり）
+struct Pane {
+    model: String, // 指定したプロファイルかモデルの名前
+    entries: Vec<Entry>,
+}
+
+struct Screen {
+    entries: Vec<Entry>,
+    panes: Vec<Pane>, // 比べているあいだは、entries の代わりにモデルごとのペインを横に並べる
+    input: String,
+    scroll: usize, // いちばん下から何行さかのぼって表示しているか
+    rows: usize, // 最後に描いたときの画面の大きさ
+    cols: usize,
+    generating: bool,
+    keymap: Keymap,
+    normal: bool, // vi のノーマルモード（文字のキーは入力ではなく操作になる）
+    model: String, // 状態の行に出す今のモデルとプロファイル
+    profile: Option<String>,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+    confirmations: UnboundedReceiver<ToolConfirmation>, // 生成中のツールの確認（Client::set_tool_confirmation で受け取る）
+    confirming: Option<ToolConfirmation>, // 答えを待っている確認
+    live: Option<LiveThroughput>, // 生成中の答えの、ここまでの速度（状態の行に出す）
+}
+
+// 生成中の答え（いちばん最後の項目）の前に入れる
+fn insert_before_answer(entries: &mut Vec<Entry>, entry: Entry) {
+    let index = entries.len().saturating_sub(1);
+    entries.insert(index, entry);
+}
+
+// 推論の途中に届いたイベントを、生成中の答えとその前に表示する
+fn show_event(entries: &mut Vec<Entry>, event: Event, show_reasoning: bool) {
+    match event {
+        Event::Routed { language, model } => {
+            insert_before_answer(entries, Entry { role: Role::Info, text: format!("（{} → {}）", language, model) });
+        }
+        Event::TokenDelta(Token::Answer(text)) => entries.last_mut().unwrap().text.push_str(&text),
+        Event::TokenDelta(Token::Reasoning(text)) if show_reasoning => {
+            let index = entries.len().saturating_sub(2);
+            match entries.get_mut(index) {
+                Some(Entry { role: Role::Reasoning, text: thoughts }) => thoughts.push_str(&text),
+                _ => insert_before_answer(entries, Entry { role: Role::Reasoning, text }),
+            }
+        }
+        Event::ToolCallStarted { name, arguments } => {
+            insert_before_answer(entries, Entry { role: Role::Info, text: format!("（ツール {} を呼び出します: {}）", name, arguments) });
+        }
+        Event::ToolCallFinished { name, output } => {
+            let text = format!("（ツール {} の結果: {}文字）", name, output.chars().count());
+            insert_before_answer(entries, Entry { role: Role::Info, text });
+        }
+        _ => {}
+    }
+}
+
+// 会話の全体を、幅 width で折り返した行にする
+fn conversation_lines(entries: &[Entry], width: usize) -> Vec<Line<'static>> {
+    let width = width.max(10);
+    let mut lines = Vec::new();
+    for entry in entries {
+        let text = entry.text.replace('\t', "    ");
+        let logical: Vec<Vec<Span<'static>>> = match &entry.role {
+            Role::User => std::iter::once(vec![Span::styled("You", USER)]).chain(styled_lines(&text, PLAIN)).collect(),
+            Role::Assistant(model) if text.is_empty() => {
+                vec![vec![Span::styled(format!("AI（{}）", model), ASSISTANT)], vec![Span::styled("…", DIM)]]
+            }
+            Role::Assistant(model) => std::iter::once(vec![Span::styled(format!("AI（{}）", model), ASSISTANT)]).chain(markdown(&text)).collect(),
+            Role::Reasoning => std::iter::once(vec![Span::styled("（考え中）", DIM)]).chain(styled_lines(&text, DIM)).collect(),
+            Role::Info => styled_lines(&text, DIM).collect(),
+            Role::Error => styled_lines(&text, ERROR).collect(),
+        };
+        for line in logical {
+            lines.extend(wrap(line, width));
+        }
+        lines.push(Line::default());
+    }
+    lines
+}
+
+impl Screen {
+    // お知らせは、ペインを開いていればどのペインにも出す
+    fn info(&mut self, text: impl Into<String>) {
+        let text = text.into();
+        if self.panes.is_empty() {
+            self.entries.push(Entry { role: Role::Info, text: text.clone() });
+        }
+        for pane in &mut self.panes {
+            pane.entries.push(Entry { role: Role::Info, text: text.clone() });
+        }
+    }
+
+    fn insert_before_answer(&mut self, entry: Entry) {
+        insert_before_answer(&mut self.entries, entry);
+    }
+
+    fn answer(&mut self) -> &mut Entry {
+        self.entries.last_mut().unwrap()
+    }
+
+    // 比較モードのモデルごとに、空のペインを開く
+    fn open_panes(&mut self, models: &[String]) {
+        self.panes = models.iter().map(|model| Pane { model: model.clone(), entries: Vec::new() }).collect();
+        self.scroll = 0;
+    }
+
+    // 入力欄の行（1行目は "> "、続きの行は字下げする）
+    fn input_lines(&self) -> Vec<Line<'static>> {
+        let mut lines = Vec::new();
+        for (i, line) in self.input.split('\n').enumerate() {
+            let prefixed = format!("{}{}", if i == 0 { "> " } else { "  " }, line);
+            lines.extend(wrap(vec![Span::raw(prefixed)], self.cols.max(10)));
+        }
+        lines
+    }
+
+    fn status(&self) -> String {
+        let mut parts = vec![match self.panes.is_empty() {
+            true => format!("モデル: {}", self.model),
+            false => format!("比較: {}", self.panes.iter().map(|pane| pane.model.as_str()).collect::<Vec<_>>().join(" / ")),
+        }];
+        if let Some(profile) = self.profile.as_ref().filter(|_| self.panes.is_empty()) {
+            parts.push(format!("プロファイル: {}", profile));
+        }
+        parts.push(format!("トークン 入力 {} / 出力 {}", self.prompt_tokens, self.completion_tokens));
+        if self.scroll > 0 {
+            parts.push(format!("{}行さかのぼって表示中", self.scroll));
+        }
+        if self.normal {
+            parts.push("ノーマルモード（i で入力  j k でスクロール）".to_string());
+        }
+        // ペインで比べているときは、止めずに中断だけできる
+        let hints = if self.generating && !self.panes.is_empty() {
+            vec![self.hint(&[Action::Cancel], "で中断")]
+        } else if self.generating {
+            vec![self.hint(&[Action::Stop], "で止めてここまでを残す"), self.hint(&[Action::Cancel], "で中断")]
+        } else {
+            vec![
+                self.hint(&[Action::Send], "送信"),
+                self.hint(&[Action::Newline], "改行"),
+                self.hint(&[Action::ScrollUp, Action::ScrollDown, Action::PageUp, Action::PageDown], "スクロール"),
+                self.hint(&[Action::SwitchSession], "セッション切替"),
+                self.hint(&[Action::Quit], "終了"),
+            ]
+        };
+        let hints: Vec<String> = hints.into_iter().flatten().collect();
+        if let Some(label) = self.live.as_ref().filter(|_| self.generating).and_then(LiveThroughput::label) {
+            parts.push(label);
+        }
+        parts.push(if self.generating { format!("生成中…（{}）", hints.join("  ")) } else { hints.join("  ") });
+        format!(" {}", parts.join(" | "))
+    }
+
+    // 動作に割り当てたキーと説明（どれにも割り当てがなければ出さない）
+    fn hint(&self, actions: &[Action], text: &str) -> Option<String> {
+        let labels: Vec<String> = actions.iter().filter_map(|action| self.keymap.label(*action)).collect();
+        (!labels.is_empty()).then(|| format!("{} {}", labels.join(" "), text))
+    }
+
+    fn render(&mut self, frame: &mut Frame) {
+        let area = frame.area();
+        (self.rows, self.cols) = (area.height as usize, area.width as usize);
+        let mut input_lines = self.input_lines();
+        let input_rows = input_lines.len().min(MAX_INPUT_ROWS);
+        let [pane, status, input] = Layout::vertical([
+            Constraint::Min(1),
+            Constraint::Length(1),
+            Constraint::Length(input_rows as u16),
+        ]).areas(area);
+
+        if self.panes.is_empty() {
+            let lines = conversation_lines(&self.entries, self.cols);
+            let pane_rows = pane.height as usize;
+            self.scroll = self.scroll.min(lines.len().saturating_sub(pane_rows));
+            frame.render_widget(Paragraph::new(visible(lines, pane_rows, self.scroll)), pane);
+        } else {
+            // モデルごとのペインを同じ幅で横に並べ、スクロールはそろえる（いちばん長いペインに合わせる）
+            let areas = Layout::horizontal(vec![Constraint::Ratio(1, self.panes.len() as u32); self.panes.len()]).split(pane);
+            let blocks: Vec<Block> = self.panes.iter().map(|pane| Block::bordered().title(format!(" {} ", pane.model))).collect();
+            let inner: Vec<_> = blocks.iter().zip(areas.iter()).map(|(block, area)| block.inner(*area)).collect();
+            let lines: Vec<Vec<Line>> = self.panes.iter().zip(&inner)
+                .map(|(pane, inner)| conversation_lines(&pane.entries, inner.width as usize))
+                .collect();
+            let pane_rows = inner.first().map(|inner| inner.height as usize).unwrap_or(0);
+            let longest = lines.iter().map(Vec::len).max().unwrap_or(0);
+            self.scroll = self.scroll.min(longest.saturating_sub(pane_rows));
+            for ((lines, block), area) in lines.into_iter().zip(blocks).zip(areas.iter()) {
+                frame.render_widget(Paragraph::new(visible(lines, pane_rows, self.scroll)).block(block), *area);
+            }
+        }
+
+        // 状態の行は反転して、幅いっぱいに表示する
+        frame.render_widget(Paragraph::new(self.status()).style(STATUS), status);
+
+        let input_shown = input_lines.split_off(input_lines.len() - input_rows);
+        let cursor_col = input_shown.last().map(Line::width).unwrap_or(0).min(self.cols.saturating_sub(1));
+        frame.set_cursor_position((input.x + cursor_col as u16, input.y + input_rows.saturating_sub(1) as u16));
+        frame.render_widget(Paragraph::new(input_shown), input);
+    }
+
+    // 入力欄の編集とスクロールをして、それ以外のキー（送信・中断・終了など）を返す
+    fn edit(&mut self, key: Key) -> Option<Key> {
+        let page = self.rows.saturating_sub(MAX_INPUT_ROWS + 1).max(1);
+        if let (Some(_), Key::Char(_) | Key::Action(Action::Send | Action::Newline)) = (&self.confirming, &key) {
+            self.answer_confirmation(matches!(key, Key::Char('y' | 'Y')));
+            return None;
+        }
+        match key {
+            Key::Char(c) if self.normal => self.normal_command(c),
+            Key::Char(c) => self.input.push(c),
+            Key::Action(Action::Newline) => self.input.push('\n'),
+            Key::Backspace => {
+                self.input.pop();
+            }
+            Key::Action(Action::DeleteWord) => self.delete_word(),
+            Key::Action(Action::ClearInput) => self.input.clear(),
+            Key::Action(Action::ScrollUp) => self.scroll += 1,
+            Key::Action(Action::ScrollDown) => self.scroll = self.scroll.saturating_sub(1),
+            Key::Action(Action::PageUp) => self.scroll += page,
+            Key::Action(Action::PageDown) => self.scroll = self.scroll.saturating_sub(page),
+            // vi では、生成中でなければ止めるキーも Esc と同じくノーマルモードにする
+            Key::Escape => self.normal = self.keymap.is_vi(),
+            Key::Action(Action::Stop) if self.keymap.is_vi() && !self.generating => self.normal = true,
+            other => return Some(other),
+        }
+        None
+    }
+
+    // vi のノーマルモードの操作（i a I A で入力に戻る、j k でスクロール、g G で先頭と最後、x で1文字、D で全部消す、S で消して入力）
+    fn normal_command(&mut self, c: char) {
+        match c {
+            'i' | 'a' | 'I' | 'A' => self.normal = false,
+            'j' => self.scroll = self.scroll.saturating_sub(1),
+            'k' => self.scroll += 1,
+            'g' => self.scroll = usize::MAX,
+            'G' => self.scroll = 0,
+            'x' => {
+                self.input.pop();
+            }
+            'D' => self.input.clear(),
+            'S' => {
+                self.input.clear();
+                self.normal = false;
+            }
+            _ => {}
+        }
+    }
+
+    // ツールの確認を出して、キーで答えるのを待つ
+    fn ask_confirmation(&mut self, confirmation: ToolConfirmation) {
+        let text = format!("ツール {} を実行します: {}\nよろしいですか？ [y/N]", confirmation.tool, confirmation.detail);
+        if self.panes.is_empty() {
+            self.insert_before_answer(Entry { role: Role::Info, text: text.clone() });
+        }
+        for pane in &mut self.panes {
+            insert_before_answer(&mut pane.entries, Entry { role: Role::Info, text: text.clone() });
+        }
+        self.confirming = Some(confirmation);
+    }
+
+    fn answer_confirmation(&mut self, allowed: bool) {
+        if let Some(confirmation) = self.confirming.take() {
+            confirmation.answer(allowed);
+        }
+        let text = if allowed { "（実行します）" } else { "（実行しませんでした）" };
+        if self.panes.is_empty() {
+            self.insert_before_answer(Entry { role: Role::Info, text: text.to_string() });
+        }
+        for pane in &mut self.panes {
+            insert_before_answer(&mut pane.entries, Entry { role: Role::Info, text: text.to_string() });
+        }
+    }
+
+    // 最後の単語（と、その後ろの空白）を消す
+    fn delete_word(&mut self) {
+        let end = self.input.trim_end().len();
+        let start = self.input[..end].char_indices().rev()
+            .find(|(_, c)| c.is_whitespace())
+            .map(|(i, c)| i + c.len_utf8())
+            .unwrap_or(0);
+        self.input.truncate(start);
+    }
+
+    // 読み込んだ会話を、画面の項目にする
+    fn show_history(&mut self, client: &Client) {
+        for message in client.history() {
+            let role = match message.role.as_str() {
+                "user" => Role::User,
+                _ => Role::Assistant(message.model.clone().unwrap_or_else(|| client.model_name().to_string())),
+            };
+            self.entries.push(Entry { role, text: message.content.clone() });
+        }
+    }
+
+    // 推論の途中に届いたイベントを表示する
+    fn show_event(&mut self, event: Event, show_reasoning: bool) {
+        if let (Some(live), Event::TokenDelta(Token::Answer(text) | Token::Reasoning(text))) = (&mut self.live, &event) {
+            live.add(text);
+        }
+        show_event(&mut self.entries, event, show_reasoning);
+    }
+}
+
+// 折り返した行のうち、いちばん下から scroll 行さかのぼった rows 行
+fn visible(lines: Vec<Line<'static>>, rows: usize, scroll: usize) -> Vec<Line<'static>> {
+    let end = lines.len() - scroll.min(lines.len());
+    let start = end.saturating_sub(rows);
+    lines.into_iter().skip(start).take(end - start).collect()
+}
+
+// 入力を受け付けて、会話を続ける（--resume で読み込んだ会話と、--attach のファイルも引き継ぐ）
+// TUI のあいだは端末を読み書きしているので、推論の途中のお知らせは出さず、ツールの確認は画面で聞く
+pub async fn run(client: &mut Client, first_message: Option<String>) -> Result<(), String> {
+    client.set_quiet(true);
+    let (asker, confirmations) = unbounded_channel();
+    client.set_tool_confirmation(move |confirmation| {
+        let _ = asker.send(confirmation);
+    });
+    let result = converse(client, first_message, confirmations).await;
+    client.clear_tool_confirmation();
+    client.set_quiet(false);
+    result
+}
+
+async fn converse(client: &mut Client, first_message: Option<String>, confirmations: UnboundedReceiver<ToolConfirmation>) -> Result<(), String> {
+    let mut terminal = Terminal::enter()?;
+    let mut screen = Screen {
+        entries: Vec::new(),
+        panes: Vec::new(),
+        input: String::new(),
+        scroll: 0,
+        rows: 0,
+        cols: 0,
+        generating: false,
+        keymap: client.keymap(),
+        normal: false,
+        model: client.model_name().to_string(),
+        profile: client.profile().map(|profile| profile.to_string()),
+        prompt_tokens: 0,
+        completion_tokens: 0,
+        confirmations,
+        confirming: None,
+        live: None,
+    };
+    screen.show_history(client);
+    client.set_stream(true);
+    if client.speculates() {
+        let _ = client.set_speculative(None);
+        screen.info("TUI では二重送信モードは使わず、今のモデルだけで答えます");
+    }
+    if let Some(models) = client.comparing().map(<[String]>::to_vec) {
+        screen.open_panes(&models);
+        screen.info(format!("{} のペインに同時に送ります（/compare off で1つの画面に戻ります）", models.join(" / ")));
+    }
+    if let Some(greeting) = client.greeting() {
+        screen.entries.push(Entry { role: Role::Assistant(client.model_name().to_string()), text: greeting.to_string() });
+    }
+    // テンプレートの最初のメッセージは、始めてすぐに送る
+    if let Some(message) = first_message {
+        if !submit(&mut screen, client, message.trim(), &mut terminal).await {
+            return Ok(());
+        }
+    }
+
+    loop {
+        terminal.draw(&mut screen);
+        let Some(keys) = terminal.keys(&screen.keymap).await else {
+            return Ok(());
+        };
+        for key in keys {
+            match screen.edit(key) {
+                Some(Key::Action(Action::Quit)) => return Ok(()),
+                Some(Key::Action(Action::Cancel)) if screen.input.is_empty() => return Ok(()),
+                Some(Key::Action(Action::Cancel)) => screen.input.clear(),
+                Some(Key::Action(Action::Send)) => {
+                    let line = std::mem::take(&mut screen.input);
+                    screen.normal = false;
+                    if !submit(&mut screen, client, line.trim(), &mut terminal).await {
+                        return Ok(());
+                    }
+                }
+                Some(Key::Action(Action::SwitchSession)) if !screen.panes.is_empty() => {
+                    screen.info("ペインで比べているあいだはセッションを切り替えられません（/compare off で1つの画面に戻ります）");
+                }
+                Some(Key::Action(Action::SwitchSession)) => switch_session(&mut screen, client),
+                _ => {}
+            }
+        }
+    }
+}
+
+// 入力された1行を処理する（終わるときは false）
+async fn submit(screen: &mut Screen, client: &mut Client, line: &str, terminal: &mut Terminal) -> bool {
+    screen.scroll = 0;
+    let command = line.split_whitespace().next().unwrap_or_default();
+    match command {
+        "" => {}
+        "/bye" => return false,
+        command if !screen.panes.is_empty() && command.starts_with('/') && !PANE_COMMANDS.contains(&command) => {
+            screen.info(format!("ペインで比べているあいだは {} を使えません（/compare off で1つの画面に戻ります）", command));
+        }
+        "/clear" => {
+            // 消した後の会話は新しいセッションとして保存する（比べているあいだのやりとりも消す）
+            client.clear_history();
+            screen.entries.clear();
+            screen.panes.iter_mut().for_each(|pane| pane.entries.clear());
+            screen.info("会話の履歴を消去しました");
+        }
+        "/compare" => match line["/compare".len()..].trim() {
+            "" => match client.comparing() {
+                Some(models) => screen.info(format!("比較モード: {}", models.join(" / "))),
+                None => screen.info("使い方: /compare <モデル,モデル,...>（終わるときは /compare off。/compare export <ファイル> で比較レポートを書き出す）"),
+            },
+            "off" => {
+                let _ = client.set_compare(None);
+                screen.panes.clear();
+                screen.info("比較モードを終了しました");
+            }
+            "export" => screen.info("使い方: /compare export <ファイル名.md / .json>"),
+            args if args.starts_with("export ") => {
+                let path = args["export ".len()..].trim();
+                match client.export_comparison(path) {
+                    Ok(turns) => screen.info(format!("比較レポートを {} に書き出しました（{}ターン）", path, turns)),
+                    Err(e) => screen.info(e.to_string()),
+                }
+            }
+            list => match client.set_compare(Some(list)) {
+                Ok(()) => {
+                    let models = client.comparing().unwrap_or_default().to_vec();
+                    screen.open_panes(&models);
+                    screen.info(format!("{} のペインに同時に送ります（/compare off で1つの画面に戻ります）", models.join(" / ")));
+                }
+                Err(e) => screen.entries.push(Entry { role: Role::Error, text: e.to_string() }),
+            },
+        },
+        "/model" => match line["/model".len()..].trim() {
+            "" => screen.info(client.profile_list()),
+            name => match client.use_profile(name) {
+                Ok(message) => {
+                    screen.model = client.model_name().to_string();
+                    screen.profile = client.profile().map(|profile| profile.to_string());
+                    screen.info(message);
+                }
+                Err(e) => screen.entries.push(Entry { role: Role::Error, text: e.to_string() }),
+            },
+        },
+        "/system" => match line["/system".len()..].trim() {
+            "" => screen.info(format!("system メッセージ: {}", client.system_prompt().unwrap_or("（なし）"))),
+            "off" => {
+                client.set_system_prompt(None);
+                screen.info("system メッセージを外しました");
+            }
+            text => {
+                client.set_system_prompt(Some(text));
+                screen.info("system メッセージを設定しました");
+            }
+        },
+        "/stats" => screen.info(client.stats_report()),
+        "/usage" => screen.info(client.usage_report()),
+        command if UNSUPPORTED_COMMANDS.contains(&command) => {
+            screen.info(format!("{} は TUI では使えません（--tui を付けずに起動すると使えます）", command));
+        }
+        _ if !screen.panes.is_empty() && !client.dry_run() => {
+            generate_panes(screen, client, line, terminal).await;
+            return client.budget_exceeded().is_none();
+        }
+        _ => {
+            generate(screen, client, line, terminal).await;
+            // 上限を超えたら TUI を閉じてから、まとめを表示して終わる
+            return client.budget_exceeded().is_none();
+        }
+    }
+    true
+}
+
+// 保存済みの次のセッション（ひとつ古いもの。いちばん古いものの次は新しいものに戻る）に切り替える
+fn switch_session(screen: &mut Screen, client: &mut Client) {
+    let sessions = client.sessions();
+    let next = match client.session_id().and_then(|id| sessions.iter().position(|(other, _)| other == id)) {
+        Some(i) => sessions.get(i + 1).or(sessions.first()),
+        None => sessions.first(),
+    };
+    let Some((id, first)) = next.filter(|(id, _)| client.session_id() != Some(id.as_str())).cloned() else {
+        screen.info("ほかに保存済みのセッションはありません");
+        return;
+    };
+    match client.resume(&id) {
+        Ok(warnings) => {
+            screen.entries.clear();
+            screen.scroll = 0;
+            screen.show_history(client);
+            screen.info(format!("セッション {} に切り替えました（{}）", id, first));
+            warnings.into_iter().for_each(|warning| screen.info(warning));
+        }
+        Err(e) => screen.entries.push(Entry { role: Role::Error, text: e.to_string() }),
+    }
+}
+
+// ペインのモデルに同時に送って、それぞれのペインに届いた分から表示する（Ctrl+C で中断したら、どのモデルのやりとりも残さない）
+async fn generate_panes(screen: &mut Screen, client: &mut Client, message: &str, terminal: &mut Terminal) {
+    let show_reasoning = client.shows_reasoning();
+    for pane in &mut screen.panes {
+        pane.entries.push(Entry { role: Role::User, text: message.to_string() });
+        pane.entries.push(Entry { role: Role::Assistant(pane.model.clone()), text: String::new() });
+    }
+    screen.generating = true;
+
+    let (sink, mut events) = unbounded_channel();
+    let keymap = screen.keymap.clone();
+    let comparison = {
+        let comparison = client.compare_events(message, move |i, event| {
+            let _ = sink.send((i, event));
+        });
+        tokio::pin!(comparison);
+        loop {
+            terminal.draw(screen);
+            tokio::select! {
+                Some((i, event)) = events.recv() => show_event(&mut screen.panes[i].entries, event, show_reasoning),
+                Some(confirmation) = screen.confirmations.recv() => screen.ask_confirmation(confirmation),
+                Some(keys) = terminal.keys(&keymap) => {
+                    let keys: Vec<Key> = keys.into_iter().filter_map(|key| screen.edit(key)).collect();
+                    if keys.iter().any(|key| matches!(key, Key::Action(Action::Cancel))) {
+                        break None;
+                    }
+                }
+                comparison = &mut comparison => break Some(comparison),
+            }
+        }
+    };
+    while let Ok((i, event)) = events.try_recv() {
+        show_event(&mut screen.panes[i].entries, event, show_reasoning);
+    }
+    screen.generating = false;
+    screen.confirming = None; // 中断したときに答えていない確認は、断ったことになる
+
+    let Some(comparison) = comparison else {
+        screen.info("（中断しました）");
+        return;
+    };
+    let (prompt_tokens, completion_tokens) = comparison.tokens();
+    screen.prompt_tokens += prompt_tokens;
+    screen.completion_tokens += completion_tokens;
+    for (pane, (_, model_name, completion)) in screen.panes.iter_mut().zip(comparison.results()) {
+        let answer = pane.entries.last_mut().unwrap();
+        *answer = match &completion.error {
+            Some(error) => Entry { role: Role::Error, text: error.clone() },
+            None => Entry { role: Role::Assistant(model_name.to_string()), text: completion.text.clone() },
+        };
+    }
+}
+
+// 1回分の推論をして、届いた分から表示する（Ctrl+C で中断したら履歴には加えない）
+async fn generate(screen: &mut Screen, client: &mut Client, message: &str, terminal: &mut Terminal) {
+    screen.entries.push(Entry { role: Role::User, text: message.to_string() });
+    let show_reasoning = client.shows_reasoning();
+    let dry_run = client.dry_run();
+    screen.entries.push(Entry { role: Role::Assistant(client.model_name().to_string()), text: String::new() });
+    screen.generating = true;
+    screen.live = client.shows_throughput().then(LiveThroughput::new);
+
+    let (sink, mut events) = unbounded_channel();
+    let stop = Notify::new();
+    let keymap = screen.keymap.clone();
+    // 中断したら ask を途中で捨てる（添付は中断しても失敗しても、この1回で使い切る）
+    let answer: Option<Answer> = {
+        let answer = client.ask(message, stop.notified(), move |event| {
+            let _ = sink.send(event);
+        });
+        tokio::pin!(answer);
+        loop {
+            terminal.draw(screen);
+            tokio::select! {
+                Some(event) = events.recv() => screen.show_event(event, show_reasoning),
+                Some(confirmation) = screen.confirmations.recv() => screen.ask_confirmation(confirmation),
+                Some(keys) = terminal.keys(&keymap) => {
+                    let keys: Vec<Key> = keys.into_iter().filter_map(|key| screen.edit(key)).collect();
+                    if keys.iter().any(|key| matches!(key, Key::Action(Action::Cancel))) {
+                        break None;
+                    }
+                    if keys.iter().any(|key| matches!(key, Key::Action(Action::Stop))) {
+                        stop.notify_one();
+                    }
+                }
+                answer = &mut answer => break Some(answer),
+            }
+        }
+    };
+    while let Ok(event) = events.try_recv() {
+        screen.show_event(event, show_reasoning);
+    }
+    screen.generating = false;
+    screen.live = None;
+    screen.confirming = None; // 中断したときに答えていない確認は、断ったことになる
+
+    let Some(mut answer) = answer else {
+        screen.info("（中断しました）");
+        return;
+    };
+    screen.answer().role = Role::Assistant(answer.model_name.clone());
+    let completion = &mut answer.completion;
+    if let Some(error) = completion.error.take() {
+        // 続きの生成で失敗したときは、そこまでの答えを残してエラーを下に出す
+        if completion.text == error {
+            *screen.answer() = Entry { role: Role::Error, text: error };
+        } else {
+            screen.answer().text = std::mem::take(&mut completion.text);
+            screen.entries.push(Entry { role: Role::Error, text: error });
+        }
+        return;
+    }
+    if dry_run {
+        screen.answer().text = std::mem::take(&mut completion.text);
+        return;
+    }
+    let (prompt_tokens, completion_tokens) = answer.tokens();
+    screen.prompt_tokens += prompt_tokens;
+    screen.completion_tokens += completion_tokens;
+    let completion = &answer.completion;
+    screen.answer().text = completion.text.clone();
+    let streamed_reasoning = matches!(screen.entries.iter().rev().nth(1), Some(Entry { role: Role::Reasoning, .. }));
+    if let Some(thoughts) = completion.reasoning.clone().filter(|_| show_reasoning && !streamed_reasoning) {
+        screen.insert_before_answer(Entry { role: Role::Reasoning, text: thoughts });
+    }
+    if completion.is_interrupted() {
+        screen.info("（接続が切れて再開もできなかったため、応答は途中までです）");
+    }
+    if completion.is_stopped() {
+        screen.info("（途中で止めました。ここまでを返事として残します）");
+    }
+    if !completion.citations.is_empty() {
+        let citations: Vec<String> = completion.citations.iter().enumerate().map(|(i, citation)| format!("  [{}] {}", i + 1, citation)).collect();
+        screen.info(format!("出典:\n{}", citations.join("\n")));
+    }
+    if !completion.search_suggestions.is_empty() {
+        screen.info(format!("検索の候補: {}", completion.search_suggestions.join(" / ")));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use milti_llm_client::width::text_width;
+
+    fn text(line: &Line) -> String {
+        line.spans.iter().map(|span| span.content.as_ref()).collect()
+    }
+
+    #[test]
+    fn wrap_counts_wide_characters_as_two_columns() {
+        let lines = wrap(vec![Span::raw("ab日本語"), Span::styled("cd", CODE)], 5);
+        let texts: Vec<String> = lines.iter().map(text).collect();
+        assert_eq!(texts, ["ab日", "本語c", "d"]);
+        assert!(texts.iter().all(|line| text_width(line) <= 5));
+        // 折り返しても色は文字について回る
+        assert_eq!(lines[1].spans.last().unwrap().style, CODE);
+    }
+
+    #[test]
+    fn markdown_styles_code_blocks_and_inline_marks() {
+        let lines = markdown("# 見出し\n- **強く** と `code`\n```rust\nlet x = 1;\n```");
+        assert_eq!(lines[0][0].style, HEADING);
+        assert_eq!(lines[1].iter().map(|span| span.style).collect::<Vec<_>>(), [PLAIN, BOLD, PLAIN, CODE]);
+        assert_eq!(lines[2][0].content, "┌──── rust");
+        assert_eq!(lines[3][1].style, CODE_BLOCK);
+    }
+
+    #[test]
+    fn pasted_newlines_do_not_send() {
+        let keys = keys(&Keymap::default(), event::Event::Paste("一行目\r\n二行目".to_string()));
+        assert_eq!(keys.iter().filter(|key| matches!(key, Key::Action(Action::Newline))).count(), 1);
+        assert!(!keys.iter().any(|key| matches!(key, Key::Action(Action::Send))));
+    }
+
+    fn screen(keymap: Keymap) -> Screen {
+        Screen {
+            entries: Vec::new(), panes: Vec::new(), input: String::new(), scroll: 0, rows: 20, cols: 80, generating: false,
+            keymap, normal: false, model: "m".to_string(), profile: None, prompt_tokens: 0, completion_tokens: 0,
+            confirmations: unbounded_channel().1, confirming: None, live: None,
+        }
+    }
+
+    fn press(screen: &mut Screen, code: KeyCode, modifiers: KeyModifiers) -> Option<Key> {
+        let keymap = screen.keymap.clone();
+        key_of(&keymap, KeyEvent::new(code, modifiers)).and_then(|key| screen.edit(key))
+    }
+
+    #[test]
+    fn keys_follow_the_configured_bindings() {
+        let config = milti_llm_client::Config::from_json(r#"{
+            "model_name": "m", "use_local_model": true, "openai_compatible": false, "local_framework": "mock",
+            "tui_keys": { "send": "ctrl+s", "newline": "enter" }
+        }"#).unwrap();
+        let mut screen = screen(Client::new(config).keymap());
+        press(&mut screen, KeyCode::Char('a'), KeyModifiers::NONE);
+        assert!(press(&mut screen, KeyCode::Enter, KeyModifiers::NONE).is_none());
+        assert_eq!(screen.input, "a\n");
+        assert!(matches!(press(&mut screen, KeyCode::Char('s'), KeyModifiers::CONTROL), Some(Key::Action(Action::Send))));
+        assert!(screen.status().contains("Ctrl+S 送信  Enter 改行"), "{}", screen.status());
+    }
+
+    #[test]
+    fn status_shows_the_throughput_while_generating() {
+        let mut screen = screen(Keymap::default());
+        screen.entries.push(Entry { role: Role::Assistant("m".to_string()), text: String::new() });
+        screen.generating = true;
+        screen.live = Some(LiveThroughput::new());
+        screen.show_event(Event::TokenDelta(Token::Answer("こんにちは".to_string())), true);
+        assert!(screen.status().contains("約5 トークン"), "{}", screen.status());
+        screen.generating = false;
+        assert!(!screen.status().contains("トークン/秒"), "{}", screen.status());
+    }
+
+    #[test]
+    fn emacs_keys_delete_words_and_lines() {
+        let mut screen = screen(Keymap::default());
+        screen.input = "hello wide  world ".to_string();
+        press(&mut screen, KeyCode::Char('w'), KeyModifiers::CONTROL);
+        assert_eq!(screen.input, "hello wide  ");
+        press(&mut screen, KeyCode::Char('w'), KeyModifiers::CONTROL);
+        assert_eq!(screen.input, "hello ");
+        press(&mut screen, KeyCode::Char('u'), KeyModifiers::CONTROL);
+        assert_eq!(screen.input, "");
+    }
+
+    #[test]
+    fn vi_mode_switches_to_normal_on_escape() {
+        let config = milti_llm_client::Config::from_json(r#"{
+            "model_name": "m", "use_local_model": true, "openai_compatible": false, "local_framework": "mock", "tui_input_mode": "vi"
+        }"#).unwrap();
+        let mut screen = screen(Client::new(config).keymap());
+        for c in "abc".chars() {
+            press(&mut screen, KeyCode::Char(c), KeyModifiers::NONE);
+        }
+        press(&mut screen, KeyCode::Esc, KeyModifiers::NONE);
+        assert!(screen.normal);
+        press(&mut screen, KeyCode::Char('x'), KeyModifiers::NONE);
+        press(&mut screen, KeyCode::Char('k'), KeyModifiers::NONE);
+        assert_eq!((screen.input.as_str(), screen.scroll), ("ab", 1));
+        press(&mut screen, KeyCode::Char('i'), KeyModifiers::NONE);
+        press(&mut screen, KeyCode::Char('z'), KeyModifiers::NONE);
+        assert_eq!(screen.input, "abz");
+        // 生成中の Esc は、ノーマルモードにせず止める
+        screen.generating = true;
+        assert!(matches!(press(&mut screen, KeyCode::Esc, KeyModifiers::NONE), Some(Key::Action(Action::Stop))));
+        assert!(!screen.normal);
+    }
+
+    #[test]
+    fn panes_show_each_model_side_by_side() {
+        let mut screen = screen(Keymap::default());
+        screen.entries.push(Entry { role: Role::User, text: "前の会話".to_string() });
+        screen.open_panes(&["gpt".to_string(), "claude".to_string()]);
+        for (pane, answer) in screen.panes.iter_mut().zip(["左の答え", "右の答え"]) {
+            pane.entries.push(Entry { role: Role::User, text: "質問".to_string() });
+            pane.entries.push(Entry { role: Role::Assistant(pane.model.clone()), text: String::new() });
+            show_event(&mut pane.entries, Event::TokenDelta(Token::Answer(answer.to_string())), true);
+        }
+        screen.info("お知らせ");
+        let mut terminal = ratatui::Terminal::new(ratatui::backend::TestBackend::new(60, 12)).unwrap();
+        terminal.draw(|frame| screen.render(frame)).unwrap();
+        let rows: Vec<String> = (0..12)
+            .map(|y| (0..60).map(|x| terminal.backend().buffer()[(x, y)].symbol().to_string()).collect::<String>().replace(' ', ""))
+            .collect();
+        let screen_text = rows.join("\n");
+        assert!(rows[0].contains("gpt") && rows[0].contains("claude"), "{}", screen_text);
+        assert!(rows.iter().any(|row| row.contains("左の答え") && row.contains("右の答え")), "{}", screen_text);
+        // お知らせはどのペインにも出す
+        assert!(rows.iter().any(|row| row.matches("お知らせ").count() == 2), "{}", screen_text);
+        assert!(!screen_text.contains("前の会話"), "{}", screen_text);
+        assert!(rows[10].starts_with("比較:gpt/claude"), "{}", screen_text);
+    }
+}
@@ -0,0 +1,936 @@
+// ライブラリとして使うときの入り口
+//
+//   let mut client = Client::new(Config::from_file("config.json")?);
+//   let answer = client.chat("こんにちは").await?;
+//
+// chat は会話の履歴を持ち、complete は履歴を使わずに1回だけ推論する。
+// ask は対話の1ターン分（添付・振り分け・二重送信・ストリーミング・統計・セッションへの保存）をまとめて行う。
+// コマンドラインのチャットクライアント（src/main.rs）も、ここにある公開の操作だけを使う。
+//
+// 設定の既定値（プロファイルを切り替えたときに戻す値）や統計、HTTPのタイムアウトと再試行の回数は、
+// Client ごとの Config に持つ。同じプロセスで別の接続先の Client を作っても混ざらない。
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use web_time::{Instant, SystemTime};
+use crate::request::PreparedRequest;
+use crate::stream::Token;
+use crate::conversation::AttachedFile;
+use crate::tools::ToolConfirmation;
+use crate::keybindings::Keymap;
+use crate::{
+    apply_setting, attachments, benchmark, cassette, commands, compare, conversation, filters, format, inline_images,
+    profiles, providers, publish, queue, realtime, reasoning, respond, respond_with_events, respond_with_tokens, router, select_model,
+    sessions, speculative, stats, templates, transcript, warmup,
+};
+use crate::{Completion, Config, Error, Event, Message, Usage};
+
+pub use attachments::Expected as AttachmentKind;
+pub use providers::ModelInfo;
+
+// 起動するときに設定ファイルの上から重ねる指定（コマンドラインのフラグに当たる。None / false なら設定ファイルのまま）
+#[derive(Default)]
+pub struct Options {
+    pub profile: Option<String>,
+    pub model: Option<String>, // プロファイル名、"モデル名@行き先"、別名のどれでもよい
+    pub dry_run: bool,
+    pub record: Option<String>, // 通信内容を記録するファイル
+    pub raw: bool,
+    pub verbose: bool,
+    pub stream: bool,
+    pub speculative: Option<String>, // "<速いモデル>,<強いモデル>"
+    pub compare: Option<String>, // "a,b,c"
+    pub max_session_tokens: Option<u64>,
+    pub max_cost: Option<f64>,
+    pub format: Option<String>, // Handlebars テンプレート（"@ファイル名" でファイルから読む）
+    pub cassette: Option<String>,
+    pub cassette_mode: Option<String>,
+    pub queue: bool, // 通信エラーで失敗したプロンプトをキューに入れる
+    pub google_search: bool, // Gemini で Google 検索のグラウンディングを使う
+}
+
+// ストリーミングで表示した部分（表示したものは、応答の後でもう一度表示しない）
+#[derive(Default, Clone, Copy)]
+pub struct Streamed {
+    pub answer: bool,
+    pub reasoning: bool,
+}
+
+// ask の1ターン分の結果
+pub struct Answer {
+    pub input: String, // 入力された文
+    pub prompt: String, // 添付したテキストを埋め込んで、実際に送った文
+    pub completion: Completion,
+    pub model_name: String, // 答えたモデル（振り分けや二重送信で変わる）
+    pub started_at: SystemTime,
+    pub elapsed: Duration,
+    pub shown: bool, // 二重送信で、先に表示した速いモデルの答えをそのまま使った
+}
+
+impl Answer {
+    // 入力と出力のトークン数（プロバイダーが返さなかったときは見積もり）
+    pub fn tokens(&self) -> (u64, u64) {
+        let (prompt_tokens, completion_tokens, _) = stats::token_counts(&self.prompt, &self.completion);
+        (prompt_tokens, completion_tokens)
+    }
+}
+
+// 比較モードの1ターン分の結果
+pub struct Comparison {
+    prompt: String,
+    results: Vec<compare::Compared>,
+}
+
+impl Comparison {
+    // 答えを横に並べた表示
+    pub fn render(&self) -> String {
+        compare::render(&self.results)
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        compare::to_json(&self.results)
+    }
+
+    // モデルごとの、指定した名前（プロファイルか "モデル名@行き先"）と実際に使ったモデルと答え（指定した順）
+    pub fn results(&self) -> impl Iterator<Item = (&str, &str, &Completion)> {
+        self.results.iter().map(|result| (result.model.as_str(), result.model_name.as_str(), &result.completion))
+    }
+
+    // 全部のモデルの、入力と出力のトークン数の合計（プロバイダーが返さなかったときは見積もり）
+    pub fn tokens(&self) -> (u64, u64) {
+        self.results.iter()
+            .map(|result| stats::token_counts(&self.prompt, &result.completion))
+            .fold((0, 0), |(prompt, completion), (p, c, _)| (prompt + p, completion + c))
+    }
+
+    // 最初に失敗したモデルの結果（なければ None）
+    pub fn failure(&self) -> Option<&Completion> {
+        self.results.iter().map(|result| &result.completion).find(|completion| completion.error.is_some())
+    }
+}
+
+pub struct Client {
+    config: Config,
+    attached_texts: Vec<String>, // /attach で追加して、次のメッセージと一緒に送るテキストの添付ファイル
+    last_request: Option<PreparedRequest>, // 直前に送った（dry-run で組み立てた）リクエスト。/curl で使う
+}
+
+impl Client {
+    pub fn new(mut config: Config) -> Client {
+        // 統計やスレッド、cache の答え、プロファイルの既定値は、この Client のものとして持ち直す
+        config.stats = Default::default();
+        config.warmup = Default::default();
+        config.cache = Default::default();
+        config.thread = Default::default();
+        config.defaults = None;
+        profiles::remember_defaults(&mut config);
+        Client { config, attached_texts: Vec::new(), last_request: None }
+    }
+
+    // 設定ファイルの上に options を重ねて作る（プロファイル → モデル → そのほかの順に適用する）
+    pub fn with_options(config: Config, options: Options) -> Result<Client, Error> {
+        let mut client = Client::new(config);
+        let config = &mut client.config;
+        if let Some(name) = options.profile.or(config.profile.clone()) {
+            profiles::apply(config, &name).map_err(Error::Config)?;
+        }
+        config.model_override = options.model.clone();
+        let model_name = options.model.unwrap_or_else(|| config.model_name.clone());
+        select_model(config, &model_name);
+        config.dry_run |= options.dry_run;
+        config.raw |= options.raw;
+        config.verbose |= options.verbose;
+        config.stream |= options.stream;
+        config.offline_queue |= options.queue;
+        config.google_search |= options.google_search;
+        if options.record.is_some() {
+            config.record_path = options.record;
+        }
+        if let Some(models) = options.compare {
+            config.compare = Some(compare::parse_models(&models).map_err(Error::Config)?);
+        }
+        if options.max_session_tokens.is_some() {
+            config.max_session_tokens = options.max_session_tokens;
+        }
+        if options.max_cost.is_some() {
+            config.max_session_cost = options.max_cost;
+        }
+        if let Some(format) = options.format.or(config.format.take()) {
+            config.format = Some(format::load_template(&format).map_err(Error::Config)?);
+        }
+        if options.cassette.is_some() {
+            config.cassette = options.cassette;
+        }
+        if options.cassette_mode.is_some() {
+            config.cassette_mode = options.cassette_mode;
+        }
+        cassette::load(config).map_err(Error::Config)?;
+        if let Some(models) = options.speculative {
+            client.set_speculative(Some(&models))?;
+        }
+        Ok(client)
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    // 起動したときに知らせること（通信の記録、カセット、無視する出力フィルター）
+    pub fn notices(&self) -> Vec<String> {
+        let mut notices = Vec::new();
+        if let Some(path) = &self.config.record_path {
+            notices.push(format!("通信内容を {} に記録します", path));
+        }
+        notices.extend(cassette::describe(&self.config));
+        for name in filters::unknown_filters(&self.config.output_filters) {
+            notices.push(format!("不明な出力フィルターです（無視します）: {}", name));
+        }
+        notices
+    }
+
+    // 起動したときに表示する、今の設定のまとめ
+    pub fn summary(&self) -> Vec<String> {
+        let config = &self.config;
+        let mut lines = vec![format!("モデル: {}", config.model_name)];
+        if config.use_local_model {
+            lines.push("ローカルモードで動作します".to_string());
+        } else {
+            lines.push("オンラインモードで動作します".to_string());
+            lines.push(format!("OpenAI互換モード: {}", if config.openai_compatible { "有効" } else { "無効" }));
+            if let Some(provider) = &config.provider {
+                if providers::PROVIDERS.contains(&provider.as_str()) {
+                    lines.push(format!("プロバイダー: {}", provider));
+                } else {
+                    lines.push(format!("不明なプロバイダーです: {}（対応: {}）", provider, providers::PROVIDERS.join(" / ")));
+                }
+            }
+            if let Some(assistant_id) = &config.assistant_id {
+                lines.push(format!("Assistants APIを使います（アシスタント: {}）", assistant_id));
+            }
+        }
+        if config.dry_run {
+            lines.push("dry-runモード: リクエストは送信せず、内容を表示します".to_string());
+        }
+        if let Some(profile) = &config.profile {
+            lines.push(format!("プロファイル: {}", profile));
+        }
+        if let Some(models) = &config.speculative {
+            lines.push(format!("二重送信モード: {} の答えを先に表示し、{} の答えが届いたら置き換えるか聞きます", models.fast, models.strong));
+        }
+        if let Some(models) = &config.compare {
+            lines.push(format!("比較モード: {} に同時に送って答えを並べます（/compare export <ファイル> で比較レポート、/compare off で終了）", models.join(" / ")));
+        }
+        if config.max_session_tokens.is_some() || config.max_session_cost.is_some() {
+            let limits: Vec<String> = [config.max_session_tokens.map(|t| format!("{} トークン", t)), config.max_session_cost.map(|c| format!("${}", c))]
+                .into_iter().flatten().collect();
+            lines.push(format!("セッションの上限: {}（超えたらまとめを表示して終わります）", limits.join(" / ")));
+        }
+        if config.max_session_cost.is_some() && config.prompt_price.is_none() && config.completion_price.is_none() {
+            lines.push("料金がわからないため、max_session_cost は使えません（\"prompt_price\" と \"completion_price\" を設定してください）".to_string());
+        }
+        if config.chat {
+            lines.push("チャット形式: 会話の履歴を送ります（/clear で消去、/system で system メッセージを変更）".to_string());
+        }
+        lines
+    }
+
+    pub fn model_name(&self) -> &str {
+        &self.config.model_name
+    }
+
+    pub fn profile(&self) -> Option<&str> {
+        self.config.profile.as_deref()
+    }
+
+    pub fn greeting(&self) -> Option<&str> {
+        self.config.greeting.as_deref()
+    }
+
+    // --tui のキーの割り当て（設定を読んだときに確かめてあるので、読めなければデフォルトにする）
+    pub fn keymap(&self) -> Keymap {
+        Keymap::from_config(&self.config).unwrap_or_default()
+    }
+
+    // 考え中の部分を表示するかどうか（"reasoning_display": "hide" なら表示しない）
+    pub fn shows_reasoning(&self) -> bool {
+        self.config.reasoning_display.as_deref() != Some("hide")
+    }
+
+    // 応答の速度を表示するかどうか（--raw なら答えだけを出す）
+    pub fn shows_throughput(&self) -> bool {
+        !self.config.raw
+    }
+
+    pub fn dry_run(&self) -> bool {
+        self.config.dry_run
+    }
+
+    // やりとりをカセットに記録・再生する（mode は "record" / "replay"。None ならファイルがあれば再生）
+    pub fn use_cassette(&mut self, path: &str, mode: Option<&str>) -> Result<(), Error> {
+        let mode = cassette::parse_mode(mode, path).map_err(Error::Config)?;
+        self.config.tape = Some(cassette::open(path, mode).map_err(Error::Config)?);
+        Ok(())
+    }
+
+    // 使うモデルを切り替える（プロファイル名、"モデル名@行き先"、別名のどれでもよい）
+    pub fn set_model(&mut self, name: &str) {
+        profiles::select(&mut self.config, name);
+    }
+
+    // 応答をストリーミングで受け取るかどうか
+    pub fn set_stream(&mut self, stream: bool) {
+        self.config.stream = stream;
+    }
+
+    // 履歴をチャット形式で送るかどうか（ChatSession ではいつも送る）
+    pub(crate) fn set_chat(&mut self, chat: bool) {
+        self.config.chat = chat;
+    }
+
+    // true なら推論の途中のお知らせを標準エラーに出さず、端末でツールの確認もしない（端末を画面として使っているあいだ）
+    pub fn set_quiet(&mut self, quiet: bool) {
+        self.config.quiet = quiet;
+    }
+
+    // ツールを実行する前の確認を、端末ではなく confirm に頼む（clear_tool_confirmation で端末に戻す）
+    pub fn set_tool_confirmation(&mut self, confirm: impl Fn(ToolConfirmation) + Send + Sync + 'static) {
+        self.config.tool_confirmation = Some(Arc::new(confirm));
+    }
+
+    pub fn clear_tool_confirmation(&mut self) {
+        self.config.tool_confirmation = None;
+    }
+
+    // /set の設定を変更して、確認のメッセージを返す（"off" で指定を外す）
+    pub fn set(&mut self, key: &str, value: &str) -> Result<String, Error> {
+        apply_setting(&mut self.config, key, value).map_err(Error::Config)?;
+        let config = &self.config;
+        Ok(if key == "model" {
+            let target = if config.use_local_model {
+                config.local_framework.as_deref().unwrap_or("local")
+            } else {
+                config.provider.as_deref().unwrap_or("online")
+            };
+            format!("モデルを {}（{}）に切り替えました", config.model_name, target)
+        } else {
+            format!("{} を {} に設定しました", key, value)
+        })
+    }
+
+    pub fn system_prompt(&self) -> Option<&str> {
+        self.config.system_prompt.as_deref()
+    }
+
+    // チャット形式で最初に送る system メッセージ（None で外す）
+    pub fn set_system_prompt(&mut self, system_prompt: Option<&str>) {
+        self.config.system_prompt = system_prompt.map(|s| s.to_string());
+    }
+
+    // 次に送るときに参考資料として付けるチャンク（関連の高い順。送ったら空にする）
+    pub fn set_retrieved(&mut self, chunks: Vec<String>) {
+        self.config.retrieved = chunks;
+    }
+
+    // プロファイルの一覧（今のプロファイルに印を付ける）
+    pub fn profile_list(&self) -> String {
+        profiles::list(&self.config)
+    }
+
+    // プロファイルに切り替えて、確認のメッセージを返す（Ollama を温めているなら、新しいモデルを温め直す）
+    pub fn use_profile(&mut self, name: &str) -> Result<String, Error> {
+        profiles::apply(&mut self.config, name).map_err(Error::Config)?;
+        warmup::retarget(&self.config);
+        Ok(format!("プロファイル {} に切り替えました（モデル: {}）", name, self.config.model_name))
+    }
+
+    // 起動時に選んでもらうプロファイルの名前と表示（複数あって、どれも指定されていないときだけ。選んだら use_profile に渡す）
+    pub fn profile_choices(&self) -> Vec<(String, String)> {
+        if self.config.profile.is_some() || self.config.profiles.len() < 2 {
+            return Vec::new();
+        }
+        profiles::choices(&self.config)
+    }
+
+    // 初めて使うとき（プロファイルが2つ以上あって、速さの記録がない）は、測っておくか聞く
+    pub fn offers_benchmark(&self) -> bool {
+        benchmark::should_offer(&self.config)
+    }
+
+    // 聞いた答えに合わせて、プロファイルごとの速さを測るか、断ったことを記録する（次からは聞かない）
+    pub async fn answer_benchmark_offer(&self, measure: bool) -> Result<(), Error> {
+        benchmark::answer_offer(&self.config, measure).await.map_err(Error::from)
+    }
+
+    // Ollama のモデルを先に読み込ませておく（warm_up / keep_alive_interval_secs）
+    pub fn warm_up(&self) {
+        warmup::start(&self.config);
+    }
+
+    // 会話テンプレートの名前
+    pub fn templates(&self) -> Vec<String> {
+        templates::list(&self.config)
+    }
+
+    // テンプレートの設定にして、最初に送るメッセージがあれば返す
+    pub async fn start_template(&mut self, name: &str) -> Result<Option<String>, Error> {
+        templates::apply(&mut self.config, name).await.map_err(Error::Config)
+    }
+
+    // サブコマンドを実行する
+    pub async fn run(&self, command: commands::Command) -> Result<(), Error> {
+        commands::run(command, &self.config).await
+    }
+
+    // これまでの会話
+    pub fn history(&self) -> &[Message] {
+        &self.config.history
+    }
+
+    // 会話の履歴と比較モードのやりとりを消す（セッションに保存していれば、続きは新しいセッションにする）
+    pub fn clear_history(&mut self) {
+        self.config.history.clear();
+        self.config.compare_turns.clear();
+        if self.config.session_id.is_some() {
+            self.new_session();
+        }
+    }
+
+    // 今の会話を保存するセッションのID
+    pub fn session_id(&self) -> Option<&str> {
+        self.config.session_id.as_deref()
+    }
+
+    // 会話を新しいセッションとして保存していく
+    pub fn new_session(&mut self) {
+        self.config.session_id = Some(sessions::new_id());
+        self.config.session_settings = None;
+    }
+
+    // 会話をセッションに保存するかどうか（dry-run や "save_sessions": false では保存しない）
+    pub fn saves_sessions(&self) -> bool {
+        sessions::is_enabled(&self.config)
+    }
+
+    // 保存済みのセッションの一覧（新しい順。IDと最初のメッセージ）
+    pub fn sessions(&self) -> Vec<(String, String)> {
+        sessions::list(&self.config)
+    }
+
+    // 保存済みのセッションを読み込んで、その続きとして会話する（読み直せなかった画像の警告を返す）
+    // 保存したときのモデル・system メッセージ・パラメータに戻し、モデルが変わればそれも知らせる
+    // （--model / ClientBuilder::model で選んだモデルがあれば、モデルはそちらにする）
+    pub fn resume(&mut self, id: &str) -> Result<Vec<String>, Error> {
+        let (mut messages, settings) = sessions::read(&self.config, id).map_err(Error::Config)?;
+        let mut warnings = sessions::resolve_attachments(&self.config, &mut messages);
+        if let Some(settings) = settings.clone() {
+            let model = self.config.model_name.clone();
+            warnings.extend(settings.restore(&mut self.config));
+            if let Some(chosen) = self.config.model_override.clone() {
+                profiles::select(&mut self.config, &chosen);
+            }
+            if self.config.model_name != model {
+                warnings.push(format!("保存したときのモデル {} に戻しました", self.config.model_name));
+            }
+            warmup::retarget(&self.config);
+        }
+        self.config.history = messages;
+        self.config.session_id = Some(id.to_string());
+        self.config.session_settings = settings;
+        Ok(warnings)
+    }
+
+    // ファイルを次のメッセージに添付して、表示するメッセージを返す
+    pub async fn attach(&mut self, path: &str, kind: AttachmentKind) -> Result<String, Error> {
+        Ok(attachments::attach(path, kind, &mut self.config, &mut self.attached_texts).await?)
+    }
+
+    // クリップボードの画像を次のメッセージに添付する
+    pub async fn paste_image(&mut self) -> Result<String, Error> {
+        let path = attachments::paste_clipboard_image().await?;
+        self.attach(&path, AttachmentKind::Image).await
+    }
+
+    // 会話を Markdown に書き出す（共有用に伏せ字にしたかどうかを返す）
+    pub fn export_transcript(&self, path: &str, redact: bool) -> Result<bool, Error> {
+        let share_safe = self.config.share_safe_export || redact;
+        transcript::export(path, &self.config.history, &self.config, share_safe)?;
+        Ok(share_safe)
+    }
+
+    // 二重送信モードかどうか
+    pub fn speculates(&self) -> bool {
+        self.config.speculative.is_some()
+    }
+
+    // 二重送信モードにする（"<速いモデル>,<強いモデル>"。None なら終わる）
+    pub fn set_speculative(&mut self, models: Option<&str>) -> Result<(), Error> {
+        self.config.speculative = models.map(|models| {
+            let (fast, strong) = models.split_once(',')
+                .ok_or_else(|| Error::Config("--speculative には <速いモデル>,<強いモデル> を指定してください".to_string()))?;
+            Ok::<_, Error>(speculative::SpeculativeConfig { fast: fast.trim().to_string(), strong: strong.trim().to_string() })
+        }).transpose()?;
+        Ok(())
+    }
+
+    // 比較モードで比べているモデル
+    pub fn comparing(&self) -> Option<&[String]> {
+        self.config.compare.as_deref()
+    }
+
+    // 比較モードを始める（None なら終わる）。どちらでも、それまでの比較のやりとりは消す
+    pub fn set_compare(&mut self, models: Option<&str>) -> Result<(), Error> {
+        self.config.compare = models.map(compare::parse_models).transpose().map_err(Error::Config)?;
+        self.config.compare_turns.clear();
+        Ok(())
+    }
+
+    // 比較のやりとりをレポートに書き出して、ターン数を返す
+    pub fn export_comparison(&self, path: &str) -> Result<usize, Error> {
+        compare::export(path, &self.config.compare_turns)?;
+        Ok(self.config.compare_turns.len())
+    }
+
+    // 比較モードのモデルに同時に送って、答えを並べる（会話の履歴には加えず、モデルごとのやりとりとして覚えておく）
+    pub async fn compare(&mut self, input: &str) -> Comparison {
+        self.compare_with(input, None).await
+    }
+
+    // compare と同じく送って、途中で届いたイベントを何番目のモデルのものかと一緒に on_event に渡す（--tui のペインで使う）
+    pub async fn compare_events(&mut self, input: &str, mut on_event: impl FnMut(usize, Event)) -> Comparison {
+        let (sink, mut events) = tokio::sync::mpsc::unbounded_channel();
+        let comparison = self.compare_with(input, Some(sink));
+        tokio::pin!(comparison);
+        let comparison = loop {
+            tokio::select! {
+                Some((i, event)) = events.recv() => on_event(i, event),
+                comparison = &mut comparison => break comparison,
+            }
+        };
+        while let Ok((i, event)) = events.try_recv() {
+            on_event(i, event);
+        }
+        comparison
+    }
+
+    async fn compare_with(&mut self, input: &str, events: Option<tokio::sync::mpsc::UnboundedSender<(usize, Event)>>) -> Comparison {
+        let prompt = self.take_attached_texts(input);
+        let images = std::mem::take(&mut self.config.images);
+        self.config.attached_files.clear();
+        let config = Config { images, ..self.config.clone() };
+        let models = self.config.compare.clone().unwrap_or_default();
+        let results = compare::compare(&prompt, &config, &models, events).await;
+        for result in &results {
+            stats::record(&self.config, &result.model_name, &prompt, &result.completion, result.elapsed);
+        }
+        self.last_request = results.iter().rev().find_map(|result| result.completion.request.clone());
+        self.config.compare_turns.push(compare::Turn { prompt: prompt.clone(), results: results.clone() });
+        Comparison { prompt, results }
+    }
+
+    // 添付したテキストを前に埋め込む（埋め込んだら空にする）
+    fn take_attached_texts(&mut self, input: &str) -> String {
+        if self.attached_texts.is_empty() {
+            return input.to_string();
+        }
+        format!("{}\n\n{}", std::mem::take(&mut self.attached_texts).join("\n\n"), input)
+    }
+
+    // ask / chat / stream / chat_events の1回分の送る内容と設定（添付と参考資料は、この1回で使い切る）
+    fn begin_turn(&mut self, input: &str) -> (String, Config, Vec<AttachedFile>) {
+        let prompt = self.take_attached_texts(input);
+        let images = std::mem::take(&mut self.config.images);
+        let file_ids = std::mem::take(&mut self.config.file_ids);
+        let retrieved = std::mem::take(&mut self.config.retrieved);
+        let attached_files = std::mem::take(&mut self.config.attached_files);
+        (prompt, Config { images, file_ids, retrieved, ..self.config.clone() }, attached_files)
+    }
+
+    // 答えが届いたあとの、ask / chat / stream / chat_events に共通の処理
+    // 成功したら（dry-run でなければ）統計と上限（max_session_tokens / max_session_cost）に数え、
+    // 出力フィルターを通して、履歴とセッションに残す
+    fn finish_turn(&mut self, prompt: &str, completion: &mut Completion, model_name: &str, started_at: SystemTime, elapsed: Duration, attached_files: Vec<AttachedFile>) {
+        self.last_request = completion.request.take();
+        if completion.error.is_some() || self.config.dry_run {
+            return;
+        }
+        stats::record(&self.config, model_name, prompt, completion, elapsed);
+        completion.text = filters::apply(&completion.text, &self.config.output_filters, self.config.raw);
+        if let Some(mut turn) = conversation::turn(prompt, completion, model_name, started_at, elapsed, attached_files) {
+            turn[0].template = self.config.template.take();
+            sessions::append(&mut self.config, &turn);
+            self.config.history.extend(turn);
+        }
+    }
+
+    // OpenAI の Realtime API に声で話しかける（stop が終わるまで続ける）
+    // 話した内容と答えの文字起こしは on_event に届き、やりとりが1回終わるたびに ask と同じように統計・履歴・セッションに残す
+    pub async fn realtime(&mut self, stop: impl Future<Output = ()>, on_event: impl FnMut(Event)) -> Result<(), Error> {
+        if self.config.dry_run {
+            return Err(Error::Config("dry-run では --realtime を使えません".to_string()));
+        }
+        let config = self.config.clone();
+        let model = realtime::model(&config);
+        realtime::run(&config, stop, on_event, |mut turn| {
+            self.finish_turn(&turn.prompt, &mut turn.completion, &model, turn.started_at, turn.elapsed, Vec::new());
+        }).await
+    }
+
+    // ask がストリーミングで答えるかどうか（テンプレートで整形するときと二重送信では、最後にまとめて返す）
+    pub fn streams(&self) -> bool {
+        let config = &self.config;
+        config.stream && !config.dry_run && config.format.is_none() && config.speculative.is_none()
+    }
+
+    // 対話の1ターン分を送る
+    //
+    // ストリーミングなら届いた分を on_event に渡し、stop が終わったらそこまでを答えとして残す。
+    // 成功したら（dry-run でなければ）統計に数え、出力フィルターを通して、履歴とセッションに残す。
+    // 添付は失敗しても途中で捨てても、この1回で使い切る。
+    pub async fn ask(&mut self, input: &str, stop: impl Future<Output = ()>, mut on_event: impl FnMut(Event)) -> Answer {
+        let (prompt, config, attached_files) = self.begin_turn(input);
+
+        let started_at = SystemTime::now();
+        let started = Instant::now();
+        // 推論の future は大きいので、ask の future に入れずにヒープに置く（呼ぶ側のスタックを使いすぎないように）
+        let (mut completion, shown, elapsed, model_name) = match &config.speculative {
+            Some(models) if !config.dry_run => {
+                let (completion, streamed, elapsed, model_name) = Box::pin(speculative::respond_speculative(&prompt, &config, models)).await;
+                (completion, streamed.answer, elapsed, model_name)
+            }
+            _ => {
+                // 言語ごとの振り分けがあれば、この1回だけ振り分け先のモデルで答える
+                let route = router::route(&prompt, &config);
+                if let Some(route) = &route {
+                    on_event(Event::Routed { language: router::language_name(route.language).to_string(), model: route.target.clone() });
+                }
+                let active = route.as_ref().map(|route| &route.config).unwrap_or(&config);
+                let completion = if active.stream && !active.dry_run && active.format.is_none() {
+                    Box::pin(Self::respond_streaming(&prompt, active, stop, &mut on_event)).await
+                } else {
+                    Box::pin(respond(&prompt, active)).await
+                };
+                (completion, false, started.elapsed(), active.model_name.clone())
+            }
+        };
+        self.finish_turn(&prompt, &mut completion, &model_name, started_at, elapsed, attached_files);
+        Answer { input: input.to_string(), prompt, completion, model_name, started_at, elapsed, shown }
+    }
+
+    // 届いたトークンを on_event に渡しながら待つ（トークンも流す設定なら、届いた順に送る）
+    async fn respond_streaming(prompt: &str, config: &Config, stop: impl Future<Output = ()>, on_event: &mut impl FnMut(Event)) -> Completion {
+        let published = config.publish.as_ref()
+            .filter(|target| target.tokens)
+            .map(|target| publish::TokenStream::start(target, &config.model_name));
+        let mut partial = String::new();
+        let forward = |event: Event| {
+            if let Event::TokenDelta(Token::Answer(text)) = &event {
+                partial.push_str(text);
+                if let Some(published) = &published {
+                    published.send(text);
+                }
+            }
+            on_event(event);
+        };
+        let completion = tokio::select! {
+            completion = respond_with_events(prompt, config, forward) => Some(completion),
+            _ = stop => None,
+        };
+        let completion = completion.unwrap_or_else(|| Completion::stopped(partial));
+        if let Some(published) = published {
+            if let Err(e) = published.finish().await {
+                if !config.quiet {
+                    eprintln!("トークンの送信に失敗しました: {}", e);
+                }
+            }
+        }
+        completion
+    }
+
+    // ask の答えのあとに表示する行（ストリーミングで表示した部分は繰り返さない）
+    pub fn render(&self, answer: &Answer, streamed: Streamed) -> Vec<String> {
+        let config = &self.config;
+        let completion = &answer.completion;
+        let streamed = if answer.shown { Streamed { answer: true, reasoning: true } } else { streamed };
+        let mut lines = Vec::new();
+        if let Some(error) = &completion.error {
+            // 続きの生成で失敗したときは、そこまでの答えも見せる
+            if !streamed.answer && completion.text != *error {
+                lines.push(format!("AI > {}", completion.text));
+            }
+            lines.push(error.clone());
+            return lines;
+        }
+        if config.dry_run {
+            return vec![completion.text.clone()];
+        }
+        if let Some(output) = self.format(answer) {
+            lines.push(output.unwrap_or_else(|e| e.to_string()));
+            return lines;
+        }
+        let thoughts = completion.reasoning.as_deref()
+            .filter(|_| !streamed.reasoning)
+            .and_then(|r| reasoning::render(r, config.reasoning_display.as_deref()));
+        lines.extend(thoughts);
+        let (text, images) = if config.raw || streamed.answer {
+            (completion.text.clone(), Vec::new())
+        } else {
+            inline_images::extract(&completion.text)
+        };
+        if !streamed.answer {
+            lines.push(format!("AI > {}", text));
+        }
+        if completion.is_interrupted() {
+            lines.push("（接続が切れて再開もできなかったため、応答は途中までです）".to_string());
+        }
+        if completion.is_stopped() {
+            lines.push("（途中で止めました。ここまでを返事として残します）".to_string());
+        }
+        if !completion.citations.is_empty() {
+            lines.push("出典:".to_string());
+            lines.extend(completion.citations.iter().enumerate().map(|(i, citation)| format!("  [{}] {}", i + 1, citation)));
+        }
+        if !completion.search_suggestions.is_empty() {
+            lines.push(format!("検索の候補: {}", completion.search_suggestions.join(" / ")));
+        }
+        for (i, image) in images.iter().enumerate() {
+            lines.push(inline_images::display(i + 1, image, config.image_display.as_deref()));
+        }
+        if !config.raw {
+            lines.push(stats::throughput(&answer.prompt, completion, answer.elapsed));
+        }
+        if config.verbose {
+            lines.push(match completion.timing {
+                Some(timing) => timing.breakdown(answer.elapsed),
+                None => format!("時間の内訳: プロバイダーが処理時間を返さないため不明です（全体 {:.2}秒）", answer.elapsed.as_secs_f64()),
+            });
+        }
+        lines.extend(completion.usage.and_then(|usage| usage.cache_report()));
+        lines
+    }
+
+    // --format のテンプレートで整形した答え（テンプレートがなければ None）
+    pub fn format(&self, answer: &Answer) -> Option<Result<String, Error>> {
+        let template = self.config.format.as_ref()?;
+        let data = format::response_data(&answer.model_name, &answer.input, &answer.completion, answer.started_at, answer.elapsed);
+        Some(format::render(template, &data).map_err(Error::Config))
+    }
+
+    // 完成した答えを publish の送り先に流す（送り先がないとき、失敗した答えと dry-run では何もしない）
+    pub async fn publish(&self, answer: &Answer) -> Result<(), Error> {
+        let Some(target) = self.config.publish.as_ref().filter(|_| answer.completion.error.is_none() && !self.config.dry_run) else {
+            return Ok(());
+        };
+        Ok(publish::publish(target, &answer.model_name, &answer.input, &answer.completion.text).await?)
+    }
+
+    // つながらずに失敗した答えなら、プロンプトをキューに入れてキューのIDを返す（キューを使わない設定なら None）
+    pub fn queue_if_offline(&self, answer: &Answer) -> Option<Result<String, Error>> {
+        if !queue::is_enabled(&self.config) || !answer.completion.is_offline() {
+            return None;
+        }
+        Some(queue::enqueue(&self.config, queue::Job::Prompt { prompt: answer.prompt.clone() }).map_err(Error::from))
+    }
+
+    // セッションの上限（max_session_tokens / max_session_cost）を超えていれば、まとめを入れたエラー
+    pub fn budget_exceeded(&self) -> Option<Error> {
+        stats::budget_exceeded(&self.config)
+    }
+
+    // /stats の表示
+    pub fn stats_report(&self) -> String {
+        stats::report(&self.config)
+    }
+
+    // /usage の表示
+    pub fn usage_report(&self) -> String {
+        stats::usage_report(&self.config)
+    }
+
+    // 直前に送ったリクエストを curl のコマンドにする
+    pub fn last_request_curl(&self) -> Option<String> {
+        self.last_request.as_ref().map(|request| request.to_curl())
+    }
+
+    // 接続先で使えるモデルの一覧
+    pub async fn list_models(&self) -> Result<Vec<ModelInfo>, Error> {
+        Ok(providers::list_models(&self.config).await?)
+    }
+
+    // 一覧に今のモデルの料金があり、まだ設定していなければ /stats の推定に使う（使うことにしたらそのお知らせ）
+    pub fn adopt_prices(&mut self, models: &[ModelInfo]) -> Option<String> {
+        let config = &mut self.config;
+        if config.prompt_price.is_some() || config.completion_price.is_some() {
+            return None;
+        }
+        let current = models.iter().find(|model| model.id == config.model_name)
+            .filter(|model| model.prompt_price.is_some() || model.completion_price.is_some())?;
+        config.prompt_price = current.prompt_price;
+        config.completion_price = current.completion_price;
+        Some(format!("{} の料金を /stats の推定料金に使います", current.id))
+    }
+
+    // 履歴を使わずに1回だけ推論する
+    pub async fn complete(&self, prompt: &str) -> Result<Completion, Error> {
+        let config = Config { history: Vec::new(), ..self.config.clone() };
+        respond(prompt, &config).await.into_result()
+    }
+
+    // 会話の続きとして送り、成功したら今回のやりとりを履歴に加える（統計・出力フィルター・セッションは ask と同じ）
+    // chat / stream / chat_events は、設定の chat に関わらず、この1回を会話の続きとして送る
+    pub async fn chat(&mut self, message: &str) -> Result<Completion, Error> {
+        let (prompt, config, attached_files) = self.begin_turn(message);
+        let config = Config { chat: true, ..config };
+        let (started_at, started) = (SystemTime::now(), Instant::now());
+        let mut completion = respond(&prompt, &config).await;
+        self.finish_turn(&prompt, &mut completion, &config.model_name, started_at, started.elapsed(), attached_files);
+        completion.into_result()
+    }
+
+    // 会話の続きとして送り、届いたトークンを on_token に渡す（ストリーミングに対応した接続先のみ）
+    pub async fn stream(&mut self, message: &str, on_token: impl FnMut(Token)) -> Result<Completion, Error> {
+        let (prompt, config, attached_files) = self.begin_turn(message);
+        let config = Config { chat: true, stream: true, ..config };
+        let (started_at, started) = (SystemTime::now(), Instant::now());
+        let mut completion = respond_with_tokens(&prompt, &config, on_token).await;
+        self.finish_turn(&prompt, &mut completion, &config.model_name, started_at, started.elapsed(), attached_files);
+        completion.into_result()
+    }
+
+    // stream と同じく会話の続きとして送り、やりとりの流れをイベントで on_event に渡す
+    // （UserMessage から始まり、AssistantMessage か Error で終わる）
+    pub async fn chat_events(&mut self, message: &str, mut on_event: impl FnMut(Event)) -> Result<Completion, Error> {
+        let (prompt, config, attached_files) = self.begin_turn(message);
+        let config = Config { chat: true, stream: true, ..config };
+        on_event(Event::UserMessage(message.to_string()));
+        let (started_at, started) = (SystemTime::now(), Instant::now());
+        let mut completion = respond_with_events(&prompt, &config, &mut on_event).await;
+        self.finish_turn(&prompt, &mut completion, &config.model_name, started_at, started.elapsed(), attached_files);
+        if let Some(error) = &completion.error {
+            on_event(Event::Error(error.clone()));
+            return completion.into_result();
+        }
+        let (prompt_tokens, completion_tokens, cached_tokens) = stats::token_counts(&prompt, &completion);
+        on_event(Event::UsageReport(Usage { prompt_tokens, completion_tokens, cached_tokens }));
+        on_event(Event::AssistantMessage(completion.text.clone()));
+        Ok(completion)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn config(endpoint: &str, api_key: &str) -> Config {
+        serde_json::from_value(json!({
+            "model_name": "gpt-4o-mini", "use_local_model": false, "openai_compatible": true,
+            "endpoint": endpoint, "api_key": api_key, "request_timeout_secs": 5,
+            "profiles": { "small": { "model_name": "gpt-4.1-nano" } },
+        })).unwrap()
+    }
+
+    #[test]
+    fn each_client_switches_profiles_back_to_its_own_defaults() {
+        let mut first = Client::new(config("https://a.example/v1/chat/completions", "key-a"));
+        let mut second = Client::new(config("https://b.example/v1/chat/completions", "key-b"));
+        first.use_profile("small").unwrap();
+        second.use_profile("small").unwrap();
+        assert_eq!(second.config.endpoint.as_deref(), Some("https://b.example/v1/chat/completions"));
+        assert_eq!(second.config.api_key.as_deref(), Some("key-b"));
+        assert_eq!(first.config.api_key.as_deref(), Some("key-a"));
+        assert_eq!(second.model_name(), "gpt-4.1-nano");
+    }
+
+    #[test]
+    fn clients_made_from_the_same_config_keep_separate_stats() {
+        let base = config("https://a.example/v1/chat/completions", "key-a");
+        let first = Client::new(base.clone());
+        let second = Client::new(base);
+        stats::record(&first.config, "gpt-4o-mini", "こんにちは", &Completion::from("はい".to_string()), Duration::from_secs(1));
+        assert_ne!(first.stats_report(), second.stats_report());
+    }
+
+    #[tokio::test]
+    async fn chat_goes_through_the_same_steps_as_ask() {
+        let dir = std::env::temp_dir().join(format!("milti_llm_client-chat-{}", std::process::id()));
+        let mut client = Client::new(serde_json::from_value(json!({
+            "model_name": "mock", "use_local_model": true, "openai_compatible": false, "local_framework": "mock",
+            "output_filters": ["redact"], "sessions_dir": dir.to_string_lossy(),
+        })).unwrap());
+        client.new_session();
+        let secret = "sk-abcdefghijklmnopqrstuvwxyz";
+        let completion = client.chat(&format!("鍵は {}", secret)).await.unwrap();
+        assert!(!completion.text.contains(secret), "{}", completion.text);
+        assert_eq!(client.history().len(), 2);
+        assert!(!client.history()[1].content.contains(secret));
+        // 統計に数え、セッションに残す
+        assert!(client.stats_report().starts_with("メッセージ数: 2"), "{}", client.stats_report());
+        assert_eq!(client.sessions().len(), 1);
+        // chat の指定はこの1回だけ（設定は変えない）
+        assert!(!client.config.chat);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn uploaded_files_are_sent_as_file_parts() {
+        let mut client = Client::new(serde_json::from_value(json!({
+            "model_name": "gpt-4o-mini", "use_local_model": false, "openai_compatible": true, "chat": true,
+            "endpoint": "https://a.example/v1/chat/completions", "dry_run": true,
+        })).unwrap());
+        client.attach("file-abc123", AttachmentKind::Any).await.unwrap();
+        client.ask("要約して", std::future::pending(), |_| {}).await;
+        let body = &client.last_request.as_ref().unwrap().body;
+        assert_eq!(body["messages"][0]["content"], json!([
+            { "type": "text", "text": "要約して" },
+            { "type": "file", "file": { "file_id": "file-abc123" } },
+        ]));
+        // 送ったら、次のメッセージには付けない
+        client.ask("次", std::future::pending(), |_| {}).await;
+        assert_eq!(client.last_request.as_ref().unwrap().body["messages"][0]["content"], "次");
+
+        // ファイルIDを送れない行き先では添付しない
+        let mut local = Client::new(serde_json::from_value(json!({
+            "model_name": "llama3", "use_local_model": true, "openai_compatible": false, "local_framework": "ollama",
+        })).unwrap());
+        assert!(local.attach("file-abc123", AttachmentKind::Any).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn images_for_a_text_only_model_are_described_by_the_vision_profile() {
+        let image = std::env::temp_dir().join(format!("milti_llm_client-vision-{}.png", std::process::id()));
+        std::fs::write(&image, b"\x89PNG\r\n\x1a\n").unwrap();
+        let image = image.to_string_lossy().to_string();
+        // 画像を送れない mock に添付すると、eyes（OpenAI互換のチャット形式）に説明を頼む（dry-run なので送る内容が返る）
+        let mut client = Client::new(serde_json::from_value(json!({
+            "model_name": "mock", "use_local_model": true, "openai_compatible": false, "local_framework": "mock", "dry_run": true,
+            "vision_profile": "eyes",
+            "profiles": {
+                "eyes": { "model_name": "gpt-4o", "use_local_model": false, "openai_compatible": true, "endpoint": "https://a.example/v1/chat/completions" },
+                "blind": { "model_name": "mock2" },
+            },
+        })).unwrap());
+        let message = client.attach(&image, AttachmentKind::Any).await.unwrap();
+        assert!(message.contains("画像の説明を添付します"), "{}", message);
+        let answer = client.ask("何が写っている？", std::future::pending(), |_| {}).await;
+        assert!(answer.prompt.contains("image_url") && answer.prompt.contains("詳しく説明してください"), "{}", answer.prompt);
+
+        // 説明役も画像を扱えなければ、OCR（ocr_fallback）がないかぎり添付しない
+        client.config.vision_profile = Some("blind".to_string());
+        let error = client.attach(&image, AttachmentKind::Any).await.unwrap_err().to_string();
+        assert!(error.contains("vision_profile の blind は画像を扱えません"), "{}", error);
+        let _ = std::fs::remove_file(image);
+    }
+
+    #[test]
+    fn options_override_the_config_file() {
+        let options = Options { model: Some("gpt-4.1".to_string()), dry_run: true, compare: Some("a,b".to_string()), ..Options::default() };
+        let client = Client::with_options(config("https://a.example/v1/chat/completions", "key-a"), options).unwrap();
+        assert_eq!(client.model_name(), "gpt-4.1");
+        assert!(client.dry_run());
+        assert_eq!(client.comparing(), Some(&["a".to_string(), "b".to_string()][..]));
+
+        let options = Options { speculative: Some("fast-only".to_string()), ..Options::default() };
+        let error = Client::with_options(config("https://a.example/v1/chat/completions", "key-a"), options).err().unwrap();
+        assert_eq!(error.kind(), crate::ErrorKind::Config);
+    }
+}
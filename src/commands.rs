@@ -0,0 +1,46 @@
+// 1回だけ実行して終わるサブコマンド（Client::run で実行する）
+//
+// コマンドラインの引数は実行ファイルの側で読み、ここではどれをどの値で実行するかだけを受け取る。
+use crate::error::Error;
+use crate::{batch, benchmark, files, finetune, gemini_cache, history, judge, pipeline, queue, sessions, snapshots, transcribe, Config};
+
+pub use batch::BatchCommand;
+pub use files::FilesCommand;
+pub use gemini_cache::CacheCommand;
+pub use finetune::FinetuneCommand;
+pub use judge::JudgeCommand;
+pub use queue::QueueCommand;
+pub use sessions::SessionCommand;
+pub use transcribe::TranscribeCommand;
+
+pub enum Command {
+    Batch(BatchCommand),
+    Finetune(FinetuneCommand),
+    Files(FilesCommand),
+    Cache(CacheCommand), // Gemini のコンテキストキャッシュ
+    Transcribe(TranscribeCommand),
+    Pipe, // 標準入力のJSONを1行ずつ推論する
+    Judge(JudgeCommand),
+    Session(SessionCommand),
+    History { json: bool }, // history stats
+    Queue(QueueCommand),
+    Test { path: String, update: bool }, // スナップショットと比べる（update なら書き換える）
+    Bench { profiles: Vec<String>, probes: Option<usize> }, // profiles が空ならすべてのプロファイル
+}
+
+pub async fn run(command: Command, config: &Config) -> Result<(), Error> {
+    match command {
+        Command::Batch(command) => batch::run(command, config).await,
+        Command::Finetune(command) => finetune::run(command, config).await,
+        Command::Files(command) => files::run(command, config).await,
+        Command::Cache(command) => gemini_cache::run(command, config).await,
+        Command::Transcribe(command) => transcribe::run(command, config).await,
+        Command::Pipe => pipeline::run(config).await,
+        Command::Judge(command) => judge::run(command, config).await.map_err(Error::from),
+        Command::Session(command) => sessions::run(command, config).await.map_err(Error::from),
+        Command::History { json } => history::run(json, config).map_err(Error::from),
+        Command::Queue(command) => queue::run(command, config).await.map_err(Error::from),
+        Command::Test { path, update } => snapshots::run(&path, update, config).await.map_err(Error::from),
+        Command::Bench { profiles, probes } => benchmark::run(profiles, probes, config).await.map_err(Error::from),
+    }
+}
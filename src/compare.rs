@@ -0,0 +1,271 @@
+// 同じプロンプトを複数のモデルに同時に送り、答えを横に並べて比べる（--compare / /compare）
+//
+// モデルはプロファイルの名前か "モデル名@行き先"（または別名）で書く。それぞれを tokio のタスクで並行に送り、
+// 全部そろったら、モデルごとの列に折り返して、かかった時間と一緒に表示する。
+// --tui では、モデルごとのペインに、それぞれのモデルのトークンを届いた分から並べて表示する。
+// 端末が狭くて列が細くなりすぎるときは、モデルごとに順に表示する。
+//
+// 対話モードで比べているあいだは、やりとりを1ターンずつ覚えておき（Config の compare_turns）、
+// チャット形式ならモデルごとに、そのモデル自身の答えをつなげた履歴を送る（別々の会話を同時に続ける）。
+// /compare export <ファイル> で、ターンごとの答えとモデルごとのまとめを比較レポートに書き出す（.json ならJSON）。
+use std::time::Duration;
+use web_time::Instant;
+use tokio::sync::mpsc::UnboundedSender;
+use crate::{benchmark, filters, profiles, respond, respond_with_events, runtime, stats, Config, Event};
+use crate::width::{char_width, text_width};
+use crate::completion::Completion;
+use crate::conversation::Message;
+
+// 端末の幅がわからないとき（COLUMNS がないとき）の幅
+const DEFAULT_TERMINAL_COLUMNS: usize = 120;
+
+// これより細い列になるなら、横に並べずに順に表示する
+const MIN_COLUMN_WIDTH: usize = 24;
+
+const COLUMN_SEPARATOR: &str = " │ ";
+
+// 1つのモデルの結果
+#[derive(Clone)]
+pub struct Compared {
+    pub model: String, // 指定したプロファイルかモデルの名前
+    pub model_name: String, // 実際に使ったモデル（料金を調べるため）
+    pub completion: Completion,
+    pub elapsed: Duration,
+    pub baseline: Option<benchmark::Measurement>, // bench で測ったふだんの速さ
+}
+
+// 比べているあいだの1回のやりとり
+#[derive(Clone)]
+pub struct Turn {
+    pub prompt: String,
+    pub results: Vec<Compared>,
+}
+
+// そのモデルの、これまでのやりとり（失敗したターンは入れない）
+fn history_of(turns: &[Turn], model: &str) -> Vec<Message> {
+    let mut history = Vec::new();
+    for turn in turns {
+        let Some(result) = turn.results.iter().find(|result| result.model == model && result.completion.error.is_none()) else {
+            continue;
+        };
+        history.push(Message::new("user", &turn.prompt));
+        history.push(Message { model: Some(result.model_name.clone()), ..Message::new("assistant", &result.completion.text) });
+    }
+    history
+}
+
+// "a,b,c" をモデルの並びにする（2つ以上なければ Err）
+pub fn parse_models(list: &str) -> Result<Vec<String>, String> {
+    let models: Vec<String> = list.split(',').map(|model| model.trim().to_string()).filter(|model| !model.is_empty()).collect();
+    if models.len() < 2 {
+        return Err("比べるモデルを2つ以上、カンマ区切りで指定してください（例: gemma,gpt,claude）".to_string());
+    }
+    Ok(models)
+}
+
+// すべてのモデルに同時に送り、指定した順に結果を返す
+// （チャット形式なら、それぞれのモデルに、比べ始める前の履歴とそのモデルとのやりとりを送る）
+// events があれば、途中で届いたイベントを何番目のモデルのものかと一緒に渡す（なければストリーミングしない）
+pub async fn compare(prompt: &str, config: &Config, models: &[String], events: Option<UnboundedSender<(usize, Event)>>) -> Vec<Compared> {
+    let tasks: Vec<_> = models.iter().enumerate()
+        .map(|(i, model)| {
+            let mut model_config = config.clone();
+            profiles::select(&mut model_config, model);
+            model_config.stream = events.is_some();
+            model_config.history.extend(history_of(&config.compare_turns, model));
+            model_config.compare_turns.clear();
+            let prompt = prompt.to_string();
+            let model_name = model_config.model_name.clone();
+            let events = events.clone();
+            (model_name, runtime::spawn(async move {
+                let started = Instant::now();
+                let mut completion = match events {
+                    Some(events) => respond_with_events(&prompt, &model_config, |event| {
+                        let _ = events.send((i, event));
+                    }).await,
+                    None => respond(&prompt, &model_config).await,
+                };
+                let elapsed = started.elapsed();
+                if !model_config.dry_run {
+                    completion.text = filters::apply(&completion.text, &model_config.output_filters, model_config.raw);
+                }
+                (completion, elapsed)
+            }))
+        })
+        .collect();
+    let mut baselines = benchmark::load(config);
+    let mut results = Vec::new();
+    for (model, (model_name, task)) in models.iter().zip(tasks) {
+        let (completion, elapsed) = task.await
+            .unwrap_or_else(|e| (Completion::failed(format!("推論のタスクが異常終了しました: {}", e)), Duration::ZERO));
+        let baseline = baselines.remove(model).filter(|baseline| baseline.error.is_none());
+        results.push(Compared { model: model.clone(), model_name, completion, elapsed, baseline });
+    }
+    results
+}
+
+// 表示幅 width で折り返した行
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for line in text.lines() {
+        let mut current = String::new();
+        let mut current_width = 0;
+        for c in line.chars() {
+            if current_width + char_width(c) > width {
+                lines.push(std::mem::take(&mut current));
+                current_width = 0;
+            }
+            current.push(c);
+            current_width += char_width(c);
+        }
+        lines.push(current);
+    }
+    lines
+}
+
+fn pad(text: &str, width: usize) -> String {
+    format!("{}{}", text, " ".repeat(width.saturating_sub(text_width(text))))
+}
+
+fn heading(result: &Compared) -> String {
+    match &result.baseline {
+        Some(baseline) => format!("{}（{:.2}秒・ふだん {:.2}秒）", result.model, result.elapsed.as_secs_f64(), baseline.latency_ms as f64 / 1000.0),
+        None => format!("{}（{:.2}秒）", result.model, result.elapsed.as_secs_f64()),
+    }
+}
+
+// 結果を横に並べた表にする
+pub fn render(results: &[Compared]) -> String {
+    let columns = std::env::var("COLUMNS").ok().and_then(|c| c.parse().ok()).unwrap_or(DEFAULT_TERMINAL_COLUMNS);
+    let separators = text_width(COLUMN_SEPARATOR) * results.len().saturating_sub(1);
+    let width = columns.saturating_sub(separators) / results.len().max(1);
+    if width < MIN_COLUMN_WIDTH {
+        return results.iter()
+            .map(|result| format!("## {}\n{}", heading(result), result.completion.text.trim_end()))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+    }
+
+    let headings: Vec<Vec<String>> = results.iter().map(|result| wrap(&heading(result), width)).collect();
+    let bodies: Vec<Vec<String>> = results.iter().map(|result| wrap(result.completion.text.trim_end(), width)).collect();
+    let rows = |cells: &[Vec<String>]| -> Vec<String> {
+        let height = cells.iter().map(Vec::len).max().unwrap_or(0);
+        (0..height)
+            .map(|row| {
+                cells.iter()
+                    .map(|lines| pad(lines.get(row).map(String::as_str).unwrap_or_default(), width))
+                    .collect::<Vec<_>>()
+                    .join(COLUMN_SEPARATOR)
+                    .trim_end()
+                    .to_string()
+            })
+            .collect()
+    };
+    let mut table = rows(&headings);
+    table.push(vec!["─".repeat(width); results.len()].join("─┼─"));
+    table.extend(rows(&bodies));
+    table.join("\n")
+}
+
+// --output json で書く形
+pub fn to_json(results: &[Compared]) -> serde_json::Value {
+    serde_json::json!(results.iter()
+        .map(|result| serde_json::json!({
+            "model": result.model,
+            "text": result.completion.text,
+            "error": result.completion.error,
+            "finish_reason": result.completion.finish_reason,
+            "usage": result.completion.usage.as_ref().map(|usage| serde_json::json!({
+                "prompt_tokens": usage.prompt_tokens,
+                "completion_tokens": usage.completion_tokens,
+            })),
+            "latency_ms": result.elapsed.as_millis() as u64,
+            "baseline_latency_ms": result.baseline.as_ref().map(|baseline| baseline.latency_ms),
+        }))
+        .collect::<Vec<_>>())
+}
+
+// 比較レポート（ターンごとにモデルの答えを並べ、最後にモデルごとの合計を書く）
+pub fn report_markdown(turns: &[Turn]) -> String {
+    let models: Vec<&str> = turns.first().map(|turn| turn.results.iter().map(|result| result.model.as_str()).collect()).unwrap_or_default();
+    let mut markdown = String::from("# 比較レポート\n\n");
+    markdown.push_str(&format!("- モデル: {}\n- ターン数: {}\n", models.join(" / "), turns.len()));
+    for (i, turn) in turns.iter().enumerate() {
+        markdown.push_str(&format!("\n## {}. You\n\n{}\n", i + 1, turn.prompt.trim_end()));
+        for result in &turn.results {
+            let text = match &result.completion.error {
+                Some(error) => format!("（失敗しました: {}）", error),
+                None => result.completion.text.trim_end().to_string(),
+            };
+            markdown.push_str(&format!("\n### {}\n\n{}\n", heading(result), text));
+        }
+    }
+    markdown.push_str("\n## まとめ\n\n| モデル | 合計時間 | 平均時間 | 出力トークン | 失敗 |\n|---|---|---|---|---|\n");
+    for model in models {
+        let results: Vec<(&Turn, &Compared)> = turns.iter()
+            .filter_map(|turn| turn.results.iter().find(|result| result.model == model).map(|result| (turn, result)))
+            .collect();
+        let total: Duration = results.iter().map(|(_, result)| result.elapsed).sum();
+        let tokens: u64 = results.iter()
+            .filter(|(_, result)| result.completion.error.is_none())
+            .map(|(turn, result)| stats::token_counts(&turn.prompt, &result.completion).1)
+            .sum();
+        let failures = results.iter().filter(|(_, result)| result.completion.error.is_some()).count();
+        markdown.push_str(&format!(
+            "| {} | {:.2}秒 | {:.2}秒 | {} | {} |\n",
+            model,
+            total.as_secs_f64(),
+            total.as_secs_f64() / results.len().max(1) as f64,
+            tokens,
+            failures,
+        ));
+    }
+    markdown
+}
+
+// 比較レポートを書き出す（.json ならターンごとの to_json の並び、そうでなければ Markdown）
+pub fn export(path: &str, turns: &[Turn]) -> Result<(), String> {
+    if turns.is_empty() {
+        return Err("まだ比べたやりとりがありません".to_string());
+    }
+    let text = match path.ends_with(".json") {
+        true => serde_json::to_string_pretty(&serde_json::json!(turns.iter()
+            .map(|turn| serde_json::json!({ "prompt": turn.prompt, "results": to_json(&turn.results) }))
+            .collect::<Vec<_>>()))
+            .unwrap_or_default(),
+        false => report_markdown(turns),
+    };
+    std::fs::write(path, text).map_err(|e| format!("比較レポートの書き出しに失敗しました: {:?}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::Token;
+
+    #[tokio::test]
+    async fn streamed_comparison_tags_tokens_with_the_model() {
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "model_name": "m", "use_local_model": true, "openai_compatible": false, "local_framework": "mock",
+            "mock": { "chunk_delay_ms": 0 },
+            "profiles": {
+                "a": { "model_name": "model-a" },
+                "b": { "model_name": "model-b" },
+            },
+        })).unwrap();
+        let (sink, mut events) = tokio::sync::mpsc::unbounded_channel();
+        let models = ["a".to_string(), "b".to_string()];
+        let results = compare("hi there", &config, &models, Some(sink)).await;
+        let mut streamed = [String::new(), String::new()];
+        while let Ok((i, event)) = events.try_recv() {
+            if let Event::TokenDelta(Token::Answer(text)) = event {
+                streamed[i].push_str(&text);
+            }
+        }
+        assert_eq!(results.iter().map(|result| result.model_name.as_str()).collect::<Vec<_>>(), ["model-a", "model-b"]);
+        for (result, streamed) in results.iter().zip(&streamed) {
+            assert_eq!(result.completion.text, "echo: hi there");
+            assert_eq!(streamed, &result.completion.text);
+        }
+    }
+}
@@ -0,0 +1,316 @@
+// 推論結果をまとめて扱うための型とヘルパー
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use crate::reasoning;
+use crate::error::{Error, ErrorKind};
+use crate::request::PreparedRequest;
+
+// 継ぎ目の重複を探すときに見る長さ（バイト数）
+// 短すぎる重なりは偶然の一致かもしれないので、MIN未満は重複とみなさない
+const MIN_OVERLAP_BYTES: usize = 8;
+const MAX_OVERLAP_BYTES: usize = 200;
+
+// トークン使用量（プロバイダーが返してくれた場合だけ入る）
+#[derive(Default, Clone, Copy, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub cached_tokens: u64, // プロンプトのうちプロバイダー側のキャッシュから読まれたトークン数
+}
+
+impl Usage {
+    // OpenAI互換の "usage" ブロックを読む
+    pub fn from_openai(usage: &serde_json::Value) -> Option<Usage> {
+        let count = |value: Option<&serde_json::Value>| value.and_then(|v| v.as_u64()).unwrap_or(0);
+        usage.as_object()?;
+        Some(Usage {
+            prompt_tokens: count(usage.get("prompt_tokens")),
+            completion_tokens: count(usage.get("completion_tokens")),
+            cached_tokens: count(usage.pointer("/prompt_tokens_details/cached_tokens")),
+        })
+    }
+
+    // Ollama の最後の行（"done": true）にあるトークン数を読む
+    pub fn from_ollama(json: &serde_json::Value) -> Option<Usage> {
+        Some(Usage {
+            prompt_tokens: json.get("prompt_eval_count")?.as_u64()?,
+            completion_tokens: json.get("eval_count").and_then(|v| v.as_u64()).unwrap_or(0),
+            cached_tokens: 0,
+        })
+    }
+
+    // 続きを生成したときなど、複数回の使用量を足し合わせる
+    pub fn add(first: Option<Usage>, second: Option<Usage>) -> Option<Usage> {
+        match (first, second) {
+            (Some(a), Some(b)) => Some(Usage {
+                prompt_tokens: a.prompt_tokens + b.prompt_tokens,
+                completion_tokens: a.completion_tokens + b.completion_tokens,
+                cached_tokens: a.cached_tokens + b.cached_tokens,
+            }),
+            (a, b) => a.or(b),
+        }
+    }
+
+    // プロンプトキャッシュが効いていれば、その報告の文字列を返す
+    pub fn cache_report(&self) -> Option<String> {
+        if self.cached_tokens == 0 || self.prompt_tokens == 0 {
+            return None;
+        }
+        Some(format!(
+            "プロンプトキャッシュ: {}/{} トークンがキャッシュから読まれました（{}%）",
+            self.cached_tokens, self.prompt_tokens, self.cached_tokens * 100 / self.prompt_tokens
+        ))
+    }
+}
+
+// 処理時間の内訳（Ollama の *_duration や llama.cpp の timings を返してくれた場合だけ入る）
+// セッションには、AIのメッセージにミリ秒で残す（"timing": {"queue_ms": .., "prompt_ms": .., "generation_ms": ..}）
+#[derive(Default, Clone, Copy, Serialize, Deserialize)]
+pub struct Timing {
+    #[serde(rename = "queue_ms", with = "millis")]
+    pub queue: Duration, // 順番待ちやモデルの読み込み
+    #[serde(rename = "prompt_ms", with = "millis")]
+    pub prompt: Duration, // プロンプトの処理（ここまでが最初のトークンが出るまで）
+    #[serde(rename = "generation_ms", with = "millis")]
+    pub generation: Duration, // 出力の生成
+}
+
+// 時間をミリ秒の数として読み書きする
+mod millis {
+    use std::time::Duration;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_millis() as u64)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        Ok(Duration::from_millis(u64::deserialize(deserializer)?))
+    }
+}
+
+impl Timing {
+    // Ollama の最後の行にある処理時間（ナノ秒）を読む
+    pub fn from_ollama(json: &serde_json::Value) -> Option<Timing> {
+        let nanos = |key: &str| Duration::from_nanos(json.get(key).and_then(|v| v.as_u64()).unwrap_or(0));
+        json.get("eval_duration")?;
+        Some(Timing {
+            queue: nanos("load_duration"),
+            prompt: nanos("prompt_eval_duration"),
+            generation: nanos("eval_duration"),
+        })
+    }
+
+    // llama.cpp サーバーの "timings" ブロック（ミリ秒）を読む
+    pub fn from_llama_cpp(timings: &serde_json::Value) -> Option<Timing> {
+        let millis = |key: &str| timings.get(key).and_then(|v| v.as_f64()).filter(|ms| *ms >= 0.0);
+        Some(Timing {
+            queue: Duration::ZERO,
+            prompt: Duration::from_secs_f64(millis("prompt_ms")? / 1000.0),
+            generation: Duration::from_secs_f64(millis("predicted_ms").unwrap_or(0.0) / 1000.0),
+        })
+    }
+
+    pub fn add(first: Option<Timing>, second: Option<Timing>) -> Option<Timing> {
+        match (first, second) {
+            (Some(a), Some(b)) => Some(Timing {
+                queue: a.queue + b.queue,
+                prompt: a.prompt + b.prompt,
+                generation: a.generation + b.generation,
+            }),
+            (a, b) => a.or(b),
+        }
+    }
+
+    // 全体の時間からプロバイダー側の時間を引いた残りを、接続などにかかった時間とみなして内訳を表示する
+    pub fn breakdown(&self, total: Duration) -> String {
+        let server = self.queue + self.prompt + self.generation;
+        format!(
+            "時間の内訳: 接続など {:.2}秒 / 待ち・読み込み {:.2}秒 / プロンプト処理 {:.2}秒（最初のトークンまで {:.2}秒） / 生成 {:.2}秒",
+            total.saturating_sub(server).as_secs_f64(),
+            self.queue.as_secs_f64(),
+            self.prompt.as_secs_f64(),
+            (total.saturating_sub(server) + self.queue + self.prompt).as_secs_f64(),
+            self.generation.as_secs_f64(),
+        )
+    }
+}
+
+// ストリーミングの途中で接続が切れたときの finish_reason
+pub const INTERRUPTED: &str = "interrupted";
+
+// 生成の途中で止めて、そこまでを答えとして残したときの finish_reason
+pub const STOPPED: &str = "stopped";
+
+// 推論結果（本文と、終了理由などのメタ情報）
+#[derive(Default, Clone)]
+#[non_exhaustive]
+pub struct Completion {
+    pub text: String,
+    pub finish_reason: Option<String>, // "stop" / "length" など（わからなければ None）
+    pub reasoning: Option<String>, // 推論モデルの考え中の部分（答えとは別に持ち、履歴には含めない）
+    pub usage: Option<Usage>,
+    pub timing: Option<Timing>,
+    pub citations: Vec<String>, // 検索つきのプロバイダーが返した出典（URLなど）
+    pub search_suggestions: Vec<String>, // 答えるために検索した言葉（Gemini の Google 検索。続けて調べるときの候補）
+    pub error: Option<String>, // 推論に失敗したときのエラー（text にも同じものが入る。続きの生成で失敗したときの text はそこまでの答え）
+    pub error_kind: Option<ErrorKind>, // 失敗したときのエラーの種類（リクエストの前の失敗など、わからなければ None）
+    pub tool_calls: Vec<serde_json::Value>, // モデルが呼び出したいツール（OpenAI の tool_calls の形）
+    pub thinking_blocks: Vec<serde_json::Value>, // Anthropic の考え中のブロック（thinking / redacted_thinking を署名ごと。次のリクエストでそのまま返す）
+    pub request: Option<PreparedRequest>, // この結果のために最後に送った（dry-run では組み立てた）リクエスト。/curl で使う
+}
+
+impl Completion {
+    // 推論に失敗したときの結果（対話ではそのまま表示し、ライブラリでは Err にして返す）
+    pub fn failed(message: String) -> Completion {
+        Completion { text: message.clone(), error: Some(message), ..Default::default() }
+    }
+
+    // 種類のわかるエラーで失敗したときの結果（"Ollama推論エラー: …" のように context を前に付ける）
+    pub fn from_error(context: &str, error: &Error) -> Completion {
+        Completion { error_kind: Some(error.kind()), ..Completion::failed(format!("{}: {}", context, error)) }
+    }
+
+    // 入力がコンテキスト長を超えたというエラーだったか（分割してやり直すときに使う）
+    pub fn is_context_overflow(&self) -> bool {
+        self.error_kind == Some(ErrorKind::ContextOverflow)
+    }
+
+    // 接続できずに失敗したか（キューに入れて送り直すときに使う）
+    pub fn is_offline(&self) -> bool {
+        self.error_kind.is_some_and(ErrorKind::is_offline)
+    }
+
+    // 失敗していれば Err にする（ライブラリの呼び出し側やサブコマンドに返すとき）
+    pub fn into_result(self) -> Result<Completion, Error> {
+        match self.error {
+            Some(message) => Err(Error::Inference { kind: self.error_kind.unwrap_or(ErrorKind::Other), message }),
+            None => Ok(self),
+        }
+    }
+
+    // 生成を途中で止めたときの結果（届いたところまでを答えにする）
+    pub fn stopped(text: String) -> Completion {
+        Completion { text, finish_reason: Some(STOPPED.to_string()), ..Default::default() }
+    }
+
+    // max_tokens に達して途中で切れたかどうか
+    pub fn is_truncated(&self) -> bool {
+        self.finish_reason.as_deref() == Some("length")
+    }
+
+    // ストリーミングの途中で接続が切れて、答えが途中までしかないか
+    pub fn is_interrupted(&self) -> bool {
+        self.finish_reason.as_deref() == Some(INTERRUPTED)
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.finish_reason.as_deref() == Some(STOPPED)
+    }
+
+    // 本文に埋め込まれた <think>…</think> を reasoning の方に移す
+    pub fn separate_reasoning(&mut self) {
+        let (thoughts, answer) = reasoning::split_think(&self.text);
+        if let Some(thoughts) = thoughts {
+            self.append_reasoning(thoughts);
+            self.text = answer;
+        }
+    }
+
+    // 考え中の部分を後ろに追加する
+    pub fn append_reasoning(&mut self, thoughts: String) {
+        self.reasoning = Some(match self.reasoning.take() {
+            Some(existing) => format!("{}\n{}", existing, thoughts),
+            None => thoughts,
+        });
+    }
+}
+
+impl From<String> for Completion {
+    fn from(text: String) -> Self {
+        Completion { text, ..Default::default() }
+    }
+}
+
+// OpenAI互換の choices[n] から本文を取り出す
+// 補完APIは "text"、チャットは "message.content"、チャットのストリーミングは "delta.content" に入っている
+pub fn choice_text(choice: &serde_json::Value) -> Option<&str> {
+    choice.get("text")
+        .or_else(|| choice.pointer("/message/content"))
+        .or_else(|| choice.pointer("/delta/content"))
+        .and_then(|text| text.as_str())
+}
+
+// ツールの呼び出し（message.tool_calls。ストリーミングのときは collect_sse でまとめたもの）
+pub fn choice_tool_calls(choice: &serde_json::Value) -> Vec<serde_json::Value> {
+    choice.pointer("/message/tool_calls").and_then(|calls| calls.as_array()).cloned().unwrap_or_default()
+}
+
+// DeepSeekなどは考え中の部分を reasoning_content（または reasoning）に入れて返してくる
+pub fn choice_reasoning(choice: &serde_json::Value) -> Option<&str> {
+    ["", "/message", "/delta"].iter()
+        .flat_map(|parent| ["reasoning_content", "reasoning"].map(|key| format!("{}/{}", parent, key)))
+        .find_map(|pointer| choice.pointer(&pointer).and_then(|r| r.as_str()))
+}
+
+// 続きの生成を継ぎ足す（前半の末尾と後半の先頭が重なっていたら、重なりを1回分にする）
+pub fn stitch(head: &str, tail: &str) -> String {
+    let max = MAX_OVERLAP_BYTES.min(head.len()).min(tail.len());
+    let overlap = (MIN_OVERLAP_BYTES..=max).rev()
+        .find(|&n| head.is_char_boundary(head.len() - n)
+            && tail.is_char_boundary(n)
+            && head[head.len() - n..] == tail[..n])
+        .unwrap_or(0);
+    format!("{}{}", head, &tail[overlap..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stitch_removes_the_repeated_overlap() {
+        assert_eq!(stitch("The quick brown fox jumps", "brown fox jumps over the dog"), "The quick brown fox jumps over the dog");
+        assert_eq!(stitch("あいうえおかきくけこ", "かきくけこさしすせそ"), "あいうえおかきくけこさしすせそ");
+    }
+
+    #[test]
+    fn stitch_ignores_short_or_missing_overlaps() {
+        // MIN_OVERLAP_BYTES より短い重なりは偶然かもしれないので、そのままつなぐ
+        assert_eq!(stitch("abc the", "the end"), "abc thethe end");
+        assert_eq!(stitch("first half.", " second half."), "first half. second half.");
+        assert_eq!(stitch("", "tail"), "tail");
+        assert_eq!(stitch("head", ""), "head");
+    }
+
+    #[test]
+    fn stitch_does_not_split_characters() {
+        // "🙂" の途中のバイトで重なりを探しても、文字の境目でなければ使わない
+        assert_eq!(stitch("終わりの🙂🙂", "🙂🙂の続き"), "終わりの🙂🙂の続き");
+    }
+
+    #[test]
+    fn from_error_keeps_the_kind() {
+        let error = Error::Api { status: 400, kind: ErrorKind::ContextOverflow, code: None, message: "too long".to_string() };
+        let completion = Completion::from_error("オンライン推論エラー", &error);
+        assert!(completion.is_context_overflow());
+        assert!(!completion.is_offline());
+        assert_eq!(completion.text, format!("オンライン推論エラー: {}", error));
+        assert_eq!(completion.error.as_deref(), Some(completion.text.as_str()));
+
+        let offline = Completion::from_error("Ollama推論エラー", &Error::Timeout(5));
+        assert!(offline.is_offline());
+        assert!(!offline.is_context_overflow());
+    }
+
+    #[test]
+    fn into_result_reports_the_kind() {
+        let failed = Completion::from_error("エラー", &Error::Timeout(5)).into_result();
+        assert!(matches!(failed, Err(Error::Inference { kind: ErrorKind::Timeout, .. })));
+        // 種類のわからない失敗は Other にする
+        let failed = Completion::failed("だめでした".to_string()).into_result();
+        assert!(matches!(failed, Err(Error::Inference { kind: ErrorKind::Other, .. })));
+        assert_eq!(Completion::from("答え".to_string()).into_result().unwrap().text, "答え");
+    }
+}
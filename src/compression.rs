@@ -0,0 +1,92 @@
+// 保存するファイル（セッションと middleware の cache）の圧縮
+//
+//   "compression": "zstd"
+//
+// zstd にすると、新しく作るファイルは名前の後ろに ".zst" を付けて、書くたびに zstd のフレームにして追記する。
+// zstd はつなげたフレームをまとめて1つとして読めるので、セッションのように追記していくファイルでもそのまま読み戻せる。
+// 読むときは中身の先頭（zstd のマジックナンバー）で見分けるので、圧縮する前に保存したファイルも、圧縮したファイルもそのまま読める。
+// zstd は native の機能のときだけ使える（wasm32 では圧縮も展開もできない）。
+use std::fs;
+use std::io;
+use std::path::Path;
+use crate::Config;
+
+pub const CHOICES: [&str; 2] = ["zstd", "none"];
+
+// 圧縮したファイルの名前の後ろに付けるもの
+pub const EXTENSION: &str = ".zst";
+
+// zstd のフレームの先頭の4バイト
+const MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+// 速さを優先する（履歴は追記のたびに圧縮するため）。zstd のデフォルトと同じ
+#[cfg(feature = "native")]
+const LEVEL: i32 = 3;
+
+// 新しく作るファイルを圧縮するかどうか
+pub fn is_enabled(config: &Config) -> bool {
+    config.compression.as_deref() == Some("zstd")
+}
+
+// 1つの zstd のフレームにする
+#[cfg(feature = "native")]
+pub fn compress(data: &[u8]) -> io::Result<Vec<u8>> {
+    zstd::encode_all(data, LEVEL)
+}
+
+#[cfg(not(feature = "native"))]
+pub fn compress(_data: &[u8]) -> io::Result<Vec<u8>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "この環境では zstd で圧縮できません"))
+}
+
+// zstd のフレームなら展開する（つながった複数のフレームもまとめて展開する）。そうでなければそのまま返す
+pub fn decompress(data: Vec<u8>) -> io::Result<Vec<u8>> {
+    if !data.starts_with(&MAGIC) {
+        return Ok(data);
+    }
+    decode(&data)
+}
+
+#[cfg(feature = "native")]
+fn decode(data: &[u8]) -> io::Result<Vec<u8>> {
+    zstd::decode_all(data)
+}
+
+#[cfg(not(feature = "native"))]
+fn decode(_data: &[u8]) -> io::Result<Vec<u8>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "この環境では zstd で圧縮したファイルを読めません"))
+}
+
+// ファイルを読んで、圧縮してあれば展開して文字列にする
+pub fn read_to_string(path: &Path) -> io::Result<String> {
+    let data = decompress(fs::read(path)?)?;
+    String::from_utf8(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// 圧縮したファイル（名前が ".zst" で終わる）なら、中身を1つのフレームにして書く
+pub fn encode_for(path: &Path, data: &[u8]) -> io::Result<Vec<u8>> {
+    match path.to_string_lossy().ends_with(EXTENSION) {
+        true => compress(data),
+        false => Ok(data.to_vec()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn appended_frames_decompress_together() {
+        let mut data = encode_for(Path::new("s.jsonl.zst"), b"{\"a\":1}\n").unwrap();
+        data.extend(compress(b"{\"b\":2}\n").unwrap());
+        assert!(data.starts_with(&MAGIC));
+        assert_eq!(decompress(data).unwrap(), b"{\"a\":1}\n{\"b\":2}\n");
+    }
+
+    #[test]
+    fn plain_text_is_returned_as_it_is() {
+        assert_eq!(decompress(b"{\"role\":\"user\"}\n".to_vec()).unwrap(), b"{\"role\":\"user\"}\n");
+        assert_eq!(encode_for(Path::new("s.jsonl"), b"x").unwrap(), b"x");
+    }
+}
@@ -0,0 +1,382 @@
+// 設定ファイルの読み込み（JSON / TOML / YAML、環境変数の展開、内容のチェック）
+//
+// 拡張子が .toml なら TOML、.yaml / .yml なら YAML、それ以外は JSON として読む。
+// TOML は toml、YAML は serde_yaml で読んで、JSON と同じ値にする（TOML の日時は文字列になる）。
+// 文字列の中の ${VAR}（${VAR:-デフォルト}）は環境変数の値にする（$${ と書けば ${ のまま残る）。
+// "api_key_env": "OPENAI_API_KEY" と書けば、api_key をその環境変数から読む（プロファイルにも書ける）。
+// finetune follow --register は、読み込んだファイルの "profiles" にプロファイルを書き足す（環境変数は展開せずに書き戻す）。
+// --config で場所を指定しなければ、カレントディレクトリ、$XDG_CONFIG_HOME/milti_llm_client
+// （XDG_CONFIG_HOME がなければ ~/.config/milti_llm_client）の順に config.json / .toml / .yaml / .yml を探す。
+use std::path::{Path, PathBuf};
+use serde_json::{Map, Value};
+use crate::{compression, context_budget, keybindings, middleware, profiles, providers, reply_language, select_model, Config, LOCAL_FRAMEWORKS, REASONING_EFFORTS};
+
+const CONFIG_NAMES: [&str; 4] = ["config.json", "config.toml", "config.yaml", "config.yml"];
+
+const APP_DIR: &str = "milti_llm_client";
+
+// 設定ファイルを探す場所（見つからなければカレントディレクトリの config.json。起動時に作る）
+pub fn default_path() -> String {
+    let mut dirs = vec![PathBuf::from(".")];
+    match std::env::var("XDG_CONFIG_HOME").ok().filter(|dir| !dir.is_empty()) {
+        Some(dir) => dirs.push(Path::new(&dir).join(APP_DIR)),
+        None => dirs.extend(std::env::var("HOME").ok().map(|home| Path::new(&home).join(".config").join(APP_DIR))),
+    }
+    dirs.iter()
+        .flat_map(|dir| CONFIG_NAMES.iter().map(move |name| dir.join(name)))
+        .find(|path| path.exists())
+        .map(|path| path.strip_prefix(".").map(Path::to_path_buf).unwrap_or(path).to_string_lossy().to_string())
+        .unwrap_or_else(|| CONFIG_NAMES[0].to_string())
+}
+
+// 設定ファイルの "profiles" に、model_name で答える name のプロファイルを書き足す（同じ名前があれば書かない）
+// based_on のプロファイルがあれば、その行き先などを写してモデルだけを変える
+// 書き戻すのは読み込んだままの値なので、${VAR} や api_key_env はそのまま残る（コメントとキーの順番は残らない）
+pub fn add_profile(path: &str, name: &str, based_on: Option<&str>, model_name: &str) -> Result<(), String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("設定ファイル {} の読み込みに失敗しました: {:?}", path, e))?;
+    let mut value = parse(path, &text)?;
+    let root = value.as_object_mut().ok_or_else(|| format!("{} の最上位がオブジェクトではありません", path))?;
+    let profiles = root.entry("profiles").or_insert_with(|| Value::Object(Map::new()))
+        .as_object_mut()
+        .ok_or_else(|| format!("{} の \"profiles\" がオブジェクトではありません", path))?;
+    if profiles.contains_key(name) {
+        return Err(format!("プロファイル {} はもう {} にあります", name, path));
+    }
+    let mut profile = based_on.and_then(|base| profiles.get(base)).and_then(Value::as_object).cloned().unwrap_or_default();
+    profile.insert("model_name".to_string(), Value::String(model_name.to_string()));
+    profiles.insert(name.to_string(), Value::Object(profile));
+    std::fs::write(path, to_text(path, &value)?).map_err(|e| format!("{} に書き込めません: {:?}", path, e))
+}
+
+// 拡張子に合わせて書く
+fn to_text(path: &str, value: &Value) -> Result<String, String> {
+    let extension = Path::new(path).extension().and_then(|ext| ext.to_str()).unwrap_or_default().to_lowercase();
+    match extension.as_str() {
+        "toml" => toml::to_string(value).map_err(|e| format!("{} をTOMLにできません: {}", path, e)),
+        "yaml" | "yml" => serde_yaml::to_string(value).map_err(|e| format!("{} をYAMLにできません: {}", path, e)),
+        _ => serde_json::to_string_pretty(value).map(|text| text + "\n").map_err(|e| format!("{} をJSONにできません: {}", path, e)),
+    }
+}
+
+// 拡張子に合わせて読む
+pub fn parse(path: &str, text: &str) -> Result<Value, String> {
+    let extension = Path::new(path).extension().and_then(|ext| ext.to_str()).unwrap_or_default().to_lowercase();
+    match extension.as_str() {
+        "toml" => toml::from_str(text).map(from_toml).map_err(|e| format!("{} のTOMLのパースに失敗しました: {}", path, e)),
+        "yaml" | "yml" => serde_yaml::from_str(text).map_err(|e| format!("{} のYAMLのパースに失敗しました: {}", path, e)),
+        _ => serde_json::from_str(text).map_err(|e| format!("{} のJSONのパースに失敗しました: {}", path, e)),
+    }
+}
+
+fn from_toml(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(text) => Value::String(text),
+        toml::Value::Integer(number) => Value::from(number),
+        toml::Value::Float(number) => Value::from(number),
+        toml::Value::Boolean(flag) => Value::Bool(flag),
+        toml::Value::Datetime(datetime) => Value::String(datetime.to_string()),
+        toml::Value::Array(items) => Value::Array(items.into_iter().map(from_toml).collect()),
+        toml::Value::Table(table) => Value::Object(table.into_iter().map(|(key, value)| (key, from_toml(value))).collect()),
+    }
+}
+
+// 環境変数を展開して、api_key_env を api_key にする
+pub fn expand(value: &mut Value) -> Result<(), String> {
+    expand_env(value, "")?;
+    let Some(root) = value.as_object_mut() else {
+        return Ok(());
+    };
+    resolve_api_key_env(root, "")?;
+    if let Some(profiles) = root.get_mut("profiles").and_then(|profiles| profiles.as_object_mut()) {
+        for (name, profile) in profiles.iter_mut() {
+            if let Some(profile) = profile.as_object_mut() {
+                resolve_api_key_env(profile, &format!("profiles.{}.", name))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn expand_env(value: &mut Value, key: &str) -> Result<(), String> {
+    match value {
+        Value::String(text) => *text = expand_string(text, key)?,
+        Value::Array(items) => {
+            for (i, item) in items.iter_mut().enumerate() {
+                expand_env(item, &format!("{}[{}]", key, i))?;
+            }
+        }
+        Value::Object(object) => {
+            for (name, item) in object.iter_mut() {
+                expand_env(item, &if key.is_empty() { name.clone() } else { format!("{}.{}", key, name) })?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn expand_string(text: &str, key: &str) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            expanded.push_str(&rest[..start - 1]);
+            expanded.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        expanded.push_str(&rest[..start]);
+        let end = rest[start..].find('}').map(|end| start + end)
+            .ok_or_else(|| format!("{} の ${{ が }} で閉じられていません", key))?;
+        let (name, default) = match rest[start + 2..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&rest[start + 2..end], None),
+        };
+        let value = std::env::var(name).ok().filter(|value| !value.is_empty()).or(default.map(str::to_string))
+            .ok_or_else(|| format!("{} に書いた環境変数 {} が設定されていません", key, name))?;
+        expanded.push_str(&value);
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+fn resolve_api_key_env(object: &mut Map<String, Value>, prefix: &str) -> Result<(), String> {
+    let Some(name) = object.remove("api_key_env") else {
+        return Ok(());
+    };
+    let name = name.as_str().ok_or_else(|| format!("{}api_key_env には環境変数の名前を書いてください", prefix))?.to_string();
+    if object.get("api_key").is_some_and(|key| !key.is_null()) {
+        return Ok(());
+    }
+    let key = std::env::var(&name).ok().filter(|key| !key.is_empty())
+        .ok_or_else(|| format!("{}api_key_env の環境変数 {} が設定されていません", prefix, name))?;
+    object.insert("api_key".to_string(), Value::String(key));
+    Ok(())
+}
+
+// 設定の内容をチェックする（問題をすべて並べて返す）
+pub fn validate(config: &Config) -> Result<(), String> {
+    let mut problems = Vec::new();
+    check_range(&mut problems, "temperature", config.temperature, 0.0, 2.0);
+    check_range(&mut problems, "top_p", config.top_p, 0.0, 1.0);
+    check_choice(&mut problems, "reasoning_effort", config.reasoning_effort.as_deref(), &REASONING_EFFORTS);
+    check_choice(&mut problems, "reasoning_display", config.reasoning_display.as_deref(), &["show", "dim", "fold", "hide"]);
+    check_choice(&mut problems, "chunk_strategy", config.chunk_strategy.as_deref(), &["summarize", "concatenate"]);
+    check_choice(&mut problems, "cassette_mode", config.cassette_mode.as_deref(), &["record", "replay"]);
+    check_choice(&mut problems, "reply_language", config.reply_language.as_deref(), &reply_language::languages());
+    check_choice(&mut problems, "compression", config.compression.as_deref(), &compression::CHOICES);
+    check_choice(&mut problems, "image_display", config.image_display.as_deref(), &["auto", "kitty", "iterm", "save"]);
+    if let Some(profile) = config.profile.as_ref().filter(|profile| !config.profiles.contains_key(*profile)) {
+        problems.push(format!("profile のプロファイル {} が profiles にありません", profile));
+    }
+    check_values(&mut problems, "", config.provider.as_deref(), config.local_framework.as_deref(), config.endpoint.as_deref());
+    check_middleware(&mut problems, "", &config.middleware);
+    problems.extend(providers::check_safety_settings("", &config.safety_settings));
+    check_context_budget(&mut problems, config);
+    problems.extend(keybindings::check(config));
+    if config.keep_alive_interval_secs == Some(0) {
+        problems.push("keep_alive_interval_secs には1以上の秒数を指定してください".to_string());
+    }
+
+    // 推論の行き先が足りているかは、プロファイルがなければ最上位の設定で、あればプロファイルごとに調べる
+    if config.profiles.is_empty() {
+        check_target(&mut problems, "", config);
+    }
+    let mut names: Vec<&String> = config.profiles.keys().collect();
+    names.sort();
+    for name in names {
+        let profile = &config.profiles[name];
+        let prefix = format!("profiles.{}: ", name);
+        check_values(&mut problems, &prefix, profile.provider.as_deref(), profile.local_framework.as_deref(), profile.endpoint.as_deref());
+        check_middleware(&mut problems, &prefix, profile.middleware.as_deref().unwrap_or_default());
+        problems.extend(providers::check_safety_settings(&prefix, &profile.safety_settings.clone().unwrap_or_default()));
+        check_target(&mut problems, &prefix, &merged(config, profile));
+    }
+
+    if problems.is_empty() {
+        return Ok(());
+    }
+    Err(format!("設定に問題があります:\n{}", problems.iter().map(|problem| format!("  - {}", problem)).collect::<Vec<_>>().join("\n")))
+}
+
+fn check_range(problems: &mut Vec<String>, key: &str, value: Option<f64>, min: f64, max: f64) {
+    if let Some(value) = value.filter(|value| !(min..=max).contains(value)) {
+        problems.push(format!("{} は {} から {} の間にしてください（{}）", key, min, max, value));
+    }
+}
+
+fn check_choice(problems: &mut Vec<String>, key: &str, value: Option<&str>, choices: &[&str]) {
+    if let Some(value) = value.filter(|value| !choices.contains(value)) {
+        problems.push(format!("{} は {} のどれかにしてください（{}）", key, choices.join(" / "), value));
+    }
+}
+
+// プロファイルに切り替えたときの設定（profiles::apply と同じく、書いていない項目は最上位の値を使う）
+fn merged(config: &Config, profile: &profiles::Profile) -> Config {
+    let mut merged = config.clone();
+    merged.endpoint = profile.endpoint.clone().or(merged.endpoint);
+    merged.provider = profile.provider.clone().or(merged.provider);
+    merged.use_local_model = profile.use_local_model.unwrap_or(merged.use_local_model);
+    merged.local_framework = profile.local_framework.clone().or(merged.local_framework);
+    merged.openai_compatible = profile.openai_compatible.unwrap_or(merged.openai_compatible);
+    merged.model_name = profile.model_name.clone().unwrap_or(merged.model_name);
+    merged
+}
+
+// provider / local_framework / endpoint に書いた値
+fn check_values(problems: &mut Vec<String>, prefix: &str, provider: Option<&str>, framework: Option<&str>, endpoint: Option<&str>) {
+    check_choice(problems, &format!("{}provider", prefix), provider, &providers::PROVIDERS);
+    check_choice(problems, &format!("{}local_framework", prefix), framework, &LOCAL_FRAMEWORKS);
+    if let Some(endpoint) = endpoint.filter(|endpoint| !endpoint.starts_with("http://") && !endpoint.starts_with("https://")) {
+        problems.push(format!("{}endpoint は http:// か https:// で始まるURLにしてください（{}）", prefix, endpoint));
+    }
+}
+
+fn check_middleware(problems: &mut Vec<String>, prefix: &str, layers: &[String]) {
+    for layer in layers {
+        check_choice(problems, &format!("{}middleware", prefix), Some(layer), &middleware::LAYERS);
+    }
+}
+
+fn check_context_budget(problems: &mut Vec<String>, config: &Config) {
+    let Some(ratios) = &config.context_budget else {
+        return;
+    };
+    let mut parts: Vec<&String> = ratios.keys().collect();
+    parts.sort();
+    for part in parts {
+        check_choice(problems, "context_budget", Some(part), &context_budget::PARTS);
+        check_range(problems, &format!("context_budget.{}", part), Some(ratios[part]), 0.0, 1.0);
+    }
+    if config.context_window.is_none() {
+        problems.push("context_budget を使うには context_window を指定してください".to_string());
+    }
+}
+
+// 推論の行き先（ローカルのフレームワークか、プロバイダーか endpoint）が足りているか
+fn check_target(problems: &mut Vec<String>, prefix: &str, config: &Config) {
+    // "モデル名@行き先" や別名の行き先も反映してから調べる
+    let mut config = config.clone();
+    let model_name = config.model_name.clone();
+    select_model(&mut config, &model_name);
+    if config.use_local_model && config.local_framework.is_none() {
+        problems.push(format!("{}ローカルで使うには local_framework（{}）を指定してください", prefix, LOCAL_FRAMEWORKS.join(" / ")));
+    }
+    if !config.use_local_model && config.endpoint.is_none() && config.provider.is_none() && config.assistant_id.is_none() {
+        problems.push(format!("{}オンラインで使うには endpoint か provider を指定してください（ローカルなら \"use_local_model\": true）", prefix));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn toml_and_yaml_read_as_the_same_values() {
+        let cases = [
+            (
+                "表とドットつきのキー",
+                "model_name = \"gpt-4o\"\n[profiles.local]\nmodel_name = \"gemma:2b@ollama\"\nsampling.temperature = 0.2\n",
+                "model_name: gpt-4o\nprofiles:\n  local:\n    model_name: gemma:2b@ollama\n    sampling:\n      temperature: 0.2\n",
+                json!({ "model_name": "gpt-4o", "profiles": { "local": { "model_name": "gemma:2b@ollama", "sampling": { "temperature": 0.2 } } } }),
+            ),
+            (
+                "配列と表の配列",
+                "stop = [\"\\n\\n\", \"END\"]\n[[tools]]\nname = \"shell\"\n[[tools]]\nname = \"http_get\"\nenabled = false\n",
+                "stop: [\"\\n\\n\", END]\ntools:\n  - name: shell\n  - name: http_get\n    enabled: false\n",
+                json!({ "stop": ["\n\n", "END"], "tools": [{ "name": "shell" }, { "name": "http_get", "enabled": false }] }),
+            ),
+            (
+                "複数行の文字列",
+                "system_prompt = \"\"\"\nあなたは親切なアシスタントです。\n短く答えてください。\n\"\"\"\n",
+                "system_prompt: |\n  あなたは親切なアシスタントです。\n  短く答えてください。\n",
+                json!({ "system_prompt": "あなたは親切なアシスタントです。\n短く答えてください。\n" }),
+            ),
+            (
+                "折りたたむ複数行とコメント",
+                "# コメント\ngreeting = 'こんにちは # コメントではない' # ここはコメント\n",
+                "# コメント\ngreeting: >-\n  こんにちは\n  # コメントではない\n",
+                json!({ "greeting": "こんにちは # コメントではない" }),
+            ),
+        ];
+        for (name, toml_text, yaml_text, expected) in cases {
+            assert_eq!(parse("config.toml", toml_text).unwrap(), expected, "TOML: {}", name);
+            assert_eq!(parse("config.yaml", yaml_text).unwrap(), expected, "YAML: {}", name);
+        }
+    }
+
+    #[test]
+    fn broken_files_name_the_path() {
+        for (path, text) in [("a.toml", "model_name = "), ("a.yaml", "model_name: [gpt"), ("a.json", "{")] {
+            let error = parse(path, text).unwrap_err();
+            assert!(error.starts_with(path), "{}", error);
+        }
+        // TOML の日時は文字列として読む
+        assert_eq!(parse("a.toml", "since = 2026-10-14T09:00:00Z").unwrap(), json!({ "since": "2026-10-14T09:00:00Z" }));
+    }
+
+    #[test]
+    fn expands_environment_variables() {
+        std::env::set_var("MILTI_LLM_CLIENT_TEST_HOST", "example.test");
+        std::env::set_var("MILTI_LLM_CLIENT_TEST_KEY", "sk-test");
+        let cases = [
+            (json!({ "endpoint": "https://${MILTI_LLM_CLIENT_TEST_HOST}/v1" }), json!({ "endpoint": "https://example.test/v1" })),
+            (json!({ "endpoint": "${MILTI_LLM_CLIENT_TEST_UNSET:-http://localhost}" }), json!({ "endpoint": "http://localhost" })),
+            (json!({ "stop": ["$${HOME}"] }), json!({ "stop": ["${HOME}"] })),
+            (
+                json!({ "profiles": { "gpt": { "api_key_env": "MILTI_LLM_CLIENT_TEST_KEY" } } }),
+                json!({ "profiles": { "gpt": { "api_key": "sk-test" } } }),
+            ),
+            // api_key が書いてあれば、そちらを使う
+            (json!({ "api_key": "sk-file", "api_key_env": "MILTI_LLM_CLIENT_TEST_KEY" }), json!({ "api_key": "sk-file" })),
+        ];
+        for (mut value, expected) in cases {
+            expand(&mut value).unwrap();
+            assert_eq!(value, expected);
+        }
+    }
+
+    #[test]
+    fn expansion_errors_name_the_key() {
+        let cases = [
+            (json!({ "endpoint": "${MILTI_LLM_CLIENT_TEST_UNSET}" }), "endpoint に書いた環境変数 MILTI_LLM_CLIENT_TEST_UNSET が設定されていません"),
+            (json!({ "stop": ["a", "${OPEN"] }), "stop[1] の ${ が } で閉じられていません"),
+            (
+                json!({ "profiles": { "gpt": { "api_key_env": "MILTI_LLM_CLIENT_TEST_UNSET" } } }),
+                "profiles.gpt.api_key_env の環境変数 MILTI_LLM_CLIENT_TEST_UNSET が設定されていません",
+            ),
+            (json!({ "api_key_env": 1 }), "api_key_env には環境変数の名前を書いてください"),
+        ];
+        for (mut value, expected) in cases {
+            assert_eq!(expand(&mut value).unwrap_err(), expected);
+        }
+    }
+
+    #[test]
+    fn registered_profiles_are_written_back_as_they_were_read() {
+        for extension in ["json", "yaml"] {
+            let path = std::env::temp_dir().join(format!("milti_llm_client-register-{}.{}", std::process::id(), extension));
+            let path = path.to_string_lossy().to_string();
+            let original = json!({
+                "model_name": "gpt-4o-mini", "api_key": "${OPENAI_API_KEY}",
+                "profiles": { "work": { "endpoint": "https://work.example/v1/chat/completions", "api_key_env": "WORK_KEY" } },
+            });
+            std::fs::write(&path, to_text(&path, &original).unwrap()).unwrap();
+
+            add_profile(&path, "tuned", Some("work"), "ft:gpt-4o-mini:org::abc").unwrap();
+            let written = parse(&path, &std::fs::read_to_string(&path).unwrap()).unwrap();
+            // 環境変数は展開せず、もとのプロファイルの行き先を写してモデルだけを変える
+            assert_eq!(written["api_key"], "${OPENAI_API_KEY}");
+            assert_eq!(written["profiles"]["tuned"], json!({
+                "endpoint": "https://work.example/v1/chat/completions", "api_key_env": "WORK_KEY", "model_name": "ft:gpt-4o-mini:org::abc",
+            }));
+            assert_eq!(written["profiles"]["work"], original["profiles"]["work"]);
+
+            // 同じ名前は上書きしない
+            assert!(add_profile(&path, "work", None, "ft:other").unwrap_err().starts_with("プロファイル work はもう"));
+            let _ = std::fs::remove_file(&path);
+        }
+    }
+}
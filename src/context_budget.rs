@@ -0,0 +1,148 @@
+// コンテキストウィンドウの配分（RAG のように、覚えておくことや検索したチャンクを一緒に送るとき）
+//
+//   "context_window": 8192,
+//   "memories": ["ユーザーは Rust のエンジニア"],
+//   "context_budget": { "system": 0.1, "memories": 0.1, "retrieved": 0.4, "history": 0.25, "reply": 0.15 }
+//
+// 今回の入力を除いた残りを、書いた割合で system メッセージ・覚えておくこと（memories）・
+// 検索したチャンク（Client::set_retrieved）・履歴・返事のために空けておく分に分ける。
+// 割合の分を使い切らなかったところの残りは、足りないところに割合に応じて回す。
+// 収まらない分は、system は後ろを、memories と検索したチャンクは後ろの項目（関連の低いもの）を、履歴は古いものを削る。
+// 返事の分が max_tokens より少なくなったときは、max_tokens をその分まで下げる。
+// 書いていない割合はデフォルト（上の例の値）のまま。"context_budget" がなければ配分せず、memories とチャンクはそのまま送る。
+use std::collections::HashMap;
+use crate::{chunking, Config};
+
+pub const PARTS: [&str; 5] = ["system", "memories", "retrieved", "history", "reply"];
+
+const DEFAULT_RATIOS: [f64; 5] = [0.1, 0.1, 0.4, 0.25, 0.15];
+
+// 返事の分の見積もり（max_tokens を書いていないときは、リクエストのデフォルトと同じ）
+const DEFAULT_REPLY_TOKENS: u32 = 64;
+
+// 削ったチャンクの残りがこれより少なければ、途中まで入れずに落とす
+const MIN_PARTIAL_TOKENS: u32 = 32;
+
+// memories と検索したチャンクを system と今回の入力に入れ、配分に収まるように削った設定を返す
+// （どちらもなく、配分も書いていなければ None）
+pub fn assemble(prompt: &str, config: &Config) -> Option<(String, Config)> {
+    if config.memories.is_empty() && config.retrieved.is_empty() && config.context_budget.is_none() {
+        return None;
+    }
+    let mut config = config.clone();
+    let mut memories = config.memories.clone();
+    let mut retrieved = std::mem::take(&mut config.retrieved);
+
+    if let (Some(ratios), Some(context_window)) = (&config.context_budget, config.context_window) {
+        let total = context_window
+            .saturating_sub(chunking::PROMPT_OVERHEAD_TOKENS)
+            .saturating_sub(chunking::estimate_tokens(prompt));
+        let history_tokens = if config.chat { config.history.iter().map(|m| chunking::estimate_tokens(&m.content)).sum() } else { 0 };
+        let needs = [
+            config.system_prompt.as_deref().map(chunking::estimate_tokens).unwrap_or(0),
+            memories.iter().map(|memory| chunking::estimate_tokens(memory)).sum(),
+            retrieved.iter().map(|chunk| chunking::estimate_tokens(chunk)).sum(),
+            history_tokens,
+            config.max_tokens.unwrap_or(DEFAULT_REPLY_TOKENS),
+        ];
+        let [system, memory, chunks, history, reply] = allocate(total, &ratios_of(ratios), needs);
+
+        let mut trimmed = Vec::new();
+        if let Some(text) = config.system_prompt.as_ref().filter(|_| needs[0] > system) {
+            config.system_prompt = chunking::split_into_chunks(text, system).into_iter().next().filter(|_| system > 0);
+            trimmed.push("system メッセージ");
+        }
+        if needs[1] > memory {
+            fit(&mut memories, memory);
+            trimmed.push("覚えておくこと");
+        }
+        if needs[2] > chunks {
+            fit(&mut retrieved, chunks);
+            trimmed.push("検索したチャンク");
+        }
+        if needs[3] > history {
+            config.history_max_tokens = Some(config.history_max_tokens.map_or(history, |max| max.min(history)));
+            trimmed.push("履歴");
+        }
+        if needs[4] > reply {
+            config.max_tokens = Some(reply.max(1));
+            trimmed.push("返事の長さ");
+        }
+        if !trimmed.is_empty() && !config.quiet {
+            eprintln!("コンテキストの配分に収まらないため、{}を削りました", trimmed.join("・"));
+        }
+    }
+
+    if !memories.is_empty() && config.chat {
+        let notes: Vec<String> = memories.iter().map(|memory| format!("- {}", memory)).collect();
+        let section = format!("覚えておくこと:\n{}", notes.join("\n"));
+        config.system_prompt = Some(match &config.system_prompt {
+            Some(system) => format!("{}\n\n{}", system, section),
+            None => section,
+        });
+    }
+    let prompt = match retrieved.is_empty() {
+        true => prompt.to_string(),
+        false => {
+            let chunks: Vec<String> = retrieved.iter().enumerate()
+                .map(|(i, chunk)| format!("--- 参考資料 {} ---\n{}", i + 1, chunk.trim_end()))
+                .collect();
+            format!("{}\n--- ここまで ---\n\n{}", chunks.join("\n\n"), prompt)
+        }
+    };
+    Some((prompt, config))
+}
+
+// 書いた割合（書いていないものはデフォルト）
+fn ratios_of(ratios: &HashMap<String, f64>) -> [f64; 5] {
+    let mut result = DEFAULT_RATIOS;
+    for (i, part) in PARTS.iter().enumerate() {
+        if let Some(ratio) = ratios.get(*part) {
+            result[i] = ratio.max(0.0);
+        }
+    }
+    result
+}
+
+// total を割合で分ける。必要な分が割合の分より少ないところは必要な分だけにして、残りを他で分け直す
+fn allocate(total: u32, ratios: &[f64; 5], needs: [u32; 5]) -> [u32; 5] {
+    let mut allocation = [0u32; 5];
+    let mut open: Vec<usize> = (0..PARTS.len()).filter(|&i| needs[i] > 0 && ratios[i] > 0.0).collect();
+    let mut remaining = total;
+    while !open.is_empty() {
+        let weight: f64 = open.iter().map(|&i| ratios[i]).sum();
+        let share = |i: usize| (remaining as f64 * ratios[i] / weight) as u32;
+        let (satisfied, short): (Vec<usize>, Vec<usize>) = open.iter().partition(|&&i| needs[i] <= share(i));
+        if satisfied.is_empty() {
+            for &i in &short {
+                allocation[i] = share(i);
+            }
+            break;
+        }
+        for &i in &satisfied {
+            allocation[i] = needs[i];
+            remaining -= needs[i];
+        }
+        open = short;
+    }
+    allocation
+}
+
+// 前から順に、budget に収まるところまで残す（最後の1つは、ある程度入るなら途中まで入れる）
+fn fit(items: &mut Vec<String>, budget: u32) {
+    let mut used = 0;
+    let mut kept = Vec::new();
+    for item in items.drain(..) {
+        let tokens = chunking::estimate_tokens(&item);
+        if used + tokens <= budget {
+            used += tokens;
+            kept.push(item);
+            continue;
+        }
+        if budget - used >= MIN_PARTIAL_TOKENS {
+            kept.extend(chunking::split_into_chunks(&item, budget - used).into_iter().next());
+        }
+        break;
+    }
+    *items = kept;
+}
@@ -0,0 +1,235 @@
+// 会話の履歴（system / user / assistant のメッセージの並び）を、チャット形式のAPIに送るための管理
+//
+// 履歴はいつも残しておき（/export で書き出せる）、送るのは "chat": true のときだけ。
+// 補完APIにはこれまでどおりプロンプトだけを送る。
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::Duration;
+use web_time::SystemTime;
+use crate::Config;
+use crate::chunking;
+use crate::completion::{Completion, Timing};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Message {
+    pub role: String, // "system" / "user" / "assistant"
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>, // 返事をしたモデル（セッションの保存と書き出し用。APIには送らない）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>, // 送った・受け取った時刻（UNIX時間の秒。APIには送らない）
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<AttachedFile>, // 添付したファイル（テキストは content に埋め込み済みなので、APIには画像だけを送る）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>, // new --template で始めた会話の最初のメッセージに、そのテンプレート名を残す（集計用。APIには送らない）
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub thinking: Vec<Value>, // 返事の前の考え中のブロック（Anthropic の署名つきのもの。同じモデルに送るときだけ返す）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>, // 返事にかかった全体の時間（history stats 用。APIには送らない）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timing: Option<Timing>, // プロバイダーが返した処理時間の内訳（返さなければ None。APIには送らない）
+}
+
+// メッセージに添付したファイル（セッションに保存しておき、再開したときに画像を読み直す）
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AttachedFile {
+    pub kind: String, // "image" / "text"（PDFや音声から起こしたもの、OCRした画像も "text"） / "file"（アップロードしたファイル。path がファイルID）
+    pub name: String,
+    pub path: String, // 添付したときのファイルのパス
+    pub hash: String, // 中身のハッシュ（画像はセッションのディレクトリの attachments/<hash> に写しを置く）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub excerpt: Option<String>, // テキストの先頭（書き出しで見せる）
+    #[serde(skip)]
+    pub data: Option<String>, // 画像の base64（送るときに使う。セッションのファイルには書かない）
+}
+
+impl Message {
+    pub fn new(role: &str, content: &str) -> Message {
+        Message { role: role.to_string(), content: content.to_string(), model: None, timestamp: None, attachments: Vec::new(), template: None, thinking: Vec::new(), latency_ms: None, timing: None }
+    }
+
+    // このメッセージと一緒に送る画像（base64。読み直せなかったものは含まない）
+    pub fn images(&self) -> Vec<String> {
+        self.attachments.iter().filter(|file| file.kind == "image").filter_map(|file| file.data.clone()).collect()
+    }
+
+    // このメッセージと一緒に送る、アップロードしたファイルのID
+    pub fn file_ids(&self) -> Vec<String> {
+        self.attachments.iter().filter(|file| file.kind == "file").map(|file| file.path.clone()).collect()
+    }
+}
+
+// 1回のやりとりを、履歴とセッションに残す user / assistant の組にする
+// 失敗した返事は会話の一部ではないので残さない（None を返す）
+pub fn turn(
+    prompt: &str,
+    completion: &Completion,
+    model: &str,
+    started_at: SystemTime,
+    elapsed: Duration,
+    attachments: Vec<AttachedFile>,
+) -> Option<[Message; 2]> {
+    if completion.error.is_some() {
+        return None;
+    }
+    let seconds = |time: SystemTime| time.duration_since(web_time::UNIX_EPOCH).map(|d| d.as_secs()).ok();
+    Some([
+        Message {
+            timestamp: seconds(started_at),
+            attachments,
+            ..Message::new("user", prompt)
+        },
+        Message {
+            model: Some(model.to_string()),
+            timestamp: seconds(started_at + elapsed),
+            thinking: completion.thinking_blocks.clone(),
+            latency_ms: Some(elapsed.as_millis() as u64),
+            timing: completion.timing,
+            ..Message::new("assistant", &completion.text)
+        },
+    ])
+}
+
+// チャット形式のURL（補完APIのURLが書かれていたら、チャットのURLに読み替える）
+pub fn chat_endpoint(endpoint: &str) -> String {
+    endpoint
+        .replace("/v1/completions", "/v1/chat/completions")
+        .replace("/api/generate", "/api/chat")
+}
+
+// 送る履歴（設定に従って古いものから削ったもの。チャット形式でなければ空）
+pub fn sent_history(config: &Config) -> &[Message] {
+    let mut history: &[Message] = if config.chat { &config.history } else { &[] };
+    if let Some(max) = config.history_max_messages {
+        history = &history[history.len().saturating_sub(max)..];
+    }
+    if let Some(max_tokens) = config.history_max_tokens {
+        let mut used = 0;
+        let keep = history.iter().rev()
+            .take_while(|message| {
+                used += chunking::estimate_tokens(&message.content);
+                used <= max_tokens
+            })
+            .count();
+        history = &history[history.len() - keep..];
+    }
+    // 最初に残すのが assistant の返事だと話がつながらないので、user から始める
+    while history.first().is_some_and(|message| message.role != "user") {
+        history = &history[1..];
+    }
+    history
+}
+
+// 送るメッセージの並び（system、履歴、今回の入力）
+pub fn messages(prompt: &str, config: &Config) -> Vec<Message> {
+    let history = sent_history(config);
+    // 履歴の画像とファイルIDは、今のモデルが扱えるときだけ送る（今回の分は config.images / config.file_ids から各形式で付ける）
+    let send_images = crate::supports_images(config);
+    let send_files = crate::providers::accepts_file_ids(config);
+    let mut messages = Vec::new();
+    if let Some(system) = &config.system_prompt {
+        messages.push(Message::new("system", system));
+    }
+    messages.extend(history.iter().map(|message| Message {
+        attachments: message.attachments.iter()
+            .filter(|file| if file.kind == "file" { send_files } else { send_images })
+            .cloned()
+            .collect(),
+        // 考え中のブロックの署名は、それを書いたモデルにしか通じない
+        thinking: if message.model.as_deref() == Some(config.model_name.as_str()) { message.thinking.clone() } else { Vec::new() },
+        ..Message::new(&message.role, &message.content)
+    }));
+    messages.push(Message::new("user", prompt));
+    messages
+}
+
+// リクエストの本文に入れる形（JSON）。ツールを使っている途中なら、そのやりとりを後ろに付ける
+// 履歴の画像は Ollama の形（"images"）で、ファイルIDは "file_ids" で付ける（OpenAI の形には providers::attach_images で直す）
+pub fn messages_json(prompt: &str, config: &Config) -> Value {
+    let mut messages: Vec<Value> = messages(prompt, config).iter()
+        .map(|message| {
+            let mut json = serde_json::json!({ "role": message.role, "content": message.content });
+            let images = message.images();
+            if !images.is_empty() {
+                json["images"] = serde_json::json!(images);
+            }
+            let file_ids = message.file_ids();
+            if !file_ids.is_empty() {
+                json["file_ids"] = serde_json::json!(file_ids);
+            }
+            json
+        })
+        .collect();
+    messages.extend(config.tool_messages.iter().cloned());
+    Value::Array(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chat_config(history: &[(&str, &str)]) -> Config {
+        let mut config: Config = serde_json::from_value(serde_json::json!({
+            "model_name": "llama3", "use_local_model": true, "openai_compatible": false, "chat": true,
+        })).unwrap();
+        config.history = history.iter().map(|(role, content)| Message::new(role, content)).collect();
+        config
+    }
+
+    fn roles(messages: &[Message]) -> Vec<&str> {
+        messages.iter().map(|message| message.role.as_str()).collect()
+    }
+
+    #[test]
+    fn sends_the_whole_history_in_chat_mode_only() {
+        let mut config = chat_config(&[("user", "1"), ("assistant", "2"), ("user", "3"), ("assistant", "4")]);
+        assert_eq!(sent_history(&config).len(), 4);
+        config.chat = false;
+        assert!(sent_history(&config).is_empty());
+    }
+
+    #[test]
+    fn trimmed_history_starts_with_a_user_message() {
+        let mut config = chat_config(&[("user", "1"), ("assistant", "2"), ("user", "3"), ("assistant", "4")]);
+        config.history_max_messages = Some(3);
+        assert_eq!(roles(sent_history(&config)), ["user", "assistant"]);
+        config.history_max_messages = Some(0);
+        assert!(sent_history(&config).is_empty());
+    }
+
+    #[test]
+    fn history_is_cut_by_estimated_tokens() {
+        let mut config = chat_config(&[("user", "一二三四五"), ("assistant", "六七八九十"), ("user", "abcd"), ("assistant", "efgh")]);
+        config.history_max_tokens = Some(2);
+        assert_eq!(sent_history(&config).iter().map(|message| message.content.as_str()).collect::<Vec<_>>(), ["abcd", "efgh"]);
+    }
+
+    #[test]
+    fn messages_put_system_history_and_prompt_in_order() {
+        let mut config = chat_config(&[("user", "1"), ("assistant", "2")]);
+        config.system_prompt = Some("丁寧に".to_string());
+        let messages = messages("3", &config);
+        assert_eq!(roles(&messages), ["system", "user", "assistant", "user"]);
+        assert_eq!(messages.last().unwrap().content, "3");
+    }
+
+    #[test]
+    fn turn_keeps_a_successful_exchange() {
+        let completion = Completion { text: "こんにちは".to_string(), ..Completion::default() };
+        let started_at = web_time::UNIX_EPOCH + Duration::from_secs(100);
+        let turn = turn("やあ", &completion, "llama3", started_at, Duration::from_secs(2), Vec::new()).unwrap();
+        assert_eq!(turn[0].role, "user");
+        assert_eq!(turn[0].content, "やあ");
+        assert_eq!(turn[0].timestamp, Some(100));
+        assert_eq!(turn[1].role, "assistant");
+        assert_eq!(turn[1].content, "こんにちは");
+        assert_eq!(turn[1].model.as_deref(), Some("llama3"));
+        assert_eq!(turn[1].timestamp, Some(102));
+    }
+
+    #[test]
+    fn turn_drops_a_failed_exchange() {
+        let completion = Completion::failed("オンライン推論エラー: HTTP 500".to_string());
+        assert!(turn("やあ", &completion, "llama3", SystemTime::now(), Duration::ZERO, Vec::new()).is_none());
+    }
+}
@@ -0,0 +1,243 @@
+// クライアント全体で使うエラーの種類
+//
+// 表示用の文字列は Display で作る。これまでどおり String のエラーを返す関数の中でも
+// `?` で使えるように、String との変換も用意してある（String から作ったものは種類がわからないので Other）。
+//
+// プロバイダーごとに違うエラーの本文（OpenAI の error.code / error.type、Anthropic の error.type、
+// Gemini の error.status、Ollama の error の文字列）とHTTPのステータスから、ErrorKind に分けておく。
+// 再試行するか、分割してやり直すか、終了コードをどれにするかは ErrorKind で決める。
+use serde_json::Value;
+use crate::exit_code;
+use crate::request::HttpResponse;
+
+// エラーの本文をそのまま見せるときの最大の長さ
+const MAX_BODY_CHARS: usize = 500;
+
+// コンテキスト長を超えたときのエラーに含まれる言い回し（プロバイダーごとに違う）
+const CONTEXT_OVERFLOW_PATTERNS: [&str; 6] = [
+    "context_length_exceeded",
+    "maximum context length",
+    "exceeds the available context size",
+    "context window",
+    "prompt is too long",
+    "too many tokens",
+];
+
+// コンテンツフィルターで止められたことを表すメッセージの断片
+const MODERATION_PATTERNS: [&str; 4] = ["content_filter", "content_policy", "content_management_policy", "flagged"];
+
+// プロバイダーのエラーコード（OpenAI の code / type、Anthropic の type、Gemini の status）と種類
+const PROVIDER_CODES: [(&str, ErrorKind); 22] = [
+    ("invalid_api_key", ErrorKind::Auth),
+    ("authentication_error", ErrorKind::Auth),
+    ("permission_error", ErrorKind::Auth),
+    ("UNAUTHENTICATED", ErrorKind::Auth),
+    ("PERMISSION_DENIED", ErrorKind::Auth),
+    ("insufficient_quota", ErrorKind::Quota),
+    ("billing_hard_limit_reached", ErrorKind::Quota),
+    ("rate_limit_exceeded", ErrorKind::RateLimit),
+    ("rate_limit_error", ErrorKind::RateLimit),
+    ("RESOURCE_EXHAUSTED", ErrorKind::RateLimit),
+    ("request_too_large", ErrorKind::ContextOverflow),
+    ("model_not_found", ErrorKind::NotFound),
+    ("not_found_error", ErrorKind::NotFound),
+    ("NOT_FOUND", ErrorKind::NotFound),
+    ("content_policy_violation", ErrorKind::ContentFilter),
+    ("overloaded_error", ErrorKind::Overloaded),
+    ("UNAVAILABLE", ErrorKind::Overloaded),
+    ("server_error", ErrorKind::Server),
+    ("api_error", ErrorKind::Server),
+    ("INTERNAL", ErrorKind::Server),
+    ("invalid_request_error", ErrorKind::InvalidRequest),
+    ("INVALID_ARGUMENT", ErrorKind::InvalidRequest),
+];
+
+// エラーの種類（機械で読める名前は as_str）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    Auth, // APIキーが無効・権限がない
+    RateLimit, // リクエストが多すぎる（待てば通る）
+    Quota, // 利用枠やクレジットを使い切った（待っても通らない）
+    ContextOverflow, // 入力がコンテキスト長を超えた
+    InvalidRequest, // リクエストの内容が不正
+    NotFound, // モデルかURLがない
+    ContentFilter, // コンテンツフィルターで止められた
+    Overloaded, // プロバイダーが混んでいる
+    Server, // プロバイダー側のエラー
+    Network, // 接続できない・途中で切れた
+    Timeout,
+    Config, // 設定の問題（送る前のエラー）
+    Budget, // セッションの上限（max_session_tokens / max_session_cost）を超えた
+    Other,
+}
+
+impl ErrorKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorKind::Auth => "auth",
+            ErrorKind::RateLimit => "rate_limit",
+            ErrorKind::Quota => "quota",
+            ErrorKind::ContextOverflow => "context_overflow",
+            ErrorKind::InvalidRequest => "invalid_request",
+            ErrorKind::NotFound => "not_found",
+            ErrorKind::ContentFilter => "content_filter",
+            ErrorKind::Overloaded => "overloaded",
+            ErrorKind::Server => "server",
+            ErrorKind::Network => "network",
+            ErrorKind::Timeout => "timeout",
+            ErrorKind::Config => "config",
+            ErrorKind::Budget => "budget",
+            ErrorKind::Other => "other",
+        }
+    }
+
+    // 表示用の説明
+    pub fn describe(self) -> &'static str {
+        match self {
+            ErrorKind::Auth => "APIキーが無効か、権限がありません",
+            ErrorKind::RateLimit => "リクエストが多すぎるため制限されました（しばらく待ってから送ってください）",
+            ErrorKind::Quota => "利用枠かクレジットを使い切りました",
+            ErrorKind::ContextOverflow => "入力がモデルのコンテキスト長を超えています",
+            ErrorKind::InvalidRequest => "リクエストの内容が受け付けられませんでした",
+            ErrorKind::NotFound => "モデルかURLが見つかりません",
+            ErrorKind::ContentFilter => "プロバイダーのコンテンツフィルターで止められました",
+            ErrorKind::Overloaded => "プロバイダーが混み合っています",
+            ErrorKind::Server => "プロバイダー側でエラーが起きました",
+            ErrorKind::Network => "接続できませんでした",
+            ErrorKind::Timeout => "タイムアウトしました",
+            ErrorKind::Config => "設定に問題があります",
+            ErrorKind::Budget => "セッションの上限に達しました",
+            ErrorKind::Other => "APIエラー",
+        }
+    }
+
+    // 接続できなかった（つながれば通るかもしれない）か。キューに入れて送り直すときに使う
+    pub fn is_offline(self) -> bool {
+        matches!(self, ErrorKind::Network | ErrorKind::Timeout)
+    }
+
+    // 同じリクエストを送り直せば通るかもしれないか
+    pub fn is_retryable(self) -> bool {
+        matches!(self, ErrorKind::RateLimit | ErrorKind::Overloaded | ErrorKind::Server | ErrorKind::Network | ErrorKind::Timeout)
+    }
+
+    // このエラーで終わるときの終了コード
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::Auth => exit_code::AUTH_FAILURE,
+            ErrorKind::ContentFilter => exit_code::MODERATION_BLOCK,
+            ErrorKind::Network | ErrorKind::Timeout => exit_code::NETWORK_FAILURE,
+            ErrorKind::Config => exit_code::CONFIG_ERROR,
+            ErrorKind::Budget => exit_code::BUDGET_EXCEEDED,
+            _ => exit_code::FAILURE,
+        }
+    }
+
+    // 失敗したレスポンスの種類と、プロバイダーのエラーコード
+    // （言い回しでわかるコンテキスト長超過とコンテンツフィルターを先に見て、次にエラーコード、最後にステータスで決める）
+    pub fn classify(status: u16, body: &str) -> (ErrorKind, Option<String>) {
+        let json = serde_json::from_str::<Value>(body).ok();
+        let code = json.as_ref().and_then(|json| {
+            ["/error/code", "/error/type", "/error/status"].iter()
+                .find_map(|pointer| json.pointer(pointer).and_then(|code| code.as_str()))
+                .map(|code| code.to_string())
+        });
+        let lower = body.to_ascii_lowercase();
+        let by_code = code.as_deref().and_then(|code| PROVIDER_CODES.iter().find(|(name, _)| *name == code)).map(|(_, kind)| *kind);
+        let kind = if CONTEXT_OVERFLOW_PATTERNS.iter().any(|pattern| lower.contains(pattern)) {
+            ErrorKind::ContextOverflow
+        } else if MODERATION_PATTERNS.iter().any(|pattern| lower.contains(pattern)) {
+            ErrorKind::ContentFilter
+        } else if let Some(kind) = by_code {
+            kind
+        } else if lower.contains("not found") {
+            ErrorKind::NotFound // Ollama の "model 'x' not found"
+        } else {
+            match status {
+                401 | 403 => ErrorKind::Auth,
+                402 => ErrorKind::Quota,
+                404 => ErrorKind::NotFound,
+                408 => ErrorKind::Timeout,
+                413 => ErrorKind::ContextOverflow,
+                429 => ErrorKind::RateLimit,
+                502..=504 | 529 => ErrorKind::Overloaded,
+                500..=599 => ErrorKind::Server,
+                400..=499 => ErrorKind::InvalidRequest,
+                _ => ErrorKind::Other,
+            }
+        };
+        (kind, code)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    #[error("設定エラー: {0}")]
+    Config(String),
+    #[error("通信エラー: {0}")]
+    Network(#[from] reqwest::Error),
+    #[error("タイムアウトしました（{0}秒応答がありません）")]
+    Timeout(u64),
+    #[error("{}（{status}）: {message}", .kind.describe())]
+    Api { status: u16, kind: ErrorKind, code: Option<String>, message: String }, // code はプロバイダーのエラーコード
+    #[error("レスポンスのパースに失敗しました: {0}")]
+    Parse(String),
+    #[error("応答の途中で接続が切れました: {cause}")]
+    Interrupted { partial: String, cause: String }, // partial はそこまでに届いた本文
+    #[error("{message}")]
+    Inference { kind: ErrorKind, message: String }, // 推論の途中のエラー（String のエラーを返す接続先のもの）
+}
+
+impl Error {
+    // エラーの種類（再試行や終了コードはこれで決める）
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Config(_) => ErrorKind::Config,
+            Error::Network(e) if e.is_timeout() => ErrorKind::Timeout,
+            Error::Network(_) | Error::Interrupted { .. } => ErrorKind::Network,
+            Error::Timeout(_) => ErrorKind::Timeout,
+            Error::Api { kind, .. } | Error::Inference { kind, .. } => *kind,
+            Error::Parse(_) => ErrorKind::Other,
+        }
+    }
+
+    // 失敗したレスポンスと、そこから取り出したメッセージでエラーを作る
+    pub fn api(response: &HttpResponse, message: String) -> Error {
+        let (kind, code) = ErrorKind::classify(response.status, &response.body);
+        Error::Api { status: response.status, kind, code, message }
+    }
+
+    // エラーのレスポンスから、プロバイダーのエラーメッセージを取り出す
+    // （{"error": {"message"}} / {"error": "..."} / {"errors": [{"message"}]} / {"message"} / {"detail"}。どれでもなければ本文）
+    pub fn from_response(response: &HttpResponse) -> Error {
+        let json = serde_json::from_str::<Value>(&response.body).ok();
+        let message = json.as_ref()
+            .and_then(|json| {
+                ["/error/message", "/error", "/errors/0/message", "/message", "/detail"].iter()
+                    .find_map(|pointer| json.pointer(pointer).and_then(|m| m.as_str()))
+            })
+            .map(|message| message.to_string())
+            .unwrap_or_else(|| response.body.trim().chars().take(MAX_BODY_CHARS).collect());
+        Error::api(response, message)
+    }
+}
+
+impl From<Error> for String {
+    fn from(error: Error) -> String {
+        error.to_string()
+    }
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Error {
+        Error::Inference { kind: ErrorKind::Other, message }
+    }
+}
+
+impl From<&str> for Error {
+    fn from(message: &str) -> Error {
+        message.to_string().into()
+    }
+}
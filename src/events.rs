@@ -0,0 +1,20 @@
+// チャットの流れを表すイベント（Client::chat_events で受け取る）
+//
+// TUI やボット、サーバーのような表示する側が、同じ形でやりとりを受け取れるようにする。
+// 1回のやりとりでは UserMessage から始まり、TokenDelta や ToolCallStarted / ToolCallFinished が途中に届き、
+// UsageReport のあとに AssistantMessage（失敗したときは Error）で終わる。
+// Client::ask では、言語ごとの振り分けで別のモデルが答えるときに、最初に Routed も届く。
+use serde_json::Value;
+use crate::completion::Usage;
+use crate::stream::Token;
+
+pub enum Event {
+    UserMessage(String), // 送ったメッセージ
+    Routed { language: String, model: String }, // 判定した言語（表示用の名前）と、この1回だけ答える振り分け先
+    TokenDelta(Token), // ストリーミングで届いた断片
+    ToolCallStarted { name: String, arguments: Value }, // ツールを呼び出す前
+    ToolCallFinished { name: String, output: String }, // ツールの結果（モデルに返す内容）
+    UsageReport(Usage), // トークン使用量（プロバイダーが返さなかったときは見積もり）
+    AssistantMessage(String), // 応答の全体
+    Error(String),
+}
@@ -0,0 +1,57 @@
+// プロセスの終了コード（ラッパーのスクリプトが失敗の種類で分岐できるように分けておく）
+use crate::completion::Completion;
+
+pub const SUCCESS: i32 = 0;
+pub const FAILURE: i32 = 1; // 以下のどれにも当てはまらないエラー
+pub const CONFIG_ERROR: i32 = 2; // 設定ファイルやコマンドラインの指定が不正
+pub const AUTH_FAILURE: i32 = 3; // APIキーが無効・権限がない（401 / 403）
+pub const NETWORK_FAILURE: i32 = 4; // 接続できない・タイムアウトなど
+pub const MODERATION_BLOCK: i32 = 5; // プロバイダーのコンテンツフィルターで止められた
+pub const BUDGET_EXCEEDED: i32 = 6; // 設定したトークン数・料金の上限を超えた
+
+// 推論の結果から、失敗していればその種類の終了コードを返す（成功なら SUCCESS）
+// 答えは返ってきても、コンテンツフィルターで止められていれば MODERATION_BLOCK にする
+pub fn for_completion(completion: &Completion) -> i32 {
+    match completion.error_kind {
+        Some(kind) => kind.exit_code(),
+        None if completion.error.is_some() => FAILURE,
+        None if completion.finish_reason.as_deref() == Some("content_filter") => MODERATION_BLOCK,
+        None => SUCCESS,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{Error, ErrorKind};
+
+    fn api_error(status: u16, body: &str) -> Completion {
+        let response = crate::request::HttpResponse { status, body: body.to_string() };
+        Completion::from_error("エラー", &Error::from_response(&response))
+    }
+
+    #[test]
+    fn successful_completions_exit_with_success() {
+        assert_eq!(for_completion(&Completion::from("答え".to_string())), SUCCESS);
+        let stopped = Completion { finish_reason: Some("stop".to_string()), ..Completion::from("答え".to_string()) };
+        assert_eq!(for_completion(&stopped), SUCCESS);
+    }
+
+    #[test]
+    fn content_filter_exits_with_moderation_block() {
+        let filtered = Completion { finish_reason: Some("content_filter".to_string()), ..Completion::default() };
+        assert_eq!(for_completion(&filtered), MODERATION_BLOCK);
+        assert_eq!(for_completion(&api_error(400, r#"{"error":{"code":"content_policy_violation","message":"no"}}"#)), MODERATION_BLOCK);
+    }
+
+    #[test]
+    fn failures_exit_with_their_kind() {
+        assert_eq!(for_completion(&api_error(401, r#"{"error":{"message":"bad key"}}"#)), AUTH_FAILURE);
+        assert_eq!(for_completion(&api_error(500, "Internal Server Error")), FAILURE);
+        assert_eq!(for_completion(&Completion::from_error("エラー", &Error::Timeout(5))), NETWORK_FAILURE);
+        assert_eq!(for_completion(&Completion::from_error("エラー", &Error::Config("x".to_string()))), CONFIG_ERROR);
+        assert_eq!(ErrorKind::Quota.exit_code(), FAILURE);
+        // 種類のわからない失敗
+        assert_eq!(for_completion(&Completion::failed("だめでした".to_string())), FAILURE);
+    }
+}
@@ -0,0 +1,372 @@
+// C から組み込むための小さなAPI（エディターやゲームなど、Rust 以外のアプリ用）
+//
+// 共有ライブラリ（libmilti_llm_client.so / .dylib / .dll）として `cargo build --release` でビルドされ、
+// 宣言は include/milti_llm_client.h にある。中身は ChatSession で、送るたびに履歴を一緒に送る。
+//
+//   mlc_client_from_file / mlc_client_from_json  設定から作る（失敗したら NULL を返し、error に理由を入れる）
+//   mlc_send     メッセージを送り始める（答えを待たずに戻る）
+//   mlc_poll     届いたイベントを1つ取り出す（timeout_ms まで待つ。0 なら待たない）
+//   mlc_send_blocking  送って、答えの全体を待つ
+//   mlc_string_free / mlc_client_free  このライブラリが返した文字列とクライアントを解放する
+//
+// 推論はクライアントごとの tokio のランタイムで動くので、呼ぶ側のスレッドは止めない。
+// 1つのクライアントで同時に送れるのは1つだけ（送っている途中の mlc_send は MLC_BUSY を返す）。
+// イベントは送るたびに番号を付けて1つのキューに入れる。mlc_send_blocking は自分の番号のものだけを待つので、
+// 前の mlc_send の読み残しを答えと取り違えない。1つのクライアントのイベントは、1つのスレッドから受け取る（ヘッダーを参照）。
+// Rust のパニックは C 側に巻き戻さず、どの関数でもエラーとして返す（そのクライアントはもう送れなくなる）。
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use futures_util::FutureExt;
+use tokio::runtime::Runtime;
+use crate::{ChatSession, Client, Config, Error, StreamEvent};
+
+// mlc_send の返り値
+pub const MLC_OK: c_int = 0;
+pub const MLC_INVALID_ARGUMENT: c_int = 1;
+pub const MLC_BUSY: c_int = 2;
+pub const MLC_INTERNAL_ERROR: c_int = 3; // ライブラリの中でパニックした（このクライアントはもう送れない）
+
+// mlc_poll で受け取るイベントの種類
+pub const MLC_EVENT_NONE: c_int = 0; // まだ何も届いていない
+pub const MLC_EVENT_TEXT: c_int = 1; // 答えの断片
+pub const MLC_EVENT_REASONING: c_int = 2; // 考え中の部分の断片
+pub const MLC_EVENT_ROUTED: c_int = 3; // {"language": …, "model": …}
+pub const MLC_EVENT_TOOL_CALL: c_int = 4; // {"name": …, "arguments": …}
+pub const MLC_EVENT_TOOL_RESULT: c_int = 5; // {"name": …, "output": …}
+pub const MLC_EVENT_DONE: c_int = 6; // 答えの全体（このあと次を送れる）
+pub const MLC_EVENT_ERROR: c_int = 7; // エラーのメッセージ（このあと次を送れる）
+
+// キューに入れるイベント（送った番号・種類・中身）
+type Queued = (u64, c_int, String);
+
+/// C から使うクライアント（中身は見せない）。
+pub struct MlcClient {
+    runtime: Runtime,
+    // 送っているあいだは推論のタスクが持っていくので None になる
+    session: Arc<Mutex<Option<ChatSession>>>,
+    broken: Arc<AtomicBool>, // 推論の途中でパニックして、会話をなくした
+    sends: AtomicU64, // これまでに送った数（イベントに付ける番号）
+    events: Mutex<Receiver<Queued>>,
+    sender: Sender<Queued>,
+}
+
+const PANICKED: &str = "ライブラリの中でパニックしました";
+
+// パニックを C 側に巻き戻さずに止めて、理由の文字列にする（境界を越えて巻き戻すのは未定義動作）
+fn catch<T>(body: impl FnOnce() -> T) -> Result<T, String> {
+    catch_unwind(AssertUnwindSafe(body)).map_err(|panic| {
+        let detail = panic.downcast_ref::<&str>().map(|detail| detail.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned());
+        match detail {
+            Some(detail) => format!("{}: {}", PANICKED, detail),
+            None => PANICKED.to_string(),
+        }
+    })
+}
+
+fn event_of(event: StreamEvent) -> (c_int, String) {
+    match event {
+        StreamEvent::Text(text) => (MLC_EVENT_TEXT, text),
+        StreamEvent::Reasoning(text) => (MLC_EVENT_REASONING, text),
+        StreamEvent::Routed { language, model } => (MLC_EVENT_ROUTED, serde_json::json!({ "language": language, "model": model }).to_string()),
+        StreamEvent::ToolCallStarted { name, arguments } => (MLC_EVENT_TOOL_CALL, serde_json::json!({ "name": name, "arguments": arguments }).to_string()),
+        StreamEvent::ToolCallFinished { name, output } => (MLC_EVENT_TOOL_RESULT, serde_json::json!({ "name": name, "output": output }).to_string()),
+    }
+}
+
+// C の文字列を読む（NULL や UTF-8 でないものは None）
+unsafe fn text<'a>(pointer: *const c_char) -> Option<&'a str> {
+    match pointer.is_null() {
+        true => None,
+        false => CStr::from_ptr(pointer).to_str().ok(),
+    }
+}
+
+// 呼ぶ側で mlc_string_free する文字列にする（途中の NUL は外す）
+fn owned(text: String) -> *mut c_char {
+    CString::new(text.replace('\0', "")).unwrap_or_default().into_raw()
+}
+
+unsafe fn set_error(error: *mut *mut c_char, message: String) {
+    if !error.is_null() {
+        *error = owned(message);
+    }
+}
+
+unsafe fn client_new(config: Result<Config, Error>, error: *mut *mut c_char) -> *mut MlcClient {
+    let runtime = match Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            set_error(error, format!("非同期ランタイムを作れませんでした: {}", e));
+            return std::ptr::null_mut();
+        }
+    };
+    match config.and_then(|config| Client::builder().config(config).build()) {
+        Ok(client) => {
+            let (sender, events) = channel();
+            let session = Arc::new(Mutex::new(Some(ChatSession::new(client))));
+            let client = MlcClient { runtime, session, broken: Arc::default(), sends: AtomicU64::new(0), events: Mutex::new(events), sender };
+            Box::into_raw(Box::new(client))
+        }
+        Err(e) => {
+            set_error(error, e.to_string());
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// 設定ファイル（JSON / TOML / YAML）を読んでクライアントを作る。
+///
+/// # Safety
+///
+/// `path` は NUL で終わる文字列。`error` は NULL か、書き込める `char *` の場所（失敗したときに mlc_string_free で解放する文字列が入る）。
+#[no_mangle]
+pub unsafe extern "C" fn mlc_client_from_file(path: *const c_char, error: *mut *mut c_char) -> *mut MlcClient {
+    let made = catch(|| {
+        let Some(path) = text(path) else {
+            set_error(error, "設定ファイルのパスがありません".to_string());
+            return std::ptr::null_mut();
+        };
+        client_new(Config::from_file(path), error)
+    });
+    made.unwrap_or_else(|message| {
+        set_error(error, message);
+        std::ptr::null_mut()
+    })
+}
+
+/// JSON で書いた設定からクライアントを作る。
+///
+/// # Safety
+///
+/// `json` は NUL で終わる文字列。`error` は [`mlc_client_from_file`] と同じ。
+#[no_mangle]
+pub unsafe extern "C" fn mlc_client_from_json(json: *const c_char, error: *mut *mut c_char) -> *mut MlcClient {
+    let made = catch(|| {
+        let Some(json) = text(json) else {
+            set_error(error, "設定がありません".to_string());
+            return std::ptr::null_mut();
+        };
+        client_new(Config::from_json(json), error)
+    });
+    made.unwrap_or_else(|message| {
+        set_error(error, message);
+        std::ptr::null_mut()
+    })
+}
+
+/// メッセージを送り始める。届いたものは [`mlc_poll`] で受け取り、最後に `MLC_EVENT_DONE` か `MLC_EVENT_ERROR` が届く。
+///
+/// # Safety
+///
+/// `client` は mlc_client_from_* が返した、まだ解放していないもの。`message` は NUL で終わる文字列。
+#[no_mangle]
+pub unsafe extern "C" fn mlc_send(client: *mut MlcClient, message: *const c_char) -> c_int {
+    match client.as_ref() {
+        Some(client) => catch(|| send(client, text(message)).map_or_else(|code| code, |_| MLC_OK)).unwrap_or(MLC_INTERNAL_ERROR),
+        None => MLC_INVALID_ARGUMENT,
+    }
+}
+
+// 送り始めて、その回のイベントに付く番号を返す（送れなければ mlc_send の返り値）
+fn send(client: &MlcClient, message: Option<&str>) -> Result<u64, c_int> {
+    let message = message.ok_or(MLC_INVALID_ARGUMENT)?.to_string();
+    if client.broken.load(Ordering::Relaxed) {
+        return Err(MLC_INTERNAL_ERROR);
+    }
+    let mut session = client.session.lock().ok().and_then(|mut session| session.take()).ok_or(MLC_BUSY)?;
+    let id = client.sends.fetch_add(1, Ordering::Relaxed) + 1;
+    let slot = client.session.clone();
+    let broken = client.broken.clone();
+    let sender = client.sender.clone();
+    client.runtime.spawn(async move {
+        let streaming = AssertUnwindSafe(async {
+            let answer = session.send_streaming(&message, |event| {
+                let (kind, text) = event_of(event);
+                let _ = sender.send((id, kind, text));
+            }).await;
+            (session, answer)
+        });
+        let (kind, text) = match streaming.catch_unwind().await {
+            Ok((session, answer)) => {
+                // 次を送れるようにしてから、終わったことを知らせる
+                if let Ok(mut slot) = slot.lock() {
+                    *slot = Some(session);
+                }
+                match answer {
+                    Ok(completion) => (MLC_EVENT_DONE, completion.text),
+                    Err(e) => (MLC_EVENT_ERROR, e.to_string()),
+                }
+            }
+            Err(_) => {
+                broken.store(true, Ordering::Relaxed);
+                (MLC_EVENT_ERROR, format!("{}（このクライアントはもう送れません）", PANICKED))
+            }
+        };
+        let _ = sender.send((id, kind, text));
+    });
+    Ok(id)
+}
+
+/// 届いたイベントを1つ取り出す（なければ `timeout_ms` ミリ秒まで待つ）。
+///
+/// `kind` に種類（`MLC_EVENT_*`）を入れ、中身の文字列を返す（mlc_string_free で解放する）。
+/// 何も届いていなければ `MLC_EVENT_NONE` にして NULL を返す。
+///
+/// # Safety
+///
+/// `client` は [`mlc_send`] と同じ。`kind` は NULL か、書き込める `int` の場所。
+#[no_mangle]
+pub unsafe extern "C" fn mlc_poll(client: *mut MlcClient, timeout_ms: c_int, kind: *mut c_int) -> *mut c_char {
+    let event = catch(|| {
+        let events = client.as_ref()?.events.lock().ok()?;
+        events.recv_timeout(Duration::from_millis(timeout_ms.max(0) as u64)).ok()
+    });
+    let (event_kind, text) = match event {
+        Ok(Some((_, event_kind, text))) => (event_kind, owned(text)),
+        Ok(None) => (MLC_EVENT_NONE, std::ptr::null_mut()),
+        Err(message) => (MLC_EVENT_ERROR, owned(message)),
+    };
+    if !kind.is_null() {
+        *kind = event_kind;
+    }
+    text
+}
+
+/// メッセージを送り、答えの全体を待って返す（この回の途中のイベントと、前に送った回の読み残しは捨てる）。
+///
+/// 失敗したら NULL を返し、`error` に理由を入れる。
+///
+/// # Safety
+///
+/// [`mlc_send`] と同じ。`error` は [`mlc_client_from_file`] と同じ。
+#[no_mangle]
+pub unsafe extern "C" fn mlc_send_blocking(client: *mut MlcClient, message: *const c_char, error: *mut *mut c_char) -> *mut c_char {
+    let answer = catch(|| {
+        let client = client.as_ref().ok_or_else(|| "クライアントかメッセージが不正です".to_string())?;
+        let id = send(client, text(message)).map_err(|code| match code {
+            MLC_BUSY => "ほかのメッセージを送っている途中です".to_string(),
+            MLC_INTERNAL_ERROR => format!("{}（このクライアントはもう送れません）", PANICKED),
+            _ => "クライアントかメッセージが不正です".to_string(),
+        })?;
+        let events = client.events.lock().map_err(|_| PANICKED.to_string())?;
+        loop {
+            match events.recv() {
+                Ok((event_id, MLC_EVENT_DONE, text)) if event_id == id => return Ok(text),
+                Ok((event_id, MLC_EVENT_ERROR, message)) if event_id == id => return Err(message),
+                Ok(_) => continue,
+                Err(_) => return Err(PANICKED.to_string()),
+            }
+        }
+    });
+    match answer.and_then(|answer| answer) {
+        Ok(text) => owned(text),
+        Err(message) => {
+            set_error(error, message);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// このライブラリが返した文字列を解放する（NULL なら何もしない）。
+///
+/// # Safety
+///
+/// `text` はこのライブラリが返した、まだ解放していない文字列か NULL。
+#[no_mangle]
+pub unsafe extern "C" fn mlc_string_free(text: *mut c_char) {
+    if !text.is_null() {
+        let _ = catch(|| drop(CString::from_raw(text)));
+    }
+}
+
+/// クライアントを解放する（送っている途中なら、その推論は止める）。
+///
+/// # Safety
+///
+/// `client` は mlc_client_from_* が返した、まだ解放していないものか NULL。
+#[no_mangle]
+pub unsafe extern "C" fn mlc_client_free(client: *mut MlcClient) {
+    if !client.is_null() {
+        let _ = catch(|| drop(Box::from_raw(client)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOCK: &CStr = c"{\"model_name\": \"m\", \"use_local_model\": true, \"openai_compatible\": false, \"local_framework\": \"mock\", \"mock\": {\"chunk_delay_ms\": 0}}";
+
+    unsafe fn take(text: *mut c_char) -> String {
+        let owned = CStr::from_ptr(text).to_string_lossy().to_string();
+        mlc_string_free(text);
+        owned
+    }
+
+    #[test]
+    fn streams_then_finishes_with_the_whole_answer() {
+        unsafe {
+            let client = mlc_client_from_json(MOCK.as_ptr(), std::ptr::null_mut());
+            assert!(!client.is_null());
+            assert_eq!(mlc_send(client, c"hello".as_ptr()), MLC_OK);
+            let mut streamed = String::new();
+            let mut kind = MLC_EVENT_NONE;
+            let done = loop {
+                let text = mlc_poll(client, 5000, &mut kind);
+                assert!(!text.is_null(), "イベントが届きませんでした");
+                match kind {
+                    MLC_EVENT_TEXT => streamed.push_str(&take(text)),
+                    MLC_EVENT_DONE => break take(text),
+                    _ => panic!("思わぬイベント {}: {}", kind, take(text)),
+                }
+            };
+            assert_eq!(streamed, "echo: hello");
+            assert_eq!(done, streamed);
+            assert!(mlc_poll(client, 0, &mut kind).is_null());
+            assert_eq!(kind, MLC_EVENT_NONE);
+            // 履歴は続いている
+            let answer = mlc_send_blocking(client, c"again".as_ptr(), std::ptr::null_mut());
+            assert_eq!(take(answer), "echo: again");
+            mlc_client_free(client);
+        }
+    }
+
+    #[test]
+    fn blocking_sends_skip_what_an_earlier_send_left_in_the_queue() {
+        unsafe {
+            let client = mlc_client_from_json(MOCK.as_ptr(), std::ptr::null_mut());
+            assert_eq!(mlc_send(client, c"first".as_ptr()), MLC_OK);
+            // 読まずに、前の回が終わるのを待つ（DONE はキューに残ったまま）
+            while (*client).session.lock().unwrap().is_none() {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            let answer = mlc_send_blocking(client, c"second".as_ptr(), std::ptr::null_mut());
+            assert_eq!(take(answer), "echo: second");
+            mlc_client_free(client);
+        }
+    }
+
+    #[test]
+    fn a_panic_becomes_an_error_message() {
+        let caught = catch(|| -> c_int { panic!("boom") });
+        assert_eq!(caught.unwrap_err(), format!("{}: boom", PANICKED));
+        assert_eq!(catch(|| 1), Ok(1));
+    }
+
+    #[test]
+    fn a_bad_config_comes_back_as_an_error() {
+        unsafe {
+            let mut error = std::ptr::null_mut();
+            assert!(mlc_client_from_json(c"{".as_ptr(), &mut error).is_null());
+            assert!(take(error).contains("設定"));
+            assert!(mlc_client_from_file(std::ptr::null(), std::ptr::null_mut()).is_null());
+            assert_eq!(mlc_send(std::ptr::null_mut(), c"hi".as_ptr()), MLC_INVALID_ARGUMENT);
+            mlc_client_free(std::ptr::null_mut());
+        }
+    }
+}
@@ -0,0 +1,119 @@
+// OpenAI互換の Files API などを使うための共通ヘルパー
+use std::path::Path;
+use serde_json::Value;
+use crate::Config;
+use crate::error::Error;
+use crate::request::PreparedRequest;
+
+// files upload で purpose を省略したときの値
+const DEFAULT_PURPOSE: &str = "user_data";
+
+// APIのベースURL（"https://api.openai.com/v1" など）を決める
+// api_base が設定されていなければ、endpoint の "/v1" までを使う
+pub fn api_base(config: &Config) -> Result<String, Error> {
+    if let Some(base) = &config.api_base {
+        return Ok(base.trim_end_matches('/').to_string());
+    }
+    let endpoint = config.endpoint.as_deref()
+        .ok_or_else(|| Error::Config("endpoint か api_base を設定してください".to_string()))?;
+    match endpoint.find("/v1") {
+        Some(i) => Ok(endpoint[..i + "/v1".len()].to_string()),
+        None => Err(Error::Config(format!("endpoint からAPIのベースURLがわかりません。api_base を設定してください: {}", endpoint))),
+    }
+}
+
+// APIキーがあれば認証ヘッダーを付ける
+pub fn authorized(request: PreparedRequest, config: &Config) -> PreparedRequest {
+    match &config.api_key {
+        Some(api_key) => request.header("Authorization", format!("Bearer {}", api_key)),
+        None => request,
+    }
+}
+
+// リクエストを送ってJSONのレスポンスを受け取る（エラーならステータスとメッセージを返す）
+pub async fn send_json(request: PreparedRequest, config: &Config) -> Result<Value, Error> {
+    let response = send_text(request, config).await?;
+    serde_json::from_str(&response)
+        .map_err(|e| Error::Parse(e.to_string()))
+}
+
+// リクエストを送って本文をそのまま受け取る
+pub async fn send_text(request: PreparedRequest, config: &Config) -> Result<String, Error> {
+    let response = request.send(config).await?;
+    if response.status >= 400 {
+        return Err(Error::from_response(&response));
+    }
+    Ok(response.body)
+}
+
+// ファイルをアップロードして、ファイルIDを返す
+pub async fn upload_file(config: &Config, file_name: &str, contents: Vec<u8>, purpose: &str) -> Result<String, Error> {
+    let url = format!("{}/files", api_base(config)?);
+    let request = authorized(PreparedRequest::upload(&url, &[("purpose", purpose)], file_name, contents), config);
+    let json = send_json(request, config).await?;
+    json.get("id").and_then(|id| id.as_str()).map(|id| id.to_string())
+        .ok_or_else(|| Error::from("アップロード結果にファイルIDがありません"))
+}
+
+// ディスク上のファイルをアップロードする
+pub async fn upload_path(config: &Config, path: &str, purpose: &str) -> Result<String, Error> {
+    let contents = std::fs::read(path)
+        .map_err(|e| format!("ファイルの読み込みに失敗しました: {:?}", e))?;
+    let file_name = Path::new(path).file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string());
+    upload_file(config, &file_name, contents, purpose).await
+}
+
+// アップロード済みファイルの中身を取得する
+pub async fn file_content(config: &Config, file_id: &str) -> Result<String, Error> {
+    let url = format!("{}/files/{}/content", api_base(config)?, file_id);
+    send_text(authorized(PreparedRequest::get(&url), config), config).await
+}
+
+// files サブコマンド
+//
+//   files upload <path> [purpose]  ファイルをアップロードする（purpose のデフォルトは "user_data"）
+//   files list                     アップロード済みのファイルを一覧表示する
+//   files delete <file_id>         ファイルを削除する
+pub enum FilesCommand {
+    Upload { path: String, purpose: Option<String> },
+    List,
+    Delete { file_id: String },
+}
+
+// files サブコマンドを実行する
+pub async fn run(command: FilesCommand, config: &Config) -> Result<(), Error> {
+    match command {
+        FilesCommand::Upload { path, purpose } => {
+            let purpose = purpose.as_deref().unwrap_or(DEFAULT_PURPOSE);
+            let file_id = upload_path(config, &path, purpose).await?;
+            println!("アップロードしました（ファイルID: {}）", file_id);
+            Ok(())
+        }
+        FilesCommand::List => {
+            let url = format!("{}/files", api_base(config)?);
+            let list = send_json(authorized(PreparedRequest::get(&url), config), config).await?;
+            let files = list.get("data").and_then(|data| data.as_array()).cloned().unwrap_or_default();
+            if files.is_empty() {
+                println!("アップロード済みのファイルはありません");
+            }
+            for file in &files {
+                let field = |key: &str| file.get(key).map(|v| v.as_str().map(|s| s.to_string()).unwrap_or_else(|| v.to_string()))
+                    .unwrap_or_else(|| "?".to_string());
+                println!("{}  {}  {} bytes  ({})", field("id"), field("filename"), field("bytes"), field("purpose"));
+            }
+            Ok(())
+        }
+        FilesCommand::Delete { file_id } => {
+            let url = format!("{}/files/{}", api_base(config)?, file_id);
+            let result = send_json(authorized(PreparedRequest::delete(&url), config), config).await?;
+            if result.get("deleted").and_then(|d| d.as_bool()) == Some(true) {
+                println!("{} を削除しました", file_id);
+                Ok(())
+            } else {
+                Err(format!("{} を削除できませんでした", file_id).into())
+            }
+        }
+    }
+}
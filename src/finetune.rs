@@ -0,0 +1,150 @@
+// OpenAI互換プラットフォームのファインチューニングジョブを管理する
+//
+//   finetune upload <train.jsonl>                  学習用ファイルをアップロードする
+//   finetune create <file_id|train.jsonl> [suffix] ジョブを作る（ファイルを渡すと先にアップロードする）
+//   finetune list                                  ジョブの一覧を表示する
+//   finetune cancel <job_id>                       ジョブを取り消す
+//   finetune follow <job_id> [--register <名前>]   終わるまでイベントを表示し続ける
+//                                                  （--register なら、できたモデルを設定ファイルのプロファイルにする）
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::Duration;
+use serde_json::Value;
+use crate::{config_file, runtime, Config};
+use crate::error::Error;
+use crate::files::{self, api_base, authorized};
+use crate::request::PreparedRequest;
+
+// イベントを問い合わせる間隔
+const FOLLOW_POLL_SECS: u64 = 10;
+
+// これ以上状態が変わらないジョブの状態
+const FINISHED_STATUSES: [&str; 3] = ["succeeded", "failed", "cancelled"];
+
+// finetune サブコマンド
+pub enum FinetuneCommand {
+    Upload { path: String },
+    Create { training_file: String, suffix: Option<String> }, // training_file はファイルIDか、アップロードする学習用ファイル
+    List,
+    Cancel { job_id: String },
+    Follow { job_id: String, register: Option<String> }, // register はできたモデルを登録するプロファイルの名前
+}
+
+// finetune サブコマンドを実行する
+pub async fn run(command: FinetuneCommand, config: &Config) -> Result<(), Error> {
+    match command {
+        FinetuneCommand::Upload { path } => {
+            let file_id = files::upload_path(config, &path, "fine-tune").await?;
+            println!("アップロードしました（ファイルID: {}）", file_id);
+            Ok(())
+        }
+        FinetuneCommand::Create { training_file, suffix } => create(config, &training_file, suffix.as_deref()).await,
+        FinetuneCommand::List => list(config).await,
+        FinetuneCommand::Cancel { job_id } => {
+            let url = format!("{}/fine_tuning/jobs/{}/cancel", api_base(config)?, job_id);
+            let job = files::send_json(authorized(PreparedRequest::new(&url, serde_json::json!({})), config), config).await?;
+            print_job(&job);
+            Ok(())
+        }
+        FinetuneCommand::Follow { job_id, register } => follow(config, &job_id, register.as_deref()).await,
+    }
+}
+
+// ジョブを作る（ファイルのパスが渡されたら、先に学習用ファイルとしてアップロードする）
+async fn create(config: &Config, training_file: &str, suffix: Option<&str>) -> Result<(), Error> {
+    let file_id = if Path::new(training_file).exists() {
+        let file_id = files::upload_path(config, training_file, "fine-tune").await?;
+        println!("学習用ファイルをアップロードしました（ファイルID: {}）", file_id);
+        file_id
+    } else {
+        training_file.to_string()
+    };
+    let mut body = serde_json::json!({
+        "model": config.model_name,
+        "training_file": file_id,
+    });
+    if let Some(suffix) = suffix {
+        body["suffix"] = serde_json::json!(suffix);
+    }
+    let url = format!("{}/fine_tuning/jobs", api_base(config)?);
+    let job = files::send_json(authorized(PreparedRequest::new(&url, body), config), config).await?;
+    print_job(&job);
+    if let Some(job_id) = job.get("id").and_then(|id| id.as_str()) {
+        println!("進み具合の確認: finetune follow {}", job_id);
+    }
+    Ok(())
+}
+
+// ジョブの一覧を表示する
+async fn list(config: &Config) -> Result<(), Error> {
+    let url = format!("{}/fine_tuning/jobs", api_base(config)?);
+    let jobs = files::send_json(authorized(PreparedRequest::get(&url), config), config).await?;
+    let jobs = jobs.get("data").and_then(|data| data.as_array()).cloned().unwrap_or_default();
+    if jobs.is_empty() {
+        println!("ファインチューニングジョブはありません");
+    }
+    for job in &jobs {
+        print_job(job);
+    }
+    Ok(())
+}
+
+// 終わるまで新しいイベントを表示し続ける（register があれば、できたモデルをその名前のプロファイルにする）
+async fn follow(config: &Config, job_id: &str, register: Option<&str>) -> Result<(), Error> {
+    let base = api_base(config)?;
+    let mut seen = HashSet::new();
+    loop {
+        let url = format!("{}/fine_tuning/jobs/{}/events?limit=100", base, job_id);
+        let events = files::send_json(authorized(PreparedRequest::get(&url), config), config).await?;
+        // 新しい順に返ってくるので、古い順に並べ直して表示する
+        let mut events = events.get("data").and_then(|data| data.as_array()).cloned().unwrap_or_default();
+        events.reverse();
+        for event in events {
+            let id = event.get("id").and_then(|id| id.as_str()).unwrap_or_default().to_string();
+            if seen.insert(id) {
+                println!("[{}] {}", str_field(&event, "level"), str_field(&event, "message"));
+            }
+        }
+
+        let url = format!("{}/fine_tuning/jobs/{}", base, job_id);
+        let job = files::send_json(authorized(PreparedRequest::get(&url), config), config).await?;
+        if FINISHED_STATUSES.contains(&str_field(&job, "status")) {
+            print_job(&job);
+            let model = job.get("fine_tuned_model").and_then(|m| m.as_str());
+            return match (model, register) {
+                (Some(model), Some(name)) => {
+                    let path = register_profile(config, name, model)?;
+                    println!("{} にプロファイル {} を書き足しました（--profile {} か /model {} で使えます）", path, name, name, name);
+                    Ok(())
+                }
+                (Some(model), None) => {
+                    println!("config.json の \"model_name\" を \"{}\" にすると、このモデルでチャットできます", model);
+                    Ok(())
+                }
+                (None, Some(name)) => Err(Error::Config(format!("モデルができなかったため、プロファイル {} は登録しませんでした", name))),
+                (None, None) => Ok(()),
+            };
+        }
+        runtime::sleep(Duration::from_secs(FOLLOW_POLL_SECS)).await;
+    }
+}
+
+// できたモデルを、読み込んだ設定ファイルのプロファイルに書き足して、そのファイルを返す
+// 接続先とAPIキーはジョブを作ったときと同じにする（プロファイルで作ったなら、その書き方を写す）
+fn register_profile<'a>(config: &'a Config, name: &str, model: &str) -> Result<&'a str, Error> {
+    let path = config.config_path.as_deref()
+        .ok_or_else(|| Error::Config("設定ファイルから読み込んでいないため、プロファイルを書き足せません".to_string()))?;
+    config_file::add_profile(path, name, config.profile.as_deref(), model).map_err(Error::Config)?;
+    Ok(path)
+}
+
+// ジョブの概要を1行で表示する
+fn print_job(job: &Value) {
+    let model = job.get("fine_tuned_model").and_then(|m| m.as_str())
+        .unwrap_or_else(|| str_field(job, "model"));
+    println!("{}: {}（{}）", str_field(job, "id"), str_field(job, "status"), model);
+}
+
+fn str_field<'a>(json: &'a Value, key: &str) -> &'a str {
+    json.get(key).and_then(|value| value.as_str()).unwrap_or("?")
+}
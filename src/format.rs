@@ -0,0 +1,53 @@
+// --format で指定したテンプレート（Handlebars）で応答を整形する
+//
+// テンプレートで使える値: content, prompt, model, reasoning, finish_reason, citations, search_suggestions,
+// usage.prompt_tokens / usage.completion_tokens / usage.cached_tokens,
+// started_at / finished_at（UNIX時間の秒）, elapsed_ms
+use std::time::Duration;
+use web_time::{SystemTime, UNIX_EPOCH};
+use handlebars::Handlebars;
+use serde_json::Value;
+use crate::completion::Completion;
+
+// "@ファイル名" ならファイルからテンプレートを読む（書き間違いは起動時に知らせる）
+pub fn load_template(format: &str) -> Result<String, String> {
+    let template = match format.strip_prefix('@') {
+        Some(path) => std::fs::read_to_string(path)
+            .map_err(|e| format!("テンプレートの読み込みに失敗しました: {:?}", e))?,
+        None => format.to_string(),
+    };
+    handlebars::Template::compile(&template)
+        .map_err(|e| format!("テンプレートが不正です: {}", e))?;
+    Ok(template)
+}
+
+// テンプレートに渡す値を作る
+pub fn response_data(model: &str, prompt: &str, completion: &Completion, started: SystemTime, elapsed: Duration) -> Value {
+    let seconds = |time: SystemTime| time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let usage = completion.usage.map(|usage| serde_json::json!({
+        "prompt_tokens": usage.prompt_tokens,
+        "completion_tokens": usage.completion_tokens,
+        "cached_tokens": usage.cached_tokens,
+    }));
+    serde_json::json!({
+        "content": completion.text,
+        "prompt": prompt,
+        "model": model,
+        "reasoning": completion.reasoning,
+        "finish_reason": completion.finish_reason,
+        "citations": completion.citations,
+        "search_suggestions": completion.search_suggestions,
+        "usage": usage,
+        "started_at": seconds(started),
+        "finished_at": seconds(started + elapsed),
+        "elapsed_ms": elapsed.as_millis() as u64,
+    })
+}
+
+// テンプレートに値を埋め込む（HTML用のエスケープはしない）
+pub fn render(template: &str, data: &Value) -> Result<String, String> {
+    let mut handlebars = Handlebars::new();
+    handlebars.register_escape_fn(handlebars::no_escape);
+    handlebars.render_template(template, data)
+        .map_err(|e| format!("テンプレートの展開に失敗しました: {}", e))
+}
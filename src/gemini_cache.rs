@@ -0,0 +1,166 @@
+// Gemini のコンテキストキャッシュ（cachedContents）
+//
+// 大きな system メッセージや資料を先にキャッシュにしておき、"cached_content" にその名前を書くと、
+// 推論のたびに送り直さずにキャッシュを参照させる（キャッシュから読んだ分のトークンは安くなる）。
+// キャッシュはモデルごとに作られ、期限（ttl）が来ると消える。
+//
+//   cache create <path>... [--system <text>] [--ttl <秒>] [--display-name <名前>]  キャッシュを作る
+//   cache list                                                                   キャッシュの一覧
+//   cache extend <name> <ttl>                                                    期限を今から ttl 秒後にする
+//   cache delete <name>                                                          キャッシュを消す
+use std::path::Path;
+use serde_json::Value;
+use crate::attachments::inline_text;
+use crate::error::Error;
+use crate::files::send_json;
+use crate::providers::{gemini_api_base, gemini_cache_name};
+use crate::request::PreparedRequest;
+use crate::Config;
+
+pub enum CacheCommand {
+    Create { paths: Vec<String>, system: Option<String>, ttl_secs: Option<u64>, display_name: Option<String> },
+    List,
+    Extend { name: String, ttl_secs: u64 },
+    Delete { name: String },
+}
+
+// cache サブコマンドを実行する
+pub async fn run(command: CacheCommand, config: &Config) -> Result<(), Error> {
+    if config.provider.as_deref() != Some("gemini") {
+        return Err(Error::Config("cache は \"provider\": \"gemini\" のときだけ使えます".to_string()));
+    }
+    match command {
+        CacheCommand::Create { paths, system, ttl_secs, display_name } => {
+            let mut documents = Vec::new();
+            for path in &paths {
+                let content = std::fs::read_to_string(path)
+                    .map_err(|e| format!("ファイルの読み込みに失敗しました: {} ({:?})", path, e))?;
+                let name = Path::new(path).file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_else(|| path.clone());
+                documents.push(inline_text(&name, &content));
+            }
+            let request = create_request(config, &documents, system.as_ref().or(config.system_prompt.as_ref()), ttl_secs, display_name.as_deref())?;
+            let cache = send_json(request, config).await?;
+            println!("キャッシュを作りました: {}", describe(&cache));
+            println!("\"cached_content\" に {} を書くと、推論のたびにこのキャッシュを参照します", field(&cache, "name"));
+            Ok(())
+        }
+        CacheCommand::List => {
+            let url = format!("{}/cachedContents", gemini_api_base(config));
+            let list = send_json(authorized(PreparedRequest::get(&url), config), config).await?;
+            let caches = list.get("cachedContents").and_then(|caches| caches.as_array()).cloned().unwrap_or_default();
+            if caches.is_empty() {
+                println!("キャッシュはありません");
+            }
+            for cache in &caches {
+                println!("{}", describe(cache));
+            }
+            Ok(())
+        }
+        CacheCommand::Extend { name, ttl_secs } => {
+            let url = format!("{}/{}?updateMask=ttl", gemini_api_base(config), gemini_cache_name(&name));
+            let request = authorized(PreparedRequest::patch(&url, serde_json::json!({ "ttl": ttl(ttl_secs) })), config);
+            let cache = send_json(request, config).await?;
+            println!("期限を延ばしました: {}", describe(&cache));
+            Ok(())
+        }
+        CacheCommand::Delete { name } => {
+            let name = gemini_cache_name(&name);
+            let url = format!("{}/{}", gemini_api_base(config), name);
+            send_json(authorized(PreparedRequest::delete(&url), config), config).await?;
+            println!("{} を削除しました", name);
+            Ok(())
+        }
+    }
+}
+
+// キャッシュを作るリクエスト（資料は1つの user メッセージにまとめて入れる）
+fn create_request(config: &Config, documents: &[String], system: Option<&String>, ttl_secs: Option<u64>, display_name: Option<&str>) -> Result<PreparedRequest, Error> {
+    if documents.is_empty() && system.is_none() {
+        return Err(Error::Config("キャッシュにするファイルか system メッセージを指定してください".to_string()));
+    }
+    let mut body = serde_json::json!({ "model": format!("models/{}", config.model_name) });
+    if !documents.is_empty() {
+        body["contents"] = serde_json::json!([{ "role": "user", "parts": [{ "text": documents.join("\n\n") }] }]);
+    }
+    if let Some(system) = system {
+        body["systemInstruction"] = serde_json::json!({ "parts": [{ "text": system }] });
+    }
+    if let Some(secs) = ttl_secs {
+        body["ttl"] = serde_json::json!(ttl(secs));
+    }
+    if let Some(display_name) = display_name {
+        body["displayName"] = serde_json::json!(display_name);
+    }
+    let url = format!("{}/cachedContents", gemini_api_base(config));
+    Ok(authorized(PreparedRequest::new(&url, body), config))
+}
+
+// APIキーがあれば x-goog-api-key を付ける
+fn authorized(request: PreparedRequest, config: &Config) -> PreparedRequest {
+    match &config.api_key {
+        Some(api_key) => request.header("x-goog-api-key", api_key.clone()),
+        None => request,
+    }
+}
+
+// ttl は "3600s" の形で送る
+fn ttl(secs: u64) -> String {
+    format!("{}s", secs)
+}
+
+fn field(cache: &Value, key: &str) -> String {
+    cache.get(key).and_then(|v| v.as_str()).unwrap_or("?").to_string()
+}
+
+// 1行で表示する（名前、表示名、モデル、トークン数、期限）
+fn describe(cache: &Value) -> String {
+    let tokens = cache.pointer("/usageMetadata/totalTokenCount").and_then(|v| v.as_u64())
+        .map(|tokens| format!("{} トークン", tokens))
+        .unwrap_or_else(|| "? トークン".to_string());
+    let display_name = cache.get("displayName").and_then(|v| v.as_str())
+        .map(|name| format!("  「{}」", name))
+        .unwrap_or_default();
+    format!("{}{}  {}  {}  期限 {}", field(cache, "name"), display_name, field(cache, "model"), tokens, field(cache, "expireTime"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        serde_json::from_value(serde_json::json!({
+            "model_name": "gemini-2.0-flash", "use_local_model": false, "openai_compatible": false,
+            "provider": "gemini", "api_key": "key",
+        })).unwrap()
+    }
+
+    #[test]
+    fn creates_a_cache_for_the_current_model() {
+        let system = "長い前置き".to_string();
+        let request = create_request(&config(), &[inline_text("a.txt", "資料")], Some(&system), Some(600), Some("手順書")).unwrap();
+        assert_eq!(request.url, "https://generativelanguage.googleapis.com/v1beta/cachedContents");
+        assert!(request.headers.contains(&("x-goog-api-key".to_string(), "key".to_string())));
+        assert_eq!(request.body["model"], "models/gemini-2.0-flash");
+        assert!(request.body["contents"][0]["parts"][0]["text"].as_str().unwrap().contains("資料"));
+        assert_eq!(request.body["systemInstruction"]["parts"][0]["text"], "長い前置き");
+        assert_eq!(request.body["ttl"], "600s");
+        assert_eq!(request.body["displayName"], "手順書");
+    }
+
+    #[test]
+    fn needs_something_to_cache() {
+        assert!(matches!(create_request(&config(), &[], None, None, None), Err(Error::Config(_))));
+        let request = create_request(&config(), &[], Some(&"前置き".to_string()), None, None).unwrap();
+        assert!(request.body.get("contents").is_none());
+        assert!(request.body.get("ttl").is_none());
+    }
+
+    #[test]
+    fn describes_a_cache_on_one_line() {
+        let cache = serde_json::json!({
+            "name": "cachedContents/abc", "displayName": "手順書", "model": "models/gemini-2.0-flash",
+            "usageMetadata": { "totalTokenCount": 4096 }, "expireTime": "2026-10-14T07:00:00Z",
+        });
+        assert_eq!(describe(&cache), "cachedContents/abc  「手順書」  models/gemini-2.0-flash  4096 トークン  期限 2026-10-14T07:00:00Z");
+    }
+}
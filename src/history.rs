@@ -0,0 +1,216 @@
+// 保存したセッションの集計（history stats）
+//
+// よく使うモデル、よく使う時間帯（UTC）、セッションあたりのやりとりの回数、よく使うテンプレートを、表かJSONで表示する。
+// 時刻のない古いメッセージは、セッションIDの先頭（始めた時刻）で数える。
+// テンプレートは new --template で始めたセッションの最初のメッセージに残した名前で数える（それより前のセッションは数えない）。
+// 応答時間は、AIのメッセージに残した全体の時間と処理時間の内訳から、モデルごとに平均する（残していない古いメッセージは数えない）。
+use std::collections::HashMap;
+use std::time::Duration;
+use crate::{sessions, Config};
+use crate::completion::Timing;
+use crate::conversation::Message;
+
+// 表に出すモデル・時間帯・テンプレートの数
+const TOP_ENTRIES: usize = 5;
+
+// 時間帯の棒グラフの最大の長さ
+const MAX_BAR_CHARS: usize = 30;
+
+pub fn run(json: bool, config: &Config) -> Result<(), String> {
+    let sessions = sessions::load_all(config);
+    let session_count = sessions.len();
+    let mut turns = 0;
+    let mut models: HashMap<String, u64> = HashMap::new();
+    let mut hours = [0u64; 24];
+    for (id, messages) in &sessions {
+        let started: Option<u64> = id.split('-').next().and_then(|seconds| seconds.parse().ok());
+        for message in messages {
+            match message.role.as_str() {
+                "user" => {
+                    turns += 1;
+                    if let Some(seconds) = message.timestamp.or(started) {
+                        hours[(seconds / 3600 % 24) as usize] += 1;
+                    }
+                }
+                "assistant" => {
+                    let model = message.model.clone().unwrap_or_else(|| "（不明）".to_string());
+                    *models.entry(model).or_default() += 1;
+                }
+                _ => {}
+            }
+        }
+    }
+    let mut top_models: Vec<(String, u64)> = models.into_iter().collect();
+    top_models.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    let mut top_hours: Vec<(usize, u64)> = hours.iter().copied().enumerate().filter(|(_, count)| *count > 0).collect();
+    top_hours.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    let top_templates = template_counts(&sessions);
+    let latencies = latencies(&sessions);
+    let average_turns = if session_count == 0 { 0.0 } else { turns as f64 / session_count as f64 };
+
+    if json {
+        let stats = serde_json::json!({
+            "sessions": session_count,
+            "turns": turns,
+            "average_turns_per_session": average_turns,
+            "models": top_models.iter().map(|(model, count)| serde_json::json!({ "model": model, "responses": count })).collect::<Vec<_>>(),
+            "hours_utc": hours,
+            "templates": top_templates.iter().map(|(template, count)| serde_json::json!({ "template": template, "sessions": count })).collect::<Vec<_>>(),
+            "latency": latencies.iter().map(|(model, latency)| {
+                let mut entry = serde_json::json!({ "model": model, "responses": latency.responses, "average_ms": latency.average().as_millis() as u64 });
+                if let Some(timing) = latency.average_timing() {
+                    let connection = latency.average_timed().saturating_sub(timing.queue + timing.prompt + timing.generation);
+                    entry["average_timing"] = serde_json::json!(timing);
+                    entry["average_timing"]["connection_ms"] = serde_json::json!(connection.as_millis() as u64);
+                }
+                entry
+            }).collect::<Vec<_>>(),
+        });
+        println!("{}", stats);
+        return Ok(());
+    }
+
+    println!("セッション数: {}（やりとり {}回、1セッションあたり平均 {:.1}回）", session_count, turns, average_turns);
+    println!();
+    println!("よく使うモデル");
+    let width = top_models.iter().take(TOP_ENTRIES).map(|(model, _)| model.chars().count()).max().unwrap_or(0);
+    for (model, count) in top_models.iter().take(TOP_ENTRIES) {
+        println!("  {}{}  {:>5}回", model, " ".repeat(width - model.chars().count()), count);
+    }
+    println!();
+    println!("よく使う時間帯（UTC）");
+    let busiest = top_hours.first().map(|(_, count)| *count).unwrap_or(1);
+    for (hour, count) in top_hours.iter().take(TOP_ENTRIES) {
+        let bar = "#".repeat(((*count as f64 / busiest as f64) * MAX_BAR_CHARS as f64).ceil() as usize);
+        println!("  {:02}時台  {:>5}回  {}", hour, count, bar);
+    }
+    println!();
+    println!("よく使うテンプレート");
+    if top_templates.is_empty() {
+        println!("  （new --template で始めたセッションはありません）");
+    }
+    let width = top_templates.iter().take(TOP_ENTRIES).map(|(template, _)| template.chars().count()).max().unwrap_or(0);
+    for (template, count) in top_templates.iter().take(TOP_ENTRIES) {
+        println!("  {}{}  {:>5}セッション", template, " ".repeat(width - template.chars().count()), count);
+    }
+    println!();
+    println!("モデルごとの平均応答時間");
+    if latencies.is_empty() {
+        println!("  （応答時間を残したメッセージはありません）");
+    }
+    let width = latencies.iter().map(|(model, _)| model.chars().count()).max().unwrap_or(0);
+    for (model, latency) in &latencies {
+        println!("  {}{}  {:>7.2}秒（{}回）", model, " ".repeat(width - model.chars().count()), latency.average().as_secs_f64(), latency.responses);
+        if let Some(timing) = latency.average_timing() {
+            println!("  {}  {}", " ".repeat(width), timing.breakdown(latency.average_timed()));
+        }
+    }
+    Ok(())
+}
+
+// 1つのモデルの応答時間の合計（内訳は、プロバイダーが返した応答の分だけ足す）
+#[derive(Default)]
+struct Latency {
+    responses: u32,
+    total: Duration,
+    timing: Option<Timing>,
+    timed_total: Duration, // 内訳のある応答の、全体の時間の合計
+    timed: u32,
+}
+
+impl Latency {
+    fn average(&self) -> Duration {
+        self.total / self.responses.max(1)
+    }
+
+    fn average_timed(&self) -> Duration {
+        self.timed_total / self.timed.max(1)
+    }
+
+    fn average_timing(&self) -> Option<Timing> {
+        let timing = self.timing?;
+        Some(Timing {
+            queue: timing.queue / self.timed,
+            prompt: timing.prompt / self.timed,
+            generation: timing.generation / self.timed,
+        })
+    }
+}
+
+// モデルごとの応答時間（応答の多い順、同じ数なら名前順）
+fn latencies(sessions: &[(String, Vec<Message>)]) -> Vec<(String, Latency)> {
+    let mut latencies: HashMap<String, Latency> = HashMap::new();
+    for message in sessions.iter().flat_map(|(_, messages)| messages) {
+        let Some(elapsed) = message.latency_ms.filter(|_| message.role == "assistant").map(Duration::from_millis) else {
+            continue;
+        };
+        let model = message.model.clone().unwrap_or_else(|| "（不明）".to_string());
+        let latency = latencies.entry(model).or_default();
+        latency.responses += 1;
+        latency.total += elapsed;
+        if message.timing.is_some() {
+            latency.timing = Timing::add(latency.timing, message.timing);
+            latency.timed_total += elapsed;
+            latency.timed += 1;
+        }
+    }
+    let mut latencies: Vec<(String, Latency)> = latencies.into_iter().collect();
+    latencies.sort_by(|a, b| b.1.responses.cmp(&a.1.responses).then_with(|| a.0.cmp(&b.0)));
+    latencies
+}
+
+// テンプレートごとの、それで始めたセッションの数（多い順、同じ数なら名前順）
+fn template_counts(sessions: &[(String, Vec<Message>)]) -> Vec<(String, u64)> {
+    let mut counts: HashMap<String, u64> = HashMap::new();
+    for (_, messages) in sessions {
+        if let Some(template) = messages.iter().find_map(|message| message.template.clone()) {
+            *counts.entry(template).or_default() += 1;
+        }
+    }
+    let mut counts: Vec<(String, u64)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(template: Option<&str>) -> (String, Vec<Message>) {
+        let first = Message { template: template.map(str::to_string), ..Message::new("user", "こんにちは") };
+        ("1700000000-1".to_string(), vec![first, Message::new("assistant", "はい")])
+    }
+
+    #[test]
+    fn counts_sessions_per_template() {
+        let sessions = [session(Some("review")), session(None), session(Some("translate")), session(Some("review"))];
+        assert_eq!(template_counts(&sessions), [("review".to_string(), 2), ("translate".to_string(), 1)]);
+    }
+
+    #[test]
+    fn latency_is_averaged_per_model_from_the_saved_timing() {
+        let answer = |latency_ms: u64, timing: Option<Timing>| Message {
+            model: Some("llama3".to_string()), latency_ms: Some(latency_ms), timing, ..Message::new("assistant", "はい")
+        };
+        let timing = |generation: u64| Timing { queue: Duration::ZERO, prompt: Duration::from_millis(200), generation: Duration::from_millis(generation) };
+        let messages = vec![
+            answer(1000, Some(timing(600))),
+            answer(3000, Some(timing(2400))),
+            answer(5000, None),
+            Message::new("assistant", "時間を残す前のメッセージ"),
+        ];
+        let latencies = latencies(&[("1700000000-1".to_string(), messages)]);
+        let (model, latency) = &latencies[0];
+        assert_eq!((model.as_str(), latency.responses, latency.average()), ("llama3", 3, Duration::from_secs(3)));
+        // 内訳は返ってきた応答だけで平均する（接続などは 2秒 - 0.2秒 - 1.5秒）
+        assert_eq!(latency.average_timing().unwrap().generation, Duration::from_millis(1500));
+        assert!(latency.average_timing().unwrap().breakdown(latency.average_timed()).contains("接続など 0.30秒"));
+    }
+
+    #[test]
+    fn a_session_counts_its_template_once() {
+        let (id, mut messages) = session(Some("review"));
+        messages.push(Message { template: Some("review".to_string()), ..Message::new("user", "もう一度") });
+        assert_eq!(template_counts(&[(id, messages)]), [("review".to_string(), 1)]);
+    }
+}
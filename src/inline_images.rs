@@ -0,0 +1,112 @@
+// 応答に含まれる画像（data URI の Markdown 画像）をターミナルに表示する
+//
+// kitty と iTerm2 の画像プロトコルに対応したターミナルではその場に表示し、
+// それ以外のターミナルでは一時ディレクトリに保存してパスを表示する。
+use base64::Engine;
+#[cfg(feature = "native")]
+use crate::runtime;
+
+// kitty の画像プロトコルで1回に送れるデータの大きさ
+const KITTY_CHUNK_BYTES: usize = 4096;
+
+// 応答から取り出した画像
+pub struct InlineImage {
+    pub alt: String,
+    pub bytes: Vec<u8>,
+    pub extension: String,
+}
+
+// 本文の ![説明](data:image/png;base64,...) を取り出して、(画像の位置に印を入れた本文, 画像) に分ける
+pub fn extract(text: &str) -> (String, Vec<InlineImage>) {
+    let mut images = Vec::new();
+    let mut output = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("![") {
+        let Some(image) = parse_image(&rest[start..]) else {
+            output.push_str(&rest[..start + 2]);
+            rest = &rest[start + 2..];
+            continue;
+        };
+        let (alt, bytes, extension, length) = image;
+        output.push_str(&rest[..start]);
+        output.push_str(&format!("[画像{}: {}]", images.len() + 1, alt));
+        images.push(InlineImage { alt, bytes, extension });
+        rest = &rest[start + length..];
+    }
+    output.push_str(rest);
+    (output, images)
+}
+
+// "![説明](data:image/xxx;base64,...)" を読み取る（説明, 中身, 拡張子, 読んだ長さ）
+fn parse_image(text: &str) -> Option<(String, Vec<u8>, String, usize)> {
+    let alt_end = text.find("](")?;
+    let alt = text[2..alt_end].to_string();
+    let url_start = alt_end + 2;
+    let url_end = url_start + text[url_start..].find(')')?;
+    let url = text[url_start..url_end].trim();
+    let (mime, data) = url.strip_prefix("data:image/")?.split_once(";base64,")?;
+    let bytes = base64::engine::general_purpose::STANDARD.decode(data.trim()).ok()?;
+    let extension = if mime == "jpeg" { "jpg" } else { mime };
+    Some((alt, bytes, extension.to_string(), url_end + 1))
+}
+
+// 画像を表示する（mode は "auto"（デフォルト） / "kitty" / "iterm" / "save"）
+pub fn display(index: usize, image: &InlineImage, mode: Option<&str>) -> String {
+    let protocol = match mode.unwrap_or("auto") {
+        "auto" => detect_protocol(),
+        other => other,
+    };
+    match protocol {
+        "kitty" if image.extension == "png" => kitty(&image.bytes),
+        "iterm" => iterm(&image.bytes),
+        _ => save(index, image),
+    }
+}
+
+// 環境変数から、画像プロトコルに対応したターミナルかどうかを判別する
+fn detect_protocol() -> &'static str {
+    let term = std::env::var("TERM").unwrap_or_default();
+    let program = std::env::var("TERM_PROGRAM").unwrap_or_default();
+    if std::env::var("KITTY_WINDOW_ID").is_ok() || term == "xterm-kitty" || program == "ghostty" {
+        "kitty"
+    } else if program == "iTerm.app" || program == "WezTerm" {
+        "iterm"
+    } else {
+        "save"
+    }
+}
+
+// kitty のグラフィックスプロトコル（PNGのみ。4096バイトずつ分けて送る）
+fn kitty(bytes: &[u8]) -> String {
+    let encoded = base64::engine::general_purpose::STANDARD.encode(bytes);
+    let chunks: Vec<&[u8]> = encoded.as_bytes().chunks(KITTY_CHUNK_BYTES).collect();
+    let mut output = String::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = if i + 1 < chunks.len() { 1 } else { 0 };
+        let control = if i == 0 { format!("f=100,a=T,m={}", more) } else { format!("m={}", more) };
+        output.push_str(&format!("\x1b_G{};{}\x1b\\", control, String::from_utf8_lossy(chunk)));
+    }
+    output
+}
+
+// iTerm2 のインライン画像プロトコル
+fn iterm(bytes: &[u8]) -> String {
+    let encoded = base64::engine::general_purpose::STANDARD.encode(bytes);
+    format!("\x1b]1337;File=inline=1;size={}:{}\x07", bytes.len(), encoded)
+}
+
+// 表示できないターミナルでは、一時ディレクトリに保存してパスを返す
+#[cfg(feature = "native")]
+fn save(index: usize, image: &InlineImage) -> String {
+    let path = std::env::temp_dir().join(format!("response-{}-{}.{}", runtime::process_id(), index, image.extension));
+    match std::fs::write(&path, &image.bytes) {
+        Ok(()) => format!("（画像{}「{}」を保存しました: {}）", index, image.alt, path.display()),
+        Err(e) => format!("（画像{}の保存に失敗しました: {:?}）", index, e),
+    }
+}
+
+// ファイルを使えない環境（wasm32 など）では保存しない
+#[cfg(not(feature = "native"))]
+fn save(index: usize, image: &InlineImage) -> String {
+    format!("（画像{}「{}」はこの環境では保存できません）", index, image.alt)
+}
@@ -0,0 +1,150 @@
+// 審査役のモデルに、候補の応答を採点してもらう（LLM-as-judge）
+//
+//   入力: {"id": 1, "prompt": "質問", "response": "候補の応答"}
+//         {"id": 1, "prompt": "質問", "responses": [{"model": "a", "text": "..."}, "..."], "reference": "模範解答"}
+//   出力: {"id": 1, "candidate": "a", "score": 8, "reason": "...", "judge": "審査役のモデル"}
+//
+// 採点の指示（ルーブリック）は Handlebars のテンプレートで、prompt / response / reference を使える。
+// 審査役には {"score": 点数, "reason": "理由"} のJSONで答えてもらい、読めなければ最初の数値を点数とみなす。
+use std::fs::File;
+use std::io::{self, Write};
+use serde::Deserialize;
+use serde_json::Value;
+use crate::{format, profiles, Config};
+
+const DEFAULT_RUBRIC: &str = "\
+あなたは公平な審査員です。次の質問に対する回答を、正確さ・役に立つか・簡潔さの観点で1から10の整数で採点してください。
+{{#if reference}}模範解答も参考にしてください。
+{{/if}}
+## 質問
+{{prompt}}
+{{#if reference}}
+## 模範解答
+{{reference}}
+{{/if}}
+## 回答
+{{response}}
+
+次のJSONだけを出力してください: {\"score\": 点数, \"reason\": \"理由（1〜2文）\"}";
+
+// config.json の "judge" に書く設定
+#[derive(Clone, Deserialize, Default)]
+pub struct JudgeConfig {
+    pub model: Option<String>, // 審査役のプロファイルかモデル（"モデル名@行き先" や別名。省略すると今のモデル）
+    pub rubric: Option<String>, // 採点の指示のテンプレート（"@ファイル名" でファイルから読む）
+}
+
+// 採点する1件（候補が1つなら "response"、複数なら "responses"）
+#[derive(Deserialize)]
+struct Candidates {
+    #[serde(default)]
+    id: Value,
+    prompt: String,
+    #[serde(default)]
+    reference: Option<String>,
+    #[serde(default)]
+    response: Option<String>,
+    #[serde(default)]
+    responses: Vec<Value>,
+}
+
+// judge サブコマンド（judge_model と rubric は config.json の "judge" より優先する。output を省略すると標準出力に書く）
+pub struct JudgeCommand {
+    pub path: String,
+    pub judge_model: Option<String>,
+    pub rubric: Option<String>,
+    pub output: Option<String>,
+}
+
+pub async fn run(command: JudgeCommand, config: &Config) -> Result<(), String> {
+    let settings = config.judge.clone().unwrap_or_default();
+    let rubric = match command.rubric.or(settings.rubric) {
+        Some(rubric) => format::load_template(&rubric)?,
+        None => DEFAULT_RUBRIC.to_string(),
+    };
+
+    // 審査役は履歴を持たず、ストリーミングもしない
+    let mut judge_config = config.clone();
+    if let Some(model) = command.judge_model.or(settings.model) {
+        profiles::select(&mut judge_config, &model);
+    }
+    judge_config.history.clear();
+    judge_config.stream = false;
+
+    let data = std::fs::read_to_string(&command.path)
+        .map_err(|e| format!("候補のファイルの読み込みに失敗しました: {:?}", e))?;
+    let mut output: Box<dyn Write> = match command.output {
+        Some(out_path) => Box::new(File::create(&out_path)
+            .map_err(|e| format!("出力ファイルを作れませんでした: {:?}", e))?),
+        None => Box::new(io::stdout()),
+    };
+
+    let mut scores = Vec::new();
+    for (i, line) in data.lines().filter(|line| !line.trim().is_empty()).enumerate() {
+        let item: Candidates = serde_json::from_str(line)
+            .map_err(|e| format!("{}行目のパースに失敗しました: {}", i + 1, e))?;
+        let candidates: Vec<(Value, String)> = match &item.response {
+            Some(response) => vec![(Value::Null, response.clone())],
+            None => item.responses.iter().enumerate()
+                .map(|(index, candidate)| match candidate {
+                    Value::String(text) => (serde_json::json!(index), text.clone()),
+                    _ => (
+                        candidate.get("model").cloned().unwrap_or(serde_json::json!(index)),
+                        candidate.get("text").and_then(|t| t.as_str()).unwrap_or_default().to_string(),
+                    ),
+                })
+                .collect(),
+        };
+        for (candidate, response) in candidates {
+            let prompt = format::render(&rubric, &serde_json::json!({
+                "prompt": item.prompt,
+                "response": response,
+                "reference": item.reference,
+            }))?;
+            let verdict = crate::respond(&prompt, &judge_config).await;
+            let (score, reason) = parse_verdict(&verdict.text);
+            if let Some(score) = score {
+                scores.push(score);
+            }
+            let result = serde_json::json!({
+                "id": item.id,
+                "candidate": candidate,
+                "score": score,
+                "reason": reason,
+                "judge": judge_config.model_name,
+            });
+            writeln!(output, "{}", result)
+                .map_err(|e| format!("採点結果の書き込みに失敗しました: {:?}", e))?;
+        }
+    }
+
+    // 結果のJSONだけを標準出力に書けるよう、まとめは標準エラーに出す
+    if scores.is_empty() {
+        eprintln!("採点できた候補はありません");
+    } else {
+        let mean = scores.iter().sum::<f64>() / scores.len() as f64;
+        eprintln!("{}件を採点しました（平均 {:.2}点）", scores.len(), mean);
+    }
+    Ok(())
+}
+
+// 審査役の答えから点数と理由を読む
+fn parse_verdict(text: &str) -> (Option<f64>, String) {
+    let json = text.find('{')
+        .zip(text.rfind('}'))
+        .and_then(|(start, end)| serde_json::from_str::<Value>(&text[start..=end]).ok());
+    if let Some(json) = json {
+        let score = json.get("score").and_then(|score| match score {
+            Value::String(score) => score.trim().parse().ok(),
+            score => score.as_f64(),
+        });
+        let reason = json.get("reason").and_then(|r| r.as_str()).unwrap_or_default().to_string();
+        if score.is_some() {
+            return (score, reason);
+        }
+    }
+    // JSONで答えなかったときは、最初に出てくる数値を点数とみなす
+    let score = text.split(|c: char| !c.is_ascii_digit() && c != '.')
+        .find_map(|word| word.trim_matches('.').parse().ok());
+    (score, text.trim().to_string())
+}
@@ -0,0 +1,315 @@
+// --tui のキーの割り当て（"tui_keys"）と入力欄の操作（"tui_input_mode"）
+//
+// "tui_keys" には動作ごとにキーを書く（"send": "ctrl+s" や "newline": ["enter", "ctrl+j"]。[] ならその動作を外す）。
+// 書いた動作はデフォルトのキーを置き換え、書いたキーはほかの動作のデフォルトからも外す。
+// キーは "ctrl+" "alt+" "shift+" に続けて、1文字か enter / esc / tab / backspace / up / down / left / right /
+// pageup / pagedown / home / end / space / f1〜f12 を書く。
+// "tui_input_mode": "vi" なら、入力欄で Esc（か stop のキー）を押すとノーマルモードになり、i / a で入力に戻る。
+use std::collections::HashMap;
+use std::fmt;
+use serde::Deserialize;
+use crate::Config;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum KeyName {
+    Char(char),
+    Enter,
+    Esc,
+    Tab,
+    Backspace,
+    Up,
+    Down,
+    Left,
+    Right,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    F(u8),
+}
+
+// 修飾キーつきの1つのキー（文字のキーの Shift は文字の大文字小文字に含める）
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct KeyBinding {
+    pub name: KeyName,
+    pub control: bool,
+    pub alt: bool,
+    pub shift: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    Send,
+    Newline,
+    ScrollUp,
+    ScrollDown,
+    PageUp,
+    PageDown,
+    Stop, // 生成を止めて、そこまでを答えとして残す
+    Cancel, // 生成を中断して捨てる（入力中なら入力を消し、空なら終わる）
+    Quit,
+    SwitchSession, // 保存済みの次のセッション（ひとつ古いもの）に切り替える
+    DeleteWord,
+    ClearInput,
+}
+
+// 設定に書く動作の名前と、デフォルトのキー
+const ACTIONS: [(&str, Action, &[&str]); 12] = [
+    ("send", Action::Send, &["enter"]),
+    ("newline", Action::Newline, &["ctrl+j", "alt+enter", "shift+enter"]),
+    ("scroll_up", Action::ScrollUp, &["up"]),
+    ("scroll_down", Action::ScrollDown, &["down"]),
+    ("page_up", Action::PageUp, &["pageup"]),
+    ("page_down", Action::PageDown, &["pagedown"]),
+    ("stop", Action::Stop, &["esc"]),
+    ("cancel", Action::Cancel, &["ctrl+c"]),
+    ("quit", Action::Quit, &["ctrl+d"]),
+    ("switch_session", Action::SwitchSession, &["ctrl+o"]),
+    ("delete_word", Action::DeleteWord, &["ctrl+w", "alt+backspace"]),
+    ("clear_input", Action::ClearInput, &["ctrl+u"]),
+];
+
+const INPUT_MODES: [&str; 2] = ["emacs", "vi"];
+
+// "tui_keys" の値（キー1つか、その配列）
+#[derive(Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Keys {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Keys {
+    fn list(&self) -> Vec<&str> {
+        match self {
+            Keys::One(key) => vec![key.as_str()],
+            Keys::Many(keys) => keys.iter().map(String::as_str).collect(),
+        }
+    }
+}
+
+impl KeyBinding {
+    pub fn parse(text: &str) -> Result<KeyBinding, String> {
+        let mut binding = KeyBinding { name: KeyName::Enter, control: false, alt: false, shift: false };
+        let mut parts: Vec<&str> = text.split('+').collect();
+        // "ctrl++" のように、最後の + はキーそのもの
+        if text.ends_with("++") || text == "+" {
+            parts.retain(|part| !part.is_empty());
+            parts.push("+");
+        }
+        let Some(key) = parts.pop().filter(|key| !key.is_empty()) else {
+            return Err(format!("キー {:?} が読めません", text));
+        };
+        for modifier in parts {
+            match modifier.to_lowercase().as_str() {
+                "ctrl" | "control" => binding.control = true,
+                "alt" | "meta" => binding.alt = true,
+                "shift" => binding.shift = true,
+                _ => return Err(format!("キー {:?} の修飾キー {} がわかりません（ctrl / alt / shift）", text, modifier)),
+            }
+        }
+        binding.name = match key.to_lowercase().as_str() {
+            "enter" | "return" => KeyName::Enter,
+            "esc" | "escape" => KeyName::Esc,
+            "tab" => KeyName::Tab,
+            "backspace" => KeyName::Backspace,
+            "up" => KeyName::Up,
+            "down" => KeyName::Down,
+            "left" => KeyName::Left,
+            "right" => KeyName::Right,
+            "pageup" => KeyName::PageUp,
+            "pagedown" => KeyName::PageDown,
+            "home" => KeyName::Home,
+            "end" => KeyName::End,
+            "space" => KeyName::Char(' '),
+            name => match (name.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()), key.chars().count()) {
+                (Some(n @ 1..=12), _) => KeyName::F(n),
+                (_, 1) => KeyName::Char(key.chars().next().unwrap()),
+                _ => return Err(format!("キー {:?} の {} がわかりません", text, key)),
+            },
+        };
+        Ok(binding.normalized())
+    }
+
+    // 文字のキーは、Shift を大文字にして、Ctrl / Alt つきなら小文字にそろえる（端末によって届き方が違うため）
+    pub fn normalized(mut self) -> KeyBinding {
+        if let KeyName::Char(c) = self.name {
+            let c = if self.shift { c.to_ascii_uppercase() } else { c };
+            let c = if self.control || self.alt { c.to_ascii_lowercase() } else { c };
+            self.name = KeyName::Char(c);
+            self.shift = false;
+        }
+        self
+    }
+}
+
+// 状態の行に出す書き方（"Ctrl+J" / "Enter" / "PgUp"）
+impl fmt::Display for KeyBinding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (on, modifier) in [(self.control, "Ctrl+"), (self.alt, "Alt+"), (self.shift, "Shift+")] {
+            if on {
+                f.write_str(modifier)?;
+            }
+        }
+        match self.name {
+            KeyName::Char(' ') => f.write_str("Space"),
+            KeyName::Char(c) if self.control || self.alt => write!(f, "{}", c.to_ascii_uppercase()),
+            KeyName::Char(c) => write!(f, "{}", c),
+            KeyName::F(n) => write!(f, "F{}", n),
+            KeyName::PageUp => f.write_str("PgUp"),
+            KeyName::PageDown => f.write_str("PgDn"),
+            KeyName::Up => f.write_str("↑"),
+            KeyName::Down => f.write_str("↓"),
+            KeyName::Left => f.write_str("←"),
+            KeyName::Right => f.write_str("→"),
+            name => write!(f, "{:?}", name),
+        }
+    }
+}
+
+// キーから動作を引く表
+#[derive(Clone, Debug)]
+pub struct Keymap {
+    bindings: Vec<(KeyBinding, Action)>,
+    vi: bool,
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        Keymap::new(&HashMap::new(), None).unwrap()
+    }
+}
+
+impl Keymap {
+    fn new(keys: &HashMap<String, Keys>, mode: Option<&str>) -> Result<Keymap, Vec<String>> {
+        let mut problems = Vec::new();
+        let mut bindings: Vec<(KeyBinding, Action)> = Vec::new();
+        let mut names: Vec<&String> = keys.keys().collect();
+        names.sort();
+        for name in names {
+            let Some((_, action, _)) = ACTIONS.iter().find(|(action, _, _)| action == name) else {
+                let actions: Vec<&str> = ACTIONS.iter().map(|(action, _, _)| *action).collect();
+                problems.push(format!("tui_keys の {} は {} のどれかにしてください", name, actions.join(" / ")));
+                continue;
+            };
+            for key in keys[name].list() {
+                match KeyBinding::parse(key) {
+                    Ok(binding) => match bindings.iter().find(|(other, _)| *other == binding) {
+                        Some((_, other)) if other != action => {
+                            problems.push(format!("tui_keys の {} が {} と {} の両方に割り当てられています", key, action_name(*other), name));
+                        }
+                        _ => bindings.push((binding, *action)),
+                    },
+                    Err(e) => problems.push(format!("tui_keys.{}: {}", name, e)),
+                }
+            }
+        }
+        // 書いていない動作はデフォルトのキーにする（書いた動作が使っているキーは除く）
+        for (name, action, defaults) in ACTIONS {
+            if keys.contains_key(name) {
+                continue;
+            }
+            for key in defaults {
+                let binding = KeyBinding::parse(key).unwrap();
+                if !bindings.iter().any(|(other, _)| *other == binding) {
+                    bindings.push((binding, action));
+                }
+            }
+        }
+        if let Some(mode) = mode.filter(|mode| !INPUT_MODES.contains(mode)) {
+            problems.push(format!("tui_input_mode は {} のどれかにしてください（{}）", INPUT_MODES.join(" / "), mode));
+        }
+        if !problems.is_empty() {
+            return Err(problems);
+        }
+        Ok(Keymap { bindings, vi: mode == Some("vi") })
+    }
+
+    pub(crate) fn from_config(config: &Config) -> Result<Keymap, Vec<String>> {
+        Keymap::new(&config.tui_keys, config.tui_input_mode.as_deref())
+    }
+
+    pub fn action(&self, key: &KeyBinding) -> Option<Action> {
+        let key = key.normalized();
+        self.bindings.iter().find(|(binding, _)| *binding == key).map(|(_, action)| *action)
+    }
+
+    // 動作に割り当てたキーの書き方（割り当てがなければ None）
+    pub fn label(&self, action: Action) -> Option<String> {
+        self.bindings.iter().find(|(_, other)| *other == action).map(|(binding, _)| binding.to_string())
+    }
+
+    // 入力欄を vi のように操作する（Esc でノーマルモードにする）
+    pub fn is_vi(&self) -> bool {
+        self.vi
+    }
+}
+
+fn action_name(action: Action) -> &'static str {
+    ACTIONS.iter().find(|(_, other, _)| *other == action).map(|(name, _, _)| *name).unwrap_or_default()
+}
+
+// 設定ファイルを読んだときに、割り当てに問題がないか調べる
+pub(crate) fn check(config: &Config) -> Vec<String> {
+    Keymap::from_config(config).err().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(text: &str) -> KeyBinding {
+        KeyBinding::parse(text).unwrap()
+    }
+
+    fn keymap(keys: serde_json::Value, mode: Option<&str>) -> Result<Keymap, Vec<String>> {
+        Keymap::new(&serde_json::from_value(keys).unwrap(), mode)
+    }
+
+    #[test]
+    fn parses_keys_with_modifiers() {
+        assert_eq!(key("Ctrl+J"), KeyBinding { name: KeyName::Char('j'), control: true, alt: false, shift: false });
+        assert_eq!(key("shift+a"), key("A"));
+        assert_eq!(key("alt+enter"), KeyBinding { name: KeyName::Enter, control: false, alt: true, shift: false });
+        assert_eq!(key("ctrl++").name, KeyName::Char('+'));
+        assert_eq!(key("f5").name, KeyName::F(5));
+        assert_eq!(key("ctrl+j").to_string(), "Ctrl+J");
+        assert_eq!(key("pageup").to_string(), "PgUp");
+        assert!(KeyBinding::parse("hyper+x").is_err());
+        assert!(KeyBinding::parse("ctrl+nope").is_err());
+    }
+
+    #[test]
+    fn defaults_match_the_old_keys() {
+        let keymap = Keymap::default();
+        assert_eq!(keymap.action(&key("enter")), Some(Action::Send));
+        assert_eq!(keymap.action(&key("ctrl+j")), Some(Action::Newline));
+        assert_eq!(keymap.action(&key("esc")), Some(Action::Stop));
+        assert_eq!(keymap.action(&key("ctrl+c")), Some(Action::Cancel));
+        assert_eq!(keymap.action(&key("a")), None);
+        assert!(!keymap.is_vi());
+    }
+
+    #[test]
+    fn configured_keys_replace_the_defaults() {
+        let keymap = keymap(serde_json::json!({ "send": "ctrl+s", "newline": ["enter"], "stop": [] }), Some("vi")).unwrap();
+        assert_eq!(keymap.action(&key("ctrl+s")), Some(Action::Send));
+        assert_eq!(keymap.action(&key("enter")), Some(Action::Newline));
+        assert_eq!(keymap.action(&key("ctrl+j")), None);
+        assert_eq!(keymap.action(&key("esc")), None);
+        assert_eq!(keymap.label(Action::Send).as_deref(), Some("Ctrl+S"));
+        assert_eq!(keymap.label(Action::Stop), None);
+        assert!(keymap.is_vi());
+    }
+
+    #[test]
+    fn reports_unknown_actions_keys_and_conflicts() {
+        let problems = keymap(serde_json::json!({ "jump": "x", "send": "ctrl+q", "quit": "ctrl+q", "cancel": "ctrl+nope" }), Some("nano")).unwrap_err();
+        assert_eq!(problems, [
+            "tui_keys.cancel: キー \"ctrl+nope\" の nope がわかりません",
+            "tui_keys の jump は send / newline / scroll_up / scroll_down / page_up / page_down / stop / cancel / quit / switch_session / delete_word / clear_input のどれかにしてください",
+            "tui_keys の ctrl+q が quit と send の両方に割り当てられています",
+            "tui_input_mode は emacs / vi のどれかにしてください（nano）",
+        ]);
+    }
+}
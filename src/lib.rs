@@ -0,0 +1,975 @@
+// 複数のLLM（ローカル・オンライン）に同じ使い方でつなぐクライアントのライブラリ
+//
+// 設定（Config）を読み込んで Client を作り、complete / chat / stream で推論する。
+// chat_events なら、やりとりの流れを型つきのイベント（Event）で受け取れる。
+// 対話の1ターン分は ask でまとめて行える。コマンドラインのチャットクライアント（src/main.rs と src/cli）は、
+// 引数と画面を受け持つだけで、ここで公開している Client の操作だけを使う。
+// ほかのアプリに組み込むときは、互換を保つ Client::builder / ChatSession / StreamEvent を使う（src/api.rs）。
+// Rust 以外のアプリからは、共有ライブラリの C のAPI（src/ffi.rs と include/milti_llm_client.h）か、Python の拡張モジュール（src/python.rs）で使える。
+mod api;
+mod assistants;
+mod attachments;
+mod batch;
+mod benchmark;
+mod cassette;
+mod chunking;
+mod client;
+mod commands;
+mod compare;
+mod completion;
+mod compression;
+mod config_file;
+mod context_budget;
+mod conversation;
+mod error;
+mod events;
+#[cfg(feature = "native")]
+mod ffi;
+pub mod exit_code;
+mod files;
+mod finetune;
+mod gemini_cache;
+mod format;
+mod history;
+mod filters;
+mod inline_images;
+pub mod keybindings;
+mod middleware;
+mod judge;
+mod mock;
+mod pipeline;
+mod profiles;
+mod providers;
+#[cfg(feature = "python")]
+mod python;
+mod publish;
+mod queue;
+mod realtime;
+mod reasoning;
+mod reply_language;
+mod sampling;
+mod sessions;
+mod snapshots;
+mod speculative;
+mod stats;
+mod stream;
+mod templates;
+mod transcribe;
+mod transcript;
+mod request;
+mod router;
+mod runtime;
+mod tools;
+mod warmup;
+pub mod width;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use serde::Deserialize;
+use mock::MockConfig;
+use request::PreparedRequest;
+
+pub use api::{ChatSession, ClientBuilder, StreamEvent};
+pub use client::{Answer, AttachmentKind, Client, Comparison, ModelInfo, Options, Streamed};
+pub use commands::{
+    BatchCommand, CacheCommand, Command, FilesCommand, FinetuneCommand, JudgeCommand, QueueCommand, SessionCommand, TranscribeCommand,
+};
+pub use completion::{Completion, Timing, Usage};
+pub use conversation::Message;
+pub use error::{Error, ErrorKind};
+pub use events::Event;
+pub use stats::LiveThroughput;
+pub use stream::Token;
+pub use tools::ToolConfirmation;
+
+// reasoning_effort に指定できる値
+const REASONING_EFFORTS: [&str; 4] = ["minimal", "low", "medium", "high"];
+
+// "モデル名@行き先" の行き先に書けるローカルフレームワーク
+const LOCAL_FRAMEWORKS: [&str; 3] = ["python", "ollama", "mock"];
+
+// 設定ファイルの内容を保持する構造体（config.json と同じ形のJSONから作る）
+#[derive(Clone, Deserialize)]
+pub struct Config {
+    model_name: String,
+    #[serde(default)]
+    aliases: HashMap<String, String>, // モデル名の別名（例: "fast" → "gemma:2b@ollama"）
+    #[serde(default)]
+    profiles: HashMap<String, profiles::Profile>, // 名前つきのモデルの設定（接続先やAPIキーもまとめて切り替える）
+    #[serde(default)]
+    templates: HashMap<String, templates::ConversationTemplate>, // new --template で始める会話の型（system メッセージ・ファイル・ツール・最初のメッセージ）
+    profile: Option<String>, // 起動時に使うプロファイル（/model で切り替えると、今のプロファイルになる）
+    endpoint: Option<String>,
+    use_local_model: bool,
+    local_framework: Option<String>, // ローカルフレームワークの指定
+    openai_compatible: bool,
+    max_tokens: Option<u32>,
+    temperature: Option<f64>,
+    top_p: Option<f64>,
+    top_k: Option<u32>,
+    #[serde(default)]
+    stop: Vec<String>, // ここに書いた文字列が出てきたら生成を止める
+    presence_penalty: Option<f64>,
+    seed: Option<u64>, // 同じ値なら同じ答えになりやすくする（対応している接続先のみ）
+    api_key: Option<String>,
+    #[serde(default)]
+    dry_run: bool, // trueならリクエストを送信せず内容を表示するだけにする
+    record_path: Option<String>, // 指定すると通信内容をJSONLで記録する
+    mock: Option<MockConfig>, // local_framework が "mock" のときの設定
+    cassette: Option<String>, // 指定するとやりとりをカセットに記録・再生する
+    cassette_mode: Option<String>, // "record" / "replay"（省略時はファイルがあれば再生）
+    #[serde(skip)]
+    config_path: Option<String>, // 読み込んだ設定ファイル（finetune follow --register でプロファイルを書き足す先）
+    #[serde(skip)]
+    tape: Option<cassette::Tape>, // 読み込んだカセット（cassette::load で入れる）
+    #[serde(skip)]
+    defaults: Option<Arc<profiles::Profile>>, // プロファイルに書いていない項目を戻す先の、最上位の値（Client::new で覚える）
+    #[serde(skip)]
+    stats: stats::Tally, // このセッションの統計（/stats と /usage、max_session_tokens / max_session_cost に使う）
+    #[serde(skip)]
+    warmup: warmup::Target, // 読み込ませておく Ollama のモデル
+    #[serde(skip)]
+    cache: middleware::Cache, // middleware の cache で覚えた答え
+    #[serde(skip)]
+    thread: assistants::Thread, // Assistants API で使っているスレッド
+    #[serde(skip)]
+    quiet: bool, // trueなら推論の途中のお知らせを標準エラーに出さず、端末でツールの確認もしない（TUI が端末を使っているあいだ）
+    context_window: Option<u32>, // モデルのコンテキスト長（トークン）。超える入力は分割して処理する
+    chunk_strategy: Option<String>, // "summarize"（デフォルト） / "concatenate"
+    reply_language: Option<String>, // 答える言語（"ja" / "en" など）。指示を付けて送り、違う言語で返ってきたら一度だけ聞き直す
+    context_budget: Option<HashMap<String, f64>>, // context_window の配分（"system" / "memories" / "retrieved" / "history" / "reply" の割合）
+    #[serde(default)]
+    memories: Vec<String>, // 覚えておいてほしいこと（チャット形式のとき system メッセージの後ろに付けて送る）
+    #[serde(skip)]
+    events: Option<stream::Sink>, // 推論の途中で届いたトークンやイベントを渡す口（ストリーミングで表示するときだけ）
+    #[serde(skip)]
+    retrieved: Vec<String>, // 次に参考資料として送る検索したチャンク（関連の高い順。Client::set_retrieved で渡す）
+    #[serde(default)]
+    warm_up: bool, // trueなら対話モードを始めるときに Ollama のモデルを読み込ませておく
+    keep_alive: Option<String>, // Ollama に読み込んだモデルを残しておく時間（"30m" / 秒数 / "-1" でずっと）。リクエストに毎回付ける
+    keep_alive_interval_secs: Option<u64>, // この間隔でモデルを読み込ませるリクエストを送り直す（使っていないあいだも残しておく）
+    #[serde(default)]
+    auto_continue: bool, // trueならmax_tokensで切れたときに自動で続きを生成する
+    max_continuations: Option<u32>, // 自動で続きを生成する最大回数（デフォルト3）
+    #[serde(default)]
+    output_filters: Vec<String>, // 応答に順番に適用する後処理フィルター（"strip_think" など）
+    format: Option<String>, // 応答を整形する Handlebars テンプレート（"@ファイル名" でファイルから読む）
+    #[serde(default)]
+    stream: bool, // trueなら応答を届いた分から少しずつ表示する（Ollama と OpenAI互換、openai / mistral / anthropic のプロバイダー、mock のみ）
+    #[serde(default)]
+    verbose: bool, // trueなら応答ごとに処理時間の内訳などの詳しい情報を表示する
+    #[serde(default)]
+    raw: bool, // trueなら応答全体を囲むコードフェンスも外す
+    reasoning_display: Option<String>, // 考え中の部分の表示方法 "show" / "dim"（デフォルト） / "fold" / "hide"
+    reasoning_effort: Option<String>, // 推論の深さ "minimal" / "low" / "medium" / "high"
+    thinking_budget: Option<u32>, // 考え中に使ってよいトークン数（Anthropic の thinking.budget_tokens 相当）
+    provider: Option<String>, // 名前つきのプロバイダー（"perplexity" / "together" / "cloudflare" / "replicate" / "nvidia" / "watsonx"）。指定すると endpoint はそのプロバイダーのURLになる
+    #[serde(default)]
+    search_domain_filter: Vec<String>, // Perplexity で検索するドメイン（"-" を付けると除外）
+    search_recency_filter: Option<String>, // Perplexity で検索する期間 "hour" / "day" / "week" / "month" / "year"
+    #[serde(default)]
+    google_search: bool, // trueなら Gemini で Google 検索のグラウンディングを使う（出典と検索の候補を答えの下に表示する）
+    account_id: Option<String>, // Cloudflare Workers AI のアカウントID
+    project_id: Option<String>, // watsonx.ai のプロジェクトID
+    api_base: Option<String>, // Files/Batch API などのベースURL（省略時は endpoint の "/v1" まで）
+    cached_content: Option<String>, // Gemini で参照するコンテキストキャッシュ（cache create で作った "cachedContents/..."）
+    #[serde(default)]
+    safety_settings: HashMap<String, String>, // Gemini の安全フィルターの基準（"harassment": "block_only_high" など）
+    assistant_id: Option<String>, // 指定するとオンライン推論に Assistants API を使う
+    #[serde(default)]
+    assistant_tools: Vec<String>, // ランで使うサーバー側ツール（"code_interpreter" / "file_search"）
+    stt_endpoint: Option<String>, // 文字起こしAPIのURL（省略時は api_base + "/audio/transcriptions"）
+    stt_model: Option<String>, // 文字起こしのモデル（省略時は "whisper-1"。--realtime で話した内容の文字起こしにも使う）
+    #[serde(default)]
+    realtime: realtime::RealtimeConfig, // --realtime で使う Realtime API のモデル・声・録音と再生のコマンド
+    vision: Option<bool>, // false ならモデルが画像を扱えないものとして扱う
+    vision_profile: Option<String>, // 画像を扱えないモデルに画像を添付したとき、説明を書いてもらうプロファイル（か "モデル名@行き先"）
+    #[serde(default)]
+    ocr_fallback: bool, // trueなら画像を扱えないモデルに画像を添付したとき、OCRした文字を代わりに送る（vision_profile で説明できなかったときも）
+    ocr_languages: Option<String>, // OCRの言語（tesseract の -l。例: "jpn+eng"）
+    prompt_price: Option<f64>, // 入力1Mトークンあたりの料金（ドル。/stats の推定料金に使う）
+    completion_price: Option<f64>, // 出力1Mトークンあたりの料金（ドル）
+    #[serde(default)]
+    prices: HashMap<String, stats::ModelPrice>, // モデルごとの料金（ここにないモデルは prompt_price / completion_price を使う）
+    max_session_tokens: Option<u64>, // セッションで使うトークン数の上限（入力と出力の合計。超えたら終わる）
+    max_session_cost: Option<f64>, // セッションの推定料金の上限（ドル。prompt_price / completion_price が必要）
+    publish: Option<publish::PublishConfig>, // 完成した応答を流す MQTT / NATS のトピック
+    image_display: Option<String>, // 応答の画像の表示方法 "auto"（デフォルト） / "kitty" / "iterm" / "save"
+    #[serde(default)]
+    chat: bool, // trueなら会話の履歴をチャット形式（/v1/chat/completions、Ollama の /api/chat）で送る
+    system_prompt: Option<String>, // チャット形式で最初に送る system メッセージ（/system で変更できる）
+    greeting: Option<String>, // 始めるときとプロファイルを切り替えたときに表示するあいさつ（モデルには送らない）
+    #[serde(default)]
+    tui_keys: HashMap<String, keybindings::Keys>, // --tui のキーの割り当て（"send": "ctrl+s" など。動作ごとにキーか、その配列）
+    tui_input_mode: Option<String>, // --tui の入力欄の操作 "emacs"（デフォルト） / "vi"
+    #[serde(default)]
+    tools: Vec<tools::ToolSpec>, // モデルから呼び出せるツール（"shell" / "read_file" / "http_get" か、command つきの定義）
+    tools_file: Option<String>, // ツールの定義を書いたファイル（デフォルトは "tools.json"。あれば読む）
+    tool_max_rounds: Option<usize>, // 最後の答えが出るまでにツールを呼び出してよい回数（デフォルト5）
+    tool_concurrency: Option<usize>, // 1回の応答で呼び出されたツールを同時に実行する数（デフォルト4。1 なら1つずつ）
+    tool_output_tokens: Option<u32>, // ツールの結果として1回に返すトークン数（見積もり。デフォルト2000）。超えた分は read_tool_output でページごとに読んでもらう
+    tool_confirm: Option<bool>, // false なら shell とコマンドのツール（と、許可していないファイルやホストへの read_file / http_get）を確認せずに実行する
+    #[serde(skip)]
+    tool_confirmation: Option<tools::Confirm>, // ツールを実行してよいかを聞く先（Client::set_tool_confirmation で入れる。なければ端末で聞く）
+    #[serde(default)]
+    tool_allowed_hosts: Vec<String>, // http_get で確認せずにアクセスしてよいホスト（サブドメインも含む）
+    #[serde(skip)]
+    tool_messages: Vec<serde_json::Value>, // ツールを使っている途中の assistant と tool のメッセージ（今回の入力の後ろに付けて送る）
+    history_max_messages: Option<usize>, // 送る履歴の最大メッセージ数（古いものから削る）
+    history_max_tokens: Option<u32>, // 送る履歴のトークン数の上限（見積もり）
+    judge: Option<judge::JudgeConfig>, // judge サブコマンドで使う審査役のモデルと採点の指示
+    request_timeout_secs: Option<u64>, // 応答が途切れてからタイムアウトにするまでの秒数（デフォルト120）
+    max_retries: Option<u32>, // 429 / 5xx や通信エラーのときに再試行する回数（デフォルト3）
+    stream_resumes: Option<u32>, // ストリーミングの途中で接続が切れたときに、続きから再開する回数（デフォルト2。0 なら再開しない）
+    #[serde(default)]
+    middleware: Vec<String>, // 推論の前後に挟む処理を外側から順に（"logging" / "cache" / "redaction" / "retry" / "budget"）
+    middleware_log: Option<String>, // logging で追記するJSONLのファイル（デフォルトは "middleware.jsonl"）
+    middleware_retries: Option<u32>, // retry で推論をやり直す回数（デフォルト2）
+    cache_max_entries: Option<usize>, // cache で覚えておく答えの数（デフォルト256。超えたら長く使っていないものから忘れる）
+    cache_dir: Option<String>, // cache で覚えた答えをファイルにも置くディレクトリ（あればプロセスをまたいで使う）
+    benchmark_file: Option<String>, // bench でプロファイルごとの速さを記録するファイル（デフォルトは "benchmarks.json"）
+    sessions_dir: Option<String>, // 会話を保存するディレクトリ（デフォルトは "sessions"）
+    save_sessions: Option<bool>, // false なら会話をファイルに保存しない
+    compression: Option<String>, // "zstd" なら、新しく作るセッションと cache のファイルを圧縮する（デフォルトは "none"）
+    #[serde(skip)]
+    template: Option<String>, // new --template で始めた会話のテンプレート名（最初のメッセージと一緒にセッションに残す）
+    #[serde(skip)]
+    session_id: Option<String>, // 今の会話を保存しているセッションのID（/load で切り替わる）
+    #[serde(skip)]
+    session_settings: Option<sessions::SessionSettings>, // 今のセッションに最後に書いた設定（変わったときだけ書き足す）
+    #[serde(skip)]
+    model_override: Option<String>, // 起動時に --model で選んだモデル（キューに入れたときに残して、送り直すときも使う）
+    #[serde(default)]
+    share_safe_export: bool, // trueなら /export で、いつも共有用（伏せ字あり、system やモデルの情報なし）に書き出す
+    #[serde(default)]
+    offline_queue: bool, // trueなら、通信エラーで失敗した1回だけの推論と batch submit をキューに入れて、あとで送り直せるようにする
+    queue_dir: Option<String>, // キューと結果（outbox.jsonl）を置くディレクトリ（デフォルトは "queue"）
+    speculative: Option<speculative::SpeculativeConfig>, // 速いモデルと強いモデルに同時に送る（速い答えを先に表示する）
+    #[serde(default)]
+    language_routes: HashMap<String, String>, // プロンプトの言語ごとに答えるプロファイルかモデル（"ja" / "en" / "default" など）
+    compare: Option<Vec<String>>, // 同じプロンプトを送って答えを横に並べるモデル（プロファイル名か "モデル名@行き先"）
+    #[serde(skip)]
+    compare_turns: Vec<compare::Turn>, // 比べているあいだのやりとり（チャット形式ならモデルごとの履歴にして送る。/compare export で書き出す）
+    #[serde(skip)]
+    history: Vec<conversation::Message>, // これまでの会話（/clear で消す。送るのは chat が true のときだけ）
+    #[serde(skip)]
+    images: Vec<String>, // 次のメッセージに添付する画像（base64）。/attach で追加して、送ったら空にする
+    #[serde(skip)]
+    file_ids: Vec<String>, // 次のメッセージに付ける、Files API でアップロードしたファイルのID。/attach file-… で追加して、送ったら空にする
+    #[serde(skip)]
+    attached_files: Vec<conversation::AttachedFile>, // 次のメッセージに添付したファイルの記録（送ったら user メッセージに移してセッションに残す）
+}
+
+impl Config {
+    // config.json と同じ形のJSONから設定を作る
+    pub fn from_json(json: &str) -> Result<Config, Error> {
+        let value = serde_json::from_str(json).map_err(|e| Error::Config(format!("JSONのパースに失敗しました: {}", e)))?;
+        Config::from_value(value)
+    }
+
+    // 設定ファイルを読み込む（拡張子が .toml / .yaml / .yml ならその形式で読む。コマンドラインと違い、ファイルがなくても作らない）
+    pub fn from_file(path: &str) -> Result<Config, Error> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| Error::Config(format!("設定ファイル {} の読み込みに失敗しました: {:?}", path, e)))?;
+        let mut config = Config::from_value(config_file::parse(path, &text).map_err(Error::Config)?)?;
+        config.config_path = Some(path.to_string());
+        tools::load_file(&mut config)?;
+        Ok(config)
+    }
+
+    // --config を指定しないときに読む設定ファイル（カレントディレクトリか XDG の設定ディレクトリ）
+    pub fn default_path() -> String {
+        config_file::default_path()
+    }
+
+    // 環境変数を展開してから設定にし、内容をチェックする
+    fn from_value(mut value: serde_json::Value) -> Result<Config, Error> {
+        config_file::expand(&mut value).map_err(Error::Config)?;
+        let config: Config = serde_json::from_value(value)
+            .map_err(|e| Error::Config(format!("設定の読み込みに失敗しました: {}", e)))?;
+        tools::check(&config).map_err(Error::Config)?;
+        config_file::validate(&config).map_err(Error::Config)?;
+        Ok(config)
+    }
+}
+
+// Pythonスクリプトを呼び出してローカル推論を実行する非同期関数
+async fn python_inference(prompt: &str, config: &Config) -> Completion {
+    let script_path = "./llm_interface.py"; // Pythonスクリプトのパス
+    if config.dry_run {
+        return format!("python {} {:?}", script_path, prompt).into();
+    }
+    let output = runtime::Command::new("python")
+        .arg(script_path)
+        .arg(prompt)
+        .output()
+        .await;
+    let text = match output {
+        Ok(output) => {
+            if output.status.success() {
+                String::from_utf8_lossy(&output.stdout).trim().to_string()
+            } else {
+                return Completion::failed(format!("Pythonスクリプトエラー: {}",
+                    String::from_utf8_lossy(&output.stderr)));
+            }
+        }
+        Err(e) => return Completion::failed(format!("Pythonスクリプト呼び出しエラー: {:?}", e)),
+    };
+    text.into()
+}
+
+// Ollama の1行分のJSONから本文（"response"）か考え中の部分（"thinking"）を取り出す
+// /api/chat では "message" の中の "content" / "thinking" に入っている
+fn ollama_text<'a>(json: &'a serde_json::Value, key: &str) -> Option<&'a str> {
+    let chat_key = if key == "response" { "content" } else { key };
+    json.get(key)
+        .or_else(|| json.get("message").and_then(|message| message.get(chat_key)))
+        .and_then(|text| text.as_str())
+}
+
+// Ollama互換エンドポイントで推論を実行する非同期関数
+async fn ollama_inference(prompt: &str, config: &Config) -> Completion {
+    let mut endpoint = config.endpoint.clone().unwrap_or("http://localhost:11434/api/generate".to_string());
+    let max_tokens = config.max_tokens.unwrap_or(64);
+    let mut request_body = serde_json::json!({
+        "model": config.model_name,
+        "prompt": prompt,
+        "max_tokens": max_tokens
+    });
+    if !config.images.is_empty() {
+        request_body["images"] = serde_json::json!(config.images);
+    }
+    // チャット形式では /api/chat に messages を送る（画像は最後の user メッセージに付ける）
+    if config.chat {
+        endpoint = conversation::chat_endpoint(&endpoint);
+        let mut messages = conversation::messages_json(prompt, config);
+        if let (false, Some(last)) = (config.images.is_empty(), messages.as_array_mut().and_then(|m| m.last_mut())) {
+            last["images"] = serde_json::json!(config.images);
+        }
+        request_body = serde_json::json!({
+            "model": config.model_name,
+            "messages": messages,
+            "options": { "num_predict": max_tokens },
+        });
+    }
+    // Ollama の think は、effort の指定があればその文字列、予算だけなら true にする
+    if let Some(effort) = &config.reasoning_effort {
+        request_body["think"] = serde_json::json!(effort);
+    } else if config.thinking_budget.is_some() {
+        request_body["think"] = serde_json::json!(true);
+    }
+    if config.stream {
+        request_body["stream"] = serde_json::json!(true);
+    }
+    if let Some(keep_alive) = warmup::keep_alive(config) {
+        request_body["keep_alive"] = keep_alive;
+    }
+    // temperature などは "options" の中に入れる
+    sampling::extend(&mut request_body["options"], config, &sampling::OPENAI_COMPATIBLE);
+    let request = PreparedRequest::new(&endpoint, request_body);
+    if config.dry_run {
+        return request.dry_run().into();
+    }
+    // ストリーミングでは1行に1つずつ届くJSONから、届いた分のトークンを表示側に渡す
+    let mut lines = stream::LineBuffer::default();
+    let res = request.send_streaming(config, |chunk| {
+        if !config.stream {
+            return;
+        }
+        for line in lines.push(chunk) {
+            let Ok(json) = serde_json::from_str::<serde_json::Value>(&line) else {
+                continue;
+            };
+            if let Some(thinking) = ollama_text(&json, "thinking").filter(|t| !t.is_empty()) {
+                stream::emit(config, stream::Token::Reasoning(thinking.to_string()));
+            }
+            if let Some(token) = ollama_text(&json, "response").filter(|t| !t.is_empty()) {
+                stream::emit(config, stream::Token::Answer(token.to_string()));
+            }
+        }
+    }).await;
+    let (res, cut) = stream::recover_partial(res, config.stream);
+    match res {
+        Ok(response) if response.status >= 400 => Completion::from_error("Ollama推論エラー", &Error::from_response(&response)),
+        Ok(response) => {
+            let mut collected_response = String::new();
+            let mut collected_thinking = String::new();
+            let mut finish_reason = None;
+            let mut usage = None;
+            let mut timing = None;
+            let mut done = !config.stream;
+            for line in response.body.lines() {
+                if let Ok(json) = serde_json::from_str::<serde_json::Value>(line) {
+                    if let Some(resp_text) = ollama_text(&json, "response") {
+                        collected_response.push_str(resp_text);
+                    }
+                    // think に対応したモデルは、考え中の部分を "thinking" に分けて返してくる
+                    if let Some(thinking) = ollama_text(&json, "thinking") {
+                        collected_thinking.push_str(thinking);
+                    }
+                    // 最後の行（"done": true）に終了理由が入っている
+                    if let Some(reason) = json.get("done_reason").and_then(|r| r.as_str()) {
+                        finish_reason = Some(reason.to_string());
+                    }
+                    done |= json.get("done").and_then(|done| done.as_bool()) == Some(true);
+                    if let Some(counts) = Usage::from_ollama(&json) {
+                        usage = Some(counts);
+                    }
+                    if let Some(durations) = Timing::from_ollama(&json) {
+                        timing = Some(durations);
+                    }
+                }
+            }
+            if collected_response.is_empty() {
+                Completion::failed("Ollama推論エラー".to_string())
+            } else {
+                Completion {
+                    text: collected_response,
+                    finish_reason: if cut || !done { Some(completion::INTERRUPTED.to_string()) } else { finish_reason },
+                    reasoning: Some(collected_thinking).filter(|t| !t.is_empty()),
+                    usage,
+                    timing,
+                    ..Default::default()
+                }
+            }
+        }
+        Err(e) => Completion::from_error("Ollama推論エラー", &e),
+    }
+}
+
+// モックプロバイダーで応答を作る非同期関数（バックエンドなしでUIなどを試すため）
+async fn mock_provider_inference(prompt: &str, config: &Config) -> Completion {
+    if config.dry_run {
+        return "mockプロバイダーのため、送信するリクエストはありません".to_string().into();
+    }
+    let text = mock::mock_inference(prompt, config.mock.as_ref()).await;
+    if config.stream {
+        mock::stream_chunks(&text, config).await;
+    }
+    text.into()
+}
+
+// --- 型定義と関数の分割 ---
+//
+// ここで、for<'a> を使って、任意のライフタイム 'a に対して返り値の Future が 'a を持つようにする
+type InferenceFn = for<'a> fn(String, &'a Config) -> runtime::BoxFuture<'a, Completion>;
+
+// infer_python と infer_ollama を定義
+fn infer_python<'a>(prompt: String, config: &'a Config) -> runtime::BoxFuture<'a, Completion> {
+    Box::pin(async move {
+        python_inference(&prompt, config).await
+    })
+}
+
+fn infer_ollama<'a>(prompt: String, config: &'a Config) -> runtime::BoxFuture<'a, Completion> {
+    Box::pin(async move {
+        ollama_inference(&prompt, config).await
+    })
+}
+
+fn infer_mock<'a>(prompt: String, config: &'a Config) -> runtime::BoxFuture<'a, Completion> {
+    Box::pin(async move {
+        mock_provider_inference(&prompt, config).await
+    })
+}
+
+// ローカル推論を実行する関数（フレームワーク選択）
+async fn local_inference(prompt: &str, config: &Config) -> Completion {
+    let frameworks: HashMap<&str, InferenceFn> = HashMap::from([
+        ("python", infer_python as InferenceFn),
+        ("ollama", infer_ollama as InferenceFn),
+        ("mock", infer_mock as InferenceFn),
+    ]);
+    if let Some(framework) = config.local_framework.as_deref() {
+        if let Some(inference_fn) = frameworks.get(framework) {
+            return inference_fn(prompt.to_owned(), config).await;
+        }
+    }
+    Completion::failed("サポートされていないローカルフレームワークです".to_string())
+}
+
+// オンライン推論を実行する非同期関数
+async fn online_inference(config: &Config, prompt: &str) -> Result<Completion, Error> {
+    let mut endpoint = config.endpoint.clone()
+        .ok_or_else(|| Error::Config("オンライン推論用のendpointが設定されていません".to_string()))?;
+
+    let max_tokens = config.max_tokens.unwrap_or(64);
+
+    let mut request_body = if config.openai_compatible && config.chat {
+        // チャット形式では、会話の履歴をまとめて /v1/chat/completions に送る
+        endpoint = conversation::chat_endpoint(&endpoint);
+        let mut body = serde_json::json!({
+            "model": config.model_name,
+            "messages": conversation::messages_json(prompt, config),
+            "max_tokens": max_tokens
+        });
+        tools::extend(&mut body, config);
+        providers::attach_images(&mut body, config);
+        body
+    } else if config.openai_compatible {
+        serde_json::json!({
+            "model": config.model_name,
+            "prompt": prompt,
+            "max_tokens": max_tokens
+        })
+    } else {
+        serde_json::json!({
+            "model": config.model_name,
+            "input": prompt,
+            "max_tokens": max_tokens
+        })
+    };
+
+    // 推論の深さの指定（OpenAI互換は reasoning_effort、カスタムAPIには Anthropic 形式の thinking も付ける）
+    if let Some(effort) = &config.reasoning_effort {
+        request_body["reasoning_effort"] = serde_json::json!(effort);
+    }
+    if let (false, Some(budget)) = (config.openai_compatible, config.thinking_budget) {
+        request_body["thinking"] = serde_json::json!({ "type": "enabled", "budget_tokens": budget });
+    }
+    sampling::extend(&mut request_body, config, &sampling::OPENAI_COMPATIBLE);
+    // ストリーミングは OpenAI互換の SSE だけ対応（使用量は最後のイベントに入れてもらう）
+    if config.stream && config.openai_compatible {
+        request_body["stream"] = serde_json::json!(true);
+        request_body["stream_options"] = serde_json::json!({ "include_usage": true });
+    }
+
+    let mut request = PreparedRequest::new(&endpoint, request_body);
+
+    if let Some(api_key) = &config.api_key {
+        request = request.header("Authorization", format!("Bearer {}", api_key));
+    }
+
+    if config.dry_run {
+        return Ok(request.dry_run().into());
+    }
+
+    // ストリーミングでは SSE の data 行ごとに、届いた分のトークンを表示側に渡す
+    let streaming = config.stream && config.openai_compatible;
+    let mut lines = stream::LineBuffer::default();
+    let res = request.send_streaming(config, |chunk| {
+        if !streaming {
+            return;
+        }
+        for event in lines.push(chunk).iter().filter_map(|line| stream::sse_data(line)) {
+            stream::emit_choice(config, &event);
+        }
+    }).await;
+    let (res, cut) = stream::recover_partial(res, streaming);
+    let res = res?;
+    if res.status >= 400 {
+        return Err(Error::from_response(&res));
+    }
+    let res_json: serde_json::Value = if streaming {
+        stream::collect_sse(&res.body)
+    } else {
+        serde_json::from_str(&res.body).unwrap_or_default()
+    };
+
+    // OpenAI互換モードとカスタムモードでレスポンス処理を分ける
+    let (output, finish_reason, reasoning, tool_calls) = if config.openai_compatible {
+        let choice = res_json.get("choices").and_then(|choices| choices.get(0));
+        let tool_calls = choice.map(completion::choice_tool_calls).unwrap_or_default();
+        // ツールを呼び出すときは本文がないこともある
+        let text = choice
+            .and_then(completion::choice_text)
+            .unwrap_or(if tool_calls.is_empty() { "レスポンスが不正です" } else { "" })
+            .to_string();
+        let reasoning = choice.and_then(completion::choice_reasoning).map(|r| r.to_string());
+        (text, choice.and_then(|choice| choice.get("finish_reason")), reasoning, tool_calls)
+    } else {
+        let text = res_json.get("generated_text")
+            .and_then(|text| text.as_str())
+            .unwrap_or("レスポンスが不正です")
+            .to_string();
+        (text, res_json.get("finish_reason"), None, Vec::new())
+    };
+
+    // 終わりの印も終了理由も届かずに終わったものも、途中で切れたものとして扱う
+    let finish_reason = finish_reason.and_then(|r| r.as_str()).map(|r| r.to_string());
+    let finished = !streaming || finish_reason.is_some() || stream::sse_finished(&res.body);
+    Ok(Completion {
+        text: output,
+        finish_reason: if cut || !finished { Some(completion::INTERRUPTED.to_string()) } else { finish_reason },
+        reasoning,
+        usage: res_json.get("usage").and_then(Usage::from_openai),
+        timing: res_json.get("timings").and_then(Timing::from_llama_cpp),
+        tool_calls,
+        ..Default::default()
+    })
+}
+
+// 画像を送れるかどうか（ローカルの Ollama と、画像に対応したアダプターのあるプロバイダー）
+fn supports_images(config: &Config) -> bool {
+    config.vision != Some(false)
+        && if config.use_local_model {
+            config.local_framework.as_deref() == Some("ollama")
+        } else if config.provider.is_none() && config.assistant_id.is_none() {
+            // provider のない OpenAI互換のチャット形式は、OpenAI と同じ image_url の形で送る
+            config.openai_compatible && config.chat
+        } else {
+            providers::accepts_images(config)
+        }
+}
+
+// 使うモデルを決める
+// 別名なら展開し、"名前@local" / "名前@ollama" / "名前@openai" / "名前@プロバイダー" なら行き先も切り替える
+fn select_model(config: &mut Config, name: &str) {
+    let name = config.aliases.get(name).cloned().unwrap_or_else(|| name.to_string());
+    let Some((model, target)) = name.rsplit_once('@') else {
+        config.model_name = name;
+        return;
+    };
+    if target == "local" {
+        config.use_local_model = true;
+    } else if LOCAL_FRAMEWORKS.contains(&target) {
+        config.use_local_model = true;
+        config.local_framework = Some(target.to_string());
+    } else if target == "openai" {
+        config.use_local_model = false;
+        config.provider = None;
+    } else if providers::PROVIDERS.contains(&target) {
+        config.use_local_model = false;
+        config.provider = Some(target.to_string());
+    } else {
+        // 行き先ではない "@" はモデル名の一部とみなす
+        config.model_name = name;
+        return;
+    }
+    config.model_name = model.to_string();
+}
+
+// /set コマンドで設定を変更する（"off" で指定を外す）
+fn apply_setting(config: &mut Config, key: &str, value: &str) -> Result<(), String> {
+    let cleared = value == "off";
+    match key {
+        "reasoning_effort" => {
+            if !cleared && !REASONING_EFFORTS.contains(&value) {
+                return Err(format!("reasoning_effort は {} のどれかを指定してください", REASONING_EFFORTS.join(" / ")));
+            }
+            config.reasoning_effort = (!cleared).then(|| value.to_string());
+        }
+        "model" => select_model(config, value),
+        "reply_language" => {
+            if !cleared && !reply_language::languages().contains(&value) {
+                return Err(format!("reply_language は {} のどれかを指定してください", reply_language::languages().join(" / ")));
+            }
+            config.reply_language = (!cleared).then(|| value.to_string());
+        }
+        "thinking_budget" => {
+            config.thinking_budget = if cleared {
+                None
+            } else {
+                Some(value.parse().map_err(|_| "thinking_budget には数値を指定してください".to_string())?)
+            };
+        }
+        "temperature" => config.temperature = parse_setting(key, value)?,
+        "top_p" => config.top_p = parse_setting(key, value)?,
+        "top_k" => config.top_k = parse_setting(key, value)?,
+        "presence_penalty" => config.presence_penalty = parse_setting(key, value)?,
+        "seed" => config.seed = parse_setting(key, value)?,
+        "stop" => {
+            // 複数あるときはカンマで区切る
+            config.stop = if cleared { Vec::new() } else { value.split(',').map(|stop| stop.to_string()).collect() };
+        }
+        _ => return Err(format!("変更できない設定です: {}", key)),
+    }
+    Ok(())
+}
+
+// /set の数値の設定を読む（"off" なら指定を外す）
+fn parse_setting<T: std::str::FromStr>(key: &str, value: &str) -> Result<Option<T>, String> {
+    if value == "off" {
+        return Ok(None);
+    }
+    value.parse().map(Some).map_err(|_| format!("{} には数値を指定してください", key))
+}
+
+// 1回分の推論を実行する（middleware があれば、その処理を挟む）
+// ストリーミングの途中で接続が切れたら、届いたところまでの答えを付けて続きを頼み、つなげる
+async fn infer_once(prompt: &str, config: &Config) -> Completion {
+    let mut completion = middleware::run(prompt, config).await;
+    let max_resumes = config.stream_resumes.unwrap_or(2);
+    let mut resumes = 0;
+    while completion.is_interrupted() && completion.tool_calls.is_empty() && resumes < max_resumes {
+        resumes += 1;
+        if !config.quiet {
+            eprintln!("（応答の途中で接続が切れたため、続きから再開します {}/{}）", resumes, max_resumes);
+        }
+        let next = middleware::run(&resume_prompt(prompt, config, &completion.text), &resume_config(config, &completion.text, RESUME_INSTRUCTION)).await;
+        if next.error.is_some() {
+            continue;
+        }
+        completion.text = completion::stitch(&completion.text, &next.text);
+        completion.finish_reason = next.finish_reason;
+        completion.usage = Usage::add(completion.usage, next.usage);
+        completion.timing = Timing::add(completion.timing, next.timing);
+        if let Some(thoughts) = next.reasoning {
+            completion.append_reasoning(thoughts);
+        }
+    }
+    completion
+}
+
+// 続きを頼むときの指示（チャット形式では、途中までの答えの後ろに user として付ける）
+const RESUME_INSTRUCTION: &str = "接続が途中で切れました。直前のあなたの答えの続きだけを、途切れたところからそのまま書いてください。";
+const CONTINUE_INSTRUCTION: &str = "長さの上限で途中で切れました。直前のあなたの答えの続きだけを、切れたところからそのまま書いてください。";
+
+// 補完APIなら、途中までの答えをプロンプトの後ろに付ければ続きから生成してくれる
+fn resume_prompt(prompt: &str, config: &Config, partial: &str) -> String {
+    if config.chat { prompt.to_string() } else { format!("{}{}", prompt, partial) }
+}
+
+// チャット形式なら、途中までの答えを assistant のメッセージとして付けて、instruction で続きを頼む
+fn resume_config(config: &Config, partial: &str, instruction: &str) -> Config {
+    let mut config = config.clone();
+    if config.chat {
+        config.tool_messages.push(serde_json::json!({ "role": "assistant", "content": partial }));
+        config.tool_messages.push(serde_json::json!({ "role": "user", "content": instruction }));
+    }
+    config
+}
+
+// プロバイダーに1回推論してもらう（ローカル/オンラインを設定で切り替える）
+async fn infer_provider(prompt: &str, config: &Config) -> Completion {
+    let mut completion = if config.use_local_model {
+        local_inference(prompt, config).await
+    } else if let Some(result) = providers::provider_inference(prompt, config).await {
+        match result {
+            Ok(completion) => completion,
+            Err(e) => Completion::from_error(&format!("{}エラー", config.provider.as_deref().unwrap_or_default()), &e),
+        }
+    } else if config.assistant_id.is_some() {
+        match assistants::assistant_inference(prompt, config).await {
+            Ok(completion) => completion,
+            Err(e) => Completion::from_error("Assistants APIエラー", &e),
+        }
+    } else {
+        match online_inference(config, prompt).await {
+            Ok(completion) => completion,
+            Err(e) => Completion::from_error("オンライン推論エラー", &e),
+        }
+    };
+    completion.separate_reasoning();
+    completion
+}
+
+// 推論を実行する（auto_continue が有効なら、max_tokensで切れたぶんの続きも生成して繋げる）
+async fn infer(prompt: &str, config: &Config) -> Completion {
+    let mut completion = if config.tools.is_empty() {
+        infer_once(prompt, config).await
+    } else {
+        tools::infer(prompt, config).await
+    };
+    let max_continuations = config.max_continuations.unwrap_or(3);
+    let mut continuations = 0;
+    while config.auto_continue && completion.is_truncated() && continuations < max_continuations {
+        let next = infer_once(&resume_prompt(prompt, config, &completion.text), &resume_config(config, &completion.text, CONTINUE_INSTRUCTION)).await;
+        // 続きが失敗したら、そこまでの答えは残してエラーも付けて返す
+        if next.error.is_some() {
+            completion.error = next.error;
+            completion.error_kind = next.error_kind;
+            break;
+        }
+        completion.text = completion::stitch(&completion.text, &next.text);
+        completion.finish_reason = next.finish_reason;
+        completion.usage = Usage::add(completion.usage, next.usage);
+        completion.timing = Timing::add(completion.timing, next.timing);
+        if let Some(thoughts) = next.reasoning {
+            completion.append_reasoning(thoughts);
+        }
+        continuations += 1;
+    }
+    completion
+}
+
+// 推論を待ちながら、届いたトークンを少しずつ on_token に渡す
+async fn respond_with_tokens(prompt: &str, config: &Config, mut on_token: impl FnMut(Token)) -> Completion {
+    respond_with_events(prompt, config, |event| {
+        if let Event::TokenDelta(token) = event {
+            on_token(token);
+        }
+    }).await
+}
+
+// 推論を待ちながら、途中で届いたイベント（トークンやツールの呼び出し）を on_event に渡す
+async fn respond_with_events(prompt: &str, config: &Config, mut on_event: impl FnMut(Event)) -> Completion {
+    let (sink, mut events) = stream::channel();
+    let config = Config { events: Some(sink), ..config.clone() };
+    let response = respond(prompt, &config);
+    tokio::pin!(response);
+    let completion = loop {
+        tokio::select! {
+            Some(event) = events.recv() => on_event(event),
+            completion = &mut response => break completion,
+        }
+    };
+    while let Ok(event) = events.try_recv() {
+        on_event(event);
+    }
+    completion
+}
+
+// 入力がコンテキストウィンドウに収まらなければ分割して処理し、収まればそのまま推論する
+// 最後に送ったリクエストは、結果に付けて返す（/curl で使う）
+async fn respond(prompt: &str, config: &Config) -> Completion {
+    let (mut completion, sent) = request::capture(async {
+        match config.reply_language.as_deref() {
+            Some(language) => reply_language::respond(prompt, config, language).await,
+            None => respond_assembled(prompt, config).await,
+        }
+    }).await;
+    if completion.request.is_none() {
+        completion.request = sent;
+    }
+    completion
+}
+
+async fn respond_assembled(prompt: &str, config: &Config) -> Completion {
+    // 覚えておくことや検索したチャンクを入れて、コンテキストの配分に収める
+    if let Some((prompt, config)) = context_budget::assemble(prompt, config) {
+        return respond_within(&prompt, &config).await;
+    }
+    respond_within(prompt, config).await
+}
+
+async fn respond_within(prompt: &str, config: &Config) -> Completion {
+    let prompt_tokens = chunking::estimate_tokens(prompt);
+    if let Some(context_window) = config.context_window {
+        let budget = context_window
+            .saturating_sub(config.max_tokens.unwrap_or(64))
+            .saturating_sub(chunking::PROMPT_OVERHEAD_TOKENS);
+        if prompt_tokens > budget {
+            return chunked_inference(prompt, config, budget).await;
+        }
+    }
+    let response = infer(prompt, config).await;
+    if !response.is_context_overflow() {
+        return response;
+    }
+    // 履歴を送っていたなら、溢れたのは履歴のせいかもしれないので、まず古い方から削って送り直す
+    if let Some(response) = respond_with_shorter_history(prompt, config).await {
+        return response;
+    }
+    // 今回の入力だけでも溢れるなら（見積もりが甘かったので）、半分ずつに分けて一度だけやり直す
+    if !config.quiet {
+        eprintln!("コンテキスト長を超えたというエラーが返ってきたため、入力を分割して再試行します");
+    }
+    chunked_inference(prompt, config, prompt_tokens / 2).await
+}
+
+// 送る履歴を半分ずつに減らしながら送り直す（履歴がなくなっても溢れるなら None）
+async fn respond_with_shorter_history(prompt: &str, config: &Config) -> Option<Completion> {
+    let mut trimmed = config.clone();
+    let mut sent = conversation::sent_history(config).len();
+    while sent > 0 {
+        trimmed.history_max_messages = Some(sent / 2);
+        sent = conversation::sent_history(&trimmed).len();
+        if !config.quiet {
+            eprintln!("コンテキスト長を超えたというエラーが返ってきたため、古い履歴を削って送り直します（履歴 {} 件）", sent);
+        }
+        let response = infer(prompt, &trimmed).await;
+        if !response.is_context_overflow() {
+            return Some(response);
+        }
+    }
+    None
+}
+
+// 長い入力をチャンクに分けて、map-reduce 風に処理する
+async fn chunked_inference(prompt: &str, config: &Config, budget: u32) -> Completion {
+    let chunks = chunking::split_into_chunks(prompt, budget);
+    if !config.quiet {
+        eprintln!("入力がコンテキストウィンドウを超えるため、{}個に分割して処理します", chunks.len());
+    }
+
+    // 途中の要約はストリーミングで表示しない
+    // 1つでも要約に失敗したら、欠けた要約でまとめずにその失敗を返す
+    let mut summaries = Vec::new();
+    let muted = Config { events: None, ..config.clone() };
+    for (i, chunk) in chunks.iter().enumerate() {
+        let summary = infer(&chunking::map_prompt(chunk, i, chunks.len()), &muted).await;
+        if summary.error.is_some() {
+            return summary;
+        }
+        summaries.push(summary.text);
+    }
+
+    let reduce_prompt = chunking::reduce_prompt(&summaries);
+    if config.chunk_strategy.as_deref() == Some("concatenate")
+        || chunking::estimate_tokens(&reduce_prompt) > budget
+    {
+        // まとめても収まらないときは、要約を繋げたものを答えとする
+        return summaries.join("\n\n").into();
+    }
+    infer(&reduce_prompt, config).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(chat: bool) -> Config {
+        serde_json::from_value(serde_json::json!({
+            "model_name": "llama3", "use_local_model": true, "openai_compatible": false, "chat": chat,
+        })).unwrap()
+    }
+
+    #[test]
+    fn completion_apis_continue_from_the_end_of_the_prompt() {
+        let config = config(false);
+        assert_eq!(resume_prompt("昔々", &config, "あるところに"), "昔々あるところに");
+        assert!(resume_config(&config, "あるところに", CONTINUE_INSTRUCTION).tool_messages.is_empty());
+    }
+
+    #[test]
+    fn chat_continues_with_the_partial_answer_as_an_assistant_message() {
+        let config = config(true);
+        assert_eq!(resume_prompt("昔話をして", &config, "昔々"), "昔話をして");
+        let continued = resume_config(&config, "昔々", CONTINUE_INSTRUCTION);
+        let messages = conversation::messages_json("昔話をして", &continued);
+        let messages: Vec<(&str, &str)> = messages.as_array().unwrap().iter()
+            .map(|message| (message["role"].as_str().unwrap(), message["content"].as_str().unwrap()))
+            .collect();
+        assert_eq!(messages, [("user", "昔話をして"), ("assistant", "昔々"), ("user", CONTINUE_INSTRUCTION)]);
+    }
+
+    fn streaming_mock() -> Config {
+        serde_json::from_value(serde_json::json!({
+            "model_name": "mock", "use_local_model": true, "local_framework": "mock", "openai_compatible": false,
+            "stream": true, "mock": { "chunk_delay_ms": 1 },
+        })).unwrap()
+    }
+
+    async fn streamed_text(prompt: &str, config: &Config) -> (String, Completion) {
+        let mut text = String::new();
+        let completion = respond_with_tokens(prompt, config, |token| {
+            if let Token::Answer(token) = token {
+                text.push_str(&token);
+            }
+        }).await;
+        (text, completion)
+    }
+
+    #[tokio::test]
+    async fn concurrent_streams_get_only_their_own_tokens() {
+        let config = streaming_mock();
+        let ((a, first), (b, second)) = tokio::join!(
+            streamed_text("one two three four", &config),
+            streamed_text("五六七八九十一二", &config),
+        );
+        assert_eq!(a, "echo: one two three four");
+        assert_eq!(b, "echo: 五六七八九十一二");
+        assert_eq!((first.text, second.text), (a, b));
+    }
+
+    #[tokio::test]
+    async fn inference_without_a_sink_streams_nothing() {
+        let (sink, mut events) = stream::channel();
+        let listening = Config { events: Some(sink), ..streaming_mock() };
+        let muted = Config { events: None, ..listening.clone() };
+        assert_eq!(respond("hi", &muted).await.text, "echo: hi");
+        drop(listening);
+        assert!(events.recv().await.is_none());
+    }
+}
@@ -0,0 +1,388 @@
+// 推論の前後に挟む処理（"middleware"）
+//
+//   "middleware": ["budget", "logging", "cache", "redaction", "retry"]
+//
+// 書いた順に外側から重ねて、1回分の推論（プロバイダーへの1往復）を包む。
+// 分割処理の途中の推論や、ツールを使うあいだの推論もそれぞれ通る。
+// プロファイルにも書けて、書いたプロファイルではそちらの並びに置き換わる（"middleware": [] で全部外す）。
+//
+//   logging    1回ごとにモデル・かかった時間・トークン数・エラーを middleware_log（デフォルトは "middleware.jsonl"）に追記する
+//   cache      同じモデル・設定・履歴・プロンプトの推論は、前の答えをそのまま返す（Client ごとに cache_max_entries 件まで覚えておく）
+//              cache_dir があれば答えをファイルにも置き、次に起動したときも使う（"compression": "zstd" なら圧縮して置く）
+//   redaction  送る前に、プロンプト・履歴・system メッセージの APIキーやトークン、メールアドレスを伏せ字にする
+//   retry      推論が失敗したら、middleware_retries 回（デフォルト2）まで待ち時間を倍にしながらやり直す
+//              （APIキーの誤り・利用枠の使い切り・コンテキスト長の超過など、送り直しても通らないエラーはやり直さない）
+//   budget     セッションの上限（max_session_tokens / max_session_cost）を超えていたら送らずに失敗にする
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use serde::{Deserialize, Serialize};
+use web_time::{Instant, SystemTime, UNIX_EPOCH};
+use crate::completion::{Completion, Usage};
+use crate::{attachments, compression, filters, request, runtime, stats, stream, Config};
+
+pub const LAYERS: [&str; 5] = ["logging", "cache", "redaction", "retry", "budget"];
+
+const DEFAULT_LOG_FILE: &str = "middleware.jsonl";
+
+const DEFAULT_RETRIES: u32 = 2;
+
+const DEFAULT_CACHE_ENTRIES: usize = 256;
+
+// cache で覚えておく答え（Client ごとに持ち、設定を複製しても同じものを指す）
+#[derive(Clone, Default)]
+pub struct Cache(Arc<Mutex<Entries>>);
+
+// キーはモデル・設定・履歴・プロンプトをまとめたJSON。多すぎたら、いちばん長く使っていないものから忘れる
+#[derive(Default)]
+struct Entries {
+    answers: HashMap<String, (u64, Completion)>, // 最後に使った番号と答え
+    used: u64,
+}
+
+impl Cache {
+    fn get(&self, key: &str) -> Option<Completion> {
+        let mut entries = self.0.lock().ok()?;
+        entries.used += 1;
+        let used = entries.used;
+        let (last_used, answer) = entries.answers.get_mut(key)?;
+        *last_used = used;
+        Some(answer.clone())
+    }
+
+    fn insert(&self, key: String, completion: Completion, max_entries: usize) {
+        let Ok(mut entries) = self.0.lock() else {
+            return;
+        };
+        entries.used += 1;
+        let used = entries.used;
+        entries.answers.insert(key, (used, completion));
+        while entries.answers.len() > max_entries.max(1) {
+            let oldest = entries.answers.iter().min_by_key(|(_, (last_used, _))| *last_used).map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.answers.remove(&oldest);
+            }
+        }
+    }
+}
+
+// 設定した順に処理を重ねて推論する
+pub async fn run(prompt: &str, config: &Config) -> Completion {
+    call(&config.middleware, prompt.to_string(), config).await
+}
+
+fn call<'a>(layers: &'a [String], prompt: String, config: &'a Config) -> runtime::BoxFuture<'a, Completion> {
+    Box::pin(async move {
+        let Some((layer, inner)) = layers.split_first() else {
+            return crate::infer_provider(&prompt, config).await;
+        };
+        match layer.as_str() {
+            "logging" => logging(inner, prompt, config).await,
+            "cache" => cache(inner, prompt, config).await,
+            "redaction" => redaction(inner, prompt, config).await,
+            "retry" => retry(inner, prompt, config).await,
+            "budget" => budget(inner, prompt, config).await,
+            _ => call(inner, prompt, config).await, // 読み込むときに調べているので、ここには来ない
+        }
+    })
+}
+
+async fn logging(inner: &[String], prompt: String, config: &Config) -> Completion {
+    let prompt_chars = prompt.chars().count();
+    let started = Instant::now();
+    let completion = call(inner, prompt, config).await;
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0);
+    let entry = serde_json::json!({
+        "timestamp_ms": timestamp,
+        "elapsed_ms": started.elapsed().as_millis(),
+        "model": config.model_name,
+        "profile": config.profile,
+        "provider": config.provider,
+        "prompt_chars": prompt_chars,
+        "prompt_tokens": completion.usage.map(|usage| usage.prompt_tokens),
+        "completion_tokens": completion.usage.map(|usage| usage.completion_tokens),
+        "finish_reason": completion.finish_reason,
+        "error": completion.error,
+    });
+    let path = config.middleware_log.as_deref().unwrap_or(DEFAULT_LOG_FILE);
+    let written = OpenOptions::new().create(true).append(true).open(path)
+        .and_then(|mut file| writeln!(file, "{}", entry));
+    if let (Err(e), false) = (written, config.quiet) {
+        eprintln!("{} の書き込みに失敗しました: {:?}", path, e);
+    }
+    completion
+}
+
+// 答えが変わりうる設定と、送る内容のすべて
+// プロバイダーに送るリクエストを dry-run で組み立てて、その宛先・ヘッダー（APIキーは伏せる）・本文をキーにする
+// （サンプリングのパラメータ・ツール・安全フィルター・検索・キャッシュの参照など、送るものは漏れなく入る）
+async fn cache_key(prompt: &str, config: &Config) -> String {
+    let dry_run = Config { dry_run: true, events: None, ..config.clone() };
+    let (_, sent) = request::capture(Box::pin(crate::infer_provider(prompt, &dry_run))).await;
+    match sent {
+        Some(request) => request.to_redacted_json().to_string(),
+        None => local_cache_key(prompt, config),
+    }
+}
+
+// HTTP で送らないもの（mock と python）は、答えに効く設定を並べてキーにする
+fn local_cache_key(prompt: &str, config: &Config) -> String {
+    serde_json::json!({
+        "model": config.model_name,
+        "provider": config.provider,
+        "endpoint": config.endpoint,
+        "local_framework": config.local_framework,
+        "use_local_model": config.use_local_model,
+        "chat": config.chat,
+        "system_prompt": config.system_prompt,
+        "history": config.history,
+        "tool_messages": config.tool_messages,
+        "images": config.images,
+        "max_tokens": config.max_tokens,
+        "temperature": config.temperature,
+        "top_p": config.top_p,
+        "top_k": config.top_k,
+        "stop": config.stop,
+        "seed": config.seed,
+        "reasoning_effort": config.reasoning_effort,
+        "mock": config.mock.as_ref().map(|mock| (&mock.mode, &mock.responses, &mock.script)),
+        "prompt": prompt,
+    }).to_string()
+}
+
+async fn cache(inner: &[String], prompt: String, config: &Config) -> Completion {
+    let key = cache_key(&prompt, config).await;
+    if let Some(cached) = config.cache.get(&key) {
+        return cached;
+    }
+    let completion = match load_stored(&key, config) {
+        Some(stored) => stored,
+        None => {
+            let completion = call(inner, prompt, config).await;
+            // 失敗したもの、途中で切れたもの、リクエストを表示しただけのもの（dry-run）は覚えない
+            if completion.error.is_some() || completion.is_interrupted() || config.dry_run {
+                return completion;
+            }
+            store(&key, &completion, config);
+            completion
+        }
+    };
+    config.cache.insert(key, completion.clone(), config.cache_max_entries.unwrap_or(DEFAULT_CACHE_ENTRIES));
+    completion
+}
+
+// cache_dir に置く答え（キーも残して、ファイル名のハッシュがたまたま同じだった別の推論の答えは使わない）
+#[derive(Serialize, Deserialize)]
+struct StoredAnswer {
+    key: String,
+    text: String,
+    finish_reason: Option<String>,
+    reasoning: Option<String>,
+    usage: Option<Usage>,
+    #[serde(default)]
+    citations: Vec<String>,
+    #[serde(default)]
+    search_suggestions: Vec<String>,
+    #[serde(default)]
+    tool_calls: Vec<serde_json::Value>,
+    #[serde(default)]
+    thinking_blocks: Vec<serde_json::Value>,
+}
+
+fn stored_path(dir: &str, key: &str, compressed: bool) -> PathBuf {
+    let extension = if compressed { compression::EXTENSION } else { "" };
+    Path::new(dir).join(format!("{}.json{}", attachments::content_hash(key), extension))
+}
+
+// 前に置いた答えを読む（圧縮したものも、していないものも探す）
+fn load_stored(key: &str, config: &Config) -> Option<Completion> {
+    let dir = config.cache_dir.as_deref()?;
+    let stored: StoredAnswer = [true, false].into_iter()
+        .find_map(|compressed| compression::read_to_string(&stored_path(dir, key, compressed)).ok())
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .filter(|stored: &StoredAnswer| stored.key == key)?;
+    Some(Completion {
+        text: stored.text,
+        finish_reason: stored.finish_reason,
+        reasoning: stored.reasoning,
+        usage: stored.usage,
+        citations: stored.citations,
+        search_suggestions: stored.search_suggestions,
+        tool_calls: stored.tool_calls,
+        thinking_blocks: stored.thinking_blocks,
+        ..Default::default()
+    })
+}
+
+fn store(key: &str, completion: &Completion, config: &Config) {
+    let Some(dir) = config.cache_dir.as_deref() else {
+        return;
+    };
+    let path = stored_path(dir, key, compression::is_enabled(config));
+    let stored = StoredAnswer {
+        key: key.to_string(),
+        text: completion.text.clone(),
+        finish_reason: completion.finish_reason.clone(),
+        reasoning: completion.reasoning.clone(),
+        usage: completion.usage,
+        citations: completion.citations.clone(),
+        search_suggestions: completion.search_suggestions.clone(),
+        tool_calls: completion.tool_calls.clone(),
+        thinking_blocks: completion.thinking_blocks.clone(),
+    };
+    let data = serde_json::to_string(&stored).unwrap_or_default();
+    let written = fs::create_dir_all(dir)
+        .and_then(|_| compression::encode_for(&path, data.as_bytes()))
+        .and_then(|data| fs::write(&path, data));
+    if let (Err(e), false) = (written, config.quiet) {
+        eprintln!("{} の書き込みに失敗しました: {:?}", path.display(), e);
+    }
+}
+
+async fn redaction(inner: &[String], prompt: String, config: &Config) -> Completion {
+    let mut redacted = config.clone();
+    redacted.system_prompt = config.system_prompt.as_deref().map(filters::redact);
+    for message in &mut redacted.history {
+        message.content = filters::redact(&message.content);
+    }
+    call(inner, filters::redact(&prompt), &redacted).await
+}
+
+// ストリーミングで途中まで表示してから失敗したときは、表示が二重にならないようにやり直さない
+async fn retry(inner: &[String], prompt: String, config: &Config) -> Completion {
+    let retries = config.middleware_retries.unwrap_or(DEFAULT_RETRIES);
+    let mut attempt = 0;
+    loop {
+        let emitted = stream::emitted(config);
+        let completion = call(inner, prompt.clone(), config).await;
+        let retryable = completion.error_kind.is_none_or(|kind| kind.is_retryable());
+        let Some(error) = completion.error.as_ref().filter(|_| retryable && attempt < retries && stream::emitted(config) == emitted) else {
+            return completion;
+        };
+        let wait = request::retry_wait(attempt);
+        if !config.quiet {
+            eprintln!("{}\n{:.1}秒後に推論をやり直します（{}/{}）", error, wait.as_secs_f64(), attempt + 1, retries);
+        }
+        runtime::sleep(wait).await;
+        attempt += 1;
+    }
+}
+
+async fn budget(inner: &[String], prompt: String, config: &Config) -> Completion {
+    match stats::over_budget(config) {
+        Some(reason) => Completion::failed(format!("{}。送信しませんでした", reason)),
+        None => call(inner, prompt, config).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(name: &str, middleware: &[&str]) -> Config {
+        let dir = std::env::temp_dir().join(format!("milti_llm_client-middleware-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        serde_json::from_value(serde_json::json!({
+            "model_name": "mock", "use_local_model": true, "local_framework": "mock", "openai_compatible": false,
+            "middleware": middleware,
+            "middleware_log": dir.join("middleware.jsonl").to_string_lossy(),
+            "cache_dir": dir.join("cache").to_string_lossy(),
+        })).unwrap()
+    }
+
+    fn log_lines(config: &Config) -> usize {
+        fs::read_to_string(config.middleware_log.as_deref().unwrap()).map(|log| log.lines().count()).unwrap_or(0)
+    }
+
+    #[tokio::test]
+    async fn layers_wrap_in_the_order_they_are_written() {
+        // logging が外側なら、cache から返した推論も記録する
+        let outer = config("outer", &["logging", "cache"]);
+        fs::create_dir_all(Path::new(outer.middleware_log.as_deref().unwrap()).parent().unwrap()).unwrap();
+        assert_eq!(run("外側", &outer).await.text, "echo: 外側");
+        run("外側", &outer).await;
+        assert_eq!(log_lines(&outer), 2);
+
+        let inner = config("inner", &["cache", "logging"]);
+        fs::create_dir_all(Path::new(inner.middleware_log.as_deref().unwrap()).parent().unwrap()).unwrap();
+        run("内側", &inner).await;
+        run("内側", &inner).await;
+        assert_eq!(log_lines(&inner), 1);
+        for config in [outer, inner] {
+            let _ = fs::remove_dir_all(Path::new(config.middleware_log.as_deref().unwrap()).parent().unwrap());
+        }
+    }
+
+    fn online(endpoint: &str) -> Config {
+        serde_json::from_value(serde_json::json!({
+            "model_name": "gpt-4o-mini", "use_local_model": false, "openai_compatible": true, "chat": true,
+            "endpoint": endpoint, "api_key": "sk-test",
+        })).unwrap()
+    }
+
+    #[tokio::test]
+    async fn the_cache_key_covers_everything_sent_to_the_provider() {
+        let base = online("https://a.example/v1/chat/completions");
+        let key = cache_key("やあ", &base).await;
+        assert_eq!(key, cache_key("やあ", &base.clone()).await);
+        let mut penalized = base.clone();
+        penalized.presence_penalty = Some(0.5);
+        let mut other_key = base.clone();
+        other_key.api_key = Some("sk-other".to_string());
+        assert_ne!(key, cache_key("やあ", &penalized).await);
+        let mut gemini = base.clone();
+        gemini.provider = Some("gemini".to_string());
+        let mut filtered = gemini.clone();
+        filtered.safety_settings.insert("harassment".to_string(), "block_only_high".to_string());
+        let mut grounded = gemini.clone();
+        grounded.google_search = true;
+        let gemini_key = cache_key("やあ", &gemini).await;
+        assert_ne!(gemini_key, cache_key("やあ", &filtered).await);
+        assert_ne!(gemini_key, cache_key("やあ", &grounded).await);
+        // APIキーはキーに残さない（伏せ字にする）
+        assert_eq!(key, cache_key("やあ", &other_key).await);
+        assert!(!key.contains("sk-test"));
+    }
+
+    #[test]
+    fn the_cache_forgets_the_least_recently_used_answer() {
+        let cache = Cache::default();
+        cache.insert("a".to_string(), "A".to_string().into(), 2);
+        cache.insert("b".to_string(), "B".to_string().into(), 2);
+        assert_eq!(cache.get("a").map(|answer| answer.text).as_deref(), Some("A"));
+        cache.insert("c".to_string(), "C".to_string().into(), 2);
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("a").is_some() && cache.get("c").is_some());
+    }
+
+    #[tokio::test]
+    async fn configs_with_their_own_cache_do_not_share_answers() {
+        let mut first = config("separate", &["cache"]);
+        first.cache_dir = None;
+        let mut second = first.clone();
+        second.cache = Cache::default();
+        run("共有しない", &first).await;
+        let key = cache_key("共有しない", &first).await;
+        assert!(first.cache.get(&key).is_some());
+        assert!(second.cache.get(&key).is_none());
+    }
+
+    #[cfg(feature = "native")]
+    #[tokio::test]
+    async fn compressed_answers_are_used_by_the_next_process() {
+        let mut config = config("stored", &["cache"]);
+        config.compression = Some("zstd".to_string());
+        assert_eq!(run("覚えておいて", &config).await.text, "echo: 覚えておいて");
+        let key = cache_key("覚えておいて", &config).await;
+        let path = stored_path(config.cache_dir.as_deref().unwrap(), &key, true);
+        assert!(!fs::read(&path).unwrap().starts_with(b"{"));
+
+        // このプロセスのキャッシュを忘れても、ファイルに置いた答えを返す（モデルには聞き直さない）
+        config.cache = Cache::default();
+        store(&key, &"置いた答え".to_string().into(), &config);
+        assert_eq!(run("覚えておいて", &config).await.text, "置いた答え");
+        let _ = fs::remove_dir_all(Path::new(config.cache_dir.as_deref().unwrap()).parent().unwrap());
+    }
+}
@@ -0,0 +1,93 @@
+// バックエンドなしで動かすためのモックプロバイダー
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use serde::Deserialize;
+use crate::runtime;
+
+// モックの設定（config.json の "mock" に書く）
+#[derive(Clone, Deserialize, Default)]
+pub struct MockConfig {
+    #[serde(default)]
+    pub mode: Option<String>, // "echo"（デフォルト） / "canned" / "script"
+    #[serde(default)]
+    pub responses: Vec<String>, // canned モードで順番に返す応答
+    #[serde(default)]
+    pub script: HashMap<String, String>, // script モードでのプロンプト → 応答の対応表
+    #[serde(default)]
+    pub latency_ms: u64, // 応答までにわざと待つ時間
+    #[serde(default)]
+    pub chunk_delay_ms: Option<u64>, // stream のとき、断片ごとに待つ時間（デフォルト30）
+}
+
+const DEFAULT_CHUNK_DELAY_MS: u64 = 30;
+
+// canned モードで次に返す応答の番号
+static NEXT_CANNED: AtomicUsize = AtomicUsize::new(0);
+
+// 設定に従ってモックの応答を作る
+pub async fn mock_inference(prompt: &str, config: Option<&MockConfig>) -> String {
+    let default_config = MockConfig::default();
+    let mock = config.unwrap_or(&default_config);
+
+    if mock.latency_ms > 0 {
+        runtime::sleep(Duration::from_millis(mock.latency_ms)).await;
+    }
+
+    match mock.mode.as_deref().unwrap_or("echo") {
+        "canned" if !mock.responses.is_empty() => {
+            let index = NEXT_CANNED.fetch_add(1, Ordering::Relaxed) % mock.responses.len();
+            mock.responses[index].clone()
+        }
+        "script" => mock.script.get(prompt)
+            .cloned()
+            .unwrap_or_else(|| format!("echo: {}", prompt)),
+        _ => format!("echo: {}", prompt),
+    }
+}
+
+// stream のときに流す断片（単語ごとに、区切りの空白は前の単語に付ける。空白のない日本語は数文字ずつ）
+pub fn chunks(text: &str) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for c in text.chars() {
+        let ends_word = c.is_whitespace();
+        current.push(c);
+        if ends_word || (!c.is_ascii() && current.chars().count() >= 4) {
+            chunks.push(std::mem::take(&mut current));
+        }
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+// 応答を断片に分けて、本物のストリーミングのように少しずつ流す
+pub async fn stream_chunks(text: &str, config: &crate::Config) {
+    let delay = config.mock.as_ref().and_then(|mock| mock.chunk_delay_ms).unwrap_or(DEFAULT_CHUNK_DELAY_MS);
+    for chunk in chunks(text) {
+        crate::stream::emit(config, crate::stream::Token::Answer(chunk));
+        if delay > 0 {
+            runtime::sleep(Duration::from_millis(delay)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_join_back_to_the_text() {
+        for text in ["echo: hello  world\n", "こんにちは、世界。元気ですか", "", "a"] {
+            assert_eq!(chunks(text).concat(), text);
+        }
+    }
+
+    #[test]
+    fn chunks_split_words_and_long_japanese_runs() {
+        assert_eq!(chunks("echo: hi there"), ["echo: ", "hi ", "there"]);
+        assert_eq!(chunks("こんにちは世界"), ["こんにち", "は世界"]);
+    }
+}
@@ -0,0 +1,81 @@
+// 標準入力からJSONのリクエストを1行ずつ読み、標準出力にJSONの応答を1行ずつ書く（コプロセスとして使うためのモード）
+//
+//   入力: {"id": 1, "prompt": "こんにちは"}
+//   出力: {"id": 1, "text": "...", "finish_reason": "stop", "reasoning": null, "usage": {...}}
+//
+// --format を指定したときは、JSONの代わりにテンプレートで整形した結果を書く。
+// 標準出力にはJSONしか書かないので、進行状況などのメッセージは標準エラーに出す。
+// セッションの上限（max_session_tokens / max_session_cost）を超えたら、そこで読むのをやめて終わる。
+use std::io::{self, BufRead, Write};
+use web_time::{Instant, SystemTime};
+use serde::Deserialize;
+use serde_json::Value;
+use crate::{filters, format, router, stats, Config};
+use crate::error::Error;
+
+#[derive(Deserialize)]
+struct PipeRequest {
+    #[serde(default)]
+    id: Value, // 応答にそのまま返す（どの入力への応答かを呼び出し側で対応づけるため）
+    prompt: String,
+}
+
+pub async fn run(config: &Config) -> Result<(), Error> {
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = line.map_err(|e| format!("標準入力の読み込みに失敗しました: {:?}", e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let output = match serde_json::from_str::<PipeRequest>(&line) {
+            Ok(request) => respond(request, config).await,
+            Err(e) => serde_json::json!({ "id": null, "error": format!("リクエストのパースに失敗しました: {}", e) }).to_string(),
+        };
+        let mut stdout = io::stdout().lock();
+        writeln!(stdout, "{}", output)
+            .and_then(|_| stdout.flush())
+            .map_err(|e| format!("標準出力への書き込みに失敗しました: {:?}", e))?;
+        // 上限を超えたら、残りの行は読まずに終わる
+        if let Some(error) = stats::budget_exceeded(config) {
+            return Err(error);
+        }
+    }
+    Ok(())
+}
+
+// 1件分の応答を、出力する1行の文字列にする
+async fn respond(request: PipeRequest, config: &Config) -> String {
+    let started_at = SystemTime::now();
+    let started = Instant::now();
+    let route = router::route(&request.prompt, config);
+    let config = route.as_ref().map(|route| &route.config).unwrap_or(config);
+    let mut completion = crate::respond(&request.prompt, config).await;
+    if !config.dry_run {
+        stats::record(config, &config.model_name, &request.prompt, &completion, started.elapsed());
+    }
+    if !config.dry_run {
+        completion.text = filters::apply(&completion.text, &config.output_filters, config.raw);
+    }
+    if let Some(template) = &config.format {
+        let mut data = format::response_data(&config.model_name, &request.prompt, &completion, started_at, started.elapsed());
+        data["id"] = request.id;
+        return match format::render(template, &data) {
+            Ok(output) => output,
+            Err(e) => serde_json::json!({ "id": data["id"], "error": e }).to_string(),
+        };
+    }
+    let usage = completion.usage.map(|usage| serde_json::json!({
+        "prompt_tokens": usage.prompt_tokens,
+        "completion_tokens": usage.completion_tokens,
+        "cached_tokens": usage.cached_tokens,
+    }));
+    serde_json::json!({
+        "id": request.id,
+        "text": completion.text,
+        "finish_reason": completion.finish_reason,
+        "reasoning": completion.reasoning,
+        "citations": completion.citations,
+        "search_suggestions": completion.search_suggestions,
+        "usage": usage,
+    }).to_string()
+}
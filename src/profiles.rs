@@ -0,0 +1,128 @@
+// 名前つきのモデルの設定（プロファイル）
+//
+// config.json の "profiles" に、モデルごとの接続先やAPIキーをまとめて書いておき、
+// --profile で起動時に、チャット中なら /model <名前> で切り替える（会話の履歴はそのまま残る）。
+// system メッセージと、始めるときに表示するあいさつ（greeting。送らない）、推論に挟む処理（middleware）もプロファイルごとに書ける。
+// プロファイルに書いていない項目は、config.json の最上位に書いた値を使う。
+// プロファイルが複数あってどれも指定されていなければ、起動時に一覧から選んでもらう（聞くのはコマンドラインの側）。
+use std::collections::HashMap;
+use std::sync::Arc;
+use serde::Deserialize;
+use crate::{benchmark, select_model, Config};
+use crate::publish::PublishConfig;
+
+#[derive(Clone, Deserialize, Default)]
+pub struct Profile {
+    pub model_name: Option<String>, // "モデル名@行き先" や別名も書ける
+    pub endpoint: Option<String>,
+    pub api_key: Option<String>,
+    pub api_base: Option<String>,
+    pub provider: Option<String>,
+    pub cached_content: Option<String>, // このプロファイルの Gemini のモデルで作ったキャッシュ
+    pub safety_settings: Option<HashMap<String, String>>, // 書いたら最上位の "safety_settings" の代わりに使う
+    pub use_local_model: Option<bool>,
+    pub local_framework: Option<String>,
+    pub openai_compatible: Option<bool>,
+    pub max_tokens: Option<u32>,
+    pub system_prompt: Option<String>,
+    pub greeting: Option<String>,
+    pub middleware: Option<Vec<String>>, // 書いたら最上位の "middleware" の代わりに使う
+    pub publish: Option<PublishConfig>, // 書いたら最上位の "publish" の代わりに、このトピックに流す
+}
+
+// config.json の最上位の値を、プロファイルに書いていない項目を戻す先として覚えておく
+// （Client::new で呼ぶ。覚えていなければ、最初に切り替えるときの値を使う）
+pub fn remember_defaults(config: &mut Config) {
+    config.defaults = Some(Arc::new(Profile {
+        model_name: Some(config.model_name.clone()),
+        endpoint: config.endpoint.clone(),
+        api_key: config.api_key.clone(),
+        api_base: config.api_base.clone(),
+        provider: config.provider.clone(),
+        cached_content: config.cached_content.clone(),
+        safety_settings: Some(config.safety_settings.clone()),
+        use_local_model: Some(config.use_local_model),
+        local_framework: config.local_framework.clone(),
+        openai_compatible: Some(config.openai_compatible),
+        max_tokens: config.max_tokens,
+        system_prompt: config.system_prompt.clone(),
+        greeting: config.greeting.clone(),
+        middleware: Some(config.middleware.clone()),
+        publish: config.publish.clone(),
+    }));
+}
+
+// プロファイルに切り替える
+pub fn apply(config: &mut Config, name: &str) -> Result<(), String> {
+    let profile = config.profiles.get(name).cloned().ok_or_else(|| {
+        let mut names: Vec<&str> = config.profiles.keys().map(String::as_str).collect();
+        names.sort();
+        format!("不明なプロファイルです: {}（設定済み: {}）", name, if names.is_empty() { "なし".to_string() } else { names.join(" / ") })
+    })?;
+    if config.defaults.is_none() {
+        remember_defaults(config);
+    }
+    let defaults = config.defaults.as_deref().cloned().unwrap_or_default();
+    config.endpoint = profile.endpoint.or(defaults.endpoint);
+    config.api_key = profile.api_key.or(defaults.api_key);
+    config.api_base = profile.api_base.or(defaults.api_base);
+    config.provider = profile.provider.or(defaults.provider);
+    config.cached_content = profile.cached_content.or(defaults.cached_content);
+    config.safety_settings = profile.safety_settings.or(defaults.safety_settings).unwrap_or_default();
+    config.use_local_model = profile.use_local_model.or(defaults.use_local_model).unwrap_or(config.use_local_model);
+    config.local_framework = profile.local_framework.or(defaults.local_framework);
+    config.openai_compatible = profile.openai_compatible.or(defaults.openai_compatible).unwrap_or(config.openai_compatible);
+    config.max_tokens = profile.max_tokens.or(defaults.max_tokens);
+    config.system_prompt = profile.system_prompt.or(defaults.system_prompt);
+    config.greeting = profile.greeting.or(defaults.greeting);
+    config.middleware = profile.middleware.or(defaults.middleware).unwrap_or_default();
+    config.publish = profile.publish.or(defaults.publish);
+    let model_name = profile.model_name.or(defaults.model_name).unwrap_or_else(|| config.model_name.clone());
+    select_model(config, &model_name);
+    config.profile = Some(name.to_string());
+    Ok(())
+}
+
+// プロファイルの名前ならそのプロファイルに、そうでなければモデル名として切り替える
+// （二重送信モードや judge の審査役の指定に使う）
+pub fn select(config: &mut Config, name: &str) {
+    if config.profiles.contains_key(name) {
+        let _ = apply(config, name);
+    } else {
+        select_model(config, name);
+    }
+}
+
+// /model で表示するプロファイルの一覧（今のプロファイルには * を付ける）
+pub fn list(config: &Config) -> String {
+    if config.profiles.is_empty() {
+        return "プロファイルは設定されていません（config.json の \"profiles\" に書いてください）".to_string();
+    }
+    let mut names: Vec<&String> = config.profiles.keys().collect();
+    names.sort();
+    names.iter()
+        .map(|name| {
+            let current = if config.profile.as_ref() == Some(*name) { "*" } else { " " };
+            let model = config.profiles[*name].model_name.as_deref().unwrap_or("-");
+            format!("{} {}（{}）", current, name, model)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// 起動時に選んでもらうプロファイルの一覧（名前と、モデルを添えた表示。名前の順）
+pub fn choices(config: &Config) -> Vec<(String, String)> {
+    let mut names: Vec<&String> = config.profiles.keys().collect();
+    names.sort();
+    // bench で測っていれば、その速さも並べる
+    let measurements = benchmark::load(config);
+    names.into_iter()
+        .map(|name| {
+            let model = config.profiles[name].model_name.as_deref().unwrap_or(&config.model_name);
+            match measurements.get(name) {
+                Some(measurement) => (name.clone(), format!("{}（{}・{}）", name, model, measurement.describe())),
+                None => (name.clone(), format!("{}（{}）", name, model)),
+            }
+        })
+        .collect()
+}
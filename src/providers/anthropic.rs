@@ -0,0 +1,387 @@
+// Anthropic の Messages API
+//
+// 認証は x-api-key ヘッダーで、バージョンの指定が必要。system はメッセージの並びではなく最上位に書き、
+// 返事は種類つきのブロック（"text"、考え中の "thinking"）の配列で返ってくる。
+// system と、最後の質問より前の履歴にはキャッシュの区切り（cache_control）を付け、次のターンで読み直させる。
+// ストリーミングではブロックごとに content_block_start / _delta / _stop が届くので、届いた順にブロックを組み立て直す。
+// 考え中のブロックは署名（signature）ごと履歴に残し、同じモデルに送るときは assistant のメッセージの先頭にそのまま返す。
+// ツールは input_schema の形で送り、tool_use のブロックを OpenAI の tool_calls の形に直して返す（引数は input_json_delta で少しずつ届く）。
+// ツールを使っている途中のやりとり（OpenAI の形の tool_messages）は、tool_use / tool_result のブロックにして後ろに付ける。
+use serde_json::Value;
+use crate::{sampling, stream, tools, Config};
+use crate::completion::{Completion, Usage};
+use crate::request::PreparedRequest;
+use crate::stream::Token;
+use super::{image_media_type, images_for, split_system, Backend};
+
+const DEFAULT_ENDPOINT: &str = "https://api.anthropic.com/v1/messages";
+const API_VERSION: &str = "2023-06-01";
+
+pub struct Anthropic;
+
+impl Backend for Anthropic {
+    fn request(&self, prompt: &str, config: &Config) -> Result<PreparedRequest, String> {
+        let endpoint = config.endpoint.as_deref().unwrap_or(DEFAULT_ENDPOINT);
+        let (system, messages) = split_system(prompt, config);
+        let mut messages: Vec<Value> = messages.iter().enumerate()
+            .map(|(i, message)| {
+                let images = images_for(i, message, &messages, config);
+                if !message.thinking.is_empty() {
+                    let mut blocks = message.thinking.clone();
+                    blocks.push(serde_json::json!({ "type": "text", "text": message.content }));
+                    return serde_json::json!({ "role": message.role, "content": blocks });
+                }
+                if images.is_empty() {
+                    return serde_json::json!({ "role": message.role, "content": message.content });
+                }
+                let mut blocks: Vec<Value> = images.iter()
+                    .map(|image| serde_json::json!({
+                        "type": "image",
+                        "source": { "type": "base64", "media_type": image_media_type(image), "data": image },
+                    }))
+                    .collect();
+                blocks.push(serde_json::json!({ "type": "text", "text": message.content }));
+                serde_json::json!({ "role": message.role, "content": blocks })
+            })
+            .collect();
+        // 最後の質問は毎回変わるので、その手前までの履歴をキャッシュさせる
+        if messages.len() > 1 {
+            let index = messages.len() - 2;
+            mark_cacheable(&mut messages[index]);
+        }
+        extend_tool_messages(&mut messages, &config.tool_messages);
+        let mut body = serde_json::json!({
+            "model": config.model_name,
+            "messages": messages,
+            "max_tokens": config.max_tokens.unwrap_or(64),
+        });
+        if let Some(system) = system {
+            body["system"] = serde_json::json!([{ "type": "text", "text": system, "cache_control": { "type": "ephemeral" } }]);
+        }
+        if let Some(budget) = config.thinking_budget {
+            body["thinking"] = serde_json::json!({ "type": "enabled", "budget_tokens": budget });
+        }
+        if config.stream {
+            body["stream"] = serde_json::json!(true);
+        }
+        let definitions: Vec<Value> = tools::definitions(config).iter()
+            .map(|definition| serde_json::json!({
+                "name": definition["function"]["name"],
+                "description": definition["function"]["description"],
+                "input_schema": definition["function"]["parameters"],
+            }))
+            .collect();
+        if !definitions.is_empty() {
+            body["tools"] = Value::Array(definitions);
+        }
+        sampling::extend(&mut body, config, &sampling::ANTHROPIC);
+
+        let mut request = PreparedRequest::new(endpoint, body)
+            .header("anthropic-version", API_VERSION.to_string());
+        if let Some(api_key) = &config.api_key {
+            request = request.header("x-api-key", api_key.clone());
+        }
+        Ok(request)
+    }
+
+    fn parse(&self, json: &Value) -> Completion {
+        let blocks = json.get("content").and_then(|c| c.as_array()).cloned().unwrap_or_default();
+        let collect = |kind: &str, key: &str| -> String {
+            blocks.iter()
+                .filter(|block| block.get("type").and_then(|t| t.as_str()) == Some(kind))
+                .filter_map(|block| block.get(key).and_then(|t| t.as_str()))
+                .collect()
+        };
+        let thinking = collect("thinking", "thinking");
+        let count = |key: &str| json.pointer(&format!("/usage/{}", key)).and_then(|v| v.as_u64());
+        Completion {
+            text: collect("text", "text"),
+            thinking_blocks: blocks.iter()
+                .filter(|block| matches!(block.get("type").and_then(|t| t.as_str()), Some("thinking" | "redacted_thinking")))
+                .cloned()
+                .collect(),
+            tool_calls: blocks.iter()
+                .filter(|block| block.get("type").and_then(|t| t.as_str()) == Some("tool_use"))
+                .map(|block| serde_json::json!({
+                    "id": block["id"],
+                    "type": "function",
+                    "function": { "name": block["name"], "arguments": block["input"].to_string() },
+                }))
+                .collect(),
+            // "max_tokens" は OpenAI の "length" に合わせる（自動で続きを生成できるように）
+            finish_reason: json.get("stop_reason").and_then(|r| r.as_str())
+                .map(|reason| if reason == "max_tokens" { "length".to_string() } else { reason.to_string() }),
+            reasoning: (!thinking.is_empty()).then_some(thinking),
+            // input_tokens にはキャッシュから読んだ分と書き込んだ分が含まれないので足す
+            usage: count("input_tokens").map(|input_tokens| Usage {
+                prompt_tokens: input_tokens
+                    + count("cache_read_input_tokens").unwrap_or(0)
+                    + count("cache_creation_input_tokens").unwrap_or(0),
+                completion_tokens: count("output_tokens").unwrap_or(0),
+                cached_tokens: count("cache_read_input_tokens").unwrap_or(0),
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn streams(&self, config: &Config) -> bool {
+        config.stream
+    }
+
+    fn stream_event(&self, event: &Value, config: &Config) {
+        if event.get("type").and_then(|t| t.as_str()) != Some("content_block_delta") {
+            return;
+        }
+        let delta = &event["delta"];
+        match delta.get("type").and_then(|t| t.as_str()) {
+            Some("text_delta") => stream::emit(config, Token::Answer(delta["text"].as_str().unwrap_or_default().to_string())),
+            Some("thinking_delta") => stream::emit(config, Token::Reasoning(delta["thinking"].as_str().unwrap_or_default().to_string())),
+            _ => {}
+        }
+    }
+
+    // message_start のメッセージに、届いたブロックと message_delta の終了理由・出力トークン数を入れていく
+    fn collect_stream(&self, events: &[Value]) -> (Value, bool) {
+        let mut message = serde_json::json!({ "content": [] });
+        let mut finished = false;
+        for event in events {
+            let index = event.get("index").and_then(|i| i.as_u64()).unwrap_or(0) as usize;
+            match event.get("type").and_then(|t| t.as_str()) {
+                Some("message_start") => {
+                    message = event["message"].clone();
+                    message["content"] = serde_json::json!([]);
+                }
+                Some("content_block_start") => {
+                    let blocks = message["content"].as_array_mut().unwrap();
+                    blocks.resize(blocks.len().max(index + 1), Value::Null);
+                    blocks[index] = event["content_block"].clone();
+                }
+                Some("content_block_delta") => {
+                    let Some(block) = message["content"].get_mut(index) else {
+                        continue;
+                    };
+                    let delta = &event["delta"];
+                    let (key, part) = match delta.get("type").and_then(|t| t.as_str()) {
+                        Some("text_delta") => ("text", &delta["text"]),
+                        Some("thinking_delta") => ("thinking", &delta["thinking"]),
+                        Some("signature_delta") => ("signature", &delta["signature"]),
+                        Some("input_json_delta") => ("partial_json", &delta["partial_json"]),
+                        _ => continue,
+                    };
+                    let joined = format!("{}{}", block[key].as_str().unwrap_or_default(), part.as_str().unwrap_or_default());
+                    block[key] = Value::String(joined);
+                }
+                // ツールの引数は、ブロックが終わったところでつなげたJSONを読む
+                Some("content_block_stop") => {
+                    let Some(block) = message["content"].get_mut(index) else {
+                        continue;
+                    };
+                    if let Some(Value::String(json)) = block.as_object_mut().and_then(|block| block.remove("partial_json")) {
+                        block["input"] = if json.is_empty() { serde_json::json!({}) } else { serde_json::from_str(&json).unwrap_or_default() };
+                    }
+                }
+                Some("message_delta") => {
+                    if let Some(reason) = event.pointer("/delta/stop_reason").filter(|r| !r.is_null()) {
+                        message["stop_reason"] = reason.clone();
+                    }
+                    if let Some(output) = event.pointer("/usage/output_tokens") {
+                        message["usage"]["output_tokens"] = output.clone();
+                    }
+                }
+                Some("message_stop") => finished = true,
+                Some("error") => return (serde_json::json!({ "error": event["error"] }), true),
+                _ => {}
+            }
+        }
+        // 途中で切れて引数が揃わなかったツールの呼び出しは使わない
+        if let Some(blocks) = message["content"].as_array_mut() {
+            blocks.retain(|block| block.get("partial_json").is_none());
+        }
+        (message, finished)
+    }
+}
+
+// ツールを使っている途中のやりとり（OpenAI の形）を Anthropic の形にして後ろに付ける
+// assistant の tool_calls は tool_use のブロックに、続く tool の結果は1つの user メッセージの tool_result のブロックにまとめる
+fn extend_tool_messages(messages: &mut Vec<Value>, tool_messages: &[Value]) {
+    for message in tool_messages {
+        let content = message.get("content").and_then(|c| c.as_str()).unwrap_or_default();
+        match message.get("role").and_then(|r| r.as_str()) {
+            Some("assistant") => {
+                let mut blocks = message.get("thinking_blocks").and_then(|b| b.as_array()).cloned().unwrap_or_default();
+                if !content.is_empty() {
+                    blocks.push(serde_json::json!({ "type": "text", "text": content }));
+                }
+                for call in message.get("tool_calls").and_then(|c| c.as_array()).into_iter().flatten() {
+                    let arguments = call.pointer("/function/arguments").and_then(|a| a.as_str()).unwrap_or_default();
+                    blocks.push(serde_json::json!({
+                        "type": "tool_use",
+                        "id": call["id"],
+                        "name": call["function"]["name"],
+                        "input": serde_json::from_str::<Value>(arguments).unwrap_or_else(|_| serde_json::json!({})),
+                    }));
+                }
+                messages.push(serde_json::json!({ "role": "assistant", "content": blocks }));
+            }
+            Some("tool") => push_user_block(messages, serde_json::json!({
+                "type": "tool_result", "tool_use_id": message["tool_call_id"], "content": content,
+            })),
+            _ => push_user_block(messages, serde_json::json!({ "type": "text", "text": content })),
+        }
+    }
+}
+
+// user のブロックを足す（直前が user ならそのメッセージに足す。Anthropic では user が続けて並べられない）
+fn push_user_block(messages: &mut Vec<Value>, block: Value) {
+    if let Some(last) = messages.last_mut().filter(|last| last["role"] == "user") {
+        if let Some(text) = last["content"].as_str() {
+            last["content"] = serde_json::json!([{ "type": "text", "text": text }]);
+        }
+        if let Some(blocks) = last["content"].as_array_mut() {
+            blocks.push(block);
+            return;
+        }
+    }
+    messages.push(serde_json::json!({ "role": "user", "content": [block] }));
+}
+
+// メッセージの最後のブロックにキャッシュの区切りを付ける（文字列の content はブロックの配列に直す）
+fn mark_cacheable(message: &mut Value) {
+    if let Some(text) = message["content"].as_str() {
+        message["content"] = serde_json::json!([{ "type": "text", "text": text }]);
+    }
+    if let Some(last) = message["content"].as_array_mut().and_then(|blocks| blocks.last_mut()) {
+        last["cache_control"] = serde_json::json!({ "type": "ephemeral" });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conversation::Message;
+
+    fn chat_config(history: &[(&str, &str)]) -> Config {
+        let mut config: Config = serde_json::from_value(serde_json::json!({
+            "model_name": "claude-3-5-sonnet-latest", "use_local_model": false, "openai_compatible": false, "chat": true,
+        })).unwrap();
+        config.history = history.iter().map(|(role, content)| Message::new(role, content)).collect();
+        config
+    }
+
+    #[test]
+    fn marks_the_system_prompt_and_history_as_cacheable() {
+        let mut config = chat_config(&[("user", "1"), ("assistant", "2")]);
+        config.system_prompt = Some("丁寧に".to_string());
+        let body = Anthropic.request("3", &config).unwrap().body;
+        assert_eq!(body["system"][0]["cache_control"]["type"], "ephemeral");
+        assert_eq!(body["messages"][1]["content"][0]["text"], "2");
+        assert_eq!(body["messages"][1]["content"][0]["cache_control"]["type"], "ephemeral");
+        // 最後の質問には付けない
+        assert_eq!(body["messages"][2]["content"], "3");
+        assert!(body["messages"][0]["content"].is_string());
+    }
+
+    #[test]
+    fn a_single_question_has_no_history_breakpoint() {
+        let body = Anthropic.request("1", &chat_config(&[])).unwrap().body;
+        assert_eq!(body["messages"][0]["content"], "1");
+        assert!(body.get("system").is_none());
+    }
+
+    #[test]
+    fn cached_tokens_count_toward_the_prompt() {
+        let completion = Anthropic.parse(&serde_json::json!({
+            "content": [{ "type": "text", "text": "ok" }],
+            "stop_reason": "end_turn",
+            "usage": { "input_tokens": 10, "cache_read_input_tokens": 1800, "cache_creation_input_tokens": 190, "output_tokens": 5 },
+        }));
+        let usage = completion.usage.unwrap();
+        assert_eq!((usage.prompt_tokens, usage.cached_tokens, usage.completion_tokens), (2000, 1800, 5));
+        assert_eq!(usage.cache_report().unwrap(), "プロンプトキャッシュ: 1800/2000 トークンがキャッシュから読まれました（90%）");
+    }
+
+    #[test]
+    fn a_cut_stream_is_not_finished_and_an_error_event_is_an_error() {
+        let start = serde_json::json!({ "type": "message_start", "message": { "content": [], "usage": { "input_tokens": 9 } } });
+        let block = serde_json::json!({ "type": "content_block_start", "index": 0, "content_block": { "type": "text", "text": "" } });
+        let delta = serde_json::json!({ "type": "content_block_delta", "index": 0, "delta": { "type": "text_delta", "text": "途中" } });
+        let (json, finished) = Anthropic.collect_stream(&[start.clone(), block, delta]);
+        assert!(!finished);
+        assert_eq!(Anthropic.parse(&json).text, "途中");
+
+        let error = serde_json::json!({ "type": "error", "error": { "type": "overloaded_error", "message": "Overloaded" } });
+        let (json, _) = Anthropic.collect_stream(&[start, error]);
+        assert_eq!(Anthropic.error_message(&json).as_deref(), Some("Overloaded"));
+    }
+
+    #[test]
+    fn thinking_blocks_go_back_only_to_the_model_that_wrote_them() {
+        let mut config = chat_config(&[("user", "1"), ("assistant", "2")]);
+        config.history[1].thinking = vec![serde_json::json!({ "type": "thinking", "thinking": "…", "signature": "s" })];
+        config.history[1].model = Some("claude-3-5-sonnet-latest".to_string());
+        let body = Anthropic.request("3", &config).unwrap().body;
+        assert_eq!(body["messages"][1]["content"][0]["signature"], "s");
+        assert_eq!(body["messages"][1]["content"][1]["text"], "2");
+
+        config.model_name = "claude-3-7-sonnet-latest".to_string();
+        let body = Anthropic.request("3", &config).unwrap().body;
+        assert_eq!(body["messages"][1]["content"][0]["text"], "2");
+        assert_eq!(body["messages"][1]["content"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn sends_tools_and_the_tool_round_as_blocks() {
+        let mut config = chat_config(&[]);
+        config.tools = serde_json::from_value(serde_json::json!(["read_file"])).unwrap();
+        config.tool_messages = vec![
+            serde_json::json!({
+                "role": "assistant", "content": "読みます",
+                "tool_calls": [{ "id": "toolu_1", "type": "function", "function": { "name": "read_file", "arguments": "{\"path\":\"a.txt\"}" } }],
+                "thinking_blocks": [{ "type": "thinking", "thinking": "…", "signature": "s" }],
+            }),
+            serde_json::json!({ "role": "tool", "tool_call_id": "toolu_1", "content": "中身" }),
+        ];
+        let body = Anthropic.request("a.txt を読んで", &config).unwrap().body;
+        assert_eq!(body["tools"][0]["name"], "read_file");
+        assert_eq!(body["tools"][0]["input_schema"]["required"], serde_json::json!(["path"]));
+        let messages = body["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[1]["content"], serde_json::json!([
+            { "type": "thinking", "thinking": "…", "signature": "s" },
+            { "type": "text", "text": "読みます" },
+            { "type": "tool_use", "id": "toolu_1", "name": "read_file", "input": { "path": "a.txt" } },
+        ]));
+        assert_eq!(messages[2], serde_json::json!({
+            "role": "user", "content": [{ "type": "tool_result", "tool_use_id": "toolu_1", "content": "中身" }],
+        }));
+    }
+
+    #[test]
+    fn assembles_tool_input_from_json_deltas() {
+        let events: Vec<Value> = [
+            serde_json::json!({ "type": "message_start", "message": { "content": [], "usage": { "input_tokens": 9 } } }),
+            serde_json::json!({ "type": "content_block_start", "index": 0, "content_block": { "type": "tool_use", "id": "toolu_1", "name": "shell", "input": {} } }),
+            serde_json::json!({ "type": "content_block_delta", "index": 0, "delta": { "type": "input_json_delta", "partial_json": "" } }),
+            serde_json::json!({ "type": "content_block_delta", "index": 0, "delta": { "type": "input_json_delta", "partial_json": "{\"comm" } }),
+            serde_json::json!({ "type": "content_block_delta", "index": 0, "delta": { "type": "input_json_delta", "partial_json": "and\": \"ls\"}" } }),
+            serde_json::json!({ "type": "content_block_stop", "index": 0 }),
+            serde_json::json!({ "type": "content_block_start", "index": 1, "content_block": { "type": "tool_use", "id": "toolu_2", "name": "read_file", "input": {} } }),
+            serde_json::json!({ "type": "content_block_stop", "index": 1 }),
+            serde_json::json!({ "type": "message_delta", "delta": { "stop_reason": "tool_use" }, "usage": { "output_tokens": 7 } }),
+            serde_json::json!({ "type": "message_stop" }),
+        ].into();
+        let (json, finished) = Anthropic.collect_stream(&events);
+        assert!(finished);
+        let completion = Anthropic.parse(&json);
+        assert_eq!(completion.finish_reason.as_deref(), Some("tool_use"));
+        assert_eq!(completion.tool_calls, [
+            serde_json::json!({ "id": "toolu_1", "type": "function", "function": { "name": "shell", "arguments": "{\"command\":\"ls\"}" } }),
+            serde_json::json!({ "id": "toolu_2", "type": "function", "function": { "name": "read_file", "arguments": "{}" } }),
+        ]);
+
+        // 引数の途中で切れた呼び出しは使わない
+        let (json, finished) = Anthropic.collect_stream(&events[..5]);
+        assert!(!finished);
+        assert!(Anthropic.parse(&json).tool_calls.is_empty());
+    }
+}
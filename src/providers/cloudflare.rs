@@ -0,0 +1,47 @@
+// Cloudflare Workers AI（アカウントごとのURLに、モデル名をパスに入れて送る）
+//
+// レスポンスは {"result": {...}, "success": true, "errors": [...]} の形で、
+// エラーメッセージも OpenAI とは違う場所（errors[0].message）に入っている。
+use serde_json::Value;
+use crate::{sampling, Config};
+use crate::completion::{Completion, Usage};
+use crate::error::Error;
+use crate::files::authorized;
+use crate::request::PreparedRequest;
+use super::chat_body;
+
+const API_BASE: &str = "https://api.cloudflare.com/client/v4";
+
+pub async fn inference(prompt: &str, config: &Config) -> Result<Completion, Error> {
+    let endpoint = match &config.endpoint {
+        Some(endpoint) => endpoint.clone(),
+        None => {
+            let account_id = config.account_id.as_deref()
+                .ok_or_else(|| Error::Config("Cloudflare Workers AI には account_id が必要です".to_string()))?;
+            format!("{}/accounts/{}/ai/run/{}", API_BASE, account_id, config.model_name)
+        }
+    };
+    // モデル名はURLに入っているので、本文には書かない
+    let mut body = chat_body(prompt, config, &sampling::OPENAI_COMPATIBLE);
+    if let Some(object) = body.as_object_mut() {
+        object.remove("model");
+    }
+    let request = authorized(PreparedRequest::new(&endpoint, body), config);
+    if config.dry_run {
+        return Ok(request.dry_run().into());
+    }
+
+    let response = request.send(config).await?;
+    let json: Value = serde_json::from_str(&response.body)
+        .map_err(|_| Error::from_response(&response))?;
+    if json.get("success").and_then(|s| s.as_bool()) != Some(true) {
+        let message = json.pointer("/errors/0/message").and_then(|m| m.as_str()).unwrap_or("理由不明");
+        return Err(Error::api(&response, message.to_string()));
+    }
+    let result = json.get("result").cloned().unwrap_or_default();
+    Ok(Completion {
+        text: result.get("response").and_then(|r| r.as_str()).unwrap_or("レスポンスが不正です").to_string(),
+        usage: result.get("usage").and_then(Usage::from_openai),
+        ..Default::default()
+    })
+}
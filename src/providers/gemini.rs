@@ -0,0 +1,313 @@
+// Google Gemini（Generative Language API の generateContent）
+//
+// 認証は x-goog-api-key ヘッダーで、モデル名はURLに入れる。メッセージは contents に
+// role（"user" / "model"）と parts の形で書き、system は systemInstruction に分けて書く。
+// cached_content を設定していれば、cache create で作ったキャッシュ（system と資料）を参照させる。
+// google_search なら Google 検索のツールを付け、返ってきた groundingMetadata から出典と検索した言葉を取り出す。
+// 安全フィルターで止められたときは、空の答えにせず、止めた理由とカテゴリをエラーにして返す。
+use std::collections::HashMap;
+use serde_json::Value;
+use crate::{sampling, Config};
+use crate::completion::{Completion, Usage};
+use crate::error::ErrorKind;
+use crate::request::PreparedRequest;
+use super::{image_media_type, images_for, split_system, Backend};
+
+const API_BASE: &str = "https://generativelanguage.googleapis.com/v1beta";
+
+pub struct Gemini;
+
+// safety_settings に書けるカテゴリと基準（"HARM_CATEGORY_" と大文字を省いた形。省かずに書いてもよい）
+const SAFETY_CATEGORIES: [&str; 5] = ["harassment", "hate_speech", "sexually_explicit", "dangerous_content", "civic_integrity"];
+const SAFETY_THRESHOLDS: [&str; 5] = ["block_none", "block_only_high", "block_medium_and_above", "block_low_and_above", "off"];
+
+// 安全フィルターで止められたときの finishReason
+const SAFETY_REASONS: [&str; 4] = ["SAFETY", "BLOCKLIST", "PROHIBITED_CONTENT", "SPII"];
+
+// cachedContents などを扱うときのベースURL（api_base があればそちら）
+pub fn api_base(config: &Config) -> String {
+    config.api_base.as_deref().unwrap_or(API_BASE).trim_end_matches('/').to_string()
+}
+
+impl Backend for Gemini {
+    fn request(&self, prompt: &str, config: &Config) -> Result<PreparedRequest, String> {
+        let endpoint = match &config.endpoint {
+            Some(endpoint) => endpoint.clone(),
+            None => format!("{}/models/{}:generateContent", API_BASE, config.model_name),
+        };
+        let (system, messages) = split_system(prompt, config);
+        let contents: Vec<Value> = messages.iter().enumerate()
+            .map(|(i, message)| {
+                let mut parts = vec![serde_json::json!({ "text": message.content })];
+                parts.extend(images_for(i, message, &messages, config).iter().map(|image| serde_json::json!({
+                    "inline_data": { "mime_type": image_media_type(image), "data": image },
+                })));
+                serde_json::json!({
+                    "role": if message.role == "assistant" { "model" } else { "user" },
+                    "parts": parts,
+                })
+            })
+            .collect();
+        let mut body = serde_json::json!({
+            "contents": contents,
+            "generationConfig": { "maxOutputTokens": config.max_tokens.unwrap_or(64) },
+        });
+        // キャッシュを参照するときは systemInstruction を一緒に送れない（キャッシュに入れたものを使う）
+        if let Some(name) = &config.cached_content {
+            body["cachedContent"] = serde_json::json!(cache_name(name));
+        } else if let Some(system) = system {
+            body["systemInstruction"] = serde_json::json!({ "parts": [{ "text": system }] });
+        }
+        if !config.safety_settings.is_empty() {
+            let mut settings: Vec<(&String, &String)> = config.safety_settings.iter().collect();
+            settings.sort();
+            body["safetySettings"] = settings.iter()
+                .map(|(category, threshold)| serde_json::json!({
+                    "category": format!("HARM_CATEGORY_{}", short_name(category, "harm_category_").to_uppercase()),
+                    "threshold": threshold.to_uppercase(),
+                }))
+                .collect();
+        }
+        if config.google_search {
+            // キャッシュを参照するリクエストにはツールを付けられない
+            if config.cached_content.is_some() {
+                return Err("google_search と cached_content は一緒に使えません".to_string());
+            }
+            body["tools"] = serde_json::json!([{ "google_search": {} }]);
+        }
+        if let Some(budget) = config.thinking_budget {
+            body["generationConfig"]["thinkingConfig"] = serde_json::json!({ "thinkingBudget": budget, "includeThoughts": true });
+        }
+        sampling::extend(&mut body["generationConfig"], config, &sampling::GEMINI);
+
+        let mut request = PreparedRequest::new(&endpoint, body);
+        if let Some(api_key) = &config.api_key {
+            request = request.header("x-goog-api-key", api_key.clone());
+        }
+        Ok(request)
+    }
+
+    fn parse(&self, json: &Value) -> Completion {
+        // 質問そのものが止められると、candidates がなく promptFeedback に理由が入る
+        if let Some(reason) = json.pointer("/promptFeedback/blockReason").and_then(|r| r.as_str()) {
+            let ratings = json.pointer("/promptFeedback/safetyRatings").cloned().unwrap_or_default();
+            return blocked(format!("Gemini の安全フィルターで質問が止められました（理由: {}{}）", reason, flagged(&ratings)));
+        }
+        let candidate = json.pointer("/candidates/0").cloned().unwrap_or_default();
+        let parts = candidate.pointer("/content/parts").and_then(|p| p.as_array()).cloned().unwrap_or_default();
+        // "thought": true の part が考え中の部分
+        let collect = |thought: bool| -> String {
+            parts.iter()
+                .filter(|part| part.get("thought").and_then(|t| t.as_bool()).unwrap_or(false) == thought)
+                .filter_map(|part| part.get("text").and_then(|t| t.as_str()))
+                .collect()
+        };
+        let thinking = collect(true);
+        let finish_reason = candidate.get("finishReason").and_then(|r| r.as_str()).unwrap_or_default();
+        if SAFETY_REASONS.contains(&finish_reason) && collect(false).is_empty() {
+            let ratings = candidate.get("safetyRatings").cloned().unwrap_or_default();
+            return blocked(format!("Gemini の安全フィルターで答えが止められました（理由: {}{}）", finish_reason, flagged(&ratings)));
+        }
+        let count = |key: &str| json.pointer(&format!("/usageMetadata/{}", key)).and_then(|v| v.as_u64());
+        let grounding = candidate.get("groundingMetadata").cloned().unwrap_or_default();
+        let list = |key: &str| grounding.get(key).and_then(|v| v.as_array()).cloned().unwrap_or_default();
+        Completion {
+            text: collect(false),
+            finish_reason: candidate.get("finishReason").and_then(|r| r.as_str())
+                .map(|reason| match reason {
+                    "STOP" => "stop".to_string(),
+                    "MAX_TOKENS" => "length".to_string(),
+                    reason if SAFETY_REASONS.contains(&reason) => "content_filter".to_string(),
+                    other => other.to_lowercase(),
+                }),
+            reasoning: (!thinking.is_empty()).then_some(thinking),
+            usage: count("promptTokenCount").map(|prompt_tokens| Usage {
+                prompt_tokens,
+                completion_tokens: count("candidatesTokenCount").unwrap_or(0),
+                cached_tokens: count("cachedContentTokenCount").unwrap_or(0),
+            }),
+            // 出典は groundingChunks の web（title と uri）で返ってくる
+            citations: list("groundingChunks").iter()
+                .filter_map(|chunk| {
+                    let uri = chunk.pointer("/web/uri")?.as_str()?;
+                    Some(match chunk.pointer("/web/title").and_then(|t| t.as_str()) {
+                        Some(title) => format!("{} {}", title, uri),
+                        None => uri.to_string(),
+                    })
+                })
+                .collect(),
+            search_suggestions: list("webSearchQueries").iter().filter_map(|q| q.as_str().map(|q| q.to_string())).collect(),
+            ..Default::default()
+        }
+    }
+}
+
+// 安全フィルターで止められたときの結果（終了コードもコンテンツフィルターのものにする）
+fn blocked(message: String) -> Completion {
+    Completion {
+        finish_reason: Some("content_filter".to_string()),
+        error_kind: Some(ErrorKind::ContentFilter),
+        ..Completion::failed(format!("{}。safety_settings で基準を変えられます", message))
+    }
+}
+
+// 止めたカテゴリ（blocked か、可能性が MEDIUM 以上のもの）を "、カテゴリ: harassment（HIGH）" の形にする
+fn flagged(ratings: &Value) -> String {
+    let categories: Vec<String> = ratings.as_array().into_iter().flatten()
+        .filter(|rating| rating.get("blocked").and_then(|b| b.as_bool()) == Some(true)
+            || matches!(rating.get("probability").and_then(|p| p.as_str()), Some("MEDIUM" | "HIGH")))
+        .filter_map(|rating| {
+            let category = rating.get("category")?.as_str()?;
+            let probability = rating.get("probability").and_then(|p| p.as_str()).unwrap_or("?");
+            Some(format!("{}（{}）", short_name(category, "harm_category_"), probability))
+        })
+        .collect();
+    if categories.is_empty() { String::new() } else { format!("、カテゴリ: {}", categories.join(" / ")) }
+}
+
+// 大文字でも接頭辞つきでも書けるように、小文字にして prefix を外す
+fn short_name(name: &str, prefix: &str) -> String {
+    let name = name.to_lowercase();
+    name.strip_prefix(prefix).map(|name| name.to_string()).unwrap_or(name)
+}
+
+// safety_settings に書いたカテゴリと基準を調べる（設定を読み込むときに使う）
+pub fn check_safety_settings(prefix: &str, settings: &HashMap<String, String>) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut categories: Vec<&String> = settings.keys().collect();
+    categories.sort();
+    for category in categories {
+        if !SAFETY_CATEGORIES.contains(&short_name(category, "harm_category_").as_str()) {
+            problems.push(format!("{}safety_settings のカテゴリは {} のどれかにしてください（{}）", prefix, SAFETY_CATEGORIES.join(" / "), category));
+        }
+        if !SAFETY_THRESHOLDS.contains(&settings[category].to_lowercase().as_str()) {
+            problems.push(format!("{}safety_settings.{} は {} のどれかにしてください（{}）", prefix, category, SAFETY_THRESHOLDS.join(" / "), settings[category]));
+        }
+    }
+    problems
+}
+
+// "cachedContents/..." を省略して書いたキャッシュの名前を補う
+pub fn cache_name(name: &str) -> String {
+    if name.starts_with("cachedContents/") { name.to_string() } else { format!("cachedContents/{}", name) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(extra: Value) -> Config {
+        let mut json = serde_json::json!({
+            "model_name": "gemini-2.0-flash", "use_local_model": false, "openai_compatible": false, "provider": "gemini",
+        });
+        json.as_object_mut().unwrap().extend(extra.as_object().cloned().unwrap_or_default());
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn sends_the_system_prompt_without_a_cache() {
+        let body = Gemini.request("こんにちは", &config(serde_json::json!({ "system_prompt": "丁寧に" }))).unwrap().body;
+        assert_eq!(body["systemInstruction"]["parts"][0]["text"], "丁寧に");
+        assert!(body.get("cachedContent").is_none());
+    }
+
+    #[test]
+    fn references_the_cache_instead_of_the_system_prompt() {
+        let body = Gemini.request("こんにちは", &config(serde_json::json!({ "system_prompt": "丁寧に", "cached_content": "abc123" }))).unwrap().body;
+        assert_eq!(body["cachedContent"], "cachedContents/abc123");
+        assert!(body.get("systemInstruction").is_none());
+        assert_eq!(body["contents"][0]["parts"][0]["text"], "こんにちは");
+    }
+
+    #[test]
+    fn adds_the_search_tool() {
+        let body = Gemini.request("今日のニュース", &config(serde_json::json!({ "google_search": true }))).unwrap().body;
+        assert_eq!(body["tools"], serde_json::json!([{ "google_search": {} }]));
+        assert!(Gemini.request("今日のニュース", &config(serde_json::json!({ "google_search": true, "cached_content": "abc" }))).is_err());
+    }
+
+    #[test]
+    fn reads_sources_and_search_queries() {
+        let completion = Gemini.parse(&serde_json::json!({
+            "candidates": [{
+                "content": { "parts": [{ "text": "晴れです" }] },
+                "finishReason": "STOP",
+                "groundingMetadata": {
+                    "webSearchQueries": ["東京 天気", "東京 天気 明日"],
+                    "searchEntryPoint": { "renderedContent": "<div></div>" },
+                    "groundingChunks": [
+                        { "web": { "uri": "https://example.com/a", "title": "example.com" } },
+                        { "web": { "uri": "https://example.org/b" } },
+                    ],
+                },
+            }],
+        }));
+        assert_eq!(completion.text, "晴れです");
+        assert_eq!(completion.citations, ["example.com https://example.com/a", "https://example.org/b"]);
+        assert_eq!(completion.search_suggestions, ["東京 天気", "東京 天気 明日"]);
+    }
+
+    #[test]
+    fn sends_safety_settings_in_api_names() {
+        let body = Gemini.request("1", &config(serde_json::json!({
+            "safety_settings": { "harassment": "block_only_high", "HARM_CATEGORY_DANGEROUS_CONTENT": "BLOCK_NONE" },
+        }))).unwrap().body;
+        assert_eq!(body["safetySettings"], serde_json::json!([
+            { "category": "HARM_CATEGORY_DANGEROUS_CONTENT", "threshold": "BLOCK_NONE" },
+            { "category": "HARM_CATEGORY_HARASSMENT", "threshold": "BLOCK_ONLY_HIGH" },
+        ]));
+        assert!(Gemini.request("1", &config(serde_json::json!({}))).unwrap().body.get("safetySettings").is_none());
+    }
+
+    #[test]
+    fn rejects_unknown_categories_and_thresholds() {
+        let settings = HashMap::from([
+            ("harassment".to_string(), "block_only_high".to_string()),
+            ("violence".to_string(), "sometimes".to_string()),
+        ]);
+        let problems = check_safety_settings("profiles.g: ", &settings);
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].starts_with("profiles.g: safety_settings のカテゴリ"));
+        assert!(problems[1].contains("safety_settings.violence"));
+    }
+
+    #[test]
+    fn a_blocked_prompt_says_why() {
+        let completion = Gemini.parse(&serde_json::json!({
+            "promptFeedback": {
+                "blockReason": "SAFETY",
+                "safetyRatings": [
+                    { "category": "HARM_CATEGORY_HARASSMENT", "probability": "HIGH", "blocked": true },
+                    { "category": "HARM_CATEGORY_HATE_SPEECH", "probability": "NEGLIGIBLE" },
+                ],
+            },
+        }));
+        assert_eq!(completion.error_kind, Some(ErrorKind::ContentFilter));
+        assert_eq!(completion.error.as_deref(), Some("Gemini の安全フィルターで質問が止められました（理由: SAFETY、カテゴリ: harassment（HIGH））。safety_settings で基準を変えられます"));
+    }
+
+    #[test]
+    fn a_blocked_answer_says_why() {
+        let completion = Gemini.parse(&serde_json::json!({
+            "candidates": [{
+                "finishReason": "SAFETY",
+                "safetyRatings": [{ "category": "HARM_CATEGORY_DANGEROUS_CONTENT", "probability": "MEDIUM" }],
+            }],
+        }));
+        assert_eq!(completion.finish_reason.as_deref(), Some("content_filter"));
+        assert!(completion.text.contains("答えが止められました（理由: SAFETY、カテゴリ: dangerous_content（MEDIUM））"));
+        // 途中まで答えていれば、そこまでは残す
+        let partial = Gemini.parse(&serde_json::json!({
+            "candidates": [{ "content": { "parts": [{ "text": "途中" }] }, "finishReason": "SAFETY" }],
+        }));
+        assert_eq!(partial.text, "途中");
+        assert!(partial.error.is_none());
+        assert_eq!(partial.finish_reason.as_deref(), Some("content_filter"));
+    }
+
+    #[test]
+    fn keeps_full_cache_names() {
+        assert_eq!(cache_name("cachedContents/abc"), "cachedContents/abc");
+        assert_eq!(cache_name("abc"), "cachedContents/abc");
+    }
+}
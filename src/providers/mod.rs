@@ -0,0 +1,287 @@
+// "provider" で名前を指定して使うオンラインのプロバイダー
+//
+// 多くは OpenAI のチャット補完API（messages 形式）に近い形なので、共通部分はここにまとめ、
+// プロバイダーごとの違い（URL、追加のオプション、レスポンスのおまけ）だけを各ファイルに書く。
+// リクエストの形・認証ヘッダー・レスポンスがまったく違うAPI（Anthropic、Gemini など）は、
+// Backend トレイトを実装したアダプターにして backend_inference で同じように送る。
+mod anthropic;
+mod cloudflare;
+mod gemini;
+mod nvidia;
+mod ollama;
+mod openai;
+mod perplexity;
+mod replicate;
+mod together;
+mod watsonx;
+
+use serde_json::Value;
+use crate::{completion, conversation, sampling, stream, tools, Config};
+use crate::completion::{choice_reasoning, choice_text, choice_tool_calls, Completion, Usage};
+use crate::error::Error;
+use crate::files::{self, api_base, authorized};
+use crate::request::{HttpResponse, PreparedRequest};
+
+pub use gemini::{api_base as gemini_api_base, cache_name as gemini_cache_name, check_safety_settings};
+
+// 対応しているプロバイダーの名前
+pub const PROVIDERS: [&str; 11] = [
+    "openai", "anthropic", "gemini", "mistral", "ollama",
+    "perplexity", "together", "cloudflare", "replicate", "nvidia", "watsonx",
+];
+
+// リクエストの組み立てとレスポンスの読み方をプロバイダーごとに実装するアダプター
+pub trait Backend: Send + Sync {
+    // URL、認証ヘッダー、本文を組み立てる
+    fn request(&self, prompt: &str, config: &Config) -> Result<PreparedRequest, String>;
+
+    // 成功したレスポンスを読む
+    fn parse(&self, json: &Value) -> Completion;
+
+    // エラーのレスポンスからメッセージを取り出す（多くは {"error": {"message": ...}} の形）
+    fn error_message(&self, json: &Value) -> Option<String> {
+        json.pointer("/error/message").and_then(|m| m.as_str()).map(|m| m.to_string())
+    }
+
+    // ストリーミングで送るか（request で "stream" を付けたときに true を返す。対応していないアダプターは false のまま）
+    fn streams(&self, _config: &Config) -> bool {
+        false
+    }
+
+    // SSE のイベントを1つ受け取って、届いた分のトークンを表示側に渡す
+    fn stream_event(&self, _event: &Value, _config: &Config) {}
+
+    // SSE のイベントを、ストリーミングしないときと同じ形のJSONにまとめる（parse でそのまま読めるように）
+    // 終わりまで届いていなければ、finished に false を返す
+    fn collect_stream(&self, events: &[Value]) -> (Value, bool) {
+        (events.last().cloned().unwrap_or_default(), true)
+    }
+}
+
+// 名前からアダプターを探す（アダプターのないプロバイダーは None）
+fn backend(name: &str) -> Option<Box<dyn Backend>> {
+    match name {
+        "openai" => Some(Box::new(openai::OpenAi { default_endpoint: openai::OPENAI_ENDPOINT, sampling: &sampling::OPENAI, stream_usage: true })),
+        "mistral" => Some(Box::new(openai::OpenAi { default_endpoint: openai::MISTRAL_ENDPOINT, sampling: &sampling::MISTRAL, stream_usage: false })),
+        "anthropic" => Some(Box::new(anthropic::Anthropic)),
+        "gemini" => Some(Box::new(gemini::Gemini)),
+        "ollama" => Some(Box::new(ollama::Ollama)),
+        _ => None,
+    }
+}
+
+// 今のプロバイダーで画像を送れるかどうか（アダプターはどれも /attach の画像を送れる）
+pub fn accepts_images(config: &Config) -> bool {
+    config.provider.as_deref().and_then(backend).is_some()
+}
+
+// Files API でアップロードしたファイルを、IDで送れるかどうか（OpenAI の形のチャット。provider のない OpenAI互換と openai）
+pub fn accepts_file_ids(config: &Config) -> bool {
+    !config.use_local_model && match config.provider.as_deref() {
+        None => config.assistant_id.is_none() && config.openai_compatible && config.chat,
+        Some(provider) => provider == "openai",
+    }
+}
+
+// アダプターで組み立てたリクエストを送って、レスポンスを読む
+async fn backend_inference(backend: &dyn Backend, prompt: &str, config: &Config) -> Result<Completion, Error> {
+    let request = backend.request(prompt, config).map_err(Error::Config)?;
+    if config.dry_run {
+        return Ok(request.dry_run().into());
+    }
+    if backend.streams(config) {
+        return backend_stream(backend, request, config).await;
+    }
+    let response = request.send(config).await?;
+    let json = serde_json::from_str::<Value>(&response.body);
+    if response.status >= 400 {
+        // 本文がJSONでないとき（ゲートウェイのHTMLなど）も、ステータスと本文を見せる
+        let error = match json.ok().and_then(|json| backend.error_message(&json)) {
+            Some(message) => Error::api(&response, message),
+            None => Error::from_response(&response),
+        };
+        return Err(error);
+    }
+    let json = json.map_err(|e| Error::Parse(e.to_string()))?;
+    Ok(backend.parse(&json))
+}
+
+// ストリーミングで送って、SSE のイベントが届くたびに表示側に渡し、最後にまとめて読む
+// 途中で接続が切れたら、そこまでの答えを途中までのものとして返す（infer_once が続きを頼む）
+async fn backend_stream(backend: &dyn Backend, request: PreparedRequest, config: &Config) -> Result<Completion, Error> {
+    let mut lines = stream::LineBuffer::default();
+    let res = request.send_streaming(config, |chunk| {
+        for event in lines.push(chunk).iter().filter_map(|line| stream::sse_data(line)) {
+            backend.stream_event(&event, config);
+        }
+    }).await;
+    let (res, cut) = stream::recover_partial(res, true);
+    let response = res?;
+    if response.status >= 400 {
+        let message = serde_json::from_str(&response.body).ok().and_then(|json| backend.error_message(&json));
+        return Err(match message {
+            Some(message) => Error::api(&response, message),
+            None => Error::from_response(&response),
+        });
+    }
+    let events: Vec<Value> = response.body.lines().filter_map(stream::sse_data).collect();
+    let (json, finished) = backend.collect_stream(&events);
+    // ストリームの途中で届いたエラー（混み合っているときなど）
+    if let Some(message) = backend.error_message(&json) {
+        return Err(Error::api(&HttpResponse { status: response.status, body: json.to_string() }, message));
+    }
+    let mut completion = backend.parse(&json);
+    if cut || !finished {
+        completion.finish_reason = Some(completion::INTERRUPTED.to_string());
+    }
+    Ok(completion)
+}
+
+// /models で表示するモデルの情報（料金は 1M トークンあたりのドル。わかる場合だけ入る）
+pub struct ModelInfo {
+    pub id: String,
+    pub context_length: Option<u64>,
+    pub prompt_price: Option<f64>,
+    pub completion_price: Option<f64>,
+}
+
+// provider が設定されていれば、そのプロバイダーで推論する（設定がなければ None）
+pub async fn provider_inference(prompt: &str, config: &Config) -> Option<Result<Completion, Error>> {
+    let provider = config.provider.as_deref()?;
+    if let Some(backend) = backend(provider) {
+        return Some(backend_inference(backend.as_ref(), prompt, config).await);
+    }
+    let result = match provider {
+        "perplexity" => perplexity::inference(prompt, config).await,
+        "together" => together::inference(prompt, config).await,
+        "cloudflare" => cloudflare::inference(prompt, config).await,
+        "replicate" => replicate::inference(prompt, config).await,
+        "nvidia" => nvidia::inference(prompt, config).await,
+        "watsonx" => watsonx::inference(prompt, config).await,
+        other => Err(Error::Config(format!("不明なプロバイダーです: {}", other))),
+    };
+    Some(result)
+}
+
+// 使えるモデルの一覧（プロバイダーの指定がなければ OpenAI互換の GET /models）
+pub async fn list_models(config: &Config) -> Result<Vec<ModelInfo>, String> {
+    match config.provider.as_deref() {
+        Some("together") => together::list_models(config).await,
+        provider => {
+            let base = match provider {
+                Some("nvidia") if config.endpoint.is_none() && config.api_base.is_none() => nvidia::API_BASE.to_string(),
+                _ => api_base(config)?,
+            };
+            let url = format!("{}/models", base);
+            let json = files::send_json(authorized(PreparedRequest::get(&url), config), config).await?;
+            Ok(json.get("data").and_then(|d| d.as_array()).into_iter()
+                .flatten()
+                .filter_map(|model| model.get("id").and_then(|id| id.as_str()))
+                .map(|id| ModelInfo { id: id.to_string(), context_length: None, prompt_price: None, completion_price: None })
+                .collect())
+        }
+    }
+}
+
+// チャット補完APIのリクエストの本文（サンプリングのパラメータは names の名前で入れる）
+fn chat_body(prompt: &str, config: &Config, names: &sampling::Names) -> Value {
+    let mut body = serde_json::json!({
+        "model": config.model_name,
+        "messages": conversation::messages_json(prompt, config),
+        "max_tokens": config.max_tokens.unwrap_or(64),
+    });
+    if let Some(effort) = &config.reasoning_effort {
+        body["reasoning_effort"] = serde_json::json!(effort);
+    }
+    sampling::extend(&mut body, config, names);
+    tools::extend(&mut body, config);
+    body
+}
+
+// base64 の先頭から画像の種類を判別する（わからなければ PNG とみなす）
+fn image_media_type(base64: &str) -> &'static str {
+    if base64.starts_with("/9j/") {
+        "image/jpeg"
+    } else if base64.starts_with("R0lG") {
+        "image/gif"
+    } else if base64.starts_with("UklG") {
+        "image/webp"
+    } else {
+        "image/png"
+    }
+}
+
+// 添付した画像とファイルを、OpenAI の形（user メッセージの content を配列にして、data URL の image_url と file_id の file）で付ける
+// 今回の分は最後の user メッセージに、履歴の分（messages_json が "images" / "file_ids" に入れたもの）はそのメッセージに付ける
+pub fn attach_images(body: &mut Value, config: &Config) {
+    let Some(messages) = body.pointer_mut("/messages").and_then(|m| m.as_array_mut()) else {
+        return;
+    };
+    if let Some(last) = messages.iter_mut().rev().find(|message| message["role"] == "user") {
+        if !config.images.is_empty() {
+            last["images"] = serde_json::json!(config.images);
+        }
+        if !config.file_ids.is_empty() {
+            let mut file_ids = last.get("file_ids").and_then(Value::as_array).cloned().unwrap_or_default();
+            file_ids.extend(config.file_ids.iter().map(|id| Value::String(id.clone())));
+            last["file_ids"] = Value::Array(file_ids);
+        }
+    }
+    for message in messages.iter_mut() {
+        let Some(object) = message.as_object_mut() else {
+            continue;
+        };
+        let (images, file_ids) = (object.remove("images"), object.remove("file_ids"));
+        if images.is_none() && file_ids.is_none() {
+            continue;
+        }
+        let strings = |list: Option<Value>| -> Vec<String> {
+            list.and_then(|list| serde_json::from_value(list).ok()).unwrap_or_default()
+        };
+        let mut parts = vec![serde_json::json!({ "type": "text", "text": message["content"] })];
+        parts.extend(strings(images).iter().map(|image| serde_json::json!({
+            "type": "image_url",
+            "image_url": { "url": format!("data:{};base64,{}", image_media_type(image), image) },
+        })));
+        parts.extend(strings(file_ids).iter().map(|id| serde_json::json!({ "type": "file", "file": { "file_id": id } })));
+        message["content"] = Value::Array(parts);
+    }
+}
+
+// メッセージごとに送る画像（最後のメッセージには今回の画像も付ける）
+fn images_for(index: usize, message: &conversation::Message, messages: &[conversation::Message], config: &Config) -> Vec<String> {
+    let mut images = message.images();
+    if index + 1 == messages.len() {
+        images.extend(config.images.iter().cloned());
+    }
+    images
+}
+
+// 送るメッセージの並びを、system とそれ以外に分ける（Anthropic と Gemini は system を別の場所に書く）
+fn split_system(prompt: &str, config: &Config) -> (Option<String>, Vec<conversation::Message>) {
+    let mut messages = conversation::messages(prompt, config);
+    let system = match messages.first() {
+        Some(first) if first.role == "system" => Some(messages.remove(0).content),
+        _ => None,
+    };
+    (system, messages)
+}
+
+// チャット補完APIのレスポンスを読む
+fn parse_chat(json: &Value) -> Completion {
+    let choice = json.pointer("/choices/0");
+    let tool_calls = choice.map(choice_tool_calls).unwrap_or_default();
+    let text = choice
+        .and_then(choice_text)
+        .unwrap_or(if tool_calls.is_empty() { "レスポンスが不正です" } else { "" })
+        .to_string();
+    let reasoning = choice.and_then(choice_reasoning).map(|r| r.to_string());
+    Completion {
+        text,
+        tool_calls,
+        finish_reason: choice.and_then(|choice| choice.get("finish_reason")).and_then(|r| r.as_str()).map(|r| r.to_string()),
+        reasoning,
+        usage: json.get("usage").and_then(Usage::from_openai),
+        ..Default::default()
+    }
+}
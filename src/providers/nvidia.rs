@@ -0,0 +1,27 @@
+// NVIDIA NIM（build.nvidia.com のホスト版。OpenAI互換のチャットAPI）
+use crate::{sampling, Config};
+use crate::completion::Completion;
+use crate::error::Error;
+use crate::files::{self, authorized};
+use crate::request::PreparedRequest;
+use super::{chat_body, parse_chat};
+
+pub const API_BASE: &str = "https://integrate.api.nvidia.com/v1";
+
+pub async fn inference(prompt: &str, config: &Config) -> Result<Completion, Error> {
+    let endpoint = match &config.endpoint {
+        Some(endpoint) => endpoint.clone(),
+        None => format!("{}/chat/completions", API_BASE),
+    };
+    // NIM は知らないパラメーターがあるとエラーにするモデルがあるので、OpenAI 独自の reasoning_effort は送らない
+    let mut body = chat_body(prompt, config, &sampling::OPENAI);
+    if let Some(object) = body.as_object_mut() {
+        object.remove("reasoning_effort");
+    }
+    let request = authorized(PreparedRequest::new(&endpoint, body), config);
+    if config.dry_run {
+        return Ok(request.dry_run().into());
+    }
+    let json = files::send_json(request, config).await?;
+    Ok(parse_chat(&json))
+}
@@ -0,0 +1,56 @@
+// Ollama の /api/chat（ローカルのフレームワークとしてではなく、リモートやクラウドの Ollama に送るとき）
+use serde_json::Value;
+use crate::{conversation, sampling, warmup, Config};
+use crate::completion::{Completion, Timing, Usage};
+use crate::files::authorized;
+use crate::request::PreparedRequest;
+use super::Backend;
+
+const DEFAULT_ENDPOINT: &str = "http://localhost:11434/api/chat";
+
+pub struct Ollama;
+
+impl Backend for Ollama {
+    fn request(&self, prompt: &str, config: &Config) -> Result<PreparedRequest, String> {
+        let endpoint = config.endpoint.as_deref().map(conversation::chat_endpoint)
+            .unwrap_or(DEFAULT_ENDPOINT.to_string());
+        let mut messages = conversation::messages_json(prompt, config);
+        if let (false, Some(last)) = (config.images.is_empty(), messages.as_array_mut().and_then(|m| m.last_mut())) {
+            last["images"] = serde_json::json!(config.images);
+        }
+        let mut body = serde_json::json!({
+            "model": config.model_name,
+            "messages": messages,
+            "stream": false,
+            "options": { "num_predict": config.max_tokens.unwrap_or(64) },
+        });
+        if let Some(effort) = &config.reasoning_effort {
+            body["think"] = serde_json::json!(effort);
+        } else if config.thinking_budget.is_some() {
+            body["think"] = serde_json::json!(true);
+        }
+        if let Some(keep_alive) = warmup::keep_alive(config) {
+            body["keep_alive"] = keep_alive;
+        }
+        sampling::extend(&mut body["options"], config, &sampling::OPENAI_COMPATIBLE);
+        Ok(authorized(PreparedRequest::new(&endpoint, body), config))
+    }
+
+    fn parse(&self, json: &Value) -> Completion {
+        Completion {
+            text: json.pointer("/message/content").and_then(|t| t.as_str()).unwrap_or("レスポンスが不正です").to_string(),
+            finish_reason: json.get("done_reason").and_then(|r| r.as_str()).map(|r| r.to_string()),
+            reasoning: json.pointer("/message/thinking").and_then(|t| t.as_str())
+                .filter(|t| !t.is_empty())
+                .map(|t| t.to_string()),
+            usage: Usage::from_ollama(json),
+            timing: Timing::from_ollama(json),
+            ..Default::default()
+        }
+    }
+
+    // Ollama のエラーは {"error": "..."} の文字列
+    fn error_message(&self, json: &Value) -> Option<String> {
+        json.get("error").and_then(|e| e.as_str()).map(|e| e.to_string())
+    }
+}
@@ -0,0 +1,53 @@
+// OpenAI のチャット補完API（Mistral など、同じ形のAPIにも使う）
+use serde_json::Value;
+use crate::{sampling, stream, Config};
+use crate::completion::Completion;
+use crate::files::authorized;
+use crate::request::PreparedRequest;
+use super::{attach_images, chat_body, parse_chat, Backend};
+
+pub const OPENAI_ENDPOINT: &str = "https://api.openai.com/v1/chat/completions";
+pub const MISTRAL_ENDPOINT: &str = "https://api.mistral.ai/v1/chat/completions";
+
+pub struct OpenAi {
+    pub default_endpoint: &'static str,
+    pub sampling: &'static sampling::Names, // サンプリングのパラメータの名前（Mistral は seed が random_seed）
+    pub stream_usage: bool, // ストリーミングで使用量を返してもらうのに stream_options が要るか（Mistral はいつも最後に返す）
+}
+
+impl Backend for OpenAi {
+    fn request(&self, prompt: &str, config: &Config) -> Result<PreparedRequest, String> {
+        let endpoint = config.endpoint.as_deref().unwrap_or(self.default_endpoint);
+        let mut body = chat_body(prompt, config, self.sampling);
+        attach_images(&mut body, config);
+        if config.stream {
+            body["stream"] = serde_json::json!(true);
+            if self.stream_usage {
+                body["stream_options"] = serde_json::json!({ "include_usage": true });
+            }
+        }
+        Ok(authorized(PreparedRequest::new(endpoint, body), config))
+    }
+
+    fn parse(&self, json: &Value) -> Completion {
+        parse_chat(json)
+    }
+
+    fn streams(&self, config: &Config) -> bool {
+        config.stream
+    }
+
+    fn stream_event(&self, event: &Value, config: &Config) {
+        stream::emit_choice(config, event);
+    }
+
+    // ツールの呼び出しは index ごとに少しずつ届くので、stream::collect_events でつなげる
+    // 終了理由の入ったイベントまで届いていれば終わりとみなす（[DONE] の行は sse_data で落ちる）
+    fn collect_stream(&self, events: &[Value]) -> (Value, bool) {
+        if let Some(error) = events.iter().find(|event| event.get("error").is_some()) {
+            return (error.clone(), true);
+        }
+        let finished = events.iter().any(|event| event.pointer("/choices/0/finish_reason").is_some_and(|reason| !reason.is_null()));
+        (stream::collect_events(events), finished)
+    }
+}
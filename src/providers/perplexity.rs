@@ -0,0 +1,45 @@
+// Perplexity（検索つきの回答。返ってきた出典を答えの下に表示する）
+use crate::{sampling, Config};
+use crate::completion::Completion;
+use crate::error::Error;
+use crate::files::{self, authorized};
+use crate::request::PreparedRequest;
+use super::{chat_body, parse_chat};
+
+const DEFAULT_ENDPOINT: &str = "https://api.perplexity.ai/chat/completions";
+
+pub async fn inference(prompt: &str, config: &Config) -> Result<Completion, Error> {
+    let endpoint = config.endpoint.as_deref().unwrap_or(DEFAULT_ENDPOINT);
+    let mut body = chat_body(prompt, config, &sampling::OPENAI_COMPATIBLE);
+    // 検索するドメインの指定（"-example.com" のように先頭に - を付けると除外）と、検索する期間
+    if !config.search_domain_filter.is_empty() {
+        body["search_domain_filter"] = serde_json::json!(config.search_domain_filter);
+    }
+    if let Some(recency) = &config.search_recency_filter {
+        body["search_recency_filter"] = serde_json::json!(recency);
+    }
+
+    let request = authorized(PreparedRequest::new(endpoint, body), config);
+    if config.dry_run {
+        return Ok(request.dry_run().into());
+    }
+    let json = files::send_json(request, config).await?;
+    let mut completion = parse_chat(&json);
+    // 出典は "citations"（URLの配列）か、新しい形式の "search_results"（title と url）で返ってくる
+    completion.citations = match json.get("search_results").and_then(|r| r.as_array()) {
+        Some(results) => results.iter()
+            .filter_map(|result| {
+                let url = result.get("url")?.as_str()?;
+                Some(match result.get("title").and_then(|t| t.as_str()) {
+                    Some(title) => format!("{} {}", title, url),
+                    None => url.to_string(),
+                })
+            })
+            .collect(),
+        None => json.get("citations").and_then(|c| c.as_array()).into_iter()
+            .flatten()
+            .filter_map(|url| url.as_str().map(|url| url.to_string()))
+            .collect(),
+    };
+    Ok(completion)
+}
@@ -0,0 +1,73 @@
+// Replicate（予測を作成して、終わるまでポーリングする）
+//
+// model_name は "owner/name"（公式モデル）か、バージョンを固定した "owner/name:version" で指定する。
+use std::time::Duration;
+use serde_json::Value;
+use crate::{runtime, sampling, Config};
+use crate::completion::{Completion, Usage};
+use crate::error::Error;
+use crate::files::{self, authorized};
+use crate::request::PreparedRequest;
+
+const API_BASE: &str = "https://api.replicate.com/v1";
+
+// 予測の状態を問い合わせる間隔
+const POLL_MILLIS: u64 = 1000;
+
+// 予測がこの状態になったら終わり
+const FINISHED_STATUSES: [&str; 3] = ["succeeded", "failed", "canceled"];
+
+pub async fn inference(prompt: &str, config: &Config) -> Result<Completion, Error> {
+    let base = config.api_base.as_deref().unwrap_or(API_BASE).trim_end_matches('/');
+    let mut input = serde_json::json!({
+        "prompt": prompt,
+        "max_tokens": config.max_tokens.unwrap_or(64),
+    });
+    sampling::extend(&mut input, config, &sampling::REPLICATE);
+    // バージョンを固定したときは /predictions に version を、そうでなければモデルのURLに送る
+    let (url, body) = match config.model_name.split_once(':') {
+        Some((_, version)) => (format!("{}/predictions", base), serde_json::json!({ "version": version, "input": input })),
+        None => (format!("{}/models/{}/predictions", base, config.model_name), serde_json::json!({ "input": input })),
+    };
+    let request = authorized(PreparedRequest::new(&url, body), config);
+    if config.dry_run {
+        return Ok(format!("{}\n\n（この後、予測が終わるまでポーリングします）", request.dry_run()).into());
+    }
+
+    let mut prediction = files::send_json(request, config).await?;
+    while !FINISHED_STATUSES.contains(&status(&prediction)) {
+        runtime::sleep(Duration::from_millis(POLL_MILLIS)).await;
+        let poll_url = match prediction.pointer("/urls/get").and_then(|u| u.as_str()) {
+            Some(poll_url) => poll_url.to_string(),
+            None => format!("{}/predictions/{}", base, prediction.get("id").and_then(|id| id.as_str()).unwrap_or("?")),
+        };
+        prediction = files::send_json(authorized(PreparedRequest::get(&poll_url), config), config).await?;
+    }
+    if status(&prediction) != "succeeded" {
+        let reason = prediction.get("error").and_then(|e| e.as_str()).unwrap_or("理由不明");
+        return Err(format!("予測が {} で終わりました: {}", status(&prediction), reason).into());
+    }
+
+    // 言語モデルの出力は、トークンごとの文字列の配列で返ってくる
+    let text = match prediction.get("output") {
+        Some(Value::Array(parts)) => parts.iter().filter_map(|part| part.as_str()).collect(),
+        Some(Value::String(text)) => text.clone(),
+        _ => "レスポンスが不正です".to_string(),
+    };
+    let count = |key: &str| prediction.pointer(&format!("/metrics/{}", key)).and_then(|v| v.as_u64());
+    let usage = count("input_token_count").map(|prompt_tokens| Usage {
+        prompt_tokens,
+        completion_tokens: count("output_token_count").unwrap_or(0),
+        cached_tokens: 0,
+    });
+    Ok(Completion {
+        text,
+        finish_reason: Some("stop".to_string()),
+        usage,
+        ..Default::default()
+    })
+}
+
+fn status(prediction: &Value) -> &str {
+    prediction.get("status").and_then(|s| s.as_str()).unwrap_or("?")
+}
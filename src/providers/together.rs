@@ -0,0 +1,44 @@
+// Together AI（OpenAI互換のチャットAPIと、料金つきのモデル一覧）
+use serde_json::Value;
+use crate::{sampling, Config};
+use crate::completion::Completion;
+use crate::error::Error;
+use crate::files::{self, authorized};
+use crate::request::PreparedRequest;
+use super::{chat_body, parse_chat, ModelInfo};
+
+const DEFAULT_ENDPOINT: &str = "https://api.together.xyz/v1/chat/completions";
+const MODELS_URL: &str = "https://api.together.xyz/v1/models";
+
+pub async fn inference(prompt: &str, config: &Config) -> Result<Completion, Error> {
+    let endpoint = config.endpoint.as_deref().unwrap_or(DEFAULT_ENDPOINT);
+    let request = authorized(PreparedRequest::new(endpoint, chat_body(prompt, config, &sampling::OPENAI_COMPATIBLE)), config);
+    if config.dry_run {
+        return Ok(request.dry_run().into());
+    }
+    let json = files::send_json(request, config).await?;
+    Ok(parse_chat(&json))
+}
+
+// モデル一覧は {"data": [...]} ではなく配列がそのまま返ってくる
+// 料金の pricing.input / pricing.output は 1M トークンあたりのドル
+pub async fn list_models(config: &Config) -> Result<Vec<ModelInfo>, String> {
+    let url = match &config.api_base {
+        Some(base) => format!("{}/models", base.trim_end_matches('/')),
+        None => MODELS_URL.to_string(),
+    };
+    let json = files::send_json(authorized(PreparedRequest::get(&url), config), config).await?;
+    let models = json.as_array().or_else(|| json.get("data").and_then(|d| d.as_array()))
+        .ok_or("モデル一覧のレスポンスが不正です")?;
+    let price = |model: &Value, key: &str| model.pointer(&format!("/pricing/{}", key))
+        .and_then(|p| p.as_f64())
+        .filter(|p| *p > 0.0);
+    Ok(models.iter()
+        .filter_map(|model| Some(ModelInfo {
+            id: model.get("id")?.as_str()?.to_string(),
+            context_length: model.get("context_length").and_then(|c| c.as_u64()),
+            prompt_price: price(model, "input"),
+            completion_price: price(model, "output"),
+        }))
+        .collect())
+}
@@ -0,0 +1,106 @@
+// IBM watsonx.ai（APIキーをIAMトークンに交換してから、プロジェクトを指定して生成する）
+use std::sync::Mutex;
+use std::time::Duration;
+use web_time::Instant;
+use serde_json::Value;
+use crate::{cassette, sampling, Config};
+use crate::completion::{Completion, Usage};
+use crate::error::Error;
+use crate::request::PreparedRequest;
+
+const IAM_URL: &str = "https://iam.cloud.ibm.com/identity/token";
+const DEFAULT_API_BASE: &str = "https://us-south.ml.cloud.ibm.com";
+const API_VERSION: &str = "2024-05-01";
+
+// トークンの期限が切れる少し前に取り直す
+const TOKEN_MARGIN_SECS: u64 = 60;
+
+// 取得済みのIAMトークンと、その期限（取得に使ったAPIキーごと）
+static TOKENS: Mutex<Vec<(String, String, Instant)>> = Mutex::new(Vec::new());
+
+pub async fn inference(prompt: &str, config: &Config) -> Result<Completion, Error> {
+    let project_id = config.project_id.as_deref()
+        .ok_or_else(|| Error::Config("watsonx.ai には project_id が必要です".to_string()))?;
+    let endpoint = match &config.endpoint {
+        Some(endpoint) => endpoint.clone(),
+        None => format!(
+            "{}/ml/v1/text/generation?version={}",
+            config.api_base.as_deref().unwrap_or(DEFAULT_API_BASE).trim_end_matches('/'), API_VERSION
+        ),
+    };
+    let mut body = serde_json::json!({
+        "model_id": config.model_name,
+        "project_id": project_id,
+        "input": prompt,
+        "parameters": { "max_new_tokens": config.max_tokens.unwrap_or(64) },
+    });
+    // temperature などは、貪欲法ではなくサンプリングで生成するときだけ使われる
+    if config.temperature.is_some() || config.top_p.is_some() || config.top_k.is_some() {
+        body["parameters"]["decoding_method"] = serde_json::json!("sample");
+    }
+    sampling::extend(&mut body["parameters"], config, &sampling::WATSONX);
+    let mut request = PreparedRequest::new(&endpoint, body);
+
+    // dry-run と再生のときはトークンを取りに行かない
+    let token = if config.dry_run || cassette::is_replaying(config) {
+        "<iam_token>".to_string()
+    } else {
+        iam_token(config).await?
+    };
+    request = request.header("Authorization", format!("Bearer {}", token));
+    if config.dry_run {
+        return Ok(request.dry_run().into());
+    }
+
+    let response = request.send(config).await?;
+    let json: Value = serde_json::from_str(&response.body)
+        .map_err(|_| Error::from_response(&response))?;
+    if response.status >= 400 {
+        let message = json.pointer("/errors/0/message").and_then(|m| m.as_str()).unwrap_or("理由不明");
+        return Err(Error::api(&response, message.to_string()));
+    }
+    let result = json.pointer("/results/0").cloned().unwrap_or_default();
+    let count = |key: &str| result.get(key).and_then(|v| v.as_u64());
+    Ok(Completion {
+        text: result.get("generated_text").and_then(|t| t.as_str()).unwrap_or("レスポンスが不正です").to_string(),
+        // "max_tokens" は OpenAI の "length" に合わせる（自動で続きを生成できるように）
+        finish_reason: result.get("stop_reason").and_then(|r| r.as_str())
+            .map(|reason| if reason == "max_tokens" { "length".to_string() } else { reason.to_string() }),
+        usage: count("input_token_count").map(|prompt_tokens| Usage {
+            prompt_tokens,
+            completion_tokens: count("generated_token_count").unwrap_or(0),
+            cached_tokens: 0,
+        }),
+        ..Default::default()
+    })
+}
+
+// APIキーをIAMトークンに交換する（期限内なら前回のものを使う）
+// 本文にAPIキーがそのまま入るので、記録やカセットに残さないよう PreparedRequest を通さずに送る
+async fn iam_token(config: &Config) -> Result<String, String> {
+    let api_key = config.api_key.as_deref()
+        .ok_or("watsonx.ai には api_key（IBM Cloud のAPIキー）が必要です")?;
+    let cached = TOKENS.lock().ok().and_then(|tokens| {
+        tokens.iter().find(|(key, _, expires)| key == api_key && Instant::now() < *expires).map(|(_, token, _)| token.clone())
+    });
+    if let Some(token) = cached {
+        return Ok(token);
+    }
+    let response = reqwest::Client::new()
+        .post(IAM_URL)
+        .form(&[("grant_type", "urn:ibm:params:oauth:grant-type:apikey"), ("apikey", api_key)])
+        .send()
+        .await
+        .map_err(|e| format!("IAMトークンの取得に失敗しました: {:?}", e))?;
+    let json: Value = response.json().await
+        .map_err(|e| format!("IAMトークンの取得に失敗しました: {:?}", e))?;
+    let token = json.get("access_token").and_then(|t| t.as_str())
+        .ok_or_else(|| format!("IAMトークンの取得に失敗しました: {}", json.get("errorMessage").and_then(|m| m.as_str()).unwrap_or("理由不明")))?
+        .to_string();
+    let lifetime = json.get("expires_in").and_then(|e| e.as_u64()).unwrap_or(0).saturating_sub(TOKEN_MARGIN_SECS);
+    if let Ok(mut tokens) = TOKENS.lock() {
+        tokens.retain(|(key, _, _)| key != api_key);
+        tokens.push((api_key.to_string(), token.clone(), Instant::now() + Duration::from_secs(lifetime)));
+    }
+    Ok(token)
+}
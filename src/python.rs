@@ -0,0 +1,201 @@
+// Python から使うための拡張モジュール（--features python。maturin でビルドする）
+//
+//   from milti_llm_client import ChatSession
+//   session = ChatSession(config_file="config.json", model="claude")
+//   print(session.send("こんにちは"))                         # 答えを待つ
+//   session.send_streaming("続けて", lambda kind, data: …)    # 届いた分から渡す
+//   answer = await session.send_async("もう一度")             # asyncio から使う
+//
+// 中身は ChatSession（src/api.rs）なので、振り分け・middleware の cache・ツールなどはそのまま働く。
+// ChatSession の引数は ClientBuilder の項目と同じ。推論は共有の tokio のランタイムで動かし、待つあいだは GIL を手放す。
+use std::collections::HashMap;
+use std::sync::Arc;
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use tokio::sync::{Mutex, MutexGuard};
+use crate::{ChatSession, Client, Completion, Config, Error, StreamEvent};
+
+create_exception!(milti_llm_client, LlmError, PyException, "推論や設定のエラー（kind 属性に \"auth\" / \"rate_limit\" などの種類が入る）");
+
+fn to_python(error: Error) -> PyErr {
+    Python::attach(|py| {
+        let exception = LlmError::new_err(error.to_string());
+        let _ = exception.value(py).setattr("kind", error.kind().as_str());
+        exception
+    })
+}
+
+// on_event に渡す種類と中身（ツールの呼び出しと振り分けは辞書にする）
+fn event_of(py: Python<'_>, event: StreamEvent) -> PyResult<(&'static str, Py<PyAny>)> {
+    Ok(match event {
+        StreamEvent::Text(text) => ("text", text.into_pyobject(py)?.into_any().unbind()),
+        StreamEvent::Reasoning(text) => ("reasoning", text.into_pyobject(py)?.into_any().unbind()),
+        StreamEvent::Routed { language, model } => ("routed", HashMap::from([("language", language), ("model", model)]).into_pyobject(py)?.into_any().unbind()),
+        StreamEvent::ToolCallStarted { name, arguments } => {
+            ("tool_call", HashMap::from([("name", name), ("arguments", arguments.to_string())]).into_pyobject(py)?.into_any().unbind())
+        }
+        StreamEvent::ToolCallFinished { name, output } => ("tool_result", HashMap::from([("name", name), ("output", output)]).into_pyobject(py)?.into_any().unbind()),
+    })
+}
+
+/// 1つの会話。送るたびに、これまでのやりとりを履歴として一緒に送る。
+#[pyclass(name = "ChatSession")]
+struct PyChatSession {
+    session: Arc<Mutex<ChatSession>>,
+}
+
+#[pymethods]
+impl PyChatSession {
+    /// 設定ファイル（config_file）か JSON の設定（config_json）の上に、ほかの引数を重ねて会話を始める。
+    #[new]
+    #[pyo3(signature = (config_file=None, *, config_json=None, model=None, profile=None, provider=None, endpoint=None, api_key=None, system_prompt=None, session_id=None))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        config_file: Option<&str>,
+        config_json: Option<&str>,
+        model: Option<&str>,
+        profile: Option<&str>,
+        provider: Option<&str>,
+        endpoint: Option<&str>,
+        api_key: Option<&str>,
+        system_prompt: Option<&str>,
+        session_id: Option<&str>,
+    ) -> PyResult<PyChatSession> {
+        let mut builder = Client::builder();
+        if let Some(path) = config_file {
+            builder = builder.config_file(path);
+        }
+        if let Some(json) = config_json {
+            builder = builder.config(Config::from_json(json).map_err(to_python)?);
+        }
+        if let Some(model) = model {
+            builder = builder.model(model);
+        }
+        if let Some(profile) = profile {
+            builder = builder.profile(profile);
+        }
+        if let Some(provider) = provider {
+            builder = builder.provider(provider);
+        }
+        if let Some(endpoint) = endpoint {
+            builder = builder.endpoint(endpoint);
+        }
+        if let Some(api_key) = api_key {
+            builder = builder.api_key(api_key);
+        }
+        if let Some(system_prompt) = system_prompt {
+            builder = builder.system_prompt(system_prompt);
+        }
+        let client = builder.build().map_err(to_python)?;
+        let session = match session_id {
+            Some(id) => ChatSession::resume(client, id).map_err(to_python)?.0,
+            None => ChatSession::new(client),
+        };
+        Ok(PyChatSession { session: Arc::new(Mutex::new(session)) })
+    }
+
+    /// メッセージを送って、答えを待つ。
+    fn send(&self, py: Python<'_>, message: &str) -> PyResult<String> {
+        let session = self.session.clone();
+        let message = message.to_string();
+        let completion = py.detach(|| runtime().block_on(async move { session.lock().await.send(&message).await }));
+        completion.map(|completion| completion.text).map_err(to_python)
+    }
+
+    /// メッセージを送って、届いた分から on_event(kind, data) を呼ぶ。終わったら答えの全体を返す。
+    ///
+    /// kind は "text" / "reasoning" / "routed" / "tool_call" / "tool_result"。
+    fn send_streaming(&self, py: Python<'_>, message: &str, on_event: Py<PyAny>) -> PyResult<String> {
+        let session = self.session.clone();
+        let message = message.to_string();
+        let mut failed = None;
+        let completion = py.detach(|| runtime().block_on(async {
+            session.lock().await.send_streaming(&message, |event| {
+                let called = Python::attach(|py| event_of(py, event).and_then(|(kind, data)| on_event.call1(py, (kind, data)).map(drop)));
+                if let Err(e) = called {
+                    failed.get_or_insert(e);
+                }
+            }).await
+        }));
+        // on_event が投げた例外は、推論が終わってから投げ直す
+        if let Some(e) = failed {
+            return Err(e);
+        }
+        completion.map(|completion| completion.text).map_err(to_python)
+    }
+
+    /// send の asyncio 版（await すると答えが返る）。
+    fn send_async<'py>(&self, py: Python<'py>, message: &str) -> PyResult<Bound<'py, PyAny>> {
+        let session = self.session.clone();
+        let message = message.to_string();
+        pyo3_async_runtimes::tokio::future_into_py(py, async move {
+            let completion: Result<Completion, Error> = session.lock().await.send(&message).await;
+            completion.map(|completion| completion.text).map_err(to_python)
+        })
+    }
+
+    /// これまでのやりとり（{"role": …, "content": …} の古い順のリスト）。
+    #[getter]
+    fn history(&self) -> PyResult<Vec<HashMap<&'static str, String>>> {
+        Ok(self.locked()?.history().iter()
+            .map(|message| HashMap::from([("role", message.role.clone()), ("content", message.content.clone())]))
+            .collect())
+    }
+
+    /// 履歴を消して、新しい会話にする。
+    fn clear(&self) -> PyResult<()> {
+        self.locked()?.clear();
+        Ok(())
+    }
+
+    /// これからのやりとりを sessions_dir に保存して、そのセッションのIDを返す。
+    fn save(&self) -> PyResult<String> {
+        Ok(self.locked()?.save().to_string())
+    }
+
+    #[getter]
+    fn id(&self) -> PyResult<Option<String>> {
+        Ok(self.locked()?.id().map(|id| id.to_string()))
+    }
+
+    #[getter]
+    fn model_name(&self) -> PyResult<String> {
+        Ok(self.locked()?.model_name().to_string())
+    }
+
+    /// 答えるモデルを変える（プロファイル名、"モデル名@行き先"、別名のどれでもよい）。
+    fn set_model(&self, model: &str) -> PyResult<()> {
+        self.locked()?.set_model(model);
+        Ok(())
+    }
+
+    #[getter]
+    fn get_system_prompt(&self) -> PyResult<Option<String>> {
+        Ok(self.locked()?.system_prompt().map(|prompt| prompt.to_string()))
+    }
+
+    #[setter]
+    fn set_system_prompt(&self, system_prompt: Option<&str>) -> PyResult<()> {
+        self.locked()?.set_system_prompt(system_prompt);
+        Ok(())
+    }
+}
+
+impl PyChatSession {
+    // 送っている途中（send_async を待っているあいだなど）は、待たずにエラーにする
+    fn locked(&self) -> PyResult<MutexGuard<'_, ChatSession>> {
+        self.session.try_lock().map_err(|_| LlmError::new_err("ほかのメッセージを送っている途中です"))
+    }
+}
+
+fn runtime() -> &'static tokio::runtime::Runtime {
+    pyo3_async_runtimes::tokio::get_runtime()
+}
+
+#[pymodule]
+fn milti_llm_client(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyChatSession>()?;
+    module.add("LlmError", module.py().get_type::<LlmError>())?;
+    Ok(())
+}
@@ -0,0 +1,202 @@
+// ネットワークにつながらないときに、1回だけの推論と batch submit をディスクに貯めておき、あとで送り直す
+//
+// "offline_queue": true（か --queue）のとき、通信エラーで失敗したリクエストを queue_dir（デフォルトは "queue"）の
+// "<ID>.json" に保存する。
+//
+//   queue list                      貯まっているリクエストの一覧
+//   queue run                       1回だけ送り直す（つながらなければそのまま残す）
+//   queue watch [--interval 秒]     全部送れるまで、間隔をあけて送り直し続ける
+//
+// 送れたものはキューから消し、結果を queue_dir の outbox.jsonl に1行ずつ追記して、
+// デスクトップ通知（notify-send / osascript があれば）でも知らせる。
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+use web_time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use crate::{batch, profiles, runtime, select_model, Config};
+use crate::runtime::Command;
+
+const DEFAULT_QUEUE_DIR: &str = "queue";
+
+// queue watch で送り直す間隔のデフォルト
+const DEFAULT_WATCH_INTERVAL_SECS: u64 = 60;
+
+// 一覧と通知に表示するプロンプトの長さ
+const PREVIEW_CHARS: usize = 40;
+
+// 貯めておくリクエストの中身
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Job {
+    Prompt { prompt: String },
+    BatchSubmit { path: String }, // ジョブファイルの絶対パス
+}
+
+#[derive(Serialize, Deserialize)]
+struct QueuedJob {
+    id: String,
+    created: u64,
+    profile: Option<String>, // 貯めたときのプロファイル
+    model: Option<String>, // 貯めたときの --model の指定
+    #[serde(flatten)]
+    job: Job,
+}
+
+fn queue_dir(config: &Config) -> PathBuf {
+    PathBuf::from(config.queue_dir.as_deref().unwrap_or(DEFAULT_QUEUE_DIR))
+}
+
+// 通信エラーのリクエストをキューに入れるかどうか
+pub fn is_enabled(config: &Config) -> bool {
+    config.offline_queue && !config.dry_run
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// キューに入れて、そのIDを返す
+pub fn enqueue(config: &Config, job: Job) -> Result<String, String> {
+    let job = match job {
+        Job::BatchSubmit { path } => Job::BatchSubmit {
+            // 送り直すときは別のディレクトリから実行するかもしれないので、絶対パスにしておく
+            path: fs::canonicalize(&path).map(|p| p.to_string_lossy().to_string()).unwrap_or(path),
+        },
+        job => job,
+    };
+    let created = now();
+    let id = format!("{}-{}", created, runtime::process_id());
+    let queued = QueuedJob { id: id.clone(), created, profile: config.profile.clone(), model: config.model_override.clone(), job };
+    fs::create_dir_all(queue_dir(config))
+        .and_then(|_| fs::write(queue_dir(config).join(format!("{}.json", id)), serde_json::to_string_pretty(&queued).unwrap_or_default()))
+        .map_err(|e| format!("キューへの保存に失敗しました: {:?}", e))?;
+    Ok(id)
+}
+
+// 貯まっているリクエスト（古い順）
+fn pending(config: &Config) -> Vec<(PathBuf, QueuedJob)> {
+    let Ok(entries) = fs::read_dir(queue_dir(config)) else {
+        return Vec::new();
+    };
+    let mut jobs: Vec<(PathBuf, QueuedJob)> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|path| {
+            let queued = serde_json::from_str(&fs::read_to_string(&path).ok()?).ok()?;
+            Some((path, queued))
+        })
+        .collect();
+    jobs.sort_by_key(|(_, queued)| queued.created);
+    jobs
+}
+
+fn describe(job: &Job) -> String {
+    match job {
+        Job::Prompt { prompt } => prompt.lines().next().unwrap_or_default().chars().take(PREVIEW_CHARS).collect(),
+        Job::BatchSubmit { path } => format!("batch submit {}", path),
+    }
+}
+
+// 1件を送り直す（まだつながらなければ None）
+async fn send(config: &Config, queued: &QueuedJob) -> Option<serde_json::Value> {
+    let mut config = config.clone();
+    if let Some(profile) = &queued.profile {
+        if let Err(e) = profiles::apply(&mut config, profile) {
+            return Some(serde_json::json!({ "error": e }));
+        }
+    }
+    if let Some(model) = &queued.model {
+        select_model(&mut config, model);
+    }
+    config.stream = false;
+    let result = match &queued.job {
+        Job::Prompt { prompt } => {
+            let completion = crate::respond(prompt, &config).await;
+            if completion.is_offline() {
+                return None;
+            }
+            serde_json::json!({ "prompt": prompt, "text": completion.text, "error": completion.error })
+        }
+        Job::BatchSubmit { path } => match batch::submit(&config, path).await {
+            Err(e) if e.kind().is_offline() => return None,
+            Ok(batch_id) => serde_json::json!({ "path": path, "batch_id": batch_id }),
+            Err(e) => serde_json::json!({ "path": path, "error": e.to_string() }),
+        },
+    };
+    Some(result)
+}
+
+// デスクトップに通知する（通知のコマンドがなければ何もしない）
+async fn notify(message: &str) {
+    let sent = Command::new("notify-send").arg("milti_llm_client").arg(message).output().await;
+    if sent.is_err() {
+        let script = format!("display notification {:?} with title \"milti_llm_client\"", message);
+        let _ = Command::new("osascript").arg("-e").arg(script).output().await;
+    }
+}
+
+// 貯まっているものを1回ずつ送り直し、送れた件数と残りの件数を返す
+async fn flush(config: &Config) -> Result<(usize, usize), String> {
+    let jobs = pending(config);
+    let mut sent = 0;
+    for (i, (path, queued)) in jobs.iter().enumerate() {
+        let Some(mut result) = send(config, queued).await else {
+            // つながらないうちは、残りも送らずに次の機会を待つ
+            return Ok((sent, jobs.len() - i));
+        };
+        result["id"] = serde_json::json!(queued.id);
+        result["finished"] = serde_json::json!(now());
+        OpenOptions::new().create(true).append(true).open(queue_dir(config).join("outbox.jsonl"))
+            .and_then(|mut outbox| writeln!(outbox, "{}", result))
+            .map_err(|e| format!("outbox.jsonl への書き込みに失敗しました: {:?}", e))?;
+        let _ = fs::remove_file(path);
+        let status = if result.get("error").is_some_and(|e| !e.is_null()) { "失敗しました" } else { "送信しました" };
+        println!("{}: {}（{}）", status, queued.id, describe(&queued.job));
+        notify(&format!("キューのリクエストを{}: {}", status, describe(&queued.job))).await;
+        sent += 1;
+    }
+    Ok((sent, 0))
+}
+
+// queue サブコマンド
+pub enum QueueCommand {
+    List,
+    Run,
+    Watch { interval_secs: Option<u64> }, // 省略すると60秒ごと
+}
+
+pub async fn run(command: QueueCommand, config: &Config) -> Result<(), String> {
+    let outbox = queue_dir(config).join("outbox.jsonl");
+    match command {
+        QueueCommand::List => {
+            let jobs = pending(config);
+            if jobs.is_empty() {
+                println!("キューは空です（{}）", queue_dir(config).display());
+            }
+            for (_, queued) in jobs {
+                println!("{}  {}", queued.id, describe(&queued.job));
+            }
+            Ok(())
+        }
+        QueueCommand::Run => {
+            let (sent, left) = flush(config).await?;
+            println!("{}件を送り直しました（結果: {}）。残り {}件", sent, outbox.display(), left);
+            Ok(())
+        }
+        QueueCommand::Watch { interval_secs } => {
+            let interval = interval_secs.unwrap_or(DEFAULT_WATCH_INTERVAL_SECS);
+            loop {
+                let (_, left) = flush(config).await?;
+                if left == 0 {
+                    println!("キューのリクエストをすべて送りました（結果: {}）", outbox.display());
+                    return Ok(());
+                }
+                eprintln!("まだつながりません。{}秒後にもう一度送ります（残り {}件）", interval, left);
+                runtime::sleep(Duration::from_secs(interval)).await;
+            }
+        }
+    }
+}
@@ -0,0 +1,453 @@
+// OpenAI の Realtime API で、声のまま会話する（--realtime）
+//
+//   "realtime": {
+//     "model": "gpt-4o-realtime-preview",
+//     "voice": "alloy",
+//     "record_command": ["rec", "-q", "-t", "raw", "-r", "24000", "-e", "signed-integer", "-b", "16", "-c", "1", "-"],
+//     "play_command": ["play", "-q", "-t", "raw", "-r", "24000", "-e", "signed-integer", "-b", "16", "-c", "1", "-"]
+//   }
+//
+// WebSocket でつなぎ、マイクの音声（PCM16・24kHz・モノラル）を送り続ける。話し終わりはサーバーが判定し（server_vad）、
+// 返ってきた音声はそのまま再生して、話した内容と答えの文字起こしを Event で知らせる。
+// 録音と再生は外のコマンド（デフォルトは sox の rec / play）に任せ、その標準出力・標準入力で生の PCM をやりとりする。
+// 話し始めたら、再生中の答えは止める（サーバーも答えを打ち切る）。
+// やりとりが1回終わるたびに、話した内容と答えの文字起こしを、ask と同じように統計・履歴・セッションに残す。
+// WebSocket と子プロセスを使うので、native の機能のときだけ動く（そうでなければ run はエラーを返す）。
+#![cfg_attr(not(feature = "native"), allow(dead_code))]
+use std::time::Duration;
+use base64::Engine;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use web_time::{Instant, SystemTime};
+use crate::completion::{Completion, Usage};
+use crate::error::Error;
+use crate::events::Event;
+use crate::stream::Token;
+use crate::{files, transcribe, Config};
+#[cfg(feature = "native")]
+use std::future::Future;
+#[cfg(feature = "native")]
+use std::process::Stdio;
+#[cfg(feature = "native")]
+use futures_util::{SinkExt, StreamExt};
+#[cfg(feature = "native")]
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+#[cfg(feature = "native")]
+use tokio::process::{Child, Command};
+#[cfg(feature = "native")]
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+#[cfg(feature = "native")]
+use tokio_tungstenite::tungstenite::{self, client::IntoClientRequest, http::HeaderValue, Message as Frame};
+
+// モデル名を省略したときの値
+const DEFAULT_MODEL: &str = "gpt-4o-realtime-preview";
+
+// api_base も endpoint もないときのつなぎ先
+const DEFAULT_API_BASE: &str = "https://api.openai.com/v1";
+
+// 録音と再生のデフォルト（sox。どちらも 24kHz・16bit・モノラルの生の PCM を標準出力・標準入力でやりとりする）
+const RECORD_COMMAND: [&str; 13] = ["rec", "-q", "-t", "raw", "-r", "24000", "-e", "signed-integer", "-b", "16", "-c", "1", "-"];
+const PLAY_COMMAND: [&str; 13] = ["play", "-q", "-t", "raw", "-r", "24000", "-e", "signed-integer", "-b", "16", "-c", "1", "-"];
+
+// 1回に送る音声の大きさ（24kHz・16bit で 100ミリ秒分）
+const CHUNK_BYTES: usize = 4800;
+
+// 話した内容を文字起こしできなかったときに、履歴に残す文
+const NO_TRANSCRIPT: &str = "（音声）";
+
+// "realtime" に書く設定
+#[derive(Clone, Default, Deserialize)]
+pub struct RealtimeConfig {
+    pub model: Option<String>, // 省略時は DEFAULT_MODEL
+    pub voice: Option<String>, // 答えの声（"alloy" など。省略するとサーバーのデフォルト）
+    pub endpoint: Option<String>, // 省略時は api_base（なければ endpoint の "/v1" まで）を wss:// にした "/realtime"
+    pub record_command: Option<Vec<String>>, // マイクの音声を標準出力に書くコマンド
+    pub play_command: Option<Vec<String>>, // 標準入力の音声を再生するコマンド
+}
+
+// やりとり1回分（話した内容の文字起こしと答え）
+pub struct Turn {
+    pub prompt: String,
+    pub completion: Completion,
+    pub started_at: SystemTime, // 話し終えた時刻
+    pub elapsed: Duration, // 話し終えてから答えが終わるまで
+}
+
+pub fn model(config: &Config) -> String {
+    config.realtime.model.clone().unwrap_or_else(|| DEFAULT_MODEL.to_string())
+}
+
+// つなぐURL（モデルはクエリで指定する）
+fn url(config: &Config) -> String {
+    let endpoint = config.realtime.endpoint.clone().unwrap_or_else(|| {
+        let base = files::api_base(config).unwrap_or_else(|_| DEFAULT_API_BASE.to_string());
+        format!("{}/realtime", base.replacen("https://", "wss://", 1).replacen("http://", "ws://", 1))
+    });
+    match endpoint.contains("model=") {
+        true => endpoint,
+        false => format!("{}{}model={}", endpoint, if endpoint.contains('?') { "&" } else { "?" }, model(config)),
+    }
+}
+
+// つないだら最初に送る、セッションの設定（system メッセージ・声・音声の形式・話し終わりの判定）
+fn session_update(config: &Config) -> Value {
+    let mut session = json!({
+        "modalities": ["text", "audio"],
+        "input_audio_format": "pcm16",
+        "output_audio_format": "pcm16",
+        "input_audio_transcription": { "model": config.stt_model.as_deref().unwrap_or(transcribe::DEFAULT_STT_MODEL) },
+        "turn_detection": { "type": "server_vad" },
+    });
+    if let Some(system) = &config.system_prompt {
+        session["instructions"] = json!(system);
+    }
+    if let Some(voice) = &config.realtime.voice {
+        session["voice"] = json!(voice);
+    }
+    json!({ "type": "session.update", "session": session })
+}
+
+// サーバーから届くイベントのうち、使うもの
+enum ServerEvent {
+    Audio(Vec<u8>), // 答えの音声（PCM16）
+    AnswerDelta(String), // 答えの文字起こしの断片
+    UserTranscript(String), // 話した内容の文字起こし
+    SpeechStarted,
+    SpeechStopped,
+    Done(Option<Usage>), // 答えが終わった（打ち切られたときも）
+    Error(String),
+}
+
+// 届いたイベントを読む（"response.output_audio.delta" のような、GA の名前も受け付ける）
+fn parse(event: &Value) -> Option<ServerEvent> {
+    let text = |key: &str| event.get(key).and_then(|v| v.as_str()).unwrap_or_default().to_string();
+    Some(match event.get("type")?.as_str()? {
+        "response.audio.delta" | "response.output_audio.delta" => {
+            ServerEvent::Audio(base64::engine::general_purpose::STANDARD.decode(text("delta")).ok()?)
+        }
+        "response.audio_transcript.delta" | "response.output_audio_transcript.delta" | "response.text.delta" | "response.output_text.delta" => {
+            ServerEvent::AnswerDelta(text("delta"))
+        }
+        "conversation.item.input_audio_transcription.completed" => ServerEvent::UserTranscript(text("transcript").trim().to_string()),
+        "input_audio_buffer.speech_started" => ServerEvent::SpeechStarted,
+        "input_audio_buffer.speech_stopped" => ServerEvent::SpeechStopped,
+        "response.done" => ServerEvent::Done(event.pointer("/response/usage").filter(|usage| usage.is_object()).map(|usage| {
+            let count = |pointer: &str| usage.pointer(pointer).and_then(|v| v.as_u64()).unwrap_or(0);
+            Usage {
+                prompt_tokens: count("/input_tokens"),
+                completion_tokens: count("/output_tokens"),
+                cached_tokens: count("/input_token_details/cached_tokens"),
+            }
+        })),
+        "error" => ServerEvent::Error(event.pointer("/error/message").and_then(|v| v.as_str()).unwrap_or("Realtime API のエラー").to_string()),
+        _ => return None,
+    })
+}
+
+// 再生のコマンドに渡すもの
+enum Playback {
+    Audio(Vec<u8>),
+    Interrupt, // 再生中の答えを止める（次の音声は新しく起動したコマンドで再生する）
+}
+
+// 話し終えてから答えが終わるまで（話した内容の文字起こしは、答えより後に届くこともある）
+struct Exchange {
+    user: Option<String>,
+    answer: String,
+    started_at: SystemTime,
+    started: Instant,
+    waiting: Option<Turn>, // 話した内容の文字起こしを待っている、終わったやりとり
+}
+
+impl Exchange {
+    fn new() -> Self {
+        Exchange { user: None, answer: String::new(), started_at: SystemTime::now(), started: Instant::now(), waiting: None }
+    }
+
+    // 届いたイベントを表示する側と再生に回し、終わったやりとりを on_turn に渡す
+    fn handle(&mut self, event: ServerEvent, play: &mut impl FnMut(Playback), on_event: &mut impl FnMut(Event), on_turn: &mut impl FnMut(Turn)) {
+        match event {
+            ServerEvent::Audio(audio) => play(Playback::Audio(audio)),
+            ServerEvent::AnswerDelta(text) => {
+                self.answer.push_str(&text);
+                on_event(Event::TokenDelta(Token::Answer(text)));
+            }
+            ServerEvent::UserTranscript(text) => {
+                on_event(Event::UserMessage(text.clone()));
+                match self.waiting.take() {
+                    Some(turn) => on_turn(Turn { prompt: text, ..turn }),
+                    None => self.user = Some(text),
+                }
+            }
+            ServerEvent::SpeechStarted => {
+                play(Playback::Interrupt);
+                self.flush(on_turn);
+            }
+            ServerEvent::SpeechStopped => {
+                self.started_at = SystemTime::now();
+                self.started = Instant::now();
+            }
+            ServerEvent::Done(usage) => {
+                if let Some(usage) = usage {
+                    on_event(Event::UsageReport(usage));
+                }
+                let text = std::mem::take(&mut self.answer);
+                if text.is_empty() {
+                    return; // 話し始める前に打ち切られた
+                }
+                on_event(Event::AssistantMessage(text.clone()));
+                let turn = Turn {
+                    prompt: NO_TRANSCRIPT.to_string(),
+                    completion: Completion { text, usage, ..Default::default() },
+                    started_at: self.started_at,
+                    elapsed: self.started.elapsed(),
+                };
+                match self.user.take() {
+                    Some(prompt) => on_turn(Turn { prompt, ..turn }),
+                    None => self.waiting = Some(turn),
+                }
+            }
+            ServerEvent::Error(message) => on_event(Event::Error(message)),
+        }
+    }
+
+    // 文字起こしが届かないまま次に進むときは、届かなかったものとして残す
+    fn flush(&mut self, on_turn: &mut impl FnMut(Turn)) {
+        if let Some(turn) = self.waiting.take() {
+            on_turn(turn);
+        }
+    }
+}
+
+// stop が終わるまで、マイクの音声を送り、届いた答えを再生する
+#[cfg(feature = "native")]
+pub async fn run(config: &Config, stop: impl Future<Output = ()>, mut on_event: impl FnMut(Event), mut on_turn: impl FnMut(Turn)) -> Result<(), Error> {
+    let (mut sink, mut stream) = connect(config).await?.split();
+    sink.send(Frame::Text(session_update(config).to_string())).await.map_err(closed)?;
+
+    let record = config.realtime.record_command.clone().unwrap_or_else(|| RECORD_COMMAND.map(str::to_string).to_vec());
+    let play = config.realtime.play_command.clone().unwrap_or_else(|| PLAY_COMMAND.map(str::to_string).to_vec());
+    let mut recorder = spawn(&record, "録音")?;
+    let mut microphone = recorder.stdout.take().ok_or("録音のコマンドの出力を読めません")?;
+    let (playback, queue) = unbounded_channel();
+    let player = tokio::spawn(play_audio(spawn(&play, "再生")?, play, queue));
+
+    let mut exchange = Exchange::new();
+    let mut buffer = vec![0u8; CHUNK_BYTES];
+    tokio::pin!(stop);
+    let result = loop {
+        tokio::select! {
+            _ = &mut stop => break Ok(()),
+            read = microphone.read(&mut buffer) => match read {
+                Ok(0) | Err(_) => break Err(Error::Config("録音のコマンドが終わりました".to_string())),
+                Ok(n) => {
+                    let audio = base64::engine::general_purpose::STANDARD.encode(&buffer[..n]);
+                    let append = json!({ "type": "input_audio_buffer.append", "audio": audio });
+                    if let Err(e) = sink.send(Frame::Text(append.to_string())).await {
+                        break Err(closed(e));
+                    }
+                }
+            },
+            frame = stream.next() => match frame {
+                Some(Ok(Frame::Text(text))) => {
+                    let Some(event) = serde_json::from_str(&text).ok().as_ref().and_then(parse) else {
+                        continue;
+                    };
+                    let mut play = |item| {
+                        let _ = playback.send(item);
+                    };
+                    exchange.handle(event, &mut play, &mut on_event, &mut on_turn);
+                }
+                Some(Ok(Frame::Close(_))) | None => break Err(closed(tungstenite::Error::ConnectionClosed)),
+                Some(Ok(_)) => {}
+                Some(Err(e)) => break Err(closed(e)),
+            },
+        }
+    };
+    exchange.flush(&mut on_turn);
+    let _ = sink.send(Frame::Close(None)).await;
+    let _ = recorder.kill().await;
+    drop(playback);
+    let _ = player.await;
+    result
+}
+
+#[cfg(not(feature = "native"))]
+pub async fn run(_config: &Config, _stop: impl std::future::Future<Output = ()>, _on_event: impl FnMut(Event), _on_turn: impl FnMut(Turn)) -> Result<(), Error> {
+    Err(Error::Config("この環境では --realtime を使えません（WebSocket と録音・再生のコマンドが必要です）".to_string()))
+}
+
+// WebSocket でつなぐ（APIキーがあれば認証ヘッダーを付ける。断られたら、そのレスポンスからエラーを作る）
+#[cfg(feature = "native")]
+async fn connect(config: &Config) -> Result<tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>, Error> {
+    let url = url(config);
+    let mut request = url.as_str().into_client_request()
+        .map_err(|e| Error::Config(format!("Realtime API のURLが不正です: {} ({})", url, e)))?;
+    if let Some(api_key) = &config.api_key {
+        let value = HeaderValue::from_str(&format!("Bearer {}", api_key))
+            .map_err(|_| Error::Config("APIキーに使えない文字が含まれています".to_string()))?;
+        request.headers_mut().insert("Authorization", value);
+    }
+    request.headers_mut().insert("OpenAI-Beta", HeaderValue::from_static("realtime=v1"));
+    match tokio_tungstenite::connect_async(request).await {
+        Ok((socket, _)) => Ok(socket),
+        Err(tungstenite::Error::Http(response)) => Err(Error::from_response(&crate::request::HttpResponse {
+            status: response.status().as_u16(),
+            body: String::from_utf8_lossy(response.body().as_deref().unwrap_or_default()).to_string(),
+        })),
+        Err(e) => Err(Error::Inference { kind: crate::ErrorKind::Network, message: format!("Realtime API（{}）に接続できません: {}", url, e) }),
+    }
+}
+
+#[cfg(feature = "native")]
+fn closed(e: tungstenite::Error) -> Error {
+    Error::Interrupted { partial: String::new(), cause: format!("Realtime API との接続が切れました: {}", e) }
+}
+
+// 録音・再生のコマンドを起動する（音声は標準出力・標準入力でやりとりし、終わるときは止める）
+#[cfg(feature = "native")]
+fn spawn(command: &[String], what: &str) -> Result<Child, Error> {
+    let (program, arguments) = command.split_first()
+        .ok_or_else(|| Error::Config(format!("{}のコマンドが空です", what)))?;
+    Command::new(program).args(arguments)
+        .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| Error::Config(format!("{}のコマンド {} を起動できません（sox を入れるか、realtime の {}_command を設定してください）: {}",
+            what, program, if what == "録音" { "record" } else { "play" }, e)))
+}
+
+// 届いた順に再生する（止めるときはコマンドごと止めて、次の音声で起動し直す）
+#[cfg(feature = "native")]
+async fn play_audio(player: Child, command: Vec<String>, mut queue: UnboundedReceiver<Playback>) {
+    let mut player = Some(player);
+    while let Some(item) = queue.recv().await {
+        match item {
+            Playback::Interrupt => {
+                if let Some(mut child) = player.take() {
+                    let _ = child.kill().await;
+                }
+            }
+            Playback::Audio(audio) => {
+                if player.is_none() {
+                    player = spawn(&command, "再生").ok();
+                }
+                let Some(stdin) = player.as_mut().and_then(|child| child.stdin.as_mut()) else {
+                    continue;
+                };
+                if stdin.write_all(&audio).await.is_err() {
+                    player = None;
+                }
+            }
+        }
+    }
+    // 終わるときは、残りを再生し終えるのを待つ
+    if let Some(mut child) = player {
+        drop(child.stdin.take());
+        let _ = child.wait().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(realtime: Value) -> Config {
+        serde_json::from_value(json!({
+            "model_name": "gpt-4o", "use_local_model": false, "openai_compatible": true,
+            "endpoint": "https://api.openai.com/v1/chat/completions", "system_prompt": "短く答えて",
+            "realtime": realtime,
+        })).unwrap()
+    }
+
+    #[test]
+    fn the_url_and_session_follow_the_config() {
+        let config = config(json!({ "voice": "alloy" }));
+        assert_eq!(url(&config), "wss://api.openai.com/v1/realtime?model=gpt-4o-realtime-preview");
+        let update = session_update(&config);
+        assert_eq!(update["session"]["instructions"], "短く答えて");
+        assert_eq!(update["session"]["voice"], "alloy");
+        assert_eq!(update["session"]["input_audio_transcription"]["model"], "whisper-1");
+        let local = self::config(json!({ "endpoint": "ws://127.0.0.1:9000/realtime?model=local" }));
+        assert_eq!(url(&local), "ws://127.0.0.1:9000/realtime?model=local");
+    }
+
+    #[test]
+    fn a_late_transcript_is_paired_with_its_answer() {
+        let mut events = Vec::new();
+        let mut turns = Vec::new();
+        let mut played = 0;
+        let mut exchange = Exchange::new();
+        let incoming = [
+            json!({ "type": "input_audio_buffer.speech_stopped" }),
+            json!({ "type": "response.audio.delta", "delta": "AAAA" }),
+            json!({ "type": "response.audio_transcript.delta", "delta": "こんにちは" }),
+            json!({ "type": "response.done", "response": { "usage": { "input_tokens": 12, "output_tokens": 5 } } }),
+            json!({ "type": "conversation.item.input_audio_transcription.completed", "transcript": " やあ\n" }),
+            json!({ "type": "session.updated" }),
+        ];
+        for event in incoming.iter().filter_map(parse) {
+            exchange.handle(event, &mut |item| {
+                if let Playback::Audio(audio) = item {
+                    played += audio.len();
+                }
+            }, &mut |event| events.push(event), &mut |turn| turns.push(turn));
+        }
+        assert_eq!(played, 3);
+        assert!(matches!(&events[0], Event::TokenDelta(Token::Answer(text)) if text == "こんにちは"));
+        assert!(matches!(&events[1], Event::UsageReport(usage) if usage.prompt_tokens == 12 && usage.completion_tokens == 5));
+        assert!(matches!(&events[3], Event::UserMessage(text) if text == "やあ"));
+        assert_eq!(turns.len(), 1);
+        assert_eq!((turns[0].prompt.as_str(), turns[0].completion.text.as_str()), ("やあ", "こんにちは"));
+    }
+
+    #[cfg(feature = "native")]
+    #[tokio::test]
+    async fn audio_goes_up_and_the_transcript_comes_back() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        // 設定と音声を1つずつ受け取ったら、答えのイベントを返す（切るのはクライアントから）
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut socket = tokio_tungstenite::accept_async(socket).await.unwrap();
+            let mut received = Vec::new();
+            while received.len() < 2 {
+                if let Some(Ok(Frame::Text(text))) = socket.next().await {
+                    received.push(serde_json::from_str::<Value>(&text).unwrap());
+                }
+            }
+            for event in [
+                json!({ "type": "conversation.item.input_audio_transcription.completed", "transcript": "天気は？" }),
+                json!({ "type": "response.audio_transcript.delta", "delta": "晴れです" }),
+                json!({ "type": "response.done", "response": { "usage": { "input_tokens": 3, "output_tokens": 2 } } }),
+            ] {
+                socket.send(Frame::Text(event.to_string())).await.unwrap();
+            }
+            while let Some(Ok(frame)) = socket.next().await {
+                if frame.is_close() {
+                    break;
+                }
+            }
+            received
+        });
+        let config = config(json!({
+            "endpoint": format!("ws://{}/realtime", address),
+            "record_command": ["sh", "-c", "printf abcd; sleep 5"],
+            "play_command": ["sh", "-c", "cat > /dev/null"],
+        }));
+        let (done, finished) = tokio::sync::oneshot::channel::<()>();
+        let mut done = Some(done);
+        let mut turns = Vec::new();
+        run(&config, async { let _ = finished.await; }, |_| {}, |turn| {
+            turns.push(turn);
+            if let Some(done) = done.take() {
+                let _ = done.send(());
+            }
+        }).await.unwrap();
+
+        let received = server.await.unwrap();
+        assert_eq!(received[0]["type"], "session.update");
+        assert_eq!(received[1], json!({ "type": "input_audio_buffer.append", "audio": "YWJjZA==" }));
+        assert_eq!((turns[0].prompt.as_str(), turns[0].completion.text.as_str()), ("天気は？", "晴れです"));
+    }
+}
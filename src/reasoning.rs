@@ -0,0 +1,43 @@
+// 推論モデル（o系、DeepSeek-R1 など）の「考え中」の部分を、最終的な答えと分けて扱う
+
+// fold 表示のときに見せる行数
+const FOLDED_LINES: usize = 2;
+
+// 本文に埋め込まれた <think>…</think> を取り出して、(考え中の部分, 残りの本文) に分ける
+pub fn split_think(text: &str) -> (Option<String>, String) {
+    let mut thoughts = Vec::new();
+    let mut answer = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("<think>") {
+        let Some(end) = rest[start..].find("</think>") else {
+            break; // 閉じタグがなければ本文として扱う
+        };
+        answer.push_str(&rest[..start]);
+        thoughts.push(rest[start + "<think>".len()..start + end].trim().to_string());
+        rest = &rest[start + end + "</think>".len()..];
+    }
+    answer.push_str(rest);
+    if thoughts.is_empty() {
+        (None, text.to_string())
+    } else {
+        (Some(thoughts.join("\n")), answer.trim_start().to_string())
+    }
+}
+
+// 表示モードに従って、考え中の部分を表示用の文字列にする（hide なら None）
+// "show": そのまま / "dim": 薄い色で表示（デフォルト） / "fold": 先頭だけ表示して残りは省略 / "hide": 表示しない
+pub fn render(reasoning: &str, mode: Option<&str>) -> Option<String> {
+    match mode.unwrap_or("dim") {
+        "hide" => None,
+        "show" => Some(format!("（考え中）\n{}", reasoning)),
+        "fold" => {
+            let lines: Vec<&str> = reasoning.lines().collect();
+            let mut text = format!("（考え中）\n{}", lines.iter().take(FOLDED_LINES).copied().collect::<Vec<_>>().join("\n"));
+            if lines.len() > FOLDED_LINES {
+                text.push_str(&format!("\n…（{}行省略）", lines.len() - FOLDED_LINES));
+            }
+            Some(text)
+        }
+        _ => Some(format!("\x1b[2m（考え中）\n{}\x1b[0m", reasoning)),
+    }
+}
@@ -0,0 +1,87 @@
+// 答える言語を決めておく（"reply_language"）
+//
+//   "reply_language": "ja"
+//
+// 送るときに、その言語で答えるように指示を付ける（チャット形式なら system メッセージの後ろに、そうでなければプロンプトの後ろに）。
+// 返ってきた答えの言語を文字の種類で判定し（コードブロックとインラインコードは除く）、違っていれば一度だけ聞き直す。
+// 言語は router と同じ判定で、ja / ko / zh / ru / ar / th / en のどれか。
+use crate::{router, stream, Config};
+use crate::completion::Completion;
+
+// 判定するのに足りる文字数（これより短い答えは判定せずにそのまま使う）
+const MIN_CHECK_CHARS: usize = 20;
+
+// 言語ごとの指示（その言語で書く）
+const INSTRUCTIONS: [(&str, &str); 7] = [
+    ("ja", "必ず日本語で答えてください。"),
+    ("ko", "반드시 한국어로 답해 주세요."),
+    ("zh", "请务必用中文回答。"),
+    ("ru", "Отвечайте только на русском языке."),
+    ("ar", "أجب باللغة العربية فقط."),
+    ("th", "กรุณาตอบเป็นภาษาไทยเท่านั้น"),
+    ("en", "Please reply in English only."),
+];
+
+pub fn languages() -> Vec<&'static str> {
+    INSTRUCTIONS.iter().map(|(code, _)| *code).collect()
+}
+
+fn instruction(language: &str) -> &'static str {
+    INSTRUCTIONS.iter().find(|(code, _)| *code == language).map(|(_, text)| *text).unwrap_or_default()
+}
+
+// 指示を付けて推論し、答えの言語が違っていれば一度だけ聞き直す
+pub async fn respond(prompt: &str, config: &Config, language: &str) -> Completion {
+    let mut instructed = config.clone();
+    let prompt = match config.chat {
+        true => {
+            instructed.system_prompt = Some(match &config.system_prompt {
+                Some(system) => format!("{}\n\n{}", system, instruction(language)),
+                None => instruction(language).to_string(),
+            });
+            prompt.to_string()
+        }
+        false => format!("{}\n\n{}", prompt, instruction(language)),
+    };
+    let completion = crate::respond_assembled(&prompt, &instructed).await;
+    if config.dry_run || completion.error.is_some() || completion.is_interrupted() || matches(&completion.text, language) {
+        return completion;
+    }
+
+    let notice = format!("（{}で答えていなかったため、聞き直します）", router::language_name(language));
+    if config.stream {
+        stream::emit(config, stream::Token::Answer(format!("\n\n{}\n\n", notice)));
+    } else if !config.quiet {
+        eprintln!("{}", notice);
+    }
+    let retry_prompt = format!("{}\n\n{}", prompt, instruction(language));
+    let retried = crate::respond_assembled(&retry_prompt, &instructed).await;
+    // 聞き直しが失敗したときは、最初の答えを使う
+    if retried.error.is_some() { completion } else { retried }
+}
+
+// 答えがその言語か（判定できないほど短いときや、文字がないときもそうみなす）
+fn matches(text: &str, language: &str) -> bool {
+    let prose = strip_code(text);
+    if prose.chars().filter(|c| c.is_alphabetic()).count() < MIN_CHECK_CHARS {
+        return true;
+    }
+    router::detect(&prose).is_none_or(|detected| detected == language)
+}
+
+// コードブロック（```）とインラインコード（`）を除いた本文
+fn strip_code(text: &str) -> String {
+    let mut prose = String::new();
+    let mut in_block = false;
+    for line in text.lines() {
+        if line.trim_start().starts_with("```") {
+            in_block = !in_block;
+            continue;
+        }
+        if !in_block {
+            prose.extend(line.split('`').step_by(2));
+            prose.push('\n');
+        }
+    }
+    prose
+}
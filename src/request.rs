@@ -0,0 +1,449 @@
+// HTTPリクエストの組み立て・表示・送信をまとめたモジュール
+use std::cell::RefCell;
+use std::fs::OpenOptions;
+use std::future::Future;
+use std::io::Write;
+use std::time::Duration;
+use web_time::{Instant, SystemTime, UNIX_EPOCH};
+use futures_util::StreamExt;
+use serde_json::Value;
+use crate::{cassette, runtime, Config};
+use crate::error::{Error, ErrorKind};
+
+// 伏せ字にするヘッダー名（小文字で比較する）
+const SECRET_HEADERS: [&str; 4] = ["authorization", "x-api-key", "api-key", "x-goog-api-key"];
+
+// curlコマンドに書き出すときにAPIキーの代わりに使う環境変数名
+const CURL_KEY_ENV: &str = "API_KEY";
+
+tokio::task_local! {
+    // capture の中で最後に送った（dry-run で組み立てた）リクエスト
+    // 推論の呼び出しごとに別々に持つので、同時に送っても混ざらない
+    static SENT: RefCell<Option<PreparedRequest>>;
+}
+
+// 応答が途切れてからタイムアウトにするまでの秒数と、429 / 5xx で再試行する回数のデフォルト
+const DEFAULT_TIMEOUT_SECS: u64 = 120;
+const DEFAULT_MAX_RETRIES: u32 = 3;
+
+// 再試行の待ち時間（1回目がこの長さで、そこから倍にしていく。MAX より長くはしない）
+const RETRY_BASE_MILLIS: u64 = 1000;
+const MAX_RETRY_WAIT_MILLIS: u64 = 60_000;
+
+// 受信したレスポンス（記録できるように本文まで読み切ったもの）
+pub struct HttpResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+// 送信前のHTTPリクエストの中身を保持する構造体
+#[derive(Clone)]
+pub struct PreparedRequest {
+    pub method: &'static str,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Value, // multipart のときはフォームの項目（"file" はファイル名）
+    pub upload: Option<Vec<u8>>, // multipart で送るファイルの中身
+    pub max_body_bytes: Option<usize>, // 受け取る本文の上限（超えた分は読まずに切る。ツールで開くページなど）
+}
+
+impl PreparedRequest {
+    // JSONボディを送るPOSTリクエストを作る
+    pub fn new(url: &str, body: Value) -> Self {
+        PreparedRequest {
+            method: "POST",
+            url: url.to_string(),
+            headers: vec![("Content-Type".to_string(), "application/json".to_string())],
+            body,
+            upload: None,
+            max_body_bytes: None,
+        }
+    }
+
+    // ファイルを multipart/form-data で送るPOSTリクエストを作る
+    pub fn upload(url: &str, fields: &[(&str, &str)], file_name: &str, contents: Vec<u8>) -> Self {
+        let mut form = serde_json::Map::new();
+        for (name, value) in fields {
+            form.insert(name.to_string(), Value::String(value.to_string()));
+        }
+        form.insert("file".to_string(), Value::String(file_name.to_string()));
+        PreparedRequest {
+            method: "POST",
+            url: url.to_string(),
+            headers: Vec::new(),
+            body: Value::Object(form),
+            upload: Some(contents),
+            max_body_bytes: None,
+        }
+    }
+
+    // ボディなしのGETリクエストを作る
+    pub fn get(url: &str) -> Self {
+        PreparedRequest { method: "GET", url: url.to_string(), headers: Vec::new(), body: Value::Null, upload: None, max_body_bytes: None }
+    }
+
+    // ボディなしのDELETEリクエストを作る
+    pub fn delete(url: &str) -> Self {
+        PreparedRequest { method: "DELETE", ..PreparedRequest::get(url) }
+    }
+
+    // JSONのボディつきのPATCHリクエストを作る（一部の項目だけ書き換える）
+    pub fn patch(url: &str, body: Value) -> Self {
+        PreparedRequest { method: "PATCH", ..PreparedRequest::new(url, body) }
+    }
+
+
+    // ヘッダーを追加する（ビルダー風に繋げて書ける）
+    pub fn header(mut self, name: &str, value: String) -> Self {
+        self.headers.push((name.to_string(), value));
+        self
+    }
+
+    // 受け取る本文を bytes バイトまでにする（それより後ろは読まない）
+    pub fn limit_body(mut self, bytes: usize) -> Self {
+        self.max_body_bytes = Some(bytes);
+        self
+    }
+
+    // APIキーを伏せた状態で、送信内容をそのまま見やすく整形する（dry-run用）
+    pub fn pretty(&self) -> String {
+        let mut text = format!("{} {}\n", self.method, self.url);
+        for (name, value) in &self.headers {
+            text.push_str(&format!("{}: {}\n", name, redact_header(name, value)));
+        }
+        if !self.body.is_null() {
+            text.push('\n');
+            text.push_str(&serde_json::to_string_pretty(&self.body).unwrap_or_default());
+        }
+        text
+    }
+
+    // dry-run用：送信はせずに記録だけして、整形した内容を返す
+    pub fn dry_run(&self) -> String {
+        self.remember();
+        self.pretty()
+    }
+
+    // そのまま実行できるcurlコマンドに変換する（APIキーは環境変数の参照に置き換える）
+    pub fn to_curl(&self) -> String {
+        let mut command = format!("curl -X {} {}", self.method, shell_quote(&self.url));
+        for (name, value) in &self.headers {
+            let header = if is_secret_header(name) {
+                match value.split_once(' ') {
+                    Some((scheme, _)) => format!("\"{}: {} ${}\"", name, scheme, CURL_KEY_ENV),
+                    None => format!("\"{}: ${}\"", name, CURL_KEY_ENV),
+                }
+            } else {
+                shell_quote(&format!("{}: {}", name, value))
+            };
+            command.push_str(&format!(" \\\n  -H {}", header));
+        }
+        if let (Some(_), Some(form)) = (&self.upload, self.body.as_object()) {
+            for (name, value) in form {
+                let value = value.as_str().unwrap_or_default();
+                let field = if name == "file" { format!("file=@{}", value) } else { format!("{}={}", name, value) };
+                command.push_str(&format!(" \\\n  -F {}", shell_quote(&field)));
+            }
+        } else if !self.body.is_null() {
+            let body = serde_json::to_string(&self.body).unwrap_or_default();
+            command.push_str(&format!(" \\\n  -d {}", shell_quote(&body)));
+        }
+        command
+    }
+
+    // capture の中なら、送ったリクエストとして覚えておく
+    fn remember(&self) {
+        let _ = SENT.try_with(|sent| *sent.borrow_mut() = Some(self.clone()));
+    }
+
+    // 実際にリクエストを送信して、レスポンスの本文まで受け取る
+    pub async fn send(&self, config: &Config) -> Result<HttpResponse, Error> {
+        self.send_streaming(config, |_| {}).await
+    }
+
+    // send と同じだが、本文を届いた分から on_chunk に渡す（ストリーミング表示用）
+    // 記録やカセットには最後まで受け取った本文を残し、再生のときは本文全体を1回で渡す
+    // 再試行できる種類のエラー（429 の回数制限・混雑・5xx。利用枠の使い切りは除く）と、
+    // 本文を受け取る前の通信エラーは、待ち時間を倍にしながら再試行する
+    pub async fn send_streaming(&self, config: &Config, mut on_chunk: impl FnMut(&str)) -> Result<HttpResponse, Error> {
+        self.remember();
+        if let Some(replayed) = cassette::replay(config, self) {
+            let response = replayed.unwrap_or_else(|message| {
+                eprintln!("{}", message);
+                HttpResponse { status: 404, body: serde_json::json!({ "error": message }).to_string() }
+            });
+            on_chunk(&response.body);
+            return Ok(response);
+        }
+        let timeout_secs = timeout_secs(config);
+        let max_retries = config.max_retries.unwrap_or(DEFAULT_MAX_RETRIES);
+        let mut attempt = 0;
+        loop {
+            let started = Instant::now();
+            let mut received = false;
+            let result = self.send_inner(&mut |chunk: &str| {
+                received = true;
+                on_chunk(chunk);
+            }, timeout_secs).await;
+            record(config, self, &result, started.elapsed().as_millis());
+            let retryable = match &result {
+                Ok(response) if response.status >= 400 => ErrorKind::classify(response.status, &response.body).0.is_retryable(),
+                Ok(_) => false,
+                Err(_) => !received, // 途中まで表示したものは、やり直すと二重になるので再試行しない
+            };
+            if retryable && attempt < max_retries {
+                let wait = retry_wait(attempt);
+                let reason = match &result {
+                    Ok(response) => format!("{}（ステータス {}）", ErrorKind::classify(response.status, &response.body).0.describe(), response.status),
+                    Err(e) => e.to_string(),
+                };
+                if !config.quiet {
+                    eprintln!("{} のため、{:.1}秒後に再試行します（{}/{}）", reason, wait.as_secs_f64(), attempt + 1, max_retries);
+                }
+                runtime::sleep(wait).await;
+                attempt += 1;
+                continue;
+            }
+            if let Ok(response) = &result {
+                cassette::store(config, self, response);
+            }
+            return result;
+        }
+    }
+
+    // 再試行や記録をせず、送ったリクエストとしても残さずに送る（裏で送る warm-up 用）
+    pub async fn send_detached(&self, timeout_secs: u64) -> Result<HttpResponse, Error> {
+        self.send_inner(&mut |_: &str| {}, timeout_secs).await
+    }
+
+    // 接続や次の断片を timeout_secs 待っても届かなければタイムアウトにする（長いストリーミングは切らない）
+    // エラーのステータスの本文は on_chunk に渡さない（再試行したときに表示側に混ざらないように）
+    async fn send_inner(&self, on_chunk: &mut impl FnMut(&str), timeout_secs: u64) -> Result<HttpResponse, Error> {
+        let timeout = Duration::from_secs(timeout_secs);
+        let client = reqwest::Client::new();
+        let mut request_builder = match (self.method, &self.upload) {
+            ("GET", _) => client.get(&self.url),
+            ("DELETE", _) => client.delete(&self.url),
+            ("PATCH", _) => client.patch(&self.url).json(&self.body),
+            (_, Some(contents)) => client.post(&self.url).multipart(self.multipart_form(contents.clone())),
+            (_, None) => client.post(&self.url).json(&self.body),
+        };
+        for (name, value) in &self.headers {
+            request_builder = request_builder.header(name.as_str(), value.as_str());
+        }
+        let response = runtime::timeout(timeout, request_builder.send()).await
+            .map_err(|_| Error::Timeout(timeout_secs))??;
+        let status = response.status().as_u16();
+        let mut chunks = response.bytes_stream();
+        let streaming = status < 400;
+        let mut body = String::new();
+        let mut pending: Vec<u8> = Vec::new(); // 文字の途中で切れた分のバイト
+        loop {
+            let chunk = match runtime::timeout(timeout, chunks.next()).await {
+                Ok(Some(Ok(chunk))) => chunk,
+                Ok(None) => break,
+                Ok(Some(Err(e))) => return Err(interrupted(body, e.into(), streaming)),
+                Err(_) => return Err(interrupted(body, Error::Timeout(timeout_secs), streaming)),
+            };
+            pending.extend_from_slice(&chunk);
+            let valid = match std::str::from_utf8(&pending) {
+                Ok(_) => pending.len(),
+                Err(e) if e.error_len().is_none() => e.valid_up_to(), // 続きが次の断片に入っている
+                Err(_) => pending.len(), // 壊れたバイトは置き換え文字にする
+            };
+            let mut text = String::from_utf8_lossy(&pending[..valid]).to_string();
+            pending.drain(..valid);
+            let full = self.max_body_bytes.is_some_and(|max| body.len() + text.len() >= max);
+            if let (true, Some(max)) = (full, self.max_body_bytes) {
+                text.truncate(text.floor_char_boundary(max - body.len()));
+                pending.clear(); // 上限より後ろは読まない
+            }
+            if streaming {
+                on_chunk(&text);
+            }
+            body.push_str(&text);
+            if full {
+                break;
+            }
+        }
+        if !pending.is_empty() {
+            let text = String::from_utf8_lossy(&pending).to_string();
+            if streaming {
+                on_chunk(&text);
+            }
+            body.push_str(&text);
+        }
+        Ok(HttpResponse { status, body })
+    }
+
+    // body に書いたフォームの項目とファイルの中身から multipart のフォームを作る
+    fn multipart_form(&self, contents: Vec<u8>) -> reqwest::multipart::Form {
+        let mut form = reqwest::multipart::Form::new();
+        let mut contents = Some(contents);
+        for (name, value) in self.body.as_object().into_iter().flatten() {
+            let value = value.as_str().unwrap_or_default().to_string();
+            if name == "file" {
+                let part = reqwest::multipart::Part::bytes(contents.take().unwrap_or_default());
+                form = form.part("file", part.file_name(value));
+            } else {
+                form = form.text(name.clone(), value);
+            }
+        }
+        form
+    }
+
+    // 秘密情報を伏せた状態のJSONにする（記録用と、middleware の cache のキー）
+    pub fn to_redacted_json(&self) -> Value {
+        let headers: serde_json::Map<String, Value> = self.headers.iter()
+            .map(|(name, value)| (name.clone(), Value::String(redact_header(name, value))))
+            .collect();
+        serde_json::json!({
+            "method": self.method,
+            "url": self.url,
+            "headers": headers,
+            "body": self.body,
+        })
+    }
+}
+
+// 本文が届き始めてから切れたときは、そこまでの本文を Interrupted に入れて返す
+fn interrupted(partial: String, cause: Error, streaming: bool) -> Error {
+    if partial.is_empty() || !streaming {
+        return cause;
+    }
+    Error::Interrupted { partial, cause: cause.to_string() }
+}
+
+// 応答が途切れてからタイムアウトにするまでの秒数（request_timeout_secs。指定がなければデフォルト）
+pub fn timeout_secs(config: &Config) -> u64 {
+    config.request_timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS)
+}
+
+// 1往復分の通信を record_path のファイルに追記する（指定がなければ何もしない）
+fn record(config: &Config, request: &PreparedRequest, result: &Result<HttpResponse, Error>, elapsed_ms: u128) {
+    let Some(path) = &config.record_path else {
+        return;
+    };
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    let response = match result {
+        Ok(response) => serde_json::json!({ "status": response.status, "body": response.body }),
+        Err(e) => serde_json::json!({ "error": e.to_string() }),
+    };
+    let entry = serde_json::json!({
+        "timestamp_ms": timestamp,
+        "elapsed_ms": elapsed_ms,
+        "request": request.to_redacted_json(),
+        "response": response,
+    });
+    let written = OpenOptions::new().create(true).append(true).open(path)
+        .and_then(|mut file| writeln!(file, "{}", entry));
+    if let Err(e) = written {
+        eprintln!("通信記録の書き込みに失敗しました: {:?}", e);
+    }
+}
+
+// 秘密情報を含むヘッダーの値を伏せ字にする（"Bearer xxx" なら "Bearer ****" のように方式名は残す）
+pub fn redact_header(name: &str, value: &str) -> String {
+    if !is_secret_header(name) {
+        return value.to_string();
+    }
+    match value.split_once(' ') {
+        Some((scheme, _)) => format!("{} ****", scheme),
+        None => "****".to_string(),
+    }
+}
+
+// APIキーなどの秘密情報を含むヘッダーかどうか
+fn is_secret_header(name: &str) -> bool {
+    SECRET_HEADERS.contains(&name.to_ascii_lowercase().as_str())
+}
+
+// attempt 回目（0から）の再試行の前に待つ時間（倍にしていき、上限で止める。回数が多くてもあふれない）
+pub fn retry_wait(attempt: u32) -> Duration {
+    let millis = 2u64.checked_pow(attempt)
+        .and_then(|factor| RETRY_BASE_MILLIS.checked_mul(factor))
+        .unwrap_or(MAX_RETRY_WAIT_MILLIS);
+    Duration::from_millis(millis.min(MAX_RETRY_WAIT_MILLIS))
+}
+
+// future を実行して、その中で最後に送ったリクエストも返す
+pub async fn capture<T>(future: impl Future<Output = T>) -> (T, Option<PreparedRequest>) {
+    SENT.scope(RefCell::new(None), async {
+        let output = future.await;
+        (output, SENT.with(|sent| sent.borrow_mut().take()))
+    }).await
+}
+
+// シェルのシングルクォートで囲む（中の ' は '\'' に置き換える）
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_wait_doubles_and_stops_at_the_cap() {
+        assert_eq!(retry_wait(0), Duration::from_millis(1000));
+        assert_eq!(retry_wait(1), Duration::from_millis(2000));
+        assert_eq!(retry_wait(5), Duration::from_millis(32_000));
+        assert_eq!(retry_wait(6), Duration::from_millis(MAX_RETRY_WAIT_MILLIS));
+    }
+
+    #[test]
+    fn retry_wait_does_not_overflow_for_large_attempts() {
+        for attempt in [63, 64, 65, 200, u32::MAX] {
+            assert_eq!(retry_wait(attempt), Duration::from_millis(MAX_RETRY_WAIT_MILLIS));
+        }
+    }
+
+    #[tokio::test]
+    async fn capture_returns_the_request_built_inside_it() {
+        let (_, sent) = capture(async {
+            PreparedRequest::get("http://localhost/first").dry_run();
+            PreparedRequest::get("http://localhost/second").dry_run()
+        }).await;
+        assert_eq!(sent.map(|request| request.url).as_deref(), Some("http://localhost/second"));
+
+        // capture の外で組み立てたものは、どこにも残らない
+        PreparedRequest::get("http://localhost/outside").dry_run();
+        let (_, sent) = capture(async {}).await;
+        assert!(sent.is_none());
+    }
+
+    #[tokio::test]
+    async fn concurrent_captures_do_not_mix() {
+        let capture_one = |url: &'static str| tokio::spawn(capture(async move {
+            PreparedRequest::get(url).dry_run();
+            tokio::task::yield_now().await;
+        }));
+        let (a, b) = (capture_one("http://localhost/a"), capture_one("http://localhost/b"));
+        assert_eq!(a.await.unwrap().1.map(|request| request.url).as_deref(), Some("http://localhost/a"));
+        assert_eq!(b.await.unwrap().1.map(|request| request.url).as_deref(), Some("http://localhost/b"));
+    }
+
+    // TCP でつなぐので、native の機能があるときだけ
+    #[cfg(feature = "native")]
+    #[tokio::test]
+    async fn limit_body_stops_reading_at_the_limit() {
+        use tokio::io::AsyncWriteExt;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let body = "あ".repeat(1000);
+            let head = format!("HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n", body.len());
+            stream.write_all(head.as_bytes()).await.unwrap();
+            let _ = stream.write_all(body.as_bytes()).await;
+        });
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "model_name": "mock", "use_local_model": false, "openai_compatible": false,
+        })).unwrap();
+        let response = PreparedRequest::get(&format!("http://127.0.0.1:{}/", port)).limit_body(100).send(&config).await.unwrap();
+        // 文字の途中では切らない（"あ" は3バイト）
+        assert_eq!(response.body, "あ".repeat(33));
+    }
+}
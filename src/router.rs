@@ -0,0 +1,70 @@
+// プロンプトの言語で、答えるモデルを振り分ける（"language_routes"）
+//
+//   "language_routes": { "ja": "gemma-ja", "en": "gpt", "default": "gpt" }
+//
+// 言語は文字の種類で大まかに判定する（かなが入っていれば ja、ハングルなら ko、漢字だけなら zh、
+// キリル文字なら ru、アラビア文字なら ar、タイ文字なら th、ラテン文字なら en）。
+// 振り分け先はプロファイルの名前か "モデル名@行き先"（または別名）。"fastest" なら、bench で測った中でいちばん速いプロファイル。
+// 判定した言語の振り分け先がなければ "default" を、それもなければ今のモデルのまま答える。
+use crate::{benchmark, profiles, Config};
+
+// 判定した言語ごとの、表示用の名前
+const LANGUAGE_NAMES: [(&str, &str); 7] = [
+    ("ja", "日本語"), ("ko", "韓国語"), ("zh", "中国語"), ("ru", "ロシア語"), ("ar", "アラビア語"), ("th", "タイ語"), ("en", "英語"),
+];
+
+// 文字の種類から言語を判定する（文字がなければ None）
+pub fn detect(text: &str) -> Option<&'static str> {
+    let mut counts = [0usize; 7]; // LANGUAGE_NAMES と同じ順
+    for c in text.chars() {
+        let index = match c as u32 {
+            0x3040..=0x30FF | 0x31F0..=0x31FF | 0xFF66..=0xFF9F => 0, // ひらがな・カタカナ
+            0x1100..=0x11FF | 0x3130..=0x318F | 0xAC00..=0xD7A3 => 1, // ハングル
+            0x4E00..=0x9FFF | 0x3400..=0x4DBF => 2, // 漢字
+            0x0400..=0x04FF => 3,
+            0x0600..=0x06FF => 4,
+            0x0E00..=0x0E7F => 5,
+            _ if c.is_ascii_alphabetic() || matches!(c, 'À'..='ɏ') => 6,
+            _ => continue,
+        };
+        counts[index] += 1;
+    }
+    // 日本語は漢字が多くても、かなが混ざっていれば日本語とみなす
+    if counts[0] > 0 {
+        return Some("ja");
+    }
+    let (index, count) = counts.iter().enumerate().max_by_key(|(_, count)| **count)?;
+    (*count > 0).then_some(LANGUAGE_NAMES[index].0)
+}
+
+// 表示用の言語の名前（判定できなかったときは "default"）
+pub fn language_name(language: &str) -> &str {
+    LANGUAGE_NAMES.iter().find(|(code, _)| *code == language).map(|(_, name)| *name).unwrap_or("判定できない言語")
+}
+
+// 振り分けた結果（判定した言語、振り分け先、そのモデルの設定）
+pub struct Route {
+    pub language: &'static str,
+    pub target: String,
+    pub config: Config,
+}
+
+// このプロンプトを別のモデルに振り分けるなら、その設定を返す（今のモデルのままなら None）
+pub fn route(prompt: &str, config: &Config) -> Option<Route> {
+    if config.language_routes.is_empty() {
+        return None;
+    }
+    let language = detect(prompt).unwrap_or("default");
+    let target = config.language_routes.get(language).or_else(|| config.language_routes.get("default"))?;
+    // まだ測っていなければ、今のモデルのまま答える
+    let target = &match target.as_str() {
+        "fastest" => benchmark::fastest(config)?,
+        _ => target.clone(),
+    };
+    if config.profile.as_ref() == Some(target) || config.model_name == *target {
+        return None;
+    }
+    let mut routed = config.clone();
+    profiles::select(&mut routed, target);
+    Some(Route { language, target: target.clone(), config: routed })
+}
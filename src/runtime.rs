@@ -0,0 +1,112 @@
+// 非同期の実行まわり（待つ・時間を区切る・並行に動かす・子プロセス）を、ビルドする先に合わせて切り替える
+//
+// ふだんは tokio のものをそのまま使う。wasm32 には tokio のランタイムがない（呼ぶ側の wasm-bindgen-futures の上で動く）ので、
+// sleep / timeout はブラウザのタイマーで待ち、spawn は spawn_local にして結果を oneshot で受け取る。
+// wasm32 の reqwest の future は Send でないので、BoxFuture もそちらでは Send を求めない。
+// native の機能を外したときは子プロセスを起動できないので、Command は実行すると Unsupported を返す。
+use std::future::Future;
+use std::pin::Pin;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use tokio::time::{sleep, timeout};
+
+#[cfg(not(target_arch = "wasm32"))]
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+#[cfg(target_arch = "wasm32")]
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
+
+// spawn で動かし始めたタスク（await すると結果が届く。異常終了したら Err）
+#[cfg(not(target_arch = "wasm32"))]
+pub type Task<T> = tokio::task::JoinHandle<T>;
+
+#[cfg(target_arch = "wasm32")]
+pub type Task<T> = tokio::sync::oneshot::Receiver<T>;
+
+// 別のタスクとして動かし始める
+#[cfg(not(target_arch = "wasm32"))]
+pub fn spawn<F>(future: F) -> Task<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    tokio::spawn(future)
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn spawn<F>(future: F) -> Task<F::Output>
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    let (sender, receiver) = tokio::sync::oneshot::channel();
+    wasm_bindgen_futures::spawn_local(async move {
+        let _ = sender.send(future.await);
+    });
+    receiver
+}
+
+#[cfg(target_arch = "wasm32")]
+pub async fn sleep(duration: std::time::Duration) {
+    gloo_timers::future::sleep(duration).await
+}
+
+// timeout の時間内に終わらなかったとき
+#[cfg(target_arch = "wasm32")]
+#[derive(Debug)]
+pub struct Elapsed;
+
+#[cfg(target_arch = "wasm32")]
+pub async fn timeout<F: Future>(duration: std::time::Duration, future: F) -> Result<F::Output, Elapsed> {
+    tokio::select! {
+        output = future => Ok(output),
+        _ = sleep(duration) => Err(Elapsed),
+    }
+}
+
+// このプロセスのID（一時ファイルやIDの名前に使う。wasm32 にはプロセスIDがないので 0）
+pub fn process_id() -> u32 {
+    match cfg!(target_arch = "wasm32") {
+        true => 0,
+        false => std::process::id(),
+    }
+}
+
+#[cfg(feature = "native")]
+pub use tokio::process::Command;
+
+// 子プロセスを使えないときの Command（組み立てはできるが、実行すると Unsupported を返す）
+#[cfg(not(feature = "native"))]
+pub struct Command;
+
+#[cfg(not(feature = "native"))]
+impl Command {
+    pub fn new(_program: impl AsRef<std::ffi::OsStr>) -> Command {
+        Command
+    }
+
+    pub fn arg(&mut self, _arg: impl AsRef<std::ffi::OsStr>) -> &mut Command {
+        self
+    }
+
+    pub fn args<S: AsRef<std::ffi::OsStr>>(&mut self, _args: impl IntoIterator<Item = S>) -> &mut Command {
+        self
+    }
+
+    pub async fn output(&mut self) -> std::io::Result<std::process::Output> {
+        Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "この環境では子プロセスを起動できません"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn timeout_gives_up_on_a_slow_future() {
+        assert!(timeout(Duration::from_millis(10), sleep(Duration::from_secs(5))).await.is_err());
+        assert_eq!(timeout(Duration::from_secs(5), async { 1 }).await.ok(), Some(1));
+        assert_eq!(spawn(async { 2 }).await.ok(), Some(2));
+    }
+}
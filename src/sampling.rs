@@ -0,0 +1,93 @@
+// サンプリングのパラメータ（temperature / top_p / top_k / stop / presence_penalty / seed）
+//
+// 設定したものだけを、接続先ごとの名前でリクエストに入れる（Ollama は "options"、Gemini は "generationConfig" の中など、
+// 入れる場所は呼び出し側で決める）。その接続先にないパラメータは送らない。
+use serde_json::{Map, Value};
+use crate::Config;
+
+// 接続先ごとのパラメータの名前（None はその接続先にないもの）
+pub struct Names {
+    temperature: &'static str,
+    top_p: &'static str,
+    top_k: Option<&'static str>,
+    stop: Option<&'static str>,
+    presence_penalty: Option<&'static str>,
+    seed: Option<&'static str>,
+}
+
+// OpenAI互換のサーバー（vLLM / llama.cpp / Together など。top_k も受け付けるものが多い）と Ollama の options
+pub const OPENAI_COMPATIBLE: Names = Names {
+    temperature: "temperature",
+    top_p: "top_p",
+    top_k: Some("top_k"),
+    stop: Some("stop"),
+    presence_penalty: Some("presence_penalty"),
+    seed: Some("seed"),
+};
+
+// OpenAI 本家（top_k はない）
+pub const OPENAI: Names = Names { top_k: None, ..OPENAI_COMPATIBLE };
+
+// Mistral（seed は random_seed）
+pub const MISTRAL: Names = Names { top_k: None, seed: Some("random_seed"), ..OPENAI_COMPATIBLE };
+
+pub const ANTHROPIC: Names = Names {
+    temperature: "temperature",
+    top_p: "top_p",
+    top_k: Some("top_k"),
+    stop: Some("stop_sequences"),
+    presence_penalty: None,
+    seed: None,
+};
+
+pub const GEMINI: Names = Names {
+    temperature: "temperature",
+    top_p: "topP",
+    top_k: Some("topK"),
+    stop: Some("stopSequences"),
+    presence_penalty: Some("presencePenalty"),
+    seed: Some("seed"),
+};
+
+pub const WATSONX: Names = Names {
+    temperature: "temperature",
+    top_p: "top_p",
+    top_k: Some("top_k"),
+    stop: Some("stop_sequences"),
+    presence_penalty: None,
+    seed: Some("random_seed"),
+};
+
+// Replicate のモデルの input（stop の形はモデルごとに違うので送らない）
+pub const REPLICATE: Names = Names { stop: None, ..OPENAI_COMPATIBLE };
+
+// 設定したパラメータを、その接続先の名前で並べる
+fn params(config: &Config, names: &Names) -> Map<String, Value> {
+    let mut params = Map::new();
+    let mut insert = |name: Option<&str>, value: Option<Value>| {
+        if let (Some(name), Some(value)) = (name, value) {
+            params.insert(name.to_string(), value);
+        }
+    };
+    insert(Some(names.temperature), config.temperature.map(Value::from));
+    insert(Some(names.top_p), config.top_p.map(Value::from));
+    insert(names.top_k, config.top_k.map(Value::from));
+    insert(names.stop, Some(&config.stop).filter(|stop| !stop.is_empty()).map(|stop| serde_json::json!(stop)));
+    insert(names.presence_penalty, config.presence_penalty.map(Value::from));
+    insert(names.seed, config.seed.map(Value::from));
+    params
+}
+
+// body の中の object（なければ作る）にパラメータを足す
+pub fn extend(object: &mut Value, config: &Config, names: &Names) {
+    let params = params(config, names);
+    if params.is_empty() {
+        return;
+    }
+    if !object.is_object() {
+        *object = Value::Object(Map::new());
+    }
+    if let Some(object) = object.as_object_mut() {
+        object.extend(params);
+    }
+}
@@ -0,0 +1,413 @@
+// 会話をセッションとしてファイルに保存し、あとから続きを再開する
+//
+// セッションは sessions_dir（デフォルトは "sessions"）の下の "<セッションID>.jsonl" で、
+// 1行に1メッセージ（{"role": "user", "content": "..."}）を追記していく。
+// --resume <セッションID> か /load <セッションID> で読み込むと、そのファイルに続きを書く。
+//
+// 会話のモデル・system メッセージ・サンプリングのパラメータは {"settings": {...}} の行に残し（変わったときだけ書き足す）、
+// 読み込んだときは最後の行の設定に戻す（今のデフォルトのプロファイルのまま続けてしまわないように）。APIキーは残さない。
+//
+// 添付したファイルはメッセージの "attachments" に名前・パス・ハッシュを残す。テキストは本文に埋め込み済みで、
+// 画像は "attachments/<ハッシュ>" に写しを置き、読み込んだときに読み直して、続きの会話でもう一度送る。
+//
+// "compression": "zstd" にすると、新しいセッションは "<セッションID>.jsonl.zst" にして、追記するたびに zstd のフレームを書き足す。
+// 前からあるセッションは、今のファイルの形のまま続きを書く。読むときはどちらの形でもそのまま読める。
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use crate::{attachments, compression, profiles, runtime, transcript, Config};
+use crate::conversation::Message;
+
+const DEFAULT_SESSIONS_DIR: &str = "sessions";
+
+// 一覧に表示する最初のメッセージの長さ
+const PREVIEW_CHARS: usize = 40;
+
+fn sessions_dir(config: &Config) -> PathBuf {
+    PathBuf::from(config.sessions_dir.as_deref().unwrap_or(DEFAULT_SESSIONS_DIR))
+}
+
+// セッションのファイル（もうあればその形のまま、なければ compression の設定で決める）
+fn session_path(config: &Config, id: &str) -> PathBuf {
+    let plain = sessions_dir(config).join(format!("{}.jsonl", id));
+    let compressed = sessions_dir(config).join(format!("{}.jsonl{}", id, compression::EXTENSION));
+    match compressed.exists() || (!plain.exists() && compression::is_enabled(config)) {
+        true => compressed,
+        false => plain,
+    }
+}
+
+// ファイルの名前からセッションIDを取り出す（セッションのファイルでなければ None）
+fn session_id_of(file_name: &str) -> Option<&str> {
+    let name = file_name.strip_suffix(compression::EXTENSION).unwrap_or(file_name);
+    name.strip_suffix(".jsonl")
+}
+
+// 添付した画像の写しを置くところ（同じ画像はセッションをまたいで1つにまとまる）
+fn attachment_path(config: &Config, hash: &str) -> PathBuf {
+    sessions_dir(config).join("attachments").join(hash)
+}
+
+// セッションを保存するかどうか（dry-run では保存しない）
+pub fn is_enabled(config: &Config) -> bool {
+    config.save_sessions != Some(false) && !config.dry_run
+}
+
+// 新しいセッションのID（起動した時刻とプロセスID）
+pub fn new_id() -> String {
+    let seconds = web_time::SystemTime::now().duration_since(web_time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    format!("{}-{}", seconds, runtime::process_id())
+}
+
+// 会話ごとの設定（保存したときのプロファイル・モデル・行き先・system メッセージ・パラメータ）
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SessionSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    profile: Option<String>, // プロファイルがあれば、まずそのプロファイルにしてから、あとの項目で上書きする
+    model_name: String,
+    #[serde(default)]
+    use_local_model: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    local_framework: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    provider: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    endpoint: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    system_prompt: Option<String>,
+    #[serde(default)]
+    params: SessionParams,
+}
+
+// /set で変えられるパラメータ（指定していないものは書かない）
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct SessionParams {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_k: Option<u32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    stop: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    presence_penalty: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reasoning_effort: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thinking_budget: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_language: Option<String>,
+}
+
+impl SessionSettings {
+    pub fn of(config: &Config) -> SessionSettings {
+        SessionSettings {
+            profile: config.profile.clone(),
+            model_name: config.model_name.clone(),
+            use_local_model: config.use_local_model,
+            local_framework: config.local_framework.clone(),
+            provider: config.provider.clone(),
+            endpoint: config.endpoint.clone(),
+            system_prompt: config.system_prompt.clone(),
+            params: SessionParams {
+                max_tokens: config.max_tokens,
+                temperature: config.temperature,
+                top_p: config.top_p,
+                top_k: config.top_k,
+                stop: config.stop.clone(),
+                presence_penalty: config.presence_penalty,
+                seed: config.seed,
+                reasoning_effort: config.reasoning_effort.clone(),
+                thinking_budget: config.thinking_budget,
+                reply_language: config.reply_language.clone(),
+            },
+        }
+    }
+
+    // 保存したときの設定に戻す（プロファイルがもうなければ、その警告を返す）
+    pub fn restore(self, config: &mut Config) -> Option<String> {
+        let missing = self.profile.as_deref()
+            .filter(|profile| profiles::apply(config, profile).is_err())
+            .map(|profile| format!("保存したときのプロファイル {} がないため、モデルとパラメータだけを戻します", profile));
+        config.profile = self.profile.filter(|_| missing.is_none());
+        config.model_name = self.model_name;
+        config.use_local_model = self.use_local_model;
+        config.local_framework = self.local_framework;
+        config.provider = self.provider;
+        config.endpoint = self.endpoint;
+        config.system_prompt = self.system_prompt;
+        let params = self.params;
+        config.max_tokens = params.max_tokens;
+        config.temperature = params.temperature;
+        config.top_p = params.top_p;
+        config.top_k = params.top_k;
+        config.stop = params.stop;
+        config.presence_penalty = params.presence_penalty;
+        config.seed = params.seed;
+        config.reasoning_effort = params.reasoning_effort;
+        config.thinking_budget = params.thinking_budget;
+        config.reply_language = params.reply_language;
+        missing
+    }
+}
+
+// セッションのファイルの1行（設定の行か、メッセージ）
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Line {
+    Settings { settings: SessionSettings },
+    Message(Message),
+}
+
+// セッションのメッセージと、最後に書いた設定（設定の行がない古いセッションなら None）を読み込む
+pub fn read(config: &Config, id: &str) -> Result<(Vec<Message>, Option<SessionSettings>), String> {
+    let path = session_path(config, id);
+    let data = compression::read_to_string(&path)
+        .map_err(|e| format!("セッション {} を読み込めませんでした（{}）: {:?}", id, path.display(), e))?;
+    let mut messages = Vec::new();
+    let mut settings = None;
+    for line in data.lines().filter(|line| !line.trim().is_empty()) {
+        match serde_json::from_str(line).map_err(|e| format!("セッション {} のパースに失敗しました: {:?}", id, e))? {
+            Line::Settings { settings: saved } => settings = Some(saved),
+            Line::Message(message) => messages.push(message),
+        }
+    }
+    Ok((messages, settings))
+}
+
+// セッションのメッセージを読み込む
+pub fn load(config: &Config, id: &str) -> Result<Vec<Message>, String> {
+    read(config, id).map(|(messages, _)| messages)
+}
+
+// 今のセッションにメッセージを追記する（保存しない設定なら何もしない）
+// 設定が最後に書いたときから変わっていれば（新しいセッションなら必ず）、先に設定の行を書く
+pub fn append(config: &mut Config, messages: &[Message]) {
+    let Some(id) = config.session_id.clone().filter(|_| is_enabled(config)) else {
+        return;
+    };
+    let settings = SessionSettings::of(config);
+    let changed = (config.session_settings.as_ref() != Some(&settings)).then(|| serde_json::json!({ "settings": settings }).to_string());
+    config.session_settings = Some(settings);
+    for file in messages.iter().flat_map(|message| &message.attachments) {
+        if let Err(e) = store_attachment(config, file) {
+            eprintln!("{} の写しの保存に失敗しました: {:?}", file.name, e);
+        }
+    }
+    let lines: String = changed.into_iter()
+        .chain(messages.iter().map(|message| serde_json::to_string(message).unwrap_or_default()))
+        .map(|line| line + "\n")
+        .collect();
+    let path = session_path(config, &id);
+    let written = fs::create_dir_all(sessions_dir(config))
+        .and_then(|_| compression::encode_for(&path, lines.as_bytes()))
+        .and_then(|data| OpenOptions::new().create(true).append(true).open(&path)?.write_all(&data));
+    if let Err(e) = written {
+        eprintln!("セッションの保存に失敗しました: {:?}", e);
+    }
+}
+
+// 画像の写しを置く（もうあれば何もしない。中身は添付したときの base64 のまま）
+fn store_attachment(config: &Config, file: &crate::conversation::AttachedFile) -> std::io::Result<()> {
+    let Some(data) = file.data.as_ref().filter(|_| file.kind == "image") else {
+        return Ok(());
+    };
+    let path = attachment_path(config, &file.hash);
+    if path.exists() {
+        return Ok(());
+    }
+    fs::create_dir_all(path.parent().unwrap_or(Path::new("")))?;
+    fs::write(path, data)
+}
+
+// 読み込んだセッションの画像を読み直す（写しがなければ元のファイルを、中身が同じときだけ使う）
+// 読み直せなかった画像の警告を返す。その画像は送らずに続ける
+pub fn resolve_attachments(config: &Config, messages: &mut [Message]) -> Vec<String> {
+    let mut warnings = Vec::new();
+    for file in messages.iter_mut().flat_map(|message| &mut message.attachments) {
+        if file.kind != "image" {
+            continue;
+        }
+        let stored = fs::read_to_string(attachment_path(config, &file.hash)).ok();
+        let data = stored.or_else(|| attachments::read_image(Path::new(&file.path)))
+            .filter(|data| attachments::content_hash(data) == file.hash);
+        if data.is_none() {
+            warnings.push(format!("添付した画像 {} を読み直せなかったため、送らずに続けます（{}）", file.name, file.path));
+        }
+        file.data = data;
+    }
+    warnings
+}
+
+// 保存済みのセッションをすべて読み込む（新しい順。読めないものは飛ばす）
+pub fn load_all(config: &Config) -> Vec<(String, Vec<Message>)> {
+    let Ok(entries) = fs::read_dir(sessions_dir(config)) else {
+        return Vec::new();
+    };
+    let mut sessions: Vec<(SystemTime, String, Vec<Message>)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let path = entry.path();
+            let id = session_id_of(path.file_name()?.to_str()?)?.to_string();
+            let modified = entry.metadata().and_then(|m| m.modified()).unwrap_or(UNIX_EPOCH);
+            let messages = load(config, &id).ok()?;
+            Some((modified, id, messages))
+        })
+        .collect();
+    sessions.sort_by_key(|(modified, _, _)| std::cmp::Reverse(*modified));
+    sessions.into_iter().map(|(_, id, messages)| (id, messages)).collect()
+}
+
+// 保存済みのセッションの一覧（新しい順。IDと最初のメッセージ）
+pub fn list(config: &Config) -> Vec<(String, String)> {
+    load_all(config).into_iter()
+        .map(|(id, messages)| {
+            let first = messages.into_iter().find(|m| m.role == "user")
+                .map(|m| m.content.lines().next().unwrap_or_default().chars().take(PREVIEW_CHARS).collect())
+                .unwrap_or_default();
+            (id, first)
+        })
+        .collect()
+}
+
+// session サブコマンド（一覧と、Markdown への書き出し）
+pub enum SessionCommand {
+    List,
+    Export { id: String, output: Option<String>, redact: bool }, // output を省略すると "<セッションID>.md"
+}
+
+pub async fn run(command: SessionCommand, config: &Config) -> Result<(), String> {
+    match command {
+        SessionCommand::List => {
+            let sessions = list(config);
+            if sessions.is_empty() {
+                println!("保存されたセッションはありません（{}）", sessions_dir(config).display());
+            }
+            for (id, first) in sessions {
+                println!("{}  {}", id, first);
+            }
+            Ok(())
+        }
+        SessionCommand::Export { id, output, redact } => {
+            let messages = load(config, &id)?;
+            let share_safe = config.share_safe_export || redact;
+            let out_path = output.unwrap_or_else(|| format!("{}.md", id));
+            transcript::export(&out_path, &messages, config, share_safe)?;
+            println!("セッション {} を {} に書き出しました", id, out_path);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn config(name: &str) -> Config {
+        let dir = std::env::temp_dir().join(format!("milti_llm_client-sessions-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut config: Config = serde_json::from_value(serde_json::json!({
+            "model_name": "llama3", "use_local_model": true, "openai_compatible": false,
+            "sessions_dir": dir.to_string_lossy(),
+            "profiles": { "fast": { "model_name": "phi3" } },
+        })).unwrap();
+        config.session_id = Some("s".to_string());
+        config
+    }
+
+    fn message(role: &str, content: &str) -> Message {
+        serde_json::from_value(serde_json::json!({ "role": role, "content": content })).unwrap()
+    }
+
+    #[test]
+    fn settings_are_written_when_they_change_and_restored() {
+        let mut config = config("restore");
+        profiles::apply(&mut config, "fast").unwrap();
+        config.temperature = Some(0.2);
+        config.system_prompt = Some("短く".to_string());
+        append(&mut config, &[message("user", "a"), message("assistant", "b")]);
+        append(&mut config, &[message("user", "c")]);
+        config.temperature = Some(0.9);
+        append(&mut config, &[message("assistant", "d")]);
+        let text = fs::read_to_string(session_path(&config, "s")).unwrap();
+        assert_eq!(text.lines().filter(|line| line.starts_with("{\"settings\"")).count(), 2);
+
+        let (messages, settings) = read(&config, "s").unwrap();
+        assert_eq!(messages.len(), 4);
+        let mut fresh = self::config("fresh");
+        assert_eq!(settings.unwrap().restore(&mut fresh), None);
+        assert_eq!((fresh.profile.as_deref(), fresh.model_name.as_str()), (Some("fast"), "phi3"));
+        assert_eq!((fresh.temperature, fresh.system_prompt.as_deref()), (Some(0.9), Some("短く")));
+        let _ = fs::remove_dir_all(sessions_dir(&config));
+    }
+
+    #[test]
+    fn older_sessions_without_settings_still_load() {
+        let config = config("older");
+        fs::create_dir_all(sessions_dir(&config)).unwrap();
+        fs::write(session_path(&config, "old"), "{\"role\":\"user\",\"content\":\"やあ\"}\n").unwrap();
+        let (messages, settings) = read(&config, "old").unwrap();
+        assert_eq!(messages[0].content, "やあ");
+        assert!(settings.is_none());
+        let _ = fs::remove_dir_all(sessions_dir(&config));
+    }
+
+    #[test]
+    fn timing_is_saved_with_the_answer_and_read_back() {
+        let mut config = config("timing");
+        let timing = crate::Timing { queue: Duration::from_millis(5), prompt: Duration::from_millis(120), generation: Duration::from_millis(900) };
+        let answer = Message { latency_ms: Some(1100), timing: Some(timing), ..message("assistant", "b") };
+        append(&mut config, &[message("user", "a"), answer]);
+        let text = fs::read_to_string(session_path(&config, "s")).unwrap();
+        assert!(text.contains("\"timing\":{\"queue_ms\":5,\"prompt_ms\":120,\"generation_ms\":900}"), "{}", text);
+        let messages = load(&config, "s").unwrap();
+        assert!(messages[0].timing.is_none());
+        let timing = messages[1].timing.unwrap();
+        assert_eq!((messages[1].latency_ms, timing.prompt, timing.generation), (Some(1100), Duration::from_millis(120), Duration::from_millis(900)));
+        let _ = fs::remove_dir_all(sessions_dir(&config));
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn compressed_sessions_are_appended_and_read_back() {
+        let mut config = config("zstd");
+        fs::create_dir_all(sessions_dir(&config)).unwrap();
+        fs::write(session_path(&config, "old"), "{\"role\":\"user\",\"content\":\"前から\"}\n").unwrap();
+        config.compression = Some("zstd".to_string());
+        append(&mut config, &[message("user", "a"), message("assistant", "b")]);
+        append(&mut config, &[message("user", "c")]);
+        let path = session_path(&config, "s");
+        assert!(path.to_string_lossy().ends_with(".jsonl.zst"));
+        assert!(!fs::read(&path).unwrap().starts_with(b"{"));
+        let (messages, settings) = read(&config, "s").unwrap();
+        assert_eq!(messages.iter().map(|m| m.content.as_str()).collect::<Vec<_>>(), ["a", "b", "c"]);
+        assert!(settings.is_some());
+
+        // 圧縮する前のセッションは、そのままの形で続きを書く
+        config.session_id = Some("old".to_string());
+        append(&mut config, &[message("assistant", "続き")]);
+        assert!(session_path(&config, "old").to_string_lossy().ends_with("old.jsonl"));
+        assert_eq!(load(&config, "old").unwrap().len(), 2);
+        let mut ids: Vec<String> = list(&config).into_iter().map(|(id, _)| id).collect();
+        ids.sort();
+        assert_eq!(ids, ["old", "s"]);
+        let _ = fs::remove_dir_all(sessions_dir(&config));
+    }
+
+    #[test]
+    fn a_missing_profile_keeps_the_model_and_warns() {
+        let mut config = config("missing");
+        let settings: SessionSettings = serde_json::from_value(serde_json::json!({ "profile": "gone", "model_name": "qwen" })).unwrap();
+        assert!(settings.restore(&mut config).unwrap().contains("gone"));
+        assert_eq!((config.profile, config.model_name.as_str()), (None, "qwen"));
+    }
+}
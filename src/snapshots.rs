@@ -0,0 +1,168 @@
+// プロンプトの回帰テスト（test サブコマンド）
+//
+//   test prompts.json [--update]
+//
+//   {
+//     "cassette": "prompts.cassette.jsonl",   再生するカセット（spec からの相対パス。省略するとモックのモデルで実行する）
+//     "snapshots": "snapshots",               スナップショットを置くディレクトリ（省略すると "<spec の名前>.snapshots"）
+//     "cases": [
+//       { "name": "greeting", "prompt": "こんにちは", "model": "gemma", "system_prompt": "敬語で答えて" }
+//     ]
+//   }
+//
+// spec は設定ファイルと同じく、拡張子が .toml / .yaml / .yml ならその形式でも書ける。
+// 各ケースの応答を "<name>.txt" のスナップショットと比べ、違っていれば差分を表示して失敗にする。
+// --update なら、違っていたものと、まだないものをスナップショットに書く（カセットがなければ記録する）。
+// 本物のAPIの答えは毎回変わるので、カセットの再生かモックのモデルでしか実行しない。
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use crate::{cassette, config_file, filters, profiles, respond, Config};
+
+#[derive(Deserialize)]
+struct Spec {
+    cassette: Option<String>,
+    snapshots: Option<String>,
+    cases: Vec<Case>,
+}
+
+#[derive(Deserialize)]
+struct Case {
+    name: String, // スナップショットのファイル名になる（英数字と - _ . だけ）
+    prompt: String,
+    model: Option<String>, // プロファイルか "モデル名@行き先"（省略すると今のモデル）
+    system_prompt: Option<String>,
+}
+
+// 1件の結果
+enum Outcome {
+    Passed,
+    Created,
+    Updated,
+    Missing,
+    Changed(String), // 差分
+    Failed(String), // 推論のエラー
+}
+
+pub async fn run(path: &str, update: bool, config: &Config) -> Result<(), String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{} の読み込みに失敗しました: {:?}", path, e))?;
+    let spec: Spec = serde_json::from_value(config_file::parse(path, &text)?)
+        .map_err(|e| format!("{} の読み込みに失敗しました: {}", path, e))?;
+    let base = Path::new(path).parent().unwrap_or(Path::new(""));
+    let snapshot_dir = match &spec.snapshots {
+        Some(dir) => base.join(dir),
+        None => base.join(format!("{}.snapshots", Path::new(path).file_stem().and_then(|stem| stem.to_str()).unwrap_or("prompts"))),
+    };
+    check_names(&spec.cases)?;
+
+    let mut config = config.clone();
+    if let Some(cassette_path) = &spec.cassette {
+        let cassette_path = base.join(cassette_path).to_string_lossy().to_string();
+        if !Path::new(&cassette_path).exists() && !update {
+            return Err(format!("カセット {} がありません（--update で記録します）", cassette_path));
+        }
+        let mode = cassette::parse_mode(None, &cassette_path)?;
+        config.tape = Some(cassette::open(&cassette_path, mode)?);
+        if mode == cassette::CassetteMode::Record {
+            eprintln!("カセット {} に記録します", cassette_path);
+        }
+    }
+
+    let mut failed = 0;
+    for case in &spec.cases {
+        let outcome = run_case(case, &config, &snapshot_dir, update).await?;
+        let (mark, detail) = match &outcome {
+            Outcome::Passed => ("ok", String::new()),
+            Outcome::Created => ("作成", String::new()),
+            Outcome::Updated => ("更新", String::new()),
+            Outcome::Missing => ("失敗", "スナップショットがありません（--update で作ります）".to_string()),
+            Outcome::Changed(diff) => ("失敗", format!("スナップショットと違います\n{}", diff)),
+            Outcome::Failed(error) => ("失敗", error.clone()),
+        };
+        if matches!(outcome, Outcome::Missing | Outcome::Changed(_) | Outcome::Failed(_)) {
+            failed += 1;
+        }
+        println!("{} {}{}", mark, case.name, if detail.is_empty() { String::new() } else { format!(": {}", detail) });
+    }
+    println!("{}件中 {}件成功（スナップショット: {}）", spec.cases.len(), spec.cases.len() - failed, snapshot_dir.display());
+    if failed > 0 {
+        return Err(format!("{}件のテストが失敗しました", failed));
+    }
+    Ok(())
+}
+
+// ファイル名にできて、重ならない名前か
+fn check_names(cases: &[Case]) -> Result<(), String> {
+    for (i, case) in cases.iter().enumerate() {
+        if case.name.is_empty() || !case.name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')) {
+            return Err(format!("ケースの name は英数字と - _ . で書いてください: {:?}", case.name));
+        }
+        if cases[..i].iter().any(|other| other.name == case.name) {
+            return Err(format!("ケースの name が重なっています: {}", case.name));
+        }
+    }
+    Ok(())
+}
+
+async fn run_case(case: &Case, config: &Config, snapshot_dir: &Path, update: bool) -> Result<Outcome, String> {
+    let mut config = config.clone();
+    if let Some(model) = &case.model {
+        profiles::select(&mut config, model);
+    }
+    if let Some(system_prompt) = &case.system_prompt {
+        config.system_prompt = Some(system_prompt.clone());
+    }
+    config.stream = false;
+    let mocked = config.use_local_model && config.local_framework.as_deref() == Some("mock");
+    if !mocked && !cassette::is_active(&config) {
+        return Ok(Outcome::Failed("本物のAPIの答えは毎回変わるため、カセット（spec の \"cassette\"）かモックのモデルで実行してください".to_string()));
+    }
+
+    let completion = respond(&case.prompt, &config).await;
+    if let Some(error) = completion.error {
+        return Ok(Outcome::Failed(error));
+    }
+    let actual = format!("{}\n", filters::apply(&completion.text, &config.output_filters, config.raw).trim_end());
+    let snapshot: PathBuf = snapshot_dir.join(format!("{}.txt", case.name));
+    let expected = fs::read_to_string(&snapshot).ok();
+    let outcome = match &expected {
+        Some(expected) if *expected == actual => return Ok(Outcome::Passed),
+        Some(_) if update => Outcome::Updated,
+        None if update => Outcome::Created,
+        Some(expected) => return Ok(Outcome::Changed(diff(expected, &actual))),
+        None => return Ok(Outcome::Missing),
+    };
+    fs::create_dir_all(snapshot_dir)
+        .and_then(|_| fs::write(&snapshot, &actual))
+        .map_err(|e| format!("{} の書き込みに失敗しました: {:?}", snapshot.display(), e))?;
+    Ok(outcome)
+}
+
+// 行ごとの差分（- がスナップショット、+ が今回の応答）
+fn diff(expected: &str, actual: &str) -> String {
+    let old: Vec<&str> = expected.lines().collect();
+    let new: Vec<&str> = actual.lines().collect();
+    // 後ろから数えた最長共通部分列の長さ
+    let mut lengths = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i][j] = if old[i] == new[j] { lengths[i + 1][j + 1] + 1 } else { lengths[i + 1][j].max(lengths[i][j + 1]) };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut lines = Vec::new();
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push(format!("    {}", old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lengths[i + 1][j] >= lengths[i][j + 1]) {
+            lines.push(format!("\x1b[31m  - {}\x1b[0m", old[i]));
+            i += 1;
+        } else {
+            lines.push(format!("\x1b[32m  + {}\x1b[0m", new[j]));
+            j += 1;
+        }
+    }
+    lines.join("\n")
+}
@@ -0,0 +1,58 @@
+// 速いモデルと強いモデルに同時に送る（投機的な二重送信）
+//
+// 速いモデルの答えが届いたらすぐ表示し、強いモデルの答えが届いたら置き換えるかどうかを聞く。
+// モデルはプロファイルの名前か "モデル名@行き先"（または別名）で書くので、ローカルとオンラインの組み合わせもできる。
+use std::io::{self, Write};
+use std::time::Duration;
+use web_time::Instant;
+use serde::Deserialize;
+use crate::{profiles, reasoning, respond, Config, Streamed};
+use crate::completion::Completion;
+
+#[derive(Clone, Deserialize)]
+pub struct SpeculativeConfig {
+    pub fast: String, // 先に答えを見せる速い（安い）モデル
+    pub strong: String, // 後から届く強いモデル
+}
+
+// 両方に送り、使うことにした答えとその答えが届くまでの時間、答えたモデルの名前を返す
+// 表示済みの答えをそのまま使うときは、呼び出し側で二重に表示しないよう Streamed に印を付ける
+pub async fn respond_speculative(prompt: &str, config: &Config, models: &SpeculativeConfig) -> (Completion, Streamed, Duration, String) {
+    let mut fast_config = config.clone();
+    profiles::select(&mut fast_config, &models.fast);
+    let mut strong_config = config.clone();
+    profiles::select(&mut strong_config, &models.strong);
+
+    let started = Instant::now();
+    let fast = respond(prompt, &fast_config);
+    let strong = respond(prompt, &strong_config);
+    tokio::pin!(fast, strong);
+
+    let (fast, fast_elapsed) = tokio::select! {
+        fast = &mut fast => (fast, started.elapsed()),
+        strong = &mut strong => {
+            // 強いモデルの方が先に届いたら、速いモデルの答えは待たない
+            println!("（{} の答えが先に届きました）", strong_config.model_name);
+            return (strong, Streamed::default(), started.elapsed(), strong_config.model_name.clone());
+        }
+    };
+    let thoughts = fast.reasoning.as_deref()
+        .and_then(|r| reasoning::render(r, config.reasoning_display.as_deref()));
+    if let Some(thoughts) = thoughts {
+        println!("{}", thoughts);
+    }
+    println!("AI（{}）> {}", fast_config.model_name, fast.text);
+    println!("\x1b[2m{} の答えを待っています…（{:.2}秒）\x1b[0m", strong_config.model_name, fast_elapsed.as_secs_f64());
+
+    let strong = strong.await;
+    let strong_elapsed = started.elapsed();
+    print!("{} の答えが届きました（{:.2}秒）。置き換えますか？ [y/N] ", strong_config.model_name, strong_elapsed.as_secs_f64());
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    let _ = io::stdin().read_line(&mut answer);
+    if matches!(answer.trim(), "y" | "Y" | "yes") {
+        (strong, Streamed::default(), strong_elapsed, strong_config.model_name.clone())
+    } else {
+        (fast, Streamed { answer: true, reasoning: true }, fast_elapsed, fast_config.model_name.clone())
+    }
+}
@@ -0,0 +1,330 @@
+// このセッションの統計（/stats で表示する）と、モデルごとの使用量（/usage で表示する）
+//
+// 推定料金は、"prices" にそのモデルの料金があればそれを、なければ prompt_price / completion_price を使う。
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use serde::Deserialize;
+use web_time::Instant;
+use crate::{sessions, Config};
+use crate::error::{Error, ErrorKind};
+use crate::chunking;
+use crate::completion::{Completion, Timing};
+
+// "prices" に書く、モデルごとの1Mトークンあたりの料金（ドル）
+#[derive(Clone, Deserialize)]
+pub struct ModelPrice {
+    #[serde(default)]
+    pub prompt: f64,
+    #[serde(default)]
+    pub completion: f64,
+}
+
+// 1つのモデルの使用量
+#[derive(Clone, Default)]
+struct ModelUsage {
+    exchanges: u64,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+    cached_tokens: u64,
+}
+
+#[derive(Default)]
+pub struct SessionStats {
+    exchanges: u64, // やりとりの回数（ユーザーとAIのメッセージ1組で1回）
+    user_tokens: u64,
+    assistant_tokens: u64,
+    cached_tokens: u64,
+    estimated: bool, // プロバイダーが使用量を返さず、見積もりで数えた分があるかどうか
+    total_latency: Duration,
+    last_prompt_tokens: u64,
+    timing: Option<Timing>, // プロバイダーが返した処理時間の合計
+    timed_latency: Duration, // 処理時間が返ってきたやりとりの、全体の時間の合計
+    timed_exchanges: u32,
+    models: Vec<(String, ModelUsage)>, // モデルごとの使用量（使い始めた順）
+}
+
+// Client ごとの統計（設定を複製しても同じものに数える）
+pub type Tally = Arc<Mutex<SessionStats>>;
+
+// 1回分のやりとりを記録する（使用量がなければトークン数は見積もる）。失敗した回は数えない
+pub fn record(config: &Config, model: &str, prompt: &str, completion: &Completion, latency: Duration) {
+    if completion.error.is_some() {
+        return;
+    }
+    let Ok(mut stats) = config.stats.lock() else {
+        return;
+    };
+    let (prompt_tokens, completion_tokens, cached_tokens) = token_counts(prompt, completion);
+    stats.estimated |= completion.usage.is_none();
+    stats.exchanges += 1;
+    stats.user_tokens += prompt_tokens;
+    stats.assistant_tokens += completion_tokens;
+    stats.cached_tokens += cached_tokens;
+    stats.total_latency += latency;
+    stats.last_prompt_tokens = prompt_tokens;
+    let index = match stats.models.iter().position(|(name, _)| name == model) {
+        Some(index) => index,
+        None => {
+            stats.models.push((model.to_string(), ModelUsage::default()));
+            stats.models.len() - 1
+        }
+    };
+    let usage = &mut stats.models[index].1;
+    usage.exchanges += 1;
+    usage.prompt_tokens += prompt_tokens;
+    usage.completion_tokens += completion_tokens;
+    usage.cached_tokens += cached_tokens;
+    if completion.timing.is_some() {
+        stats.timing = Timing::add(stats.timing, completion.timing);
+        stats.timed_latency += latency;
+        stats.timed_exchanges += 1;
+    }
+}
+
+// (入力, 出力, キャッシュ) のトークン数。使用量がなければ見積もる
+pub fn token_counts(prompt: &str, completion: &Completion) -> (u64, u64, u64) {
+    match completion.usage {
+        Some(usage) => (usage.prompt_tokens, usage.completion_tokens, usage.cached_tokens),
+        None => (chunking::estimate_tokens(prompt) as u64, chunking::estimate_tokens(&completion.text) as u64, 0),
+    }
+}
+
+// 応答の後に表示する、出力トークン数・かかった時間・速度
+pub fn throughput(prompt: &str, completion: &Completion, elapsed: Duration) -> String {
+    let (_, completion_tokens, _) = token_counts(prompt, completion);
+    let seconds = elapsed.as_secs_f64();
+    let per_second = if seconds > 0.0 { completion_tokens as f64 / seconds } else { 0.0 };
+    format!(
+        "\x1b[2m{}{} トークン / {:.2}秒（{:.1} トークン/秒）\x1b[0m",
+        if completion.usage.is_none() { "約" } else { "" }, completion_tokens, seconds, per_second
+    )
+}
+
+// ストリーミング中に表示する、ここまでの出力トークン数と速度（見積もり）
+// 速度は最初の断片が届いてから数える（届くまでの待ち時間を除いた、生成そのものの速さ）
+pub struct LiveThroughput {
+    first_token: Option<Instant>,
+    ascii: u64, // 見積もりは断片ごとではなく、届いた文字をまとめて数える（英数字は4文字で1トークン）
+    others: u64,
+}
+
+impl LiveThroughput {
+    pub fn new() -> Self {
+        LiveThroughput { first_token: None, ascii: 0, others: 0 }
+    }
+
+    // 届いた断片（答えでも考え中の部分でも）を数える
+    pub fn add(&mut self, text: &str) {
+        self.first_token.get_or_insert_with(Instant::now);
+        let ascii = text.chars().filter(|c| c.is_ascii()).count() as u64;
+        self.ascii += ascii;
+        self.others += text.chars().count() as u64 - ascii;
+    }
+
+    pub fn tokens(&self) -> u64 {
+        self.ascii.div_ceil(4) + self.others
+    }
+
+    // 表示する文（まだ何も届いていなければ None）
+    pub fn label(&self) -> Option<String> {
+        let elapsed = self.first_token?.elapsed();
+        Some(self.label_after(elapsed))
+    }
+
+    fn label_after(&self, elapsed: Duration) -> String {
+        let seconds = elapsed.as_secs_f64();
+        match seconds > 0.0 {
+            true => format!("約{} トークン（{:.1} トークン/秒）", self.tokens(), self.tokens() as f64 / seconds),
+            false => format!("約{} トークン", self.tokens()),
+        }
+    }
+}
+
+impl Default for LiveThroughput {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// モデルの料金（"prices" になければ prompt_price / completion_price。どちらもなければ None）
+fn price(config: &Config, model: &str) -> Option<ModelPrice> {
+    config.prices.get(model).cloned().or_else(|| {
+        (config.prompt_price.is_some() || config.completion_price.is_some()).then(|| ModelPrice {
+            prompt: config.prompt_price.unwrap_or(0.0),
+            completion: config.completion_price.unwrap_or(0.0),
+        })
+    })
+}
+
+fn model_cost(config: &Config, model: &str, usage: &ModelUsage) -> Option<f64> {
+    let price = price(config, model)?;
+    Some(usage.prompt_tokens as f64 * price.prompt / 1_000_000.0 + usage.completion_tokens as f64 * price.completion / 1_000_000.0)
+}
+
+// ここまでの推定料金（ドル。どのモデルの料金もわからなければ None）
+fn estimated_cost(stats: &SessionStats, config: &Config) -> Option<f64> {
+    stats.models.iter()
+        .filter_map(|(model, usage)| model_cost(config, model, usage))
+        .reduce(|a, b| a + b)
+}
+
+// セッションの上限（max_session_tokens / max_session_cost）を超えていれば、その説明を返す
+pub fn over_budget(config: &Config) -> Option<String> {
+    let stats = config.stats.lock().ok()?;
+    let tokens = stats.user_tokens + stats.assistant_tokens;
+    if let Some(max) = config.max_session_tokens.filter(|max| tokens >= *max) {
+        return Some(format!("トークン数が上限に達しました（{} / {}）", tokens, max));
+    }
+    let cost = estimated_cost(&stats, config)?;
+    config.max_session_cost
+        .filter(|max| cost >= *max)
+        .map(|max| format!("推定料金が上限に達しました（${:.4} / ${:.4}）", cost, max))
+}
+
+// 上限を超えていたら、ここまでのまとめを付けたエラーにする（呼び出し側は BUDGET_EXCEEDED で終わる）
+pub fn budget_exceeded(config: &Config) -> Option<Error> {
+    let reason = over_budget(config)?;
+    let mut lines = vec![report(config)];
+    if let Some(id) = config.session_id.as_ref().filter(|_| sessions::is_enabled(config)) {
+        lines.push(format!("ここまでの会話はセッション {} に保存してあります（--resume {} で続きから再開できます）", id, id));
+    }
+    lines.push(format!("{}。ここで終わります", reason));
+    Some(Error::Inference { kind: ErrorKind::Budget, message: lines.join("\n") })
+}
+
+// 統計を表示用の文字列にする
+pub fn report(config: &Config) -> String {
+    let Ok(stats) = config.stats.lock() else {
+        return "統計を読めませんでした".to_string();
+    };
+    if stats.exchanges == 0 {
+        return "まだやりとりがありません".to_string();
+    }
+    let mut lines = vec![
+        format!("メッセージ数: {}（ユーザー {} / AI {}）", stats.exchanges * 2, stats.exchanges, stats.exchanges),
+        format!(
+            "トークン数: ユーザー {} / AI {}{}",
+            stats.user_tokens, stats.assistant_tokens, if stats.estimated { "（一部は見積もり）" } else { "" }
+        ),
+    ];
+    if stats.cached_tokens > 0 {
+        lines.push(format!("キャッシュから読まれたトークン: {}", stats.cached_tokens));
+    }
+    match estimated_cost(&stats, config) {
+        None => lines.push("推定料金: 不明（\"prompt_price\" と \"completion_price\" を設定すると表示します）".to_string()),
+        Some(cost) => lines.push(format!("推定料金: ${:.4}", cost)),
+    }
+    let average = stats.total_latency / stats.exchanges as u32;
+    lines.push(format!("平均応答時間: {:.2}秒", average.as_secs_f64()));
+    if let Some(timing) = stats.timing {
+        let count = stats.timed_exchanges;
+        let average = Timing {
+            queue: timing.queue / count,
+            prompt: timing.prompt / count,
+            generation: timing.generation / count,
+        };
+        lines.push(format!("平均の{}", average.breakdown(stats.timed_latency / count)));
+    }
+    match config.context_window {
+        Some(window) if window > 0 => lines.push(format!(
+            "コンテキスト使用率: {}/{} トークン（{}%、直前のプロンプト）",
+            stats.last_prompt_tokens, window, stats.last_prompt_tokens * 100 / window as u64
+        )),
+        _ => lines.push("コンテキスト使用率: 不明（\"context_window\" を設定すると表示します）".to_string()),
+    }
+    lines.join("\n")
+}
+
+// /usage で表示する、モデルごとの使用量と推定料金
+pub fn usage_report(config: &Config) -> String {
+    let Ok(stats) = config.stats.lock() else {
+        return "使用量を読めませんでした".to_string();
+    };
+    if stats.models.is_empty() {
+        return "まだやりとりがありません".to_string();
+    }
+    let line = |name: &str, usage: &ModelUsage, cost: Option<f64>| format!(
+        "{}: {}回 / 入力 {} / 出力 {}{} / 推定料金 {}",
+        name, usage.exchanges, usage.prompt_tokens, usage.completion_tokens,
+        if usage.cached_tokens > 0 { format!("（キャッシュ {}）", usage.cached_tokens) } else { String::new() },
+        cost.map(|cost| format!("${:.4}", cost)).unwrap_or_else(|| "不明".to_string()),
+    );
+    let mut lines = Vec::new();
+    let mut total = ModelUsage::default();
+    for (model, usage) in &stats.models {
+        lines.push(line(model, usage, model_cost(config, model, usage)));
+        total.exchanges += usage.exchanges;
+        total.prompt_tokens += usage.prompt_tokens;
+        total.completion_tokens += usage.completion_tokens;
+        total.cached_tokens += usage.cached_tokens;
+    }
+    if stats.models.len() > 1 {
+        lines.push(line("合計", &total, estimated_cost(&stats, config)));
+        if stats.models.iter().any(|(model, _)| price(config, model).is_none()) {
+            lines.push("（料金がわからないモデルの分は、合計の推定料金に含みません）".to_string());
+        }
+    }
+    if stats.estimated {
+        lines.push("（プロバイダーが使用量を返さなかった分は、見積もりのトークン数です）".to_string());
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        serde_json::from_value(serde_json::json!({ "model_name": "llama3", "use_local_model": true, "openai_compatible": false })).unwrap()
+    }
+
+    fn recorded(config: &Config, model: &str) -> bool {
+        config.stats.lock().unwrap().models.iter().any(|(name, _)| name == model)
+    }
+
+    #[test]
+    fn live_throughput_counts_the_pieces_together() {
+        let mut live = LiveThroughput::new();
+        assert!(live.label().is_none());
+        // 英数字は断片ごとに切り上げず、まとめて数える
+        for piece in ["He", "ll", "o ", "wo", "rld", "！"] {
+            live.add(piece);
+        }
+        assert_eq!(live.tokens(), chunking::estimate_tokens("Hello world！") as u64);
+        assert_eq!(live.label_after(Duration::from_secs(2)), "約4 トークン（2.0 トークン/秒）");
+        assert_eq!(live.label_after(Duration::ZERO), "約4 トークン");
+    }
+
+    #[test]
+    fn failed_exchanges_are_not_counted() {
+        let config = config();
+        let failed = Completion::failed("オンライン推論エラー: HTTP 500".to_string());
+        record(&config, "stats-test-failed", "やあ", &failed, Duration::from_secs(1));
+        assert!(!recorded(&config, "stats-test-failed"));
+
+        let answered = Completion { text: "こんにちは".to_string(), ..Completion::default() };
+        record(&config, "stats-test-answered", "やあ", &answered, Duration::from_secs(1));
+        assert!(recorded(&config, "stats-test-answered"));
+    }
+
+    #[test]
+    fn each_config_counts_its_own_exchanges() {
+        let (first, second) = (config(), config());
+        let answered = Completion { text: "こんにちは".to_string(), ..Completion::default() };
+        record(&first, "llama3", "やあ", &answered, Duration::from_secs(1));
+        record(&first.clone(), "llama3", "やあ", &answered, Duration::from_secs(1));
+        assert_eq!(first.stats.lock().unwrap().exchanges, 2);
+        assert_eq!(second.stats.lock().unwrap().exchanges, 0);
+    }
+
+    #[test]
+    fn exceeding_the_token_budget_is_a_budget_error() {
+        let config = Config { max_session_tokens: Some(10), ..config() };
+        assert!(budget_exceeded(&config).is_none());
+        let answered = Completion { usage: Some(crate::Usage { prompt_tokens: 8, completion_tokens: 4, cached_tokens: 0 }), ..Completion::default() };
+        record(&config, "llama3", "やあ", &answered, Duration::from_secs(1));
+        let error = budget_exceeded(&config).unwrap();
+        assert_eq!(error.kind().exit_code(), crate::exit_code::BUDGET_EXCEEDED);
+        assert!(error.to_string().ends_with("トークン数が上限に達しました（12 / 10）。ここで終わります"));
+    }
+}
@@ -0,0 +1,241 @@
+// ストリーミングで届いたトークンを、表示する側（main）に渡す
+//
+// 推論の関数は今までどおり最後に Completion 全体を返し、届いた分はその途中でここに流す。
+// 表示する側は推論ごとに channel で口（Sink）と受け取り側を作り、口を Config の events に入れて推論に渡す。
+// 推論を待つあいだ受け取り側から読んで、少しずつ表示する（推論が終わって口がなくなると None が返る）。
+// トークンのほかに、ツールの呼び出しなども同じ口からイベント（events.rs）として流す。
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use serde_json::Value;
+use crate::completion::{choice_reasoning, choice_text};
+use crate::error::Error;
+use crate::events::Event;
+use crate::request::HttpResponse;
+use crate::Config;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+// 届いたトークン
+pub enum Token {
+    Answer(String), // 答えの本文
+    Reasoning(String), // 考え中の部分
+}
+
+// 1回の推論のイベントの送り口
+#[derive(Clone)]
+pub struct Sink {
+    sender: UnboundedSender<Event>,
+    emitted: Arc<AtomicUsize>, // これまでに渡したトークンの数（推論の途中で表示が始まったかを調べる）
+}
+
+// 送り口と受け取り側を作る
+pub fn channel() -> (Sink, UnboundedReceiver<Event>) {
+    let (sender, receiver) = unbounded_channel();
+    (Sink { sender, emitted: Arc::new(AtomicUsize::new(0)) }, receiver)
+}
+
+// 表示する側にトークンを渡す（送り口のない推論では何もしない。分割処理の途中の要約などは、口を外して推論する）
+pub fn emit(config: &Config, token: Token) {
+    emit_event(config, Event::TokenDelta(token));
+}
+
+pub fn emit_event(config: &Config, event: Event) {
+    if let Some(sink) = &config.events {
+        if matches!(event, Event::TokenDelta(_)) {
+            sink.emitted.fetch_add(1, Ordering::Relaxed);
+        }
+        let _ = sink.sender.send(event);
+    }
+}
+
+pub fn emitted(config: &Config) -> usize {
+    config.events.as_ref().map_or(0, |sink| sink.emitted.load(Ordering::Relaxed))
+}
+
+// 届いた断片を行に区切る（行の途中で切れた分は、次の断片とつなげてから返す）
+#[derive(Default)]
+pub struct LineBuffer {
+    pending: String,
+}
+
+impl LineBuffer {
+    pub fn push(&mut self, chunk: &str) -> Vec<String> {
+        self.pending.push_str(chunk);
+        let mut lines = Vec::new();
+        while let Some(end) = self.pending.find('\n') {
+            lines.push(self.pending[..end].trim_end_matches('\r').to_string());
+            self.pending.drain(..=end);
+        }
+        lines
+    }
+}
+
+// ストリーミングの途中で接続が切れたときは、そこまでに届いた本文をレスポンスとして読めるようにする
+// （切れていたら true も返す。ストリーミングしていないときは、途中までのJSONは読めないのでエラーのまま）
+pub fn recover_partial(result: Result<HttpResponse, Error>, streaming: bool) -> (Result<HttpResponse, Error>, bool) {
+    match result {
+        Err(Error::Interrupted { partial, .. }) if streaming => (Ok(HttpResponse { status: 200, body: partial }), true),
+        result => (result, false),
+    }
+}
+
+// SSE の終わりの印（data: [DONE]）まで届いたか
+pub fn sse_finished(body: &str) -> bool {
+    body.lines().any(|line| line.strip_prefix("data:").is_some_and(|data| data.trim() == "[DONE]"))
+}
+
+// SSE の "data: {...}" 行のJSON（終わりの印の [DONE] やそれ以外の行は None）
+pub fn sse_data(line: &str) -> Option<Value> {
+    let data = line.strip_prefix("data:")?.trim();
+    if data == "[DONE]" {
+        return None;
+    }
+    serde_json::from_str(data).ok()
+}
+
+// OpenAI互換の SSE のイベント1つから、届いた分の考え中の部分と答えを表示側に渡す
+pub fn emit_choice(config: &Config, event: &Value) {
+    let Some(choice) = event.pointer("/choices/0") else {
+        return;
+    };
+    if let Some(thought) = choice_reasoning(choice).filter(|t| !t.is_empty()) {
+        emit(config, Token::Reasoning(thought.to_string()));
+    }
+    if let Some(token) = choice_text(choice).filter(|t| !t.is_empty()) {
+        emit(config, Token::Answer(token.to_string()));
+    }
+}
+
+// OpenAI互換の補完API（チャットも含む）の SSE を、ストリーミングしないときと同じ形のJSONにまとめる
+// （エラーのときは SSE ではなく普通のJSONが返ってくるので、そのまま読む）
+pub fn collect_sse(body: &str) -> Value {
+    let events: Vec<Value> = body.lines().filter_map(sse_data).collect();
+    if events.is_empty() {
+        return serde_json::from_str(body).unwrap_or_default();
+    }
+    collect_events(&events)
+}
+
+// SSE の data 行のJSONを順にまとめる
+pub fn collect_events(events: &[Value]) -> Value {
+    let mut text = String::new();
+    let mut reasoning = String::new();
+    let mut finish_reason = Value::Null;
+    let mut usage = Value::Null;
+    let mut timings = Value::Null;
+    let mut tool_calls: Vec<Value> = Vec::new();
+    for event in events {
+        if let Some(choice) = event.pointer("/choices/0") {
+            text.push_str(choice_text(choice).unwrap_or_default());
+            // ツールの呼び出しは index ごとに、arguments の文字列が少しずつ届く
+            for delta in choice.pointer("/delta/tool_calls").and_then(|calls| calls.as_array()).into_iter().flatten() {
+                let index = delta.get("index").and_then(|index| index.as_u64()).unwrap_or(0) as usize;
+                while tool_calls.len() <= index {
+                    tool_calls.push(serde_json::json!({ "type": "function", "function": { "name": "", "arguments": "" } }));
+                }
+                let call = &mut tool_calls[index];
+                if let Some(id) = delta.get("id").filter(|id| !id.is_null()) {
+                    call["id"] = id.clone();
+                }
+                for key in ["name", "arguments"] {
+                    if let Some(part) = delta.pointer(&format!("/function/{}", key)).and_then(|part| part.as_str()) {
+                        let joined = format!("{}{}", call["function"][key].as_str().unwrap_or_default(), part);
+                        call["function"][key] = Value::String(joined);
+                    }
+                }
+            }
+            reasoning.push_str(choice_reasoning(choice).unwrap_or_default());
+            if let Some(reason) = choice.get("finish_reason").filter(|r| !r.is_null()) {
+                finish_reason = reason.clone();
+            }
+        }
+        // 使用量や処理時間は最後のイベントに入ってくる
+        if let Some(value) = event.get("usage").filter(|u| !u.is_null()) {
+            usage = value.clone();
+        }
+        if let Some(value) = event.get("timings").filter(|t| !t.is_null()) {
+            timings = value.clone();
+        }
+    }
+    let reasoning = if reasoning.is_empty() { Value::Null } else { Value::String(reasoning) };
+    serde_json::json!({
+        "choices": [{
+            "text": text,
+            "finish_reason": finish_reason,
+            "reasoning_content": reasoning,
+            "message": { "tool_calls": tool_calls },
+        }],
+        "usage": usage,
+        "timings": timings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        serde_json::from_value(serde_json::json!({
+            "model_name": "llama3", "use_local_model": true, "openai_compatible": false,
+        })).unwrap()
+    }
+
+    #[test]
+    fn each_sink_counts_only_its_own_tokens() {
+        let (sink, mut events) = channel();
+        let listening = Config { events: Some(sink), ..config() };
+        emit(&listening, Token::Answer("a".to_string()));
+        emit_event(&listening, Event::Error("e".to_string()));
+        emit(&config(), Token::Answer("b".to_string()));
+        assert_eq!((emitted(&listening), emitted(&config())), (1, 0));
+        assert!(matches!(events.try_recv(), Ok(Event::TokenDelta(Token::Answer(text))) if text == "a"));
+        assert!(matches!(events.try_recv(), Ok(Event::Error(_))));
+        assert!(events.try_recv().is_err());
+    }
+
+    fn sse(events: &[Value]) -> String {
+        events.iter().map(|event| format!("data: {}\n\n", event)).collect::<String>() + "data: [DONE]\n\n"
+    }
+
+    #[test]
+    fn assembles_tool_calls_from_deltas() {
+        let body = sse(&[
+            serde_json::json!({ "choices": [{ "delta": { "role": "assistant", "content": "調べます" } }] }),
+            serde_json::json!({ "choices": [{ "delta": { "tool_calls": [{ "index": 0, "id": "call_a", "type": "function", "function": { "name": "read_", "arguments": "" } }] } }] }),
+            serde_json::json!({ "choices": [{ "delta": { "tool_calls": [{ "index": 0, "function": { "name": "file", "arguments": "{\"pa" } }] } }] }),
+            serde_json::json!({ "choices": [{ "delta": { "tool_calls": [{ "index": 1, "id": "call_b", "function": { "name": "shell", "arguments": "{\"command\":" } }] } }] }),
+            serde_json::json!({ "choices": [{ "delta": { "tool_calls": [{ "index": 0, "function": { "arguments": "th\":\"a.txt\"}" } }] } }] }),
+            serde_json::json!({ "choices": [{ "delta": { "tool_calls": [{ "index": 1, "id": null, "function": { "arguments": "\"ls\"}" } }] } }] }),
+            serde_json::json!({ "choices": [{ "delta": {}, "finish_reason": "tool_calls" }] }),
+            serde_json::json!({ "choices": [], "usage": { "prompt_tokens": 20, "completion_tokens": 10 } }),
+        ]);
+        let json = collect_sse(&body);
+        let choice = &json["choices"][0];
+        assert_eq!(choice["text"], "調べます");
+        assert_eq!(choice["finish_reason"], "tool_calls");
+        assert_eq!(choice["message"]["tool_calls"], serde_json::json!([
+            { "id": "call_a", "type": "function", "function": { "name": "read_file", "arguments": "{\"path\":\"a.txt\"}" } },
+            { "id": "call_b", "type": "function", "function": { "name": "shell", "arguments": "{\"command\":\"ls\"}" } },
+        ]));
+        assert_eq!(json["usage"]["prompt_tokens"], 20);
+    }
+
+    #[test]
+    fn joins_text_and_reasoning() {
+        let body = sse(&[
+            serde_json::json!({ "choices": [{ "delta": { "reasoning_content": "考え" } }] }),
+            serde_json::json!({ "choices": [{ "delta": { "content": "こんに" } }] }),
+            serde_json::json!({ "choices": [{ "delta": { "content": "ちは" }, "finish_reason": "stop" }] }),
+        ]);
+        let json = collect_sse(&body);
+        assert_eq!(json["choices"][0]["text"], "こんにちは");
+        assert_eq!(json["choices"][0]["reasoning_content"], "考え");
+        assert_eq!(json["choices"][0]["message"]["tool_calls"], serde_json::json!([]));
+        assert!(sse_finished(&body));
+    }
+
+    #[test]
+    fn an_error_body_is_read_as_json() {
+        let json = collect_sse(r#"{"error": {"message": "bad"}}"#);
+        assert_eq!(json["error"]["message"], "bad");
+    }
+}
@@ -0,0 +1,77 @@
+// よく使う会話の型（new --template <名前>）
+//
+//   "templates": {
+//     "code-review": {
+//       "description": "差分のレビュー",
+//       "model": "gpt",
+//       "system_prompt": "あなたは厳しめのコードレビュアーです",
+//       "files": ["CONTRIBUTING.md"],
+//       "tools": ["read_file", "shell"],
+//       "first_message": "git diff の内容をレビューしてください"
+//     }
+//   }
+//
+// model はプロファイルか "モデル名@行き先"（または別名）。files のテキストは system メッセージの後ろに付けて、
+// 会話のあいだずっと送る。tools を書いたら、そのツールだけを使う。first_message は始めてすぐに送る。
+// テンプレートで始めた会話はいつもどおり新しいセッションとして保存する。
+use serde::Deserialize;
+use crate::attachments::{self, Attachment};
+use crate::tools::ToolSpec;
+use crate::{profiles, tools, Config};
+
+#[derive(Clone, Deserialize)]
+pub struct ConversationTemplate {
+    pub description: Option<String>,
+    pub model: Option<String>,
+    pub system_prompt: Option<String>,
+    #[serde(default)]
+    pub files: Vec<String>, // ずっと送るテキストのファイル（起動したディレクトリからの相対パス）
+    pub tools: Option<Vec<ToolSpec>>,
+    pub first_message: Option<String>,
+}
+
+// テンプレートの一覧（名前と説明）
+pub fn list(config: &Config) -> Vec<String> {
+    let mut names: Vec<&String> = config.templates.keys().collect();
+    names.sort();
+    names.into_iter()
+        .map(|name| match &config.templates[name].description {
+            Some(description) => format!("{}  {}", name, description),
+            None => name.clone(),
+        })
+        .collect()
+}
+
+// テンプレートの設定で会話を始められるようにして、最初に送るメッセージを返す
+pub async fn apply(config: &mut Config, name: &str) -> Result<Option<String>, String> {
+    let template = config.templates.get(name).cloned().ok_or_else(|| {
+        let names = list(config);
+        match names.is_empty() {
+            true => format!("テンプレート {} がありません（config.json の templates に書いてください）", name),
+            false => format!("テンプレート {} がありません:\n  {}", name, names.join("\n  ")),
+        }
+    })?;
+    if let Some(model) = &template.model {
+        profiles::select(config, model);
+    }
+    let mut pinned = Vec::new();
+    for path in &template.files {
+        match attachments::load(path, config).await? {
+            Attachment::Text { name, content } => pinned.push(attachments::inline_text(&name, &content)),
+            Attachment::Image { name, .. } => return Err(format!("テンプレートの files にはテキストのファイルを書いてください（{} は画像です）", name)),
+        }
+    }
+    let system = template.system_prompt.or(config.system_prompt.take());
+    config.system_prompt = match (system, pinned.is_empty()) {
+        (system, true) => system,
+        (Some(system), false) => Some(format!("{}\n\n{}", system, pinned.join("\n\n"))),
+        (None, false) => Some(pinned.join("\n\n")),
+    };
+    if let Some(specs) = template.tools {
+        config.tools = specs;
+        tools::check(config)?;
+    }
+    config.chat = true;
+    config.template = Some(name.to_string());
+    Ok(template.first_message)
+}
@@ -0,0 +1,647 @@
+// モデルから呼び出せるツール（OpenAI の tools / function calling）
+//
+//   "tools": ["shell", "read_file", "http_get"]                  組み込みのツール
+//   "tools": [{ "name": "weather", "description": "天気を調べる",
+//               "parameters": { "type": "object", "properties": { "city": { "type": "string" } } },
+//               "command": "./weather.sh" }]                    自分で書いたツール（引数のJSONを標準入力に渡す）
+//
+// tools_file（デフォルトは "tools.json"。あれば）にも同じ形の配列を書ける。
+// tools.json はカレントディレクトリにあれば確認せずに読むので、読んだときは標準エラーにツールの名前を出す。
+// チャット形式のリクエストに tools を付け、応答に tool_calls があればツールを実行して結果を "tool" のメッセージで返し、
+// モデルが最後の答えを出すまで（tool_max_rounds 回まで）繰り返す。途中のやりとりは履歴には残さない。
+// 1回の応答に tool_calls が複数あれば、tool_concurrency 個まで同時に実行し、結果は呼び出された順に返す。
+// tool_output_tokens を超える結果は最初のページだけを返して全体を取っておき、続きは read_tool_output で読んでもらう。
+// shell と自分で書いたツールは、"tool_confirm": false でなければ実行する前に確認する。
+// 確認は Client::set_tool_confirmation で渡した関数（TUI は画面で聞く）に頼み、なければ端末で聞く（端末でなければ実行しない）。
+// read_file はカレントディレクトリの中のファイル、http_get は tool_allowed_hosts のホストなら確認せずに使い、それ以外は同じように確認する。
+//
+// 組み込みのツールは、引数の構造体（Deserialize と JsonSchema を付け、フィールドの /// が引数の説明になる）と
+// それを受け取る async fn を書いて、builtin! で BUILTINS に並べれば足せる。
+// parameters の JSON Schema はその構造体から作り、届いた引数もその型に読んでから関数に渡す。
+use std::io::{self, IsTerminal};
+#[cfg(feature = "native")]
+use std::io::Write;
+use std::path::Path;
+#[cfg(feature = "native")]
+use std::process::Stdio;
+use std::sync::Arc;
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Value;
+#[cfg(feature = "native")]
+use tokio::io::AsyncWriteExt;
+#[cfg(feature = "native")]
+use tokio::process::Command;
+use tokio::sync::{oneshot, Mutex, Semaphore};
+use crate::completion::{Completion, Usage};
+use crate::error::Error;
+use crate::events::Event;
+use crate::request::PreparedRequest;
+use crate::{chunking, runtime, stream};
+use crate::Config;
+
+const DEFAULT_TOOLS_FILE: &str = "tools.json";
+
+// 最後の答えが出るまでにツールを呼び出してよい回数のデフォルト
+const DEFAULT_MAX_ROUNDS: usize = 5;
+
+// 同時に実行するツールの数のデフォルト
+const DEFAULT_CONCURRENCY: usize = 4;
+
+// ツールの結果として1回に返すトークン数（見積もり）のデフォルト（これより長い結果はページに分ける）
+const DEFAULT_OUTPUT_TOKENS: u32 = 2000;
+
+// 長い結果の続きを読むツール（結果を切ったときだけリクエストに付ける）
+const PAGE_TOOL: &str = "read_tool_output";
+
+// http_get で受け取る本文の上限（バイト）
+const HTTP_GET_MAX_BYTES: usize = 1024 * 1024;
+
+type ToolFuture<'a> = runtime::BoxFuture<'a, Result<String, String>>;
+
+// 組み込みのツール（名前と説明、引数の JSON Schema、実行する関数）
+struct Builtin {
+    name: &'static str,
+    description: &'static str,
+    parameters: fn() -> Value,
+    run: fn(&Config, Value) -> ToolFuture<'_>,
+}
+
+// builtin!("名前", "説明", 関数(引数の型)) で、引数の型から parameters を作り、引数をその型に読んで関数を呼ぶ Builtin にする
+macro_rules! builtin {
+    ($name:literal, $description:literal, $function:ident($arguments:ty)) => {{
+        fn run(config: &Config, arguments: Value) -> ToolFuture<'_> {
+            Box::pin(async move { $function(config, parse_arguments::<$arguments>(arguments)?).await })
+        }
+        Builtin { name: $name, description: $description, parameters: parameters::<$arguments>, run }
+    }};
+}
+
+static BUILTINS: [Builtin; 3] = [
+    builtin!("shell", "シェルのコマンドを実行して、標準出力と標準エラーと終了コードを返す", shell(ShellArguments)),
+    builtin!("read_file", "テキストファイルの内容を読む", read_file(ReadFileArguments)),
+    builtin!("http_get", "URL に GET でアクセスして、ステータスと本文を返す", http_get(HttpGetArguments)),
+];
+
+fn builtin(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|builtin| builtin.name == name)
+}
+
+// 引数の型の JSON Schema（ルートの "$schema" と "title" は送らない）
+fn parameters<T: JsonSchema>() -> Value {
+    let mut schema = schemars::schema_for!(T).to_value();
+    if let Some(schema) = schema.as_object_mut() {
+        schema.remove("$schema");
+        schema.remove("title");
+    }
+    schema
+}
+
+fn parse_arguments<T: DeserializeOwned>(arguments: Value) -> Result<T, String> {
+    serde_json::from_value(arguments).map_err(|e| format!("引数が不正です: {}", e))
+}
+
+// 設定に書くツール（組み込みの名前か、コマンドで動くツールの定義）
+#[derive(Clone, Deserialize)]
+#[serde(untagged)]
+pub enum ToolSpec {
+    Builtin(String),
+    Command {
+        name: String,
+        #[serde(default)]
+        description: String,
+        #[serde(default = "empty_parameters")]
+        parameters: Value, // 引数の JSON Schema
+        command: String, // sh -c で実行するコマンド
+    },
+}
+
+fn empty_parameters() -> Value {
+    serde_json::json!({ "type": "object", "properties": {} })
+}
+
+impl ToolSpec {
+    fn name(&self) -> &str {
+        match self {
+            ToolSpec::Builtin(name) => name,
+            ToolSpec::Command { name, .. } => name,
+        }
+    }
+
+    // リクエストの "tools" に入れる形
+    fn definition(&self) -> Value {
+        let (name, description, parameters) = match self {
+            // read_tool_output は取っておいた結果を読むので、BUILTINS ではなく call_all で実行する
+            ToolSpec::Builtin(name) if name == PAGE_TOOL => {
+                (PAGE_TOOL, "長いため途中で切ったツールの結果の、続きのページを読む", parameters::<PageArguments>())
+            }
+            // 名前は check で確かめてある
+            ToolSpec::Builtin(name) => match builtin(name) {
+                Some(builtin) => (builtin.name, builtin.description, (builtin.parameters)()),
+                None => (name.as_str(), "", empty_parameters()),
+            },
+            ToolSpec::Command { name, description, parameters, .. } => (name.as_str(), description.as_str(), parameters.clone()),
+        };
+        serde_json::json!({
+            "type": "function",
+            "function": { "name": name, "description": description, "parameters": parameters },
+        })
+    }
+}
+
+// tools_file の内容を設定のツールに足す（ファイルを指定していなくて tools.json もなければ何もしない）
+pub fn load_file(config: &mut Config) -> Result<(), Error> {
+    let path = config.tools_file.clone().unwrap_or_else(|| DEFAULT_TOOLS_FILE.to_string());
+    let json = match std::fs::read_to_string(&path) {
+        Ok(json) => json,
+        Err(_) if config.tools_file.is_none() => return Ok(()),
+        Err(e) => return Err(Error::Config(format!("{} の読み込みに失敗しました: {:?}", path, e))),
+    };
+    let specs: Vec<ToolSpec> = serde_json::from_str(&json)
+        .map_err(|e| Error::Config(format!("{} のパースに失敗しました: {}", path, e)))?;
+    if config.tools_file.is_none() && !specs.is_empty() {
+        let names: Vec<&str> = specs.iter().map(ToolSpec::name).collect();
+        eprintln!("カレントディレクトリの {} からツールを読み込みました: {}", path, names.join(", "));
+    }
+    config.tools.extend(specs);
+    check(config).map_err(Error::Config)
+}
+
+// 組み込みにない名前を書いていないか調べる
+pub fn check(config: &Config) -> Result<(), String> {
+    for spec in &config.tools {
+        if let ToolSpec::Builtin(name) = spec {
+            if !BUILTINS.iter().any(|builtin| builtin.name == name) {
+                let names: Vec<&str> = BUILTINS.iter().map(|builtin| builtin.name).collect();
+                return Err(format!("不明なツールです: {}（{} のどれかか、command つきの定義を書いてください）", name, names.join(" / ")));
+            }
+        }
+    }
+    Ok(())
+}
+
+// チャット形式のリクエストの本文に tools を付ける（ツールがなければ何もしない）
+pub fn extend(body: &mut Value, config: &Config) {
+    if !config.tools.is_empty() {
+        body["tools"] = Value::Array(definitions(config));
+    }
+}
+
+// ツールの定義（OpenAI の形。形の違うAPIのアダプターは、ここから作り直す）
+pub fn definitions(config: &Config) -> Vec<Value> {
+    config.tools.iter().map(ToolSpec::definition).collect()
+}
+
+// ツールを実行してよいかの問い合わせ（answer で答える。答えずに捨てたら断ったことになる）
+pub struct ToolConfirmation {
+    pub tool: String,
+    pub detail: String, // 実行するコマンドや、読むファイル・開く URL
+    reply: oneshot::Sender<bool>,
+}
+
+impl ToolConfirmation {
+    pub fn answer(self, allowed: bool) {
+        let _ = self.reply.send(allowed);
+    }
+}
+
+// 問い合わせを受け取る関数（Config の tool_confirmation に入れる。答えは別のタスクやスレッドから返してよい）
+pub type Confirm = Arc<dyn Fn(ToolConfirmation) + Send + Sync>;
+
+// 実行する前に確認する（"tool_confirm": false なら確認しない）
+// tool_confirmation があればそこに聞き、なければ端末で聞く（端末でないときと、端末を画面に使っているときは実行しない）
+async fn confirm(config: &Config, name: &str, detail: &str) -> Result<(), String> {
+    if config.tool_confirm == Some(false) {
+        return Ok(());
+    }
+    if config.tool_confirmation.is_none() && (!io::stdin().is_terminal() || config.quiet) {
+        return Err("確認できないため実行しませんでした（\"tool_confirm\": false で確認せずに実行します）".to_string());
+    }
+    // 同時に実行しているツールの確認が混ざらないように、1つずつ聞く（待つあいだも、ほかのタスクは動ける）
+    static ASKING: Mutex<()> = Mutex::const_new(());
+    let _asking = ASKING.lock().await;
+    let allowed = match &config.tool_confirmation {
+        Some(ask) => {
+            let (reply, answer) = oneshot::channel();
+            ask(ToolConfirmation { tool: name.to_string(), detail: detail.to_string(), reply });
+            answer.await.unwrap_or(false)
+        }
+        None => ask_on_terminal(format!("ツール {} を実行します: {}\nよろしいですか？ [y/N] ", name, detail)).await,
+    };
+    match allowed {
+        true => Ok(()),
+        false => Err("ユーザーが実行を断りました".to_string()),
+    }
+}
+
+// 端末で y/N を聞く（標準入力を読むあいだ非同期のスレッドを止めないように、読むのは別のスレッドでする）
+#[cfg(feature = "native")]
+async fn ask_on_terminal(question: String) -> bool {
+    let answer = tokio::task::spawn_blocking(move || {
+        eprint!("{}", question);
+        let _ = io::stderr().flush();
+        let mut answer = String::new();
+        let _ = io::stdin().read_line(&mut answer);
+        answer
+    }).await.unwrap_or_default();
+    matches!(answer.trim(), "y" | "Y" | "yes")
+}
+
+#[cfg(not(feature = "native"))]
+async fn ask_on_terminal(_question: String) -> bool {
+    false
+}
+
+// カレントディレクトリの中のファイルかどうか（.. やシンボリックリンクでは外に出られない。ないファイルは外とみなす）
+fn is_in_current_dir(path: &Path) -> bool {
+    let (Ok(path), Ok(current)) = (path.canonicalize(), std::env::current_dir().and_then(|dir| dir.canonicalize())) else {
+        return false;
+    };
+    path.starts_with(current)
+}
+
+// ホストが許可したもの（かそのサブドメイン）かどうか
+fn is_allowed_host(host: &str, allowed: &[String]) -> bool {
+    let host = host.to_ascii_lowercase();
+    allowed.iter().any(|allowed| {
+        let allowed = allowed.trim_start_matches('.').to_ascii_lowercase();
+        host == allowed || host.ends_with(&format!(".{}", allowed))
+    })
+}
+
+// sh -c でコマンドを実行して、出力と終了コードをまとめる（input があれば標準入力に渡す）
+#[cfg(feature = "native")]
+async fn run_command(command: &str, input: Option<&str>) -> Result<String, String> {
+    let mut child = Command::new("sh").arg("-c").arg(command)
+        .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("コマンドを実行できません: {:?}", e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        if let Some(input) = input {
+            let _ = stdin.write_all(input.as_bytes()).await;
+        }
+    }
+    let output = child.wait_with_output().await.map_err(|e| format!("コマンドの実行に失敗しました: {:?}", e))?;
+    Ok(format!(
+        "exit code: {}\nstdout:\n{}\nstderr:\n{}",
+        output.status.code().map(|code| code.to_string()).unwrap_or_else(|| "なし（シグナルで終了）".to_string()),
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr),
+    ))
+}
+
+#[cfg(not(feature = "native"))]
+async fn run_command(_command: &str, _input: Option<&str>) -> Result<String, String> {
+    Err("この環境ではコマンドを実行できません".to_string())
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct ShellArguments {
+    /// sh -c で実行するコマンド
+    command: String,
+}
+
+async fn shell(config: &Config, arguments: ShellArguments) -> Result<String, String> {
+    confirm(config, "shell", &arguments.command).await?;
+    run_command(&arguments.command, None).await
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct ReadFileArguments {
+    /// 読むファイルのパス
+    path: String,
+}
+
+async fn read_file(config: &Config, arguments: ReadFileArguments) -> Result<String, String> {
+    let path = arguments.path;
+    if !is_in_current_dir(Path::new(&path)) {
+        confirm(config, "read_file", &format!("カレントディレクトリの外のファイルを読みます: {}", path)).await?;
+    }
+    std::fs::read_to_string(&path).map_err(|e| format!("{} を読めません: {:?}", path, e))
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct HttpGetArguments {
+    /// http:// か https:// の URL
+    url: String,
+}
+
+async fn http_get(config: &Config, arguments: HttpGetArguments) -> Result<String, String> {
+    let url = arguments.url;
+    let parsed = reqwest::Url::parse(&url).map_err(|e| format!("URL が不正です: {}", e))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(format!("http:// か https:// の URL だけ開けます: {}", url));
+    }
+    if !is_allowed_host(parsed.host_str().unwrap_or_default(), &config.tool_allowed_hosts) {
+        confirm(config, "http_get", &format!("tool_allowed_hosts にないホストにアクセスします: {}", url)).await?;
+    }
+    // タイムアウトと再試行は provider への送信と同じ。本文は truncate でページに分ける前に上限で切る
+    let response = PreparedRequest::get(&url).limit_body(HTTP_GET_MAX_BYTES).send(config).await
+        .map_err(|e| format!("通信エラー: {}", e))?;
+    let note = match response.body.len() >= HTTP_GET_MAX_BYTES {
+        true => format!("\n（長いため {} バイトで打ち切りました）", HTTP_GET_MAX_BYTES),
+        false => String::new(),
+    };
+    Ok(format!("status: {}\n{}{}", response.status, response.body, note))
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct PageArguments {
+    /// 切ったときに知らせた結果の番号
+    output_id: usize,
+    /// 読むページ（1から）
+    page: usize,
+}
+
+async fn execute(config: &Config, spec: &ToolSpec, arguments: &Value) -> Result<String, String> {
+    match spec {
+        ToolSpec::Builtin(name) => match builtin(name) {
+            Some(builtin) => (builtin.run)(config, arguments.clone()).await,
+            None => Err(format!("不明なツールです: {}", name)),
+        },
+        ToolSpec::Command { name, command, .. } => {
+            let input = arguments.to_string();
+            confirm(config, name, &format!("{} {}", command, input)).await?;
+            run_command(command, Some(&input)).await
+        }
+    }
+}
+
+// 1つのツールを実行して、モデルに返す結果を作る（失敗したときも、その理由を結果として返す）
+async fn call(config: &Config, name: &str, arguments: &Value) -> String {
+    let Some(spec) = config.tools.iter().find(|spec| spec.name() == name) else {
+        return format!("エラー: 不明なツールです: {}", name);
+    };
+    execute(config, spec, arguments).await.unwrap_or_else(|e| format!("エラー: {}", e))
+}
+
+// 長い結果のページ（tool_output_tokens ごとに、なるべく行の区切りで分ける）
+fn pages(config: &Config, output: &str) -> Vec<String> {
+    chunking::split_into_chunks(output, config.tool_output_tokens.unwrap_or(DEFAULT_OUTPUT_TOKENS))
+}
+
+// 長すぎる結果は stored に全体を取っておき、最初のページと続きの読み方を返す
+fn truncate(config: &Config, output: String, stored: &mut Vec<String>) -> String {
+    let mut pages = pages(config, &output);
+    if pages.len() <= 1 {
+        return output;
+    }
+    stored.push(output);
+    format!(
+        "{}\n（長いため {} ページのうち1ページ目だけを返しました。続きは {} に {{\"output_id\": {}, \"page\": 2}} を渡して読めます）",
+        pages.swap_remove(0), pages.len() + 1, PAGE_TOOL, stored.len(),
+    )
+}
+
+// read_tool_output で、取っておいた結果のページを返す
+fn read_page(config: &Config, arguments: &Value, stored: &[String]) -> String {
+    let (id, page) = match parse_arguments::<PageArguments>(arguments.clone()) {
+        Ok(arguments) => (arguments.output_id, arguments.page),
+        Err(e) => return format!("エラー: {}", e),
+    };
+    let Some(output) = id.checked_sub(1).and_then(|i| stored.get(i)) else {
+        return format!("エラー: 結果 {} はありません（1 から {} まで）", id, stored.len());
+    };
+    let pages = pages(config, output);
+    match page.checked_sub(1).and_then(|i| pages.get(i)) {
+        Some(text) if page < pages.len() => format!("{}\n（{} ページのうち {} ページ目です。続きは \"page\": {}）", text, pages.len(), page, page + 1),
+        Some(text) => format!("{}\n（{} ページのうち最後のページです）", text, pages.len()),
+        None => format!("エラー: 結果 {} のページは 1 から {} までです（{}）", id, pages.len(), page),
+    }
+}
+
+// ツールを使いながら推論する（tool_calls がなくなるまで、ツールの結果を付けて送り直す）
+// 1回の応答のツールの呼び出しを同時に実行して、呼び出された順に結果を返す
+// 長い結果は stored に取っておいて、最初のページだけを返す
+async fn call_all(config: &Config, tool_calls: &[Value], stored: &mut Vec<String>) -> Vec<String> {
+    let permits = Arc::new(Semaphore::new(config.tool_concurrency.unwrap_or(DEFAULT_CONCURRENCY).max(1)));
+    let tasks: Vec<_> = tool_calls.iter()
+        .map(|tool_call| {
+            let name = tool_call.pointer("/function/name").and_then(|name| name.as_str()).unwrap_or_default().to_string();
+            // arguments は JSON の文字列で届く
+            let arguments = tool_call.pointer("/function/arguments")
+                .and_then(|arguments| arguments.as_str())
+                .and_then(|arguments| serde_json::from_str(arguments).ok())
+                .unwrap_or(Value::Null);
+            if !config.quiet {
+                eprintln!("\x1b[2m（ツール {} を呼び出します: {}）\x1b[0m", name, arguments);
+            }
+            stream::emit_event(config, Event::ToolCallStarted { name: name.clone(), arguments: arguments.clone() });
+            let page = (name == PAGE_TOOL).then(|| read_page(config, &arguments, stored));
+            let config = config.clone();
+            let permits = permits.clone();
+            (page.is_none(), runtime::spawn(async move {
+                let _permit = permits.acquire_owned().await;
+                let output = match page {
+                    Some(page) => page,
+                    None => call(&config, &name, &arguments).await,
+                };
+                stream::emit_event(&config, Event::ToolCallFinished { name, output: output.clone() });
+                output
+            }))
+        })
+        .collect();
+    let mut outputs = Vec::new();
+    for (cut, task) in tasks {
+        let output = task.await.unwrap_or_else(|e| format!("エラー: ツールのタスクが異常終了しました: {}", e));
+        // 読んだページはもう切らない
+        outputs.push(if cut { truncate(config, output, stored) } else { output });
+    }
+    outputs
+}
+
+pub async fn infer(prompt: &str, config: &Config) -> Completion {
+    let mut config = config.clone();
+    let max_rounds = config.tool_max_rounds.unwrap_or(DEFAULT_MAX_ROUNDS);
+    let mut usage = None;
+    let mut stored = Vec::new(); // 切った結果の全体（read_tool_output の output_id の順）
+    for _ in 0..=max_rounds {
+        let mut completion = crate::infer_once(prompt, &config).await;
+        usage = Usage::add(usage, completion.usage);
+        if completion.tool_calls.is_empty() || completion.error.is_some() || config.dry_run {
+            completion.usage = usage;
+            return completion;
+        }
+        let content = Some(completion.text.as_str()).filter(|text| !text.is_empty());
+        let mut message = serde_json::json!({
+            "role": "assistant",
+            "content": content,
+            "tool_calls": completion.tool_calls,
+        });
+        // Anthropic の考え中のブロックは、ツールの結果と一緒にそのまま返さないといけない
+        if !completion.thinking_blocks.is_empty() {
+            message["thinking_blocks"] = serde_json::json!(completion.thinking_blocks);
+        }
+        config.tool_messages.push(message);
+        let outputs = call_all(&config, &completion.tool_calls, &mut stored).await;
+        if !stored.is_empty() && !config.tools.iter().any(|spec| spec.name() == PAGE_TOOL) {
+            config.tools.push(ToolSpec::Builtin(PAGE_TOOL.to_string()));
+        }
+        for (tool_call, output) in completion.tool_calls.iter().zip(outputs) {
+            config.tool_messages.push(serde_json::json!({
+                "role": "tool",
+                "tool_call_id": tool_call.get("id"),
+                "content": output,
+            }));
+        }
+    }
+    Completion::failed(format!("ツールの呼び出しが {} 回を超えたため止めました（tool_max_rounds で変えられます）", max_rounds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_under_the_current_dir_are_inside() {
+        assert!(is_in_current_dir(Path::new("Cargo.toml")));
+        assert!(is_in_current_dir(Path::new("./src/tools.rs")));
+    }
+
+    #[test]
+    fn files_outside_the_current_dir_are_not() {
+        assert!(!is_in_current_dir(Path::new("/etc/hostname")));
+        assert!(!is_in_current_dir(Path::new("../")));
+        assert!(!is_in_current_dir(Path::new("src/../../Cargo.toml")));
+        // 存在しないファイルは外とみなす（確認してから読む）
+        assert!(!is_in_current_dir(Path::new("no-such-file.txt")));
+    }
+
+    #[test]
+    fn allowed_hosts_include_subdomains_only() {
+        let allowed = vec!["example.com".to_string(), ".Docs.RS".to_string()];
+        assert!(is_allowed_host("example.com", &allowed));
+        assert!(is_allowed_host("api.example.com", &allowed));
+        assert!(is_allowed_host("docs.rs", &allowed));
+        assert!(is_allowed_host("DOCS.rs", &allowed));
+        assert!(!is_allowed_host("evil-example.com", &allowed));
+        assert!(!is_allowed_host("example.com.evil.net", &allowed));
+        assert!(!is_allowed_host("example.com", &[]));
+    }
+
+    #[test]
+    fn builtin_schemas_come_from_the_argument_types() {
+        let spec = ToolSpec::Builtin("read_file".to_string());
+        let definition = spec.definition();
+        assert_eq!(definition["function"]["description"], "テキストファイルの内容を読む");
+        assert_eq!(definition["function"]["parameters"], serde_json::json!({
+            "type": "object",
+            "properties": { "path": { "type": "string", "description": "読むファイルのパス" } },
+            "required": ["path"],
+        }));
+        let page = ToolSpec::Builtin(PAGE_TOOL.to_string()).definition();
+        assert_eq!(page["function"]["parameters"]["required"], serde_json::json!(["output_id", "page"]));
+        assert_eq!(page["function"]["parameters"]["properties"]["page"]["description"], "読むページ（1から）");
+    }
+
+    // sh でコマンドを実行するので、native の機能があるときだけ
+    #[cfg(feature = "native")]
+    #[tokio::test]
+    async fn builtin_arguments_are_checked_against_the_type() {
+        let config = command_config(1);
+        let spec = ToolSpec::Builtin("shell".to_string());
+        let missing = execute(&config, &spec, &serde_json::json!({})).await.unwrap_err();
+        assert!(missing.starts_with("引数が不正です: missing field `command`"), "{}", missing);
+        let wrong = execute(&config, &spec, &serde_json::json!({ "command": 1 })).await.unwrap_err();
+        assert!(wrong.starts_with("引数が不正です: invalid type"), "{}", wrong);
+        assert_eq!(execute(&config, &spec, &serde_json::json!({ "command": "echo hi" })).await.unwrap().lines().next(), Some("exit code: 0"));
+    }
+
+    #[tokio::test]
+    async fn confirmation_goes_to_the_callback_even_when_quiet() {
+        let asked = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let record = asked.clone();
+        let config = Config {
+            tool_confirm: None,
+            quiet: true,
+            tool_confirmation: Some(Arc::new(move |confirmation: ToolConfirmation| {
+                record.lock().unwrap().push(confirmation.tool.clone());
+                // 別のタスクから答えてもよい
+                let allowed = confirmation.detail == "ok";
+                tokio::spawn(async move { confirmation.answer(allowed) });
+            })),
+            ..command_config(1)
+        };
+        assert_eq!(confirm(&config, "shell", "ok").await, Ok(()));
+        assert_eq!(confirm(&config, "shell", "rm -rf /").await, Err("ユーザーが実行を断りました".to_string()));
+        assert_eq!(*asked.lock().unwrap(), ["shell", "shell"]);
+
+        // 答えずに捨てたら断ったことになる
+        let config = Config { tool_confirmation: Some(Arc::new(drop)), ..config };
+        assert!(confirm(&config, "shell", "ok").await.is_err());
+        // 聞く先がなく、端末も使えないときは実行しない
+        let config = Config { tool_confirmation: None, ..config };
+        assert!(confirm(&config, "shell", "ok").await.unwrap_err().starts_with("確認できないため"));
+    }
+
+    fn command_config(concurrency: usize) -> Config {
+        serde_json::from_value(serde_json::json!({
+            "model_name": "m", "use_local_model": true, "openai_compatible": false, "chat": true,
+            "tool_confirm": false, "tool_concurrency": concurrency,
+            "tools": [{ "name": "slow", "command": "s=$(tr -dc 0-9.); sleep $s; echo $s" }],
+        })).unwrap()
+    }
+
+    #[cfg(feature = "native")]
+    fn slow_calls(seconds: &[&str]) -> Vec<Value> {
+        seconds.iter().enumerate()
+            .map(|(i, s)| serde_json::json!({ "id": format!("call_{}", i), "function": { "name": "slow", "arguments": format!("{{\"s\": {}}}", s) } }))
+            .collect()
+    }
+
+    #[cfg(feature = "native")]
+    #[tokio::test]
+    async fn tool_calls_run_at_the_same_time_and_come_back_in_order() {
+        let calls = slow_calls(&["0.4", "0.1", "0.4"]);
+        let started = web_time::Instant::now();
+        let outputs = call_all(&command_config(3), &calls, &mut Vec::new()).await;
+        assert!(started.elapsed() < std::time::Duration::from_millis(800), "{:?}", started.elapsed());
+        assert_eq!(outputs.len(), 3);
+        // 短いものが先に終わっても、呼び出された順に並ぶ
+        let seconds: Vec<&str> = outputs.iter().map(|output| output.lines().nth(2).unwrap_or_default()).collect();
+        assert_eq!(seconds, ["0.4", "0.1", "0.4"], "{:?}", outputs);
+
+        let unknown = call_all(&command_config(3), &[serde_json::json!({ "function": { "name": "nope", "arguments": "{}" } })], &mut Vec::new()).await;
+        assert_eq!(unknown, ["エラー: 不明なツールです: nope"]);
+    }
+
+    #[cfg(feature = "native")]
+    #[tokio::test]
+    async fn tool_concurrency_bounds_how_many_run_at_once() {
+        let started = web_time::Instant::now();
+        call_all(&command_config(1), &slow_calls(&["0.2", "0.2"]), &mut Vec::new()).await;
+        assert!(started.elapsed() >= std::time::Duration::from_millis(400));
+    }
+
+    #[test]
+    fn long_outputs_are_cut_into_pages_that_can_be_read_later() {
+        let config = Config { tool_output_tokens: Some(10), ..command_config(1) };
+        let output: String = (1..=9).map(|i| format!("line {:02} ...\n", i)).collect(); // 1行 3 トークン
+        let mut stored = Vec::new();
+        let first = truncate(&config, output.clone(), &mut stored);
+        assert_eq!(stored, [output]);
+        assert!(first.starts_with("line 01 ...\nline 02 ...\nline 03 ...\n\n（長いため 3 ページのうち1ページ目だけ"), "{}", first);
+        assert!(first.contains("read_tool_output に {\"output_id\": 1, \"page\": 2}"));
+
+        let page = |page: u64| read_page(&config, &serde_json::json!({ "output_id": 1, "page": page }), &stored);
+        assert_eq!(page(2), "line 04 ...\nline 05 ...\nline 06 ...\n\n（3 ページのうち 2 ページ目です。続きは \"page\": 3）");
+        assert_eq!(page(3), "line 07 ...\nline 08 ...\nline 09 ...\n\n（3 ページのうち最後のページです）");
+        assert!(page(4).starts_with("エラー: 結果 1 のページは 1 から 3 まで"));
+        assert!(read_page(&config, &serde_json::json!({ "output_id": 2, "page": 1 }), &stored).starts_with("エラー: 結果 2 はありません"));
+
+        // 短い結果はそのまま返して、取っておかない
+        assert_eq!(truncate(&config, "short".to_string(), &mut stored), "short");
+        assert_eq!(stored.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn the_page_tool_reads_from_the_stored_outputs() {
+        let config = Config { tool_output_tokens: Some(10), ..command_config(1) };
+        let mut stored = vec!["aaaa bbbb cccc dddd\n".repeat(4)];
+        let call = serde_json::json!({ "function": { "name": PAGE_TOOL, "arguments": "{\"output_id\": 1, \"page\": 2}" } });
+        let outputs = call_all(&config, &[call], &mut stored).await;
+        assert_eq!(outputs, ["aaaa bbbb cccc dddd\naaaa bbbb cccc dddd\n\n（2 ページのうち最後のページです）"]);
+        assert_eq!(stored.len(), 1);
+    }
+}
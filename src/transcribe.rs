@@ -0,0 +1,60 @@
+// 音声ファイルの文字起こし（OpenAI互換の /audio/transcriptions を使う）
+//
+//   transcribe <audio> [--format text|srt|vtt] [--language ja] [--output out.srt]
+use std::path::Path;
+use crate::Config;
+use crate::error::Error;
+use crate::files::{self, api_base, authorized};
+use crate::request::PreparedRequest;
+
+// 文字起こしのモデル名を省略したときの値
+pub const DEFAULT_STT_MODEL: &str = "whisper-1";
+
+// 出力形式として指定できる値（API の response_format と同じ）
+const FORMATS: [&str; 3] = ["text", "srt", "vtt"];
+
+// 音声ファイルを文字起こしする
+pub async fn transcribe_file(config: &Config, path: &str, format: &str, language: Option<&str>) -> Result<String, Error> {
+    let contents = std::fs::read(path)
+        .map_err(|e| format!("音声ファイルの読み込みに失敗しました: {:?}", e))?;
+    let file_name = Path::new(path).file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string());
+    let url = match &config.stt_endpoint {
+        Some(endpoint) => endpoint.clone(),
+        None => format!("{}/audio/transcriptions", api_base(config)?),
+    };
+    let model = config.stt_model.as_deref().unwrap_or(DEFAULT_STT_MODEL);
+    let mut fields = vec![("model", model), ("response_format", format)];
+    if let Some(language) = language {
+        fields.push(("language", language));
+    }
+    let request = authorized(PreparedRequest::upload(&url, &fields, &file_name, contents), config);
+    files::send_text(request, config).await
+}
+
+// transcribe サブコマンド（format を省略すると "text"、output を省略すると標準出力に書く）
+pub struct TranscribeCommand {
+    pub path: String,
+    pub format: Option<String>,
+    pub language: Option<String>,
+    pub output: Option<String>,
+}
+
+// transcribe サブコマンドを実行する
+pub async fn run(command: TranscribeCommand, config: &Config) -> Result<(), Error> {
+    let format = command.format.unwrap_or_else(|| "text".to_string());
+    if !FORMATS.contains(&format.as_str()) {
+        return Err(format!("--format は {} のどれかを指定してください", FORMATS.join(" / ")).into());
+    }
+    let transcript = transcribe_file(config, &command.path, &format, command.language.as_deref()).await?;
+    match command.output {
+        Some(out_path) => {
+            std::fs::write(&out_path, &transcript)
+                .map_err(|e| format!("文字起こしの保存に失敗しました: {:?}", e))?;
+            println!("文字起こしを {} に保存しました", out_path);
+        }
+        None => println!("{}", transcript.trim_end()),
+    }
+    Ok(())
+}
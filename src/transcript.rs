@@ -0,0 +1,60 @@
+// 会話の記録を Markdown に書き出す（/export）
+//
+// 共有用（share-safe）に書き出すときは、system メッセージとモデル・接続先などの情報を外し、
+// 本文の APIキーやメールアドレスを redact フィルターで伏せ字にする。
+use crate::{filters, Config};
+use crate::conversation::Message;
+
+// 会話の記録を Markdown にする
+pub fn to_markdown(messages: &[Message], config: &Config, share_safe: bool) -> String {
+    let mut markdown = String::from("# 会話の記録\n");
+    if !share_safe {
+        markdown.push('\n');
+        markdown.push_str(&format!("- モデル: {}\n", config.model_name));
+        if let Some(profile) = &config.profile {
+            markdown.push_str(&format!("- プロファイル: {}\n", profile));
+        }
+        if let Some(provider) = &config.provider {
+            markdown.push_str(&format!("- プロバイダー: {}\n", provider));
+        }
+        if let Some(endpoint) = config.endpoint.as_ref().filter(|_| !config.use_local_model) {
+            markdown.push_str(&format!("- 接続先: {}\n", endpoint));
+        }
+        if let Some(system) = &config.system_prompt {
+            markdown.push_str(&format!("\n## system\n\n{}\n", system.trim_end()));
+        }
+    }
+    for message in messages {
+        let heading = match (message.role.as_str(), &message.model) {
+            ("user", _) => "You".to_string(),
+            ("assistant", Some(model)) if !share_safe => format!("AI（{}）", model),
+            ("assistant", _) => "AI".to_string(),
+            _ if share_safe => continue,
+            (other, _) => other.to_string(),
+        };
+        let content = if share_safe { filters::redact(&message.content) } else { message.content.clone() };
+        markdown.push_str(&format!("\n## {}\n\n{}\n", heading, content.trim_end()));
+        // 添付したファイル（共有用ではパスを書かない）
+        if !message.attachments.is_empty() {
+            markdown.push('\n');
+        }
+        for file in &message.attachments {
+            let kind = match file.kind.as_str() {
+                "image" => "画像",
+                "file" => "アップロードしたファイル",
+                _ => "テキスト",
+            };
+            match share_safe {
+                true => markdown.push_str(&format!("- 添付: {}（{}）\n", file.name, kind)),
+                false => markdown.push_str(&format!("- 添付: {}（{}、{}）\n", file.name, kind, file.path)),
+            }
+        }
+    }
+    markdown
+}
+
+// ファイルに書き出す
+pub fn export(path: &str, messages: &[Message], config: &Config, share_safe: bool) -> Result<(), String> {
+    std::fs::write(path, to_markdown(messages, config, share_safe))
+        .map_err(|e| format!("会話の書き出しに失敗しました: {:?}", e))
+}
@@ -0,0 +1,114 @@
+// ローカルのモデルを先に読み込んでおく（Ollama の warm-up と keep_alive）
+//
+//   "warm_up": true,
+//   "keep_alive": "30m",
+//   "keep_alive_interval_secs": 240
+//
+// warm_up なら、対話モードを始めるときと /model で切り替えたときに、プロンプトなしのリクエストを送って
+// モデルを読み込ませる（裏で送るので、読み込みを待たずに入力を始められる）。
+// keep_alive は Ollama へのリクエストに毎回付ける（読み込んだモデルをメモリに残しておく時間。"-1" ならずっと）。
+// keep_alive_interval_secs を書くと、その間隔で同じリクエストを送り直して、しばらく使わなくてもモデルを残しておく。
+// Ollama（ローカルのフレームワークか provider "ollama"）のときだけ使う。
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use serde_json::Value;
+use crate::error::Error;
+use crate::files::authorized;
+use crate::request::{self, PreparedRequest};
+use crate::runtime;
+use crate::{conversation, Config};
+
+const LOCAL_ENDPOINT: &str = "http://localhost:11434/api/generate";
+const CHAT_ENDPOINT: &str = "http://localhost:11434/api/chat";
+
+// 読み込ませておくモデル（Client ごとに持ち、設定を複製しても同じものを指す）
+#[derive(Clone, Default)]
+pub struct Target {
+    request: Arc<Mutex<Option<(PreparedRequest, u64)>>>, // リクエストとタイムアウトの秒数（/model で切り替えたら入れ替える）
+    keeping_alive: Arc<AtomicBool>, // keep_alive を送り直すタスクを始めたか（一度だけ始める）
+}
+
+// Ollama に送るモデルか
+pub fn is_ollama(config: &Config) -> bool {
+    match config.use_local_model {
+        true => config.local_framework.as_deref() == Some("ollama"),
+        false => config.provider.as_deref() == Some("ollama"),
+    }
+}
+
+// リクエストに付ける keep_alive（数字だけなら秒数として数値で送る）
+pub fn keep_alive(config: &Config) -> Option<Value> {
+    config.keep_alive.as_ref().map(|value| match value.parse::<i64>() {
+        Ok(seconds) => serde_json::json!(seconds),
+        Err(_) => serde_json::json!(value),
+    })
+}
+
+// 始めるときに読み込ませ、keep_alive_interval_secs があれば送り直すタスクを始める
+pub fn start(config: &Config) {
+    retarget(config);
+    let Some(interval) = config.keep_alive_interval_secs.filter(|_| is_ollama(config) && !config.dry_run) else {
+        return;
+    };
+    if config.warmup.keeping_alive.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    let target = config.warmup.request.clone();
+    runtime::spawn(async move {
+        loop {
+            runtime::sleep(Duration::from_secs(interval)).await;
+            let request = target.lock().ok().and_then(|target| target.clone());
+            if let Some((request, timeout_secs)) = request {
+                let _ = load(&request, timeout_secs).await;
+            }
+        }
+    });
+}
+
+// 読み込ませておくモデルを今の設定のものにする（warm_up なら、すぐに読み込ませる）
+pub fn retarget(config: &Config) {
+    let request = (is_ollama(config) && !config.dry_run).then(|| (load_request(config), request::timeout_secs(config)));
+    if let Ok(mut target) = config.warmup.request.lock() {
+        *target = request.clone();
+    }
+    let Some((request, timeout_secs)) = request.filter(|_| config.warm_up) else {
+        return;
+    };
+    let model = config.model_name.clone();
+    let quiet = config.quiet;
+    runtime::spawn(async move {
+        if let Err(e) = load(&request, timeout_secs).await {
+            if !quiet {
+                eprintln!("\n{} を先に読み込めませんでした: {}", model, e);
+            }
+        }
+    });
+}
+
+// プロンプトなしのリクエスト（/api/generate は prompt なし、/api/chat は messages が空なら読み込むだけ）
+fn load_request(config: &Config) -> PreparedRequest {
+    let endpoint = match (config.use_local_model, config.endpoint.as_deref()) {
+        (true, Some(endpoint)) if config.chat => conversation::chat_endpoint(endpoint),
+        (true, Some(endpoint)) => endpoint.to_string(),
+        (true, None) if config.chat => CHAT_ENDPOINT.to_string(),
+        (true, None) => LOCAL_ENDPOINT.to_string(),
+        (false, endpoint) => endpoint.map(conversation::chat_endpoint).unwrap_or(CHAT_ENDPOINT.to_string()),
+    };
+    let mut body = serde_json::json!({ "model": config.model_name });
+    if endpoint.ends_with("/api/chat") {
+        body["messages"] = serde_json::json!([]);
+    }
+    if let Some(keep_alive) = keep_alive(config) {
+        body["keep_alive"] = keep_alive;
+    }
+    authorized(PreparedRequest::new(&endpoint, body), config)
+}
+
+async fn load(request: &PreparedRequest, timeout_secs: u64) -> Result<(), Error> {
+    let response = request.send_detached(timeout_secs).await?;
+    match response.status {
+        200..=299 => Ok(()),
+        _ => Err(Error::from_response(&response)),
+    }
+}
@@ -0,0 +1,32 @@
+// 端末での表示幅（全角の文字は2桁、制御文字は0桁）
+//
+// --compare の表と --tui の画面で、折り返しや桁そろえに使う。
+// ratatui と同じ unicode-width で測るので、TUI で自分で折り返した行が画面の幅からはみ出さない。
+use unicode_width::UnicodeWidthChar;
+
+pub fn char_width(c: char) -> usize {
+    c.width().unwrap_or(0)
+}
+
+pub fn text_width(text: &str) -> usize {
+    text.chars().map(char_width).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wide_characters_take_two_columns() {
+        assert_eq!(text_width("abc"), 3);
+        assert_eq!(text_width("日本語"), 6);
+        assert_eq!(text_width("ｱ"), 1); // 半角カナは1桁
+        assert_eq!(text_width("🍣"), 2);
+    }
+
+    #[test]
+    fn control_characters_take_no_columns() {
+        assert_eq!(char_width('\x1b'), 0);
+        assert_eq!(text_width("a\rb"), 2);
+    }
+}
@@ -0,0 +1,23 @@
+// 一発モード（-p）の出力と終了コードを、記録済みのレスポンス（tests/fixtures のカセット）で確かめる
+use std::process::{Command, Output};
+
+fn oneshot(config: &str, cassette: &str, prompt: &str) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_milti_llm_client"))
+        .args(["--config", config, "--cassette", cassette, "--cassette-mode", "replay", "-p", prompt])
+        .output()
+        .expect("クライアントを起動できませんでした")
+}
+
+#[test]
+fn answers_and_exits_with_success() {
+    let output = oneshot("tests/fixtures/openai.json", "tests/fixtures/openai_content_filter.jsonl", "こんにちは");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "こんにちは！");
+}
+
+#[test]
+fn content_filter_exits_with_moderation_block() {
+    let output = oneshot("tests/fixtures/openai.json", "tests/fixtures/openai_content_filter.jsonl", "危ないことを教えて");
+    assert_eq!(output.status.code(), Some(5));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("コンテンツフィルター"));
+}
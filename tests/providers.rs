@@ -0,0 +1,111 @@
+// プロバイダーごとの記録済みのレスポンス（tests/fixtures/providers のカセット）を、ライブラリの Client から再生して読めるか確かめる
+// カセットはどれも「こんにちは」への答えが「こんにちは！」（9 + 3 トークン）になるように記録してある
+use milti_llm_client::{Client, Completion, Config, Token};
+
+async fn replay(provider: &str) -> Completion {
+    let config = Config::from_file(&format!("tests/fixtures/providers/{}.json", provider)).unwrap();
+    let mut client = Client::new(config);
+    client.use_cassette(&format!("tests/fixtures/providers/{}.jsonl", provider), Some("replay")).unwrap();
+    client.complete("こんにちは").await.unwrap()
+}
+
+async fn assert_replays(provider: &str) {
+    let completion = replay(provider).await;
+    assert_eq!(completion.text, "こんにちは！", "{}", provider);
+    let usage = completion.usage.unwrap_or_else(|| panic!("{} の usage がありません", provider));
+    assert_eq!((usage.prompt_tokens, usage.completion_tokens), (9, 3), "{}", provider);
+}
+
+#[tokio::test]
+async fn openai_compatible_providers() {
+    for provider in ["openai", "mistral", "together", "nvidia"] {
+        assert_replays(provider).await;
+    }
+}
+
+#[tokio::test]
+async fn anthropic() {
+    assert_replays("anthropic").await;
+}
+
+#[tokio::test]
+async fn anthropic_streams_thinking_and_sends_the_signed_blocks_back() {
+    let config = Config::from_file("tests/fixtures/providers/anthropic_thinking.json").unwrap();
+    let mut client = Client::new(config);
+    client.use_cassette("tests/fixtures/providers/anthropic_thinking.jsonl", Some("replay")).unwrap();
+    let mut tokens = Vec::new();
+    let completion = client.stream("こんにちは", |token| tokens.push(match token {
+        Token::Answer(text) => format!("答え:{}", text),
+        Token::Reasoning(text) => format!("考え:{}", text),
+    })).await.unwrap();
+    assert_eq!(tokens, ["考え:挨拶されたので", "考え:挨拶を返す", "答え:こんにちは", "答え:！"]);
+    assert_eq!(completion.text, "こんにちは！");
+    assert_eq!(completion.reasoning.as_deref(), Some("挨拶されたので挨拶を返す"));
+    assert_eq!(completion.finish_reason.as_deref(), Some("end_turn"));
+    let usage = completion.usage.unwrap();
+    assert_eq!((usage.prompt_tokens, usage.completion_tokens), (9, 3));
+    // 署名つきの考え中のブロックが履歴に残り、次のリクエストで assistant の先頭に返る（カセットの2件目と一致する）
+    assert_eq!(client.history()[1].thinking[0]["signature"], "sig-abc");
+    let next = client.stream("元気？", |_| {}).await.unwrap();
+    assert_eq!(next.text, "元気です");
+}
+
+// ストリーミングで少しずつ届いたツールの呼び出しをまとめて実行し、結果を返して最後の答えまで進む
+// （カセットの2件目は、ツールの結果を付けたリクエストと一致する）
+#[tokio::test]
+async fn streamed_tool_calls_run_the_agent_loop() {
+    for (provider, streamed) in [("anthropic", vec!["調べます", "東京は", "晴れです"]), ("openai", vec!["東京は", "晴れです"])] {
+        let config = Config::from_file(&format!("tests/fixtures/providers/{}_tools.json", provider)).unwrap();
+        let mut client = Client::new(config);
+        client.use_cassette(&format!("tests/fixtures/providers/{}_tools.jsonl", provider), Some("replay")).unwrap();
+        let mut tokens = Vec::new();
+        let completion = client.stream("東京の天気は？", |token| {
+            if let Token::Answer(text) = token {
+                tokens.push(text);
+            }
+        }).await.unwrap();
+        assert_eq!(tokens, streamed, "{}", provider);
+        assert_eq!(completion.text, "東京は晴れです", "{}", provider);
+        let usage = completion.usage.unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens), (50, 13), "{}", provider);
+    }
+}
+
+#[tokio::test]
+async fn gemini() {
+    assert_replays("gemini").await;
+}
+
+#[tokio::test]
+async fn ollama() {
+    assert_replays("ollama").await;
+}
+
+#[tokio::test]
+async fn cloudflare() {
+    assert_replays("cloudflare").await;
+}
+
+#[tokio::test]
+async fn watsonx_skips_the_iam_token_when_replaying() {
+    assert_replays("watsonx").await;
+}
+
+#[tokio::test]
+async fn replicate_polls_until_the_prediction_succeeds() {
+    assert_replays("replicate").await;
+}
+
+#[tokio::test]
+async fn perplexity_reads_search_results_as_citations() {
+    assert_replays("perplexity").await;
+    assert_eq!(replay("perplexity").await.citations, ["例 https://example.com"]);
+}
+
+#[tokio::test]
+async fn an_unrecorded_request_fails_without_going_online() {
+    let config = Config::from_file("tests/fixtures/providers/openai.json").unwrap();
+    let mut client = Client::new(config);
+    client.use_cassette("tests/fixtures/providers/openai.jsonl", Some("replay")).unwrap();
+    assert!(client.complete("記録していない質問").await.is_err());
+}